@@ -1,47 +1,84 @@
+use std::net::IpAddr;
+
 use rocket::{
     http::Status,
     serde::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::{util::check_authorized_admin, DB};
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    fetch::get_character,
+    media::get_media_by_id,
+    util::{check_authorized_admin, get_token_user_id, Order, PageQuery},
+    DB,
+};
 
-use super::{create_character, delete_character, get_all_characters, next_character_id};
+use super::{character_in_use, create_character, delete_character, get_characters};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct CharacterAddData<'r> {
     jwt: &'r str,
     name: &'r str,
     short_description: &'r str,
     full_description: &'r str,
-    image: &'r str,
+    media_id: u32,
 }
 
 #[post("/admin/characters/add", format = "json", data = "<data>")]
 pub async fn admin_characters_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<CharacterAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let character_id = match next_character_id(&mut db).await {
-        Ok(val) => val,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
 
-    if let Err(err) = create_character(
+    if let Err(err) = get_media_by_id(&mut db, data.media_id).await {
+        return (Status::NotFound, json!({"error": err}));
+    }
+
+    let character_id = match create_character(
         &mut db,
-        character_id,
         data.name,
         data.short_description,
         data.full_description,
-        data.image,
+        data.media_id,
+    )
+    .await
+    {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "character",
+        Some(character_id),
+        None,
+        Some(&json!({
+            "name": data.name,
+            "short_desc": data.short_description,
+            "full_desc": data.full_description,
+            "media_id": data.media_id
+        })),
+        ip,
     )
     .await
     {
@@ -51,7 +88,7 @@ pub async fn admin_characters_add(
     (Status::Ok, json!({"character_id": character_id}))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct CharacterDeleteData<'r> {
     jwt: &'r str,
@@ -61,38 +98,90 @@ pub struct CharacterDeleteData<'r> {
 #[post("/admin/characters/delete", format = "json", data = "<data>")]
 pub async fn admin_characters_delete(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<CharacterDeleteData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let character = match get_character(&mut db, data.character_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    match character_in_use(&mut db, data.character_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return (
+                Status::Conflict,
+                json!({"error": "Character is used in dialogues"}),
+            )
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
     if let Err(err) = delete_character(&mut db, data.character_id).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "delete",
+        "character",
+        Some(data.character_id),
+        Some(&json!(character)),
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct CharacterGetData<'r> {
     jwt: &'r str,
+    cursor: Option<u32>,
+    limit: Option<u32>,
+    order: Option<Order>,
+    search: Option<&'r str>,
 }
 
 #[post("/admin/characters/get", format = "json", data = "<data>")]
 pub async fn admin_characters_get(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<CharacterGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let characters = match get_all_characters(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let characters = match get_characters(&mut db, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(characters))
+    (
+        Status::Ok,
+        json!({
+            "characters": characters.items,
+            "total": characters.total,
+            "next_cursor": characters.next_cursor
+        }),
+    )
 }
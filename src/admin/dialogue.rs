@@ -1,63 +1,109 @@
+use std::net::IpAddr;
+
 use rocket::{
     http::Status,
     serde::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::{util::check_authorized_admin, DB};
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    util::{check_authorized_admin, get_token_user_id, Order, PageQuery},
+    DB,
+};
 
 use super::{
-    create_dialogue, delete_dialogue, delete_dialogue_parts, get_all_dialogues,
-    get_unused_dialogues, next_dialogue_id, set_dialogue_parts,
+    check_content_exists, create_dialogue, delete_dialogue, get_dialogue_by_id, get_dialogue_parts, get_dialogues,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct DialogueAddData<'r> {
     jwt: &'r str,
     quest_id: Option<u32>,
     name: &'r str,
     is_skippable: bool,
-    parts: Vec<(u32, &'r str)>,
+    parts: Vec<(u32, String)>,
 }
 
 #[post("/admin/dialogues/add", format = "json", data = "<data>")]
 pub async fn admin_dialogues_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<DialogueAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let dialogue_id = match next_dialogue_id(&mut db).await {
-        Ok(val) => val,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
 
-    if let Err(err) = create_dialogue(
+    if data.parts.is_empty() {
+        return (Status::BadRequest, json!({"error": "Empty dialogue_parts not allowed"}));
+    }
+
+    if let Some(quest_id) = data.quest_id {
+        if let Some(err) = check_content_exists(&mut db, "quests", quest_id).await {
+            return err;
+        }
+    }
+
+    let mut character_ids: Vec<u32> = data.parts.iter().map(|(id, _)| *id).collect();
+    character_ids.sort_unstable();
+    character_ids.dedup();
+    for character_id in character_ids {
+        if let Some(err) = check_content_exists(&mut db, "characters", character_id).await {
+            return err;
+        }
+    }
+
+    let dialogue_id = match create_dialogue(
         &mut db,
-        dialogue_id,
         data.quest_id,
         data.name,
         data.is_skippable,
+        &data.parts,
     )
     .await
     {
-        return (Status::InternalServerError, json!({"error": err}));
-    }
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
 
-    if let Err(err) = set_dialogue_parts(&mut db, dialogue_id, &data.parts).await {
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "dialogue",
+        Some(dialogue_id),
+        None,
+        Some(&json!({
+            "quest_id": data.quest_id,
+            "name": data.name,
+            "is_skippable": data.is_skippable,
+            "parts": data.parts
+        })),
+        ip,
+    )
+    .await
+    {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
     (Status::Ok, json!({"dialogue_id": dialogue_id}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct DialogueDeleteData<'r> {
     jwt: &'r str,
@@ -67,59 +113,112 @@ pub struct DialogueDeleteData<'r> {
 #[post("/admin/dialogues/delete", format = "json", data = "<data>")]
 pub async fn admin_dialogues_delete(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<DialogueDeleteData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let dialogue = match get_dialogue_by_id(&mut db, data.dialogue_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    let parts = match get_dialogue_parts(&mut db, data.dialogue_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
     if let Err(err) = delete_dialogue(&mut db, data.dialogue_id).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if let Err(err) = delete_dialogue_parts(&mut db, data.dialogue_id).await {
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "delete",
+        "dialogue",
+        Some(data.dialogue_id),
+        Some(&json!({"dialogue": dialogue, "parts": parts})),
+        None,
+        ip,
+    )
+    .await
+    {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct DialogueGetData<'r> {
     jwt: &'r str,
+    quest_id: Option<u32>,
+    cursor: Option<u32>,
+    limit: Option<u32>,
+    order: Option<Order>,
+    search: Option<&'r str>,
 }
 
 #[post("/admin/dialogues/get", format = "json", data = "<data>")]
 pub async fn admin_dialogues_get(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<DialogueGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let dialogues = match get_all_dialogues(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let dialogues = match get_dialogues(&mut db, data.quest_id, false, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(dialogues))
+    (
+        Status::Ok,
+        json!({
+            "dialogues": dialogues.items,
+            "total": dialogues.total,
+            "next_cursor": dialogues.next_cursor
+        }),
+    )
 }
 
 #[post("/admin/dialogues/get/unused", format = "json", data = "<data>")]
 pub async fn admin_dialogues_get_unused(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<DialogueGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let dialogues = match get_unused_dialogues(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let dialogues = match get_dialogues(&mut db, data.quest_id, true, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(dialogues))
+    (
+        Status::Ok,
+        json!({
+            "dialogues": dialogues.items,
+            "total": dialogues.total,
+            "next_cursor": dialogues.next_cursor
+        }),
+    )
 }
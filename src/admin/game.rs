@@ -1,17 +1,30 @@
+use std::net::IpAddr;
+
 use rocket::{
     http::Status,
     serde::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    events::{EventBus, GameEvent},
+    util::{check_authorized_admin, get_token_user_id},
+    DB,
+};
 
-use crate::{util::check_authorized_admin, DB};
-
-use super::{game_set_location_radius, game_set_state, game_set_tutorial};
+use super::{
+    check_content_exists, game_set_location_radius, game_set_state, game_set_tutorial,
+    get_game_state,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GamePauseData<'r> {
     jwt: &'r str,
@@ -20,20 +33,52 @@ pub struct GamePauseData<'r> {
 #[post("/admin/game/pause", format = "json", data = "<data>")]
 pub async fn admin_game_pause(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
     data: Json<GamePauseData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let before = match get_game_state(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
     if let Err(err) = game_set_state(&mut db, true).await {
         return (Status::BadRequest, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "pause",
+        "game",
+        None,
+        Some(&json!({"paused": before.paused})),
+        Some(&json!({"paused": true})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if !before.paused {
+        events.publish(GameEvent::GamePaused);
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GameUnpauseData<'r> {
     jwt: &'r str,
@@ -42,20 +87,52 @@ pub struct GameUnpauseData<'r> {
 #[post("/admin/game/unpause", format = "json", data = "<data>")]
 pub async fn admin_game_unpause(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
     data: Json<GameUnpauseData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let before = match get_game_state(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
     if let Err(err) = game_set_state(&mut db, false).await {
         return (Status::BadRequest, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "unpause",
+        "game",
+        None,
+        Some(&json!({"paused": before.paused})),
+        Some(&json!({"paused": false})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if before.paused {
+        events.publish(GameEvent::GameUnpaused);
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GameSetTutorialData<'r> {
     jwt: &'r str,
@@ -65,9 +142,25 @@ pub struct GameSetTutorialData<'r> {
 #[post("/admin/quests/select_tutorial", format = "json", data = "<data>")]
 pub async fn admin_quests_select_tutorial(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<GameSetTutorialData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let before = match get_game_state(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Some(err) = check_content_exists(&mut db, "quests", data.quest_id).await {
         return err;
     }
 
@@ -75,10 +168,25 @@ pub async fn admin_quests_select_tutorial(
         return (Status::BadRequest, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "set_tutorial",
+        "game",
+        None,
+        Some(&json!({"tutorial_id": before.tutorial_id})),
+        Some(&json!({"tutorial_id": data.quest_id})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GameSetLocationRadiusData<'r> {
     jwt: &'r str,
@@ -88,15 +196,47 @@ pub struct GameSetLocationRadiusData<'r> {
 #[post("/admin/game/set_location_radius", format = "json", data = "<data>")]
 pub async fn admin_game_set_location_radius(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
     data: Json<GameSetLocationRadiusData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let before = match get_game_state(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
     if let Err(err) = game_set_location_radius(&mut db, data.distance).await {
         return (Status::BadRequest, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "set_location_radius",
+        "game",
+        None,
+        Some(&json!({"location_radius": before.location_radius})),
+        Some(&json!({"location_radius": data.distance})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    events.publish(GameEvent::LocationRadiusChanged {
+        radius: data.distance,
+    });
+
     (Status::Ok, json!({}))
 }
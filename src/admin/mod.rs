@@ -3,9 +3,22 @@ pub mod dialogue;
 pub mod task;
 pub mod quest;
 pub mod game;
-
-use rocket::serde::Serialize;
-use sqlx::{prelude::FromRow, query, query_as, Row, SqliteConnection};
+pub mod user;
+
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, serde_json, serde_json::Map, Value},
+        Deserialize, Serialize,
+    },
+};
+use schemars::JsonSchema;
+use sqlx::{prelude::FromRow, query, query_as, Column, Connection, QueryBuilder, Row, ValueRef};
+
+use crate::{
+    db::{json_text, sync_ids, table_columns, Backend, DbConnection, DbRow, NullableId},
+    util::{fetch_page, Condition, Page, PageQuery},
+};
 
 //  █████╗ ██████╗ ███╗   ███╗██╗███╗   ██╗    ███████╗██╗   ██╗███╗   ██╗ ██████╗████████╗██╗ ██████╗ ███╗   ██╗███████╗
 // ██╔══██╗██╔══██╗████╗ ████║██║████╗  ██║    ██╔════╝██║   ██║████╗  ██║██╔════╝╚══██╔══╝██║██╔═══██╗████╗  ██║██╔════╝
@@ -14,6 +27,42 @@ use sqlx::{prelude::FromRow, query, query_as, Row, SqliteConnection};
 // ██║  ██║██████╔╝██║ ╚═╝ ██║██║██║ ╚████║    ██║     ╚██████╔╝██║ ╚████║╚██████╗   ██║   ██║╚██████╔╝██║ ╚████║███████║
 // ╚═╝  ╚═╝╚═════╝ ╚═╝     ╚═╝╚═╝╚═╝  ╚═══╝    ╚═╝      ╚═════╝ ╚═╝  ╚═══╝ ╚═════╝   ╚═╝   ╚═╝ ╚═════╝ ╚═╝  ╚═══╝╚══════╝
 
+// Handlers look references up first so a dangling id is a 404 instead of a foreign key error
+pub async fn check_content_exists(
+    db: &mut DbConnection,
+    table: &str,
+    id: u32,
+) -> Option<(Status, Value)> {
+    let (id_column, noun) = match table {
+        "characters" => ("character_id", "Character"),
+        "dialogues" => ("dialogue_id", "Dialogue"),
+        "tasks" => ("task_id", "Task"),
+        "quests" => ("quest_id", "Quest"),
+        _ => {
+            return Some((
+                Status::InternalServerError,
+                json!({"error": format!("Unknown content table: {}", table)}),
+            ))
+        }
+    };
+
+    match query(&format!("SELECT 1 FROM {} WHERE {} = $1", table, id_column))
+        .bind(i64::from(id))
+        .fetch_optional(db)
+        .await
+    {
+        Ok(Some(_)) => None,
+        Ok(None) => Some((
+            Status::NotFound,
+            json!({"error": format!("{} {} not found", noun, id)}),
+        )),
+        Err(err) => Some((
+            Status::InternalServerError,
+            json!({"error": format!("Failed to look up {}: {}", table, err)}),
+        )),
+    }
+}
+
 //  ██████╗██╗  ██╗ █████╗ ██████╗  █████╗  ██████╗████████╗███████╗██████╗
 // ██╔════╝██║  ██║██╔══██╗██╔══██╗██╔══██╗██╔════╝╚══██╔══╝██╔════╝██╔══██╗
 // ██║     ███████║███████║██████╔╝███████║██║        ██║   █████╗  ██████╔╝
@@ -21,62 +70,49 @@ use sqlx::{prelude::FromRow, query, query_as, Row, SqliteConnection};
 // ╚██████╗██║  ██║██║  ██║██║  ██║██║  ██║╚██████╗   ██║   ███████╗██║  ██║
 //  ╚═════╝╚═╝  ╚═╝╚═╝  ╚═╝╚═╝  ╚═╝╚═╝  ╚═╝ ╚═════╝   ╚═╝   ╚══════╝╚═╝  ╚═╝
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct Character {
+    #[sqlx(try_from = "i64")]
     pub character_id: u32,
     pub name: String,
     pub short_desc: String,
     pub full_desc: String,
-    pub image: String,
-}
-
-pub async fn next_character_id(db: &mut SqliteConnection) -> Result<u32, String> {
-    match query("SELECT MAX(character_id) FROM characters")
-        .fetch_optional(db)
-        .await
-    {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
-        },
-        Err(_) => Err("Failed to perform a database query".to_owned()),
-    }
+    // Only set on characters created before images were uploaded as media
+    pub image: Option<String>,
+    #[sqlx(try_from = "NullableId")]
+    pub media_id: Option<u32>,
 }
 
 pub async fn create_character(
-    db: &mut SqliteConnection,
-    id: u32,
+    db: &mut DbConnection,
     name: &str,
     short_desc: &str,
     full_desc: &str,
-    image: &str,
-) -> Result<(), String> {
+    media_id: u32,
+) -> Result<u32, String> {
     match query(
         "INSERT INTO
         characters
-        (character_id, name, short_desc, full_desc, image)
-        VALUES (?,?,?,?,?)",
+        (name, short_desc, full_desc, media_id)
+        VALUES ($1,$2,$3,$4)
+        RETURNING character_id",
     )
-    .bind(id)
     .bind(name)
     .bind(short_desc)
     .bind(full_desc)
-    .bind(image)
-    .execute(db)
+    .bind(i64::from(media_id))
+    .fetch_one(db)
     .await
     {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to create user: {}", err)),
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to create character: {}", err)),
     }
 }
 
-pub async fn delete_character(db: &mut SqliteConnection, id: u32) -> Result<(), String> {
-    match query("DELETE FROM characters WHERE character_id = ?")
-        .bind(id)
+pub async fn delete_character(db: &mut DbConnection, id: u32) -> Result<(), String> {
+    match query("DELETE FROM characters WHERE character_id = $1")
+        .bind(i64::from(id))
         .execute(db)
         .await
     {
@@ -85,16 +121,35 @@ pub async fn delete_character(db: &mut SqliteConnection, id: u32) -> Result<(),
     }
 }
 
-pub async fn get_all_characters(db: &mut SqliteConnection) -> Result<Vec<Character>, String> {
-    match query_as::<_, Character>("SELECT * FROM characters")
-        .fetch_all(db)
+pub async fn character_in_use(db: &mut DbConnection, id: u32) -> Result<bool, String> {
+    match query("SELECT 1 FROM dialogue_parts WHERE character_id = $1 LIMIT 1")
+        .bind(i64::from(id))
+        .fetch_optional(db)
         .await
     {
-        Ok(val) => Ok(val),
-        Err(err) => Err(format!("Failed to get characters: {}", err)),
+        Ok(val) => Ok(val.is_some()),
+        Err(err) => Err(format!("Failed to look up dialogue parts: {}", err)),
     }
 }
 
+pub async fn get_characters(
+    db: &mut DbConnection,
+    page: &PageQuery<'_>,
+) -> Result<Page<Character>, String> {
+    fetch_page(
+        db,
+        "characters",
+        "character_id",
+        &["name", "short_desc", "full_desc"],
+        &[],
+        page,
+    )
+    .await?
+    .try_map(|row| {
+        Character::from_row(row).map_err(|err| format!("Failed to read character: {}", err))
+    })
+}
+
 // ██████╗ ██╗ █████╗ ██╗      ██████╗  ██████╗ ██╗   ██╗███████╗
 // ██╔══██╗██║██╔══██╗██║     ██╔═══██╗██╔════╝ ██║   ██║██╔════╝
 // ██║  ██║██║███████║██║     ██║   ██║██║  ███╗██║   ██║█████╗
@@ -102,141 +157,178 @@ pub async fn get_all_characters(db: &mut SqliteConnection) -> Result<Vec<Charact
 // ██████╔╝██║██║  ██║███████╗╚██████╔╝╚██████╔╝╚██████╔╝███████╗
 // ╚═════╝ ╚═╝╚═╝  ╚═╝╚══════╝ ╚═════╝  ╚═════╝  ╚═════╝ ╚══════╝
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct Dialogue {
+    #[sqlx(try_from = "i64")]
     pub dialogue_id: u32,
+    #[sqlx(try_from = "NullableId")]
     pub quest_id: Option<u32>,
     pub name: String,
     pub is_skippable: bool,
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct DialoguePart {
+    #[sqlx(try_from = "i64")]
     pub dialogue_id: u32,
+    #[sqlx(try_from = "i64")]
     pub part_id: u32,
+    #[sqlx(try_from = "i64")]
     pub character_id: u32,
     pub text: String,
 }
 
-pub async fn next_dialogue_id(db: &mut SqliteConnection) -> Result<u32, String> {
-    match query("SELECT MAX(dialogue_id) FROM dialogues")
-        .fetch_optional(db)
-        .await
-    {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
-        },
-        Err(_) => Err("Failed to perform a database query".to_owned()),
-    }
-}
-
+// Creates the dialogue and its parts together, nothing is left behind if either fails
 pub async fn create_dialogue(
-    db: &mut SqliteConnection,
-    id: u32,
+    db: &mut DbConnection,
     quest_id: Option<u32>,
     name: &str,
     is_skippable: bool,
-) -> Result<(), String> {
-    match query(
+    parts: &[(u32, String)],
+) -> Result<u32, String> {
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+    };
+
+    let id = match query(
         "INSERT INTO
         dialogues
-        (dialogue_id, quest_id, name, is_skippable)
-        VALUES (?,?,?,?)",
+        (quest_id, name, is_skippable)
+        VALUES ($1,$2,$3)
+        RETURNING dialogue_id",
     )
-    .bind(id)
-    .bind(quest_id)
+    .bind(quest_id.map(i64::from))
     .bind(name)
     .bind(is_skippable)
-    .execute(db)
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(_) => Ok(()),
+        Ok(val) => val.get::<i64, _>(0) as u32,
+        Err(err) => return Err(format!("Failed to create dialogue: {}", err)),
+    };
+
+    set_dialogue_parts(&mut tx, id, parts).await?;
+
+    match tx.commit().await {
+        Ok(_) => Ok(id),
         Err(err) => Err(format!("Failed to create dialogue: {}", err)),
     }
 }
 
-pub async fn delete_dialogue(db: &mut SqliteConnection, id: u32) -> Result<(), String> {
-    match query("DELETE FROM dialogues WHERE dialogue_id = ?")
-        .bind(id)
-        .execute(db)
+// Deletes the dialogue together with its parts
+pub async fn delete_dialogue(db: &mut DbConnection, id: u32) -> Result<(), String> {
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+    };
+
+    if let Err(err) = query("DELETE FROM dialogues WHERE dialogue_id = $1")
+        .bind(i64::from(id))
+        .execute(&mut *tx)
         .await
     {
+        return Err(format!("Failed to delete dialogue: {}", err));
+    }
+
+    delete_dialogue_parts(&mut tx, id).await?;
+
+    match tx.commit().await {
         Ok(_) => Ok(()),
         Err(err) => Err(format!("Failed to delete dialogue: {}", err)),
     }
 }
 
-pub async fn get_all_dialogues(db: &mut SqliteConnection) -> Result<Vec<Dialogue>, String> {
-    match query_as::<_, Dialogue>("SELECT * FROM dialogues")
-        .fetch_all(db)
+pub async fn get_dialogue_by_id(db: &mut DbConnection, id: u32) -> Result<Dialogue, String> {
+    match query_as::<_, Dialogue>("SELECT * FROM dialogues WHERE dialogue_id = $1")
+        .bind(i64::from(id))
+        .fetch_optional(db)
         .await
     {
-        Ok(val) => Ok(val),
-        Err(err) => Err(format!("Failed to get dialogues: {}", err)),
+        Ok(Some(val)) => Ok(val),
+        Ok(None) => Err("Dialogue not found".to_string()),
+        Err(err) => Err(format!("Failed to get dialogue: {}", err)),
     }
 }
 
-pub async fn get_unused_dialogues(db: &mut SqliteConnection) -> Result<Vec<Dialogue>, String> {
-    match query_as::<_, Dialogue>("SELECT * FROM dialogues WHERE quest_id is Null")
-        .fetch_all(db)
-        .await
-    {
-        Ok(val) => Ok(val),
-        Err(err) => Err(format!("Failed to get unused dialogues: {}", err)),
+// Unused dialogues aren't part of any quest yet
+pub async fn get_dialogues(
+    db: &mut DbConnection,
+    quest_id: Option<u32>,
+    unused: bool,
+    page: &PageQuery<'_>,
+) -> Result<Page<Dialogue>, String> {
+    let mut conditions = vec![];
+    if let Some(quest_id) = quest_id {
+        conditions.push(Condition::Equals("quest_id", quest_id));
     }
+    if unused {
+        conditions.push(Condition::IsNull("quest_id"));
+    }
+
+    fetch_page(db, "dialogues", "dialogue_id", &["name"], &conditions, page)
+        .await?
+        .try_map(|row| {
+            Dialogue::from_row(row).map_err(|err| format!("Failed to read dialogue: {}", err))
+        })
 }
 
+// Rows per insert, keeps the bound parameters well below sqlite's limit
+const DIALOGUE_PARTS_PER_INSERT: usize = 500;
+
 pub async fn set_dialogue_parts(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     dialogue_id: u32,
-    dialogue_parts: &Vec<(u32, &str)>,
+    dialogue_parts: &[(u32, String)],
 ) -> Result<(), String> {
     if dialogue_parts.is_empty() {
         return Err("Empty dialogue_parts not allowed".to_string());
     }
 
-    let mut insertion_query =
-        "INSERT INTO dialogue_parts (dialogue_id, part_id, character_id, text) VALUES ".to_string();
-    for (part_id, (part_user, part_text)) in dialogue_parts.iter().enumerate() {
-        insertion_query += &format!(
-            "({}, {}, {}, \"{}\"),",
-            dialogue_id, part_id, part_user, part_text
+    for (chunk_id, chunk) in dialogue_parts
+        .chunks(DIALOGUE_PARTS_PER_INSERT)
+        .enumerate()
+    {
+        let mut insertion = QueryBuilder::<Backend>::new(
+            "INSERT INTO dialogue_parts (dialogue_id, part_id, character_id, text) ",
         );
+        insertion.push_values(chunk.iter().enumerate(), |mut row, (i, (character_id, text))| {
+            row.push_bind(i64::from(dialogue_id))
+                .push_bind((chunk_id * DIALOGUE_PARTS_PER_INSERT + i) as i64)
+                .push_bind(i64::from(*character_id))
+                .push_bind(text.as_str());
+        });
+
+        if let Err(err) = insertion.build().execute(&mut *db).await {
+            return Err(format!("Failed to set dialogue parts: {}", err));
+        }
     }
-    insertion_query.pop().unwrap();
 
-    match query(&insertion_query).execute(db).await {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to set dialogue parts: {}", err)),
-    }
+    Ok(())
 }
 
 pub async fn get_dialogue_parts(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     dialogue_id: u32,
 ) -> Result<Vec<DialoguePart>, String> {
-    match query_as::<_, DialoguePart>("SELECT * FROM dialogue_parts WHERE dialogue_id = ?")
-        .bind(dialogue_id)
+    match query_as::<_, DialoguePart>("SELECT * FROM dialogue_parts WHERE dialogue_id = $1")
+        .bind(i64::from(dialogue_id))
         .fetch_all(db)
         .await
     {
         Ok(mut val) => {
-            val.sort_by(|a, b| a.part_id.cmp(&b.part_id));
+            val.sort_by_key(|a| a.part_id);
             Ok(val)
         }
         Err(err) => Err(format!("Failed to get unused dialogues: {}", err)),
     }
 }
 
-pub async fn delete_dialogue_parts(db: &mut SqliteConnection, id: u32) -> Result<(), String> {
-    match query("DELETE FROM dialogue_parts WHERE dialogue_id = ?")
-        .bind(id)
+pub async fn delete_dialogue_parts(db: &mut DbConnection, id: u32) -> Result<(), String> {
+    match query("DELETE FROM dialogue_parts WHERE dialogue_id = $1")
+        .bind(i64::from(id))
         .execute(db)
         .await
     {
@@ -252,23 +344,28 @@ pub async fn delete_dialogue_parts(db: &mut SqliteConnection, id: u32) -> Result
 //    ██║   ██║  ██║███████║██║  ██╗
 //    ╚═╝   ╚═╝  ╚═╝╚══════╝╚═╝  ╚═╝
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct LocationTask {
+    #[sqlx(try_from = "i64")]
     pub task_id: u32,
     pub name: String,
+    #[sqlx(try_from = "NullableId")]
     pub quest_id: Option<u32>,
     pub desc: Option<String>,
     pub min_radius: f32,
     pub max_radius: f32,
+    #[sqlx(try_from = "NullableId")]
     pub location_to_duplicate: Option<u32>,
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct ChoiceTask {
+    #[sqlx(try_from = "i64")]
     pub task_id: u32,
     pub name: String,
+    #[sqlx(try_from = "NullableId")]
     pub quest_id: Option<u32>,
     pub desc: Option<String>,
     pub question: String,
@@ -276,162 +373,143 @@ pub struct ChoiceTask {
     pub choice_answers: Vec<u32>,
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct TextTask {
+    #[sqlx(try_from = "i64")]
     pub task_id: u32,
     pub name: String,
+    #[sqlx(try_from = "NullableId")]
     pub quest_id: Option<u32>,
     pub desc: Option<String>,
     pub question: String,
     pub text_answers: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub enum Task {
     Location(LocationTask),
     Choice(ChoiceTask),
     Text(TextTask),
-    Invalid(String),
 }
 
-pub async fn next_task_id(db: &mut SqliteConnection) -> Result<u32, String> {
-    match query("SELECT MAX(task_id) FROM tasks")
-        .fetch_optional(db)
-        .await
-    {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
-        },
-        Err(_) => Err("Failed to perform a database query".to_owned()),
-    }
+// What a task asks of the player, stored as json in tasks.payload
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum TaskKind {
+    Location {
+        min_radius: f32,
+        max_radius: f32,
+        location_to_duplicate: Option<u32>,
+    },
+    Choice {
+        question: String,
+        answers: Vec<String>,
+        correct_answers: Vec<u32>,
+    },
+    Text {
+        question: String,
+        answers: Vec<String>,
+    },
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn add_location_task(
-    db: &mut SqliteConnection,
-    task_id: u32,
-    name: &str,
-    quest_id: Option<u32>,
-    desc: Option<&str>,
-    min_radius: f32,
-    max_radius: f32,
-    location_to_duplicate: Option<u32>,
-) -> Result<(), String> {
-    match query(
-        "INSERT INTO tasks
-        (task_id, type, name, quest_id, desc, min_radius, max_radius, location_to_duplicate)
-        VALUES
-        (?,\'location\',?,?,?,?,?,?)",
-    )
-    .bind(task_id)
-    .bind(name)
-    .bind(quest_id)
-    .bind(desc)
-    .bind(min_radius)
-    .bind(max_radius)
-    .bind(location_to_duplicate)
-    .execute(db)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to add location task: {}", err)),
+impl TaskKind {
+    pub fn task_type(&self) -> TaskType {
+        match self {
+            TaskKind::Location { .. } => TaskType::Location,
+            TaskKind::Choice { .. } => TaskType::Choice,
+            TaskKind::Text { .. } => TaskType::Text,
+        }
     }
-}
-
-#[allow(clippy::too_many_arguments)]
-pub async fn add_choice_task(
-    db: &mut SqliteConnection,
-    task_id: u32,
-    name: &str,
-    quest_id: Option<u32>,
-    desc: Option<&str>,
-    question: &str,
-    answers: &Vec<&str>,
-    correct_answers: &Vec<u32>,
-) -> Result<(), String> {
-    let mut ans = [false; 32];
-    let mut ans_str = String::new();
 
-    for id in correct_answers.iter() {
-        if *id >= 32 {
-            return Err("Questions with more than 32 answers are not allowed".to_string());
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            TaskKind::Location {
+                min_radius,
+                max_radius,
+                ..
+            } => {
+                if !(*min_radius >= 0.0 && min_radius <= max_radius && max_radius.is_finite()) {
+                    return Err("Radius must satisfy 0 <= min_radius <= max_radius".to_owned());
+                }
+            }
+            TaskKind::Choice {
+                answers,
+                correct_answers,
+                ..
+            } => {
+                if answers.is_empty() {
+                    return Err("Choice task needs at least one answer".to_owned());
+                }
+                for (i, id) in correct_answers.iter().enumerate() {
+                    if *id as usize >= answers.len() {
+                        return Err(format!("Correct answer {} is out of range", id));
+                    }
+                    if correct_answers[..i].contains(id) {
+                        return Err(format!("Correct answer {} is listed twice", id));
+                    }
+                }
+            }
+            TaskKind::Text { answers, .. } => {
+                if answers.is_empty() {
+                    return Err("Text task needs at least one answer".to_owned());
+                }
+            }
         }
-        ans[*id as usize] = true;
+        Ok(())
     }
 
-    for i in ans.iter() {
-        ans_str.push(if *i { '1' } else { '0' });
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|err| format!("Failed to encode task: {}", err))
     }
 
-    let answers = answers
-        .iter()
-        .map(|x| format!("{}\n", x))
-        .fold("".to_string(), |acc, x| format!("{}{}", acc, x));
-
-    match query(
-        "INSERT INTO tasks
-        (task_id, type, name, quest_id, desc, question, answers, choice_answers)
-        VALUES
-        (?,\'choice\',?,?,?,?,?,?)",
-    )
-    .bind(task_id)
-    .bind(name)
-    .bind(quest_id)
-    .bind(desc)
-    .bind(question)
-    .bind(answers)
-    .bind(ans_str)
-    .execute(db)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to add choice task: {}", err)),
+    pub fn from_json(task_type: &str, payload: &str) -> Result<TaskKind, String> {
+        let kind: TaskKind = serde_json::from_str(payload)
+            .map_err(|err| format!("Failed to decode task: {}", err))?;
+        if kind.task_type().as_str() != task_type {
+            return Err(format!(
+                "Task payload is {} but the task is marked as {}",
+                kind.task_type().as_str(),
+                task_type
+            ));
+        }
+        Ok(kind)
     }
 }
 
-pub async fn add_text_task(
-    db: &mut SqliteConnection,
-    task_id: u32,
+pub async fn add_task(
+    db: &mut DbConnection,
     name: &str,
     quest_id: Option<u32>,
     desc: Option<&str>,
-    question: &str,
-    correct_answers: &Vec<&str>,
-) -> Result<(), String> {
-    let answers = correct_answers
-        .iter()
-        .map(|x| format!("{}\n", x))
-        .fold("".to_string(), |acc, x| format!("{}{}", acc, x));
+    kind: &TaskKind,
+) -> Result<u32, String> {
+    let payload = kind.to_json()?;
 
     match query(
         "INSERT INTO tasks
-        (task_id, type, name, quest_id, desc, question, text_answers)
+        (type, name, quest_id, \"desc\", payload)
         VALUES
-        (?,\'text\',?,?,?,?,?)",
+        ($1,$2,$3,$4,$5)
+        RETURNING task_id",
     )
-    .bind(task_id)
+    .bind(kind.task_type().as_str())
     .bind(name)
-    .bind(quest_id)
+    .bind(quest_id.map(i64::from))
     .bind(desc)
-    .bind(question)
-    .bind(answers)
-    .execute(db)
+    .bind(payload)
+    .fetch_one(db)
     .await
     {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to add text task: {}", err)),
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to add task: {}", err)),
     }
 }
 
-pub async fn delete_task(db: &mut SqliteConnection, id: u32) -> Result<(), String> {
-    match query("DELETE FROM tasks WHERE task_id = ?")
-        .bind(id)
+pub async fn delete_task(db: &mut DbConnection, id: u32) -> Result<(), String> {
+    match query("DELETE FROM tasks WHERE task_id = $1")
+        .bind(i64::from(id))
         .execute(db)
         .await
     {
@@ -440,250 +518,113 @@ pub async fn delete_task(db: &mut SqliteConnection, id: u32) -> Result<(), Strin
     }
 }
 
-pub async fn get_tasks(db: &mut SqliteConnection) -> Result<Vec<Task>, String> {
-    let rows = match query("SELECT * FROM tasks").fetch_all(db).await {
-        Ok(val) => val,
-        Err(err) => return Err(format!("Failed to get tasks: {}", err)),
-    };
-
-    let tasks: Vec<Task> = rows
-        .iter()
-        .map(|row| {
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(min_radius)),
-                Ok(Some(max_radius)),
-                Ok(location_to_duplicate),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("min_radius"),
-                row.try_get("max_radius"),
-                row.try_get("location_to_duplicate"),
-            ) {
-                if task_type != "location" {
-                    return Task::Invalid("Task which matches the chracteristics of a location task is not marked as such".to_string());
-                }
-
-                return Task::Location(LocationTask {
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    min_radius,
-                    max_radius,
-                    location_to_duplicate,
-                });
-            }
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(question)),
-                Ok(Some(answers)),
-                Ok(Some(choice_answers)),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("question"),
-                row.try_get::<Option<&str>, _>("answers"),
-                row.try_get::<Option<&str>, _>("choice_answers"),
-            ) {
-                if task_type != "choice" {
-                    return Task::Invalid("Task which matches the chracteristics of a choice task is not marked as such".to_string());
-                }
-
-                let answers = answers.trim().split("\n").map(|x| x.to_owned()).collect();
-                let choice_answers = choice_answers.trim().chars().enumerate().filter_map(|(i, x)| {
-                    if x == '1' {
-                        Some(i as u32)
-                    } else {
-                        None
-                    }
-                }).collect();
-
-                return Task::Choice(ChoiceTask{
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    question,
-                    answers,
-                    choice_answers
-                });
-            }
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(question)),
-                Ok(Some(text_answers)),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("question"),
-                row.try_get::<Option<&str>, _>("text_answers"),
-            ) {
-                if task_type != "text" {
-                    return Task::Invalid("Task which matches the chracteristics of a text task is not marked as such".to_string());
-                }
-                
-                let text_answers = text_answers.trim().split("\n").map(|x| x.to_owned()).collect();
-
-                return Task::Text(TextTask{
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    question,
-                    text_answers
-                });
-            }
-            Task::Invalid("Task does not match any category".to_string())
-        })
-        .collect();
-
-    Ok(tasks)
+#[derive(Debug, FromRow)]
+struct TaskRow {
+    #[sqlx(try_from = "i64")]
+    task_id: u32,
+    #[sqlx(rename = "type")]
+    task_type: String,
+    name: String,
+    #[sqlx(try_from = "NullableId")]
+    quest_id: Option<u32>,
+    desc: Option<String>,
+    payload: Option<String>,
 }
 
-pub async fn get_tasks_unused(db: &mut SqliteConnection) -> Result<Vec<Task>, String> {
-    let rows = match query("SELECT * FROM tasks WHERE tasks.quest_id is Null").fetch_all(db).await {
-        Ok(val) => val,
-        Err(err) => return Err(format!("Failed to get tasks: {}", err)),
+fn task_from_row(row: &DbRow) -> Result<Task, String> {
+    let row = TaskRow::from_row(row).map_err(|err| format!("Failed to read task: {}", err))?;
+    let payload = match row.payload {
+        Some(val) => val,
+        None => return Err(format!("Task {} has no payload", row.task_id)),
     };
+    let kind = TaskKind::from_json(&row.task_type, &payload)
+        .map_err(|err| format!("Task {}: {}", row.task_id, err))?;
+
+    Ok(match kind {
+        TaskKind::Location {
+            min_radius,
+            max_radius,
+            location_to_duplicate,
+        } => Task::Location(LocationTask {
+            task_id: row.task_id,
+            name: row.name,
+            quest_id: row.quest_id,
+            desc: row.desc,
+            min_radius,
+            max_radius,
+            location_to_duplicate,
+        }),
+        TaskKind::Choice {
+            question,
+            answers,
+            correct_answers,
+        } => Task::Choice(ChoiceTask {
+            task_id: row.task_id,
+            name: row.name,
+            quest_id: row.quest_id,
+            desc: row.desc,
+            question,
+            answers,
+            choice_answers: correct_answers,
+        }),
+        TaskKind::Text { question, answers } => Task::Text(TextTask {
+            task_id: row.task_id,
+            name: row.name,
+            quest_id: row.quest_id,
+            desc: row.desc,
+            question,
+            text_answers: answers,
+        }),
+    })
+}
 
-    let tasks: Vec<Task> = rows
-        .iter()
-        .map(|row| {
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(min_radius)),
-                Ok(Some(max_radius)),
-                Ok(location_to_duplicate),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("min_radius"),
-                row.try_get("max_radius"),
-                row.try_get("location_to_duplicate"),
-            ) {
-                if task_type != "location" {
-                    return Task::Invalid("Task which matches the chracteristics of a location task is not marked as such".to_string());
-                }
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum TaskType {
+    Location,
+    Choice,
+    Text,
+}
 
-                return Task::Location(LocationTask {
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    min_radius,
-                    max_radius,
-                    location_to_duplicate,
-                });
-            }
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(question)),
-                Ok(Some(answers)),
-                Ok(Some(choice_answers)),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("question"),
-                row.try_get::<Option<&str>, _>("answers"),
-                row.try_get::<Option<&str>, _>("choice_answers"),
-            ) {
-                if task_type != "choice" {
-                    return Task::Invalid("Task which matches the chracteristics of a choice task is not marked as such".to_string());
-                }
+impl TaskType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskType::Location => "location",
+            TaskType::Choice => "choice",
+            TaskType::Text => "text",
+        }
+    }
+}
 
-                let answers = answers.trim().split("\n").map(|x| x.to_owned()).collect();
-                let choice_answers = choice_answers.trim().chars().enumerate().filter_map(|(i, x)| {
-                    if x == '1' {
-                        Some(i as u32)
-                    } else {
-                        None
-                    }
-                }).collect();
-
-                return Task::Choice(ChoiceTask{
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    question,
-                    answers,
-                    choice_answers
-                });
-            }
-            if let (
-                Ok(Some(task_id)),
-                Ok(Some(task_type)),
-                Ok(Some(name)),
-                Ok(quest_id),
-                Ok(desc),
-                Ok(Some(question)),
-                Ok(Some(text_answers)),
-            ) = (
-                row.try_get("task_id"),
-                row.try_get::<Option<&str>, _>("type"),
-                row.try_get("name"),
-                row.try_get("quest_id"),
-                row.try_get("desc"),
-                row.try_get("question"),
-                row.try_get::<Option<&str>, _>("text_answers"),
-            ) {
-                if task_type != "text" {
-                    return Task::Invalid("Task which matches the chracteristics of a text task is not marked as such".to_string());
-                }
-                
-                let text_answers = text_answers.trim().split("\n").map(|x| x.to_owned()).collect();
-
-                return Task::Text(TextTask{
-                    task_id,
-                    name,
-                    quest_id,
-                    desc,
-                    question,
-                    text_answers
-                });
-            }
-            Task::Invalid("Task does not match any category".to_string())
-        })
-        .collect();
+// Unused tasks aren't part of any quest yet
+pub async fn get_tasks(
+    db: &mut DbConnection,
+    quest_id: Option<u32>,
+    task_type: Option<TaskType>,
+    unused: bool,
+    page: &PageQuery<'_>,
+) -> Result<Page<Task>, String> {
+    let mut conditions = vec![];
+    if let Some(quest_id) = quest_id {
+        conditions.push(Condition::Equals("quest_id", quest_id));
+    }
+    if let Some(task_type) = task_type {
+        conditions.push(Condition::Matches("type", task_type.as_str()));
+    }
+    if unused {
+        conditions.push(Condition::IsNull("quest_id"));
+    }
 
-    Ok(tasks)
+    fetch_page(
+        db,
+        "tasks",
+        "task_id",
+        &["name", "\"desc\"", &json_text("payload", "question")],
+        &conditions,
+        page,
+    )
+    .await?
+    .try_map(task_from_row)
 }
 
 //  ██████╗ ██╗   ██╗███████╗███████╗████████╗
@@ -696,16 +637,20 @@ pub async fn get_tasks_unused(db: &mut SqliteConnection) -> Result<Vec<Task>, St
 
 #[derive(Debug, FromRow)]
 pub struct QuestRow {
+    #[sqlx(try_from = "i64")]
     pub quest_id: u32,
+    #[sqlx(rename = "quest_name")]
     pub name: String,
     pub desc: String,
     pub unlocks: String,
+    #[sqlx(try_from = "i64")]
     pub points: u32,
+    #[sqlx(try_from = "i64")]
     pub coins: u32,
     pub rewards: String
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct Quest {
     pub quest_id: u32,
@@ -717,79 +662,63 @@ pub struct Quest {
     pub rewards: Vec<u32>
 }
 
-impl From<&QuestRow> for Quest {
-    fn from(value: &QuestRow) -> Quest {
-        
-        let unlocks = value.unlocks.split('\n').map(|x| x.parse::<u32>().unwrap_or(0)).collect();
-        let rewards = value.rewards.split('\n').map(|x| x.parse::<u32>().unwrap_or(0)).collect();
+fn decode_id_list(quest_id: u32, column: &str, value: &str) -> Result<Vec<u32>, String> {
+    serde_json::from_str(value)
+        .map_err(|err| format!("Quest {} has invalid {}: {}", quest_id, column, err))
+}
 
-        Quest {
+impl TryFrom<&QuestRow> for Quest {
+    type Error = String;
+
+    fn try_from(value: &QuestRow) -> Result<Quest, String> {
+        Ok(Quest {
             quest_id: value.quest_id,
             desc: value.desc.clone(),
             coins: value.coins,
             points: value.points,
             name: value.name.clone(),
-            rewards,
-            unlocks
-        }
-    }
-}
-
-pub async fn next_quest_id(db: &mut SqliteConnection) -> Result<u32, String> {
-    match query("SELECT MAX(quest_id) FROM quests")
-        .fetch_optional(db)
-        .await
-    {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
-        },
-        Err(_) => Err("Failed to perform a database query".to_owned()),
+            rewards: decode_id_list(value.quest_id, "rewards", &value.rewards)?,
+            unlocks: decode_id_list(value.quest_id, "unlocks", &value.unlocks)?,
+        })
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_quest(
-    db: &mut SqliteConnection,
-    id: u32,
+    db: &mut DbConnection,
     name: &str,
     desc: &str,
-    unlocks: &Vec<u32>,
+    unlocks: &[u32],
     points: u32,
     coins: u32,
-    rewards: &Vec<u32>,
-) -> Result<(), String> {
-    let unlocks_str: String = unlocks.iter().map(|x| {let mut y = x.to_string(); y.push('\n'); y})
-        .fold("".to_string(), |mut acc, x| { acc.push_str(&x); acc });
-    
-    let rewards_str: String = rewards.iter().map(|x| {let mut y = x.to_string(); y.push('\n'); y})
-        .fold("".to_string(), |mut acc, x| { acc.push_str(&x); acc });
+    rewards: &[u32],
+) -> Result<u32, String> {
+    let unlocks_str = json!(unlocks).to_string();
+    let rewards_str = json!(rewards).to_string();
 
     match query(
         "INSERT INTO
-        quests (quest_id, quest_name, desc, unlocks, points, coins, rewards)
-        VALUES (?,?,?,?,?,?)",
+        quests (quest_name, \"desc\", unlocks, points, coins, rewards)
+        VALUES ($1,$2,$3,$4,$5,$6)
+        RETURNING quest_id",
     )
-    .bind(id)
     .bind(name)
     .bind(desc)
     .bind(unlocks_str)
-    .bind(points)
-    .bind(coins)
+    .bind(i64::from(points))
+    .bind(i64::from(coins))
     .bind(rewards_str)
-    .execute(db)
+    .fetch_one(db)
     .await
     {
-        Ok(_) => Ok(()),
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
         Err(err) => Err(format!("Failed to create quest: {}", err)),
     }
 }
 
-pub async fn delete_quest(db: &mut SqliteConnection, id: u32) -> Result<(), String> {
-    match query("DELETE FROM quests WHERE quest_id = ?")
-        .bind(id)
+pub async fn delete_quest(db: &mut DbConnection, id: u32) -> Result<(), String> {
+    match query("DELETE FROM quests WHERE quest_id = $1")
+        .bind(i64::from(id))
         .execute(db)
         .await
     {
@@ -798,23 +727,21 @@ pub async fn delete_quest(db: &mut SqliteConnection, id: u32) -> Result<(), Stri
     }
 }
 
-pub async fn get_all_quests(db: &mut SqliteConnection) -> Result<Vec<Quest>, String> {
-    let rows = match query_as::<_, QuestRow>("SELECT * FROM quests")
-        .fetch_all(db)
-        .await
-    {
-        Ok(val) => val,
-        Err(err) => return Err(format!("Failed to get quests: {}", err)),
-    };
-
-    Ok(rows.iter().map(|row| {
-        Quest::from(row)
-    }).collect())
+pub async fn get_quests(
+    db: &mut DbConnection,
+    page: &PageQuery<'_>,
+) -> Result<Page<Quest>, String> {
+    fetch_page(db, "quests", "quest_id", &["quest_name", "\"desc\""], &[], page)
+        .await?
+        .try_map(|row| match QuestRow::from_row(row) {
+            Ok(val) => Quest::try_from(&val),
+            Err(err) => Err(format!("Failed to read quest: {}", err)),
+        })
 }
 
-pub async fn get_quest_by_id(db: &mut SqliteConnection, id: u32) -> Result<Quest, String> {
-    let rows = match query_as::<_, QuestRow>("SELECT * FROM quests WHERE quest_id = ?")
-        .bind(id)
+pub async fn get_quest_by_id(db: &mut DbConnection, id: u32) -> Result<Quest, String> {
+    let rows = match query_as::<_, QuestRow>("SELECT * FROM quests WHERE quest_id = $1")
+        .bind(i64::from(id))
         .fetch_all(db)
         .await
     {
@@ -823,9 +750,7 @@ pub async fn get_quest_by_id(db: &mut SqliteConnection, id: u32) -> Result<Quest
     };
 
     if let Some(row) = rows.first() {
-        Ok(
-            Quest::from(row)
-        )
+        Quest::try_from(row)
     } else {
         Err(
             "Quest not found".to_string()
@@ -842,16 +767,20 @@ pub async fn get_quest_by_id(db: &mut SqliteConnection, id: u32) -> Result<Quest
 
 #[derive(Debug, FromRow)]
 struct QuestStageRow {
+    #[sqlx(try_from = "i64")]
     _quest_id: u32,
+    #[sqlx(try_from = "i64")]
     stage_id: u32,
+    #[sqlx(try_from = "NullableId")]
     task_id: Option<u32>,
+    #[sqlx(try_from = "NullableId")]
     dialogue_id: Option<u32>,
     task_name: Option<String>,
     task_type: Option<String>,
     dialogue_name: Option<String>
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStage {
     pub stage_id: u32,
@@ -860,30 +789,31 @@ pub struct QuestStage {
     pub name: String
 }
 
+const QUEST_STAGE_ATTEMPTS: u32 = 16;
+
 #[derive(Debug)]
 pub enum QuestStageContent {
     Dialogue(u32),
     Task(u32)
 }
 
-pub async fn next_quest_stage_id(db: &mut SqliteConnection, quest_id: u32) -> Result<u32, String> {
-    match query("SELECT MAX(stage_id) FROM quest_stages WHERE quest_id = ?")
-        .bind(quest_id)
-        .fetch_optional(db)
+pub async fn last_quest_stage_id(db: &mut DbConnection, quest_id: u32) -> Result<u32, String> {
+    match query("SELECT COALESCE(MAX(stage_id), 0) FROM quest_stages WHERE quest_id = $1")
+        .bind(i64::from(quest_id))
+        .fetch_one(db)
         .await
     {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(id) => Ok(id as u32),
+            Err(_) => Err("Database error".to_owned()),
         },
         Err(_) => Err("Failed to perform a database query".to_owned()),
     }
 }
 
-pub async fn add_quest_stage(db: &mut SqliteConnection, quest_id: u32, stage_id: u32, content: QuestStageContent) -> Result<(), String> {
+// Stage ids are positions within the quest, the next one is picked inside the insert. Concurrent
+// inserts into one quest can pick the same id on postgres, the loser tries again
+pub async fn add_quest_stage(db: &mut DbConnection, quest_id: u32, content: QuestStageContent) -> Result<u32, String> {
     let task_id: Option<u32> = match content {
         QuestStageContent::Task(val) => Some(val),
         QuestStageContent::Dialogue(_) => None
@@ -894,30 +824,42 @@ pub async fn add_quest_stage(db: &mut SqliteConnection, quest_id: u32, stage_id:
         QuestStageContent::Task(_) => None
     };
     
-    match query(
-        "INSERT INTO
-        quest_stages
-        (quest_id, stage_id, task_id, dialogue_id)
-        VALUES (?,?,?,?)",
-    )
-    .bind(quest_id)
-    .bind(stage_id)
-    .bind(task_id)
-    .bind(dialogue_id)
-    .execute(db)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to add quest stage: {}", err)),
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match query(
+            "INSERT INTO
+            quest_stages
+            (quest_id, stage_id, task_id, dialogue_id)
+            SELECT $1, COALESCE(MAX(stage_id), 0) + 1, $2, $3 FROM quest_stages WHERE quest_id = $4
+            RETURNING stage_id",
+        )
+        .bind(i64::from(quest_id))
+        .bind(task_id.map(i64::from))
+        .bind(dialogue_id.map(i64::from))
+        .bind(i64::from(quest_id))
+        .fetch_one(&mut *db)
+        .await
+        {
+            Ok(row) => {
+                return match row.try_get::<i64, _>(0) {
+                    Ok(id) => Ok(id as u32),
+                    Err(_) => Err("Database error".to_owned()),
+                }
+            }
+            Err(sqlx::Error::Database(err))
+                if err.is_unique_violation() && attempts < QUEST_STAGE_ATTEMPTS => {}
+            Err(err) => return Err(format!("Failed to add quest stage: {}", err)),
+        }
     }
 }
 
-pub async fn get_all_quest_stages(db: &mut SqliteConnection, quest_id: u32) -> Result<Vec<QuestStage>, String> {
+pub async fn get_all_quest_stages(db: &mut DbConnection, quest_id: u32) -> Result<Vec<QuestStage>, String> {
     let rows = match query_as::<_, QuestStageRow>("SELECT quest_stages.quest_id, quest_stages.stage_id, quest_stages.task_id, quest_stages.dialogue_id, tasks.name, tasks.type, dialogues.name
         JOIN tasks ON tasks.task_id = quest_stages.task_id
         JOIN dialogues ON dialogues.dialogue_id = quest_stages.dialogue_id
-        WHERE quest_stages.quest_id = ?")
-        .bind(quest_id)
+        WHERE quest_stages.quest_id = $1")
+        .bind(i64::from(quest_id))
         .fetch_all(db)
         .await 
         {
@@ -946,10 +888,10 @@ pub async fn get_all_quest_stages(db: &mut SqliteConnection, quest_id: u32) -> R
     }).collect())
 }
 
-pub async fn delete_quest_stage(db: &mut SqliteConnection, quest_id: u32, stage_id: u32) -> Result<(), String> {
-    match query("DELETE FROM quest_stages WHERE quest_id = ? AND stage_id = ?")
-        .bind(quest_id)
-        .bind(stage_id)
+pub async fn delete_quest_stage(db: &mut DbConnection, quest_id: u32, stage_id: u32) -> Result<(), String> {
+    match query("DELETE FROM quest_stages WHERE quest_id = $1 AND stage_id = $2")
+        .bind(i64::from(quest_id))
+        .bind(i64::from(stage_id))
         .execute(db)
         .await
     {
@@ -958,46 +900,42 @@ pub async fn delete_quest_stage(db: &mut SqliteConnection, quest_id: u32, stage_
     }
 }
 
-pub async fn change_quest_stage_id_forward(db: &mut SqliteConnection, quest_id: u32, pos: u32) -> Result<(), String> {
-    match query("UPDATE quest_stages 
-        SET stage_id = CASE
-            stage_id = ? THEN ? + 1
-            stage_id = ? + 1 THEN ?
-        WHERE quest_id = ? AND stage_id IN (?, ? + 1)")
-        .bind(pos)
-        .bind(pos)
-        .bind(pos)
-        .bind(pos)
-        .bind(quest_id)
-        .bind(pos)
-        .bind(pos)
-        .execute(db)
-        .await
-    {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to change quest stage ids: {}", err))
+// Stage 0 is never handed out, so it parks one row while the other takes its id
+async fn swap_quest_stages(db: &mut DbConnection, quest_id: u32, a: u32, b: u32) -> Result<(), String> {
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to change quest stage ids: {}", err)),
+    };
+
+    for (from, to) in [(a, 0), (b, a), (0, b)] {
+        let res = query("UPDATE quest_stages SET stage_id = $1 WHERE quest_id = $2 AND stage_id = $3")
+            .bind(i64::from(to))
+            .bind(i64::from(quest_id))
+            .bind(i64::from(from))
+            .execute(&mut *tx)
+            .await;
+
+        match res {
+            Ok(val) if val.rows_affected() == 1 => {}
+            Ok(_) => return Err("Quest stage not found".to_string()),
+            Err(err) => return Err(format!("Failed to change quest stage ids: {}", err)),
+        }
     }
-}
 
-pub async fn change_quest_stage_id_back(db: &mut SqliteConnection, quest_id: u32, pos: u32) -> Result<(), String> {
-    match query("UPDATE quest_stages 
-        SET stage_id = CASE
-            stage_id = ? THEN ? - 1
-            stage_id = ? - 1 THEN ?
-        WHERE quest_id = ? AND stage_id IN (?, ? - 1)")
-        .bind(pos)
-        .bind(pos)
-        .bind(pos)
-        .bind(pos)
-        .bind(quest_id)
-        .bind(pos)
-        .bind(pos)
-        .execute(db)
+    tx.commit()
         .await
-    {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("Failed to change quest stage ids: {}", err))
+        .map_err(|err| format!("Failed to change quest stage ids: {}", err))
+}
+
+pub async fn change_quest_stage_id_forward(db: &mut DbConnection, quest_id: u32, pos: u32) -> Result<(), String> {
+    swap_quest_stages(db, quest_id, pos, pos + 1).await
+}
+
+pub async fn change_quest_stage_id_back(db: &mut DbConnection, quest_id: u32, pos: u32) -> Result<(), String> {
+    if pos <= 1 {
+        return Err("Quest stage not found".to_string());
     }
+    swap_quest_stages(db, quest_id, pos, pos - 1).await
 }
 
 
@@ -1009,8 +947,27 @@ pub async fn change_quest_stage_id_back(db: &mut SqliteConnection, quest_id: u32
 //  ╚═════╝ ╚═╝  ╚═╝╚═╝     ╚═╝╚══════╝
 
 
-pub async fn game_set_state(db: &mut SqliteConnection, paused: bool) -> Result<(), String> {
-    match query("UPDATE game SET paused = ?")
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct GameState {
+    pub paused: bool,
+    pub location_radius: f32,
+    #[sqlx(try_from = "NullableId")]
+    pub tutorial_id: Option<u32>,
+}
+
+pub async fn get_game_state(db: &mut DbConnection) -> Result<GameState, String> {
+    match query_as::<_, GameState>("SELECT paused, location_radius, tutorial_id FROM game")
+        .fetch_one(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get game state: {}", err)),
+    }
+}
+
+pub async fn game_set_state(db: &mut DbConnection, paused: bool) -> Result<(), String> {
+    match query("UPDATE game SET paused = $1")
         .bind(paused)
         .execute(db)
         .await
@@ -1020,9 +977,9 @@ pub async fn game_set_state(db: &mut SqliteConnection, paused: bool) -> Result<(
     }
 }
 
-pub async fn game_set_tutorial(db: &mut SqliteConnection, quest_id: u32) -> Result<(), String> {
-    match query("UPDATE game SET tutorial_id = ?")
-        .bind(quest_id)
+pub async fn game_set_tutorial(db: &mut DbConnection, quest_id: u32) -> Result<(), String> {
+    match query("UPDATE game SET tutorial_id = $1")
+        .bind(i64::from(quest_id))
         .execute(db)
         .await
     {
@@ -1031,8 +988,8 @@ pub async fn game_set_tutorial(db: &mut SqliteConnection, quest_id: u32) -> Resu
     }
 }
 
-pub async fn game_set_location_radius(db: &mut SqliteConnection, r: f32) -> Result<(), String> {
-    match query("UPDATE game SET location_radius = ?")
+pub async fn game_set_location_radius(db: &mut DbConnection, r: f32) -> Result<(), String> {
+    match query("UPDATE game SET location_radius = $1")
         .bind(r)
         .execute(db)
         .await
@@ -1041,3 +998,233 @@ pub async fn game_set_location_radius(db: &mut SqliteConnection, r: f32) -> Resu
         Err(err) => Err(format!("Failed to set location radius: {}", err))
     }
 }
+
+//  ██████╗ ██████╗ ███╗   ██╗████████╗███████╗███╗   ██╗████████╗
+// ██╔════╝██╔═══██╗████╗  ██║╚══██╔══╝██╔════╝████╗  ██║╚══██╔══╝
+// ██║     ██║   ██║██╔██╗ ██║   ██║   █████╗  ██╔██╗ ██║   ██║
+// ██║     ██║   ██║██║╚██╗██║   ██║   ██╔══╝  ██║╚██╗██║   ██║
+// ╚██████╗╚██████╔╝██║ ╚████║   ██║   ███████╗██║ ╚████║   ██║
+//  ╚═════╝ ╚═════╝ ╚═╝  ╚═══╝   ╚═╝   ╚══════╝╚═╝  ╚═══╝   ╚═╝
+
+// Ordered so that every row is inserted after the rows it references
+const CONTENT_TABLES: [&str; 7] = [
+    "media",
+    "characters",
+    "quests",
+    "dialogues",
+    "dialogue_parts",
+    "tasks",
+    "quest_stages",
+];
+
+// Generated ids of the content tables, new rows continue after the imported ones
+const CONTENT_IDS: [(&str, &str); 5] = [
+    ("media", "media_id"),
+    ("characters", "character_id"),
+    ("quests", "quest_id"),
+    ("dialogues", "dialogue_id"),
+    ("tasks", "task_id"),
+];
+
+pub fn row_to_json(row: &DbRow) -> Result<Map<String, Value>, String> {
+    let mut object = Map::new();
+
+    for (i, column) in row.columns().iter().enumerate() {
+        let is_null = match row.try_get_raw(i) {
+            Ok(val) => val.is_null(),
+            Err(err) => return Err(format!("Failed to read column {}: {}", column.name(), err)),
+        };
+
+        let value = if is_null {
+            Value::Null
+        } else if let Ok(val) = row.try_get::<i64, _>(i) {
+            Value::from(val)
+        } else if let Ok(val) = row.try_get::<f64, _>(i) {
+            Value::from(val)
+        } else if let Ok(val) = row.try_get::<f32, _>(i) {
+            Value::from(val)
+        } else if let Ok(val) = row.try_get::<bool, _>(i) {
+            Value::from(val)
+        } else if let Ok(val) = row.try_get::<String, _>(i) {
+            Value::from(val)
+        } else {
+            return Err(format!("Unsupported value in column {}", column.name()));
+        };
+
+        object.insert(column.name().to_owned(), value);
+    }
+
+    Ok(object)
+}
+
+pub async fn export_content(db: &mut DbConnection) -> Result<Value, String> {
+    let mut content = Map::new();
+
+    for table in CONTENT_TABLES {
+        let rows = match query(&format!("SELECT * FROM {}", table))
+            .fetch_all(&mut *db)
+            .await
+        {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Failed to export {}: {}", table, err)),
+        };
+
+        let rows = rows
+            .iter()
+            .map(|x| row_to_json(x).map(Value::Object))
+            .collect::<Result<Vec<Value>, String>>()?;
+
+        content.insert(table.to_owned(), Value::Array(rows));
+    }
+
+    let game = get_game_state(db).await?;
+    content.insert(
+        "game".to_owned(),
+        json!({"location_radius": game.location_radius, "tutorial_id": game.tutorial_id}),
+    );
+
+    Ok(Value::Object(content))
+}
+
+async fn import_row(
+    db: &mut DbConnection,
+    table: &str,
+    columns: &[(String, String)],
+    row: &Value,
+) -> Result<(), String> {
+    let row = match row.as_object() {
+        Some(val) => val,
+        None => return Err(format!("Rows of {} have to be objects", table)),
+    };
+
+    let mut types = Vec::new();
+    for key in row.keys() {
+        match columns.iter().find(|(name, _)| name == key) {
+            Some((_, column_type)) => types.push(column_type.to_lowercase()),
+            None => return Err(format!("Unknown column {} in {}", key, table)),
+        }
+    }
+
+    // Nulls are written inline as their type depends on the column
+    let mut placeholders = 0;
+    let values = row
+        .values()
+        .map(|x| match x {
+            Value::Null => "NULL".to_owned(),
+            _ => {
+                placeholders += 1;
+                format!("${}", placeholders)
+            }
+        })
+        .collect::<Vec<String>>();
+
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table,
+        row.keys()
+            .map(|x| format!("\"{}\"", x))
+            .collect::<Vec<String>>()
+            .join(", "),
+        values.join(", ")
+    );
+
+    // Exports from sqlite keep booleans as numbers
+    let mut statement = query(&sql);
+    for (value, column_type) in row.values().zip(types) {
+        statement = match value {
+            Value::Null => statement,
+            Value::Bool(val) => statement.bind(*val),
+            Value::Number(val) if column_type.starts_with("bool") => {
+                statement.bind(val.as_f64() != Some(0.0))
+            }
+            Value::Number(val) => match val.as_i64() {
+                Some(val) => statement.bind(val),
+                None => statement.bind(val.as_f64()),
+            },
+            Value::String(val) => statement.bind(val.as_str()),
+            _ => return Err(format!("Nested values aren't supported in {}", table)),
+        };
+    }
+
+    match statement.execute(db).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to import a row of {}: {}", table, err)),
+    }
+}
+
+// Replaces all content with the one from an export, nothing is changed if any row fails
+pub async fn import_content(db: &mut DbConnection, content: &Value) -> Result<(), String> {
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+    };
+
+    for table in CONTENT_TABLES.iter().rev() {
+        if let Err(err) = query(&format!("DELETE FROM {}", table))
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(format!("Failed to clear {}: {}", table, err));
+        }
+    }
+
+    for table in CONTENT_TABLES {
+        let rows = match content.get(table).and_then(|x| x.as_array()) {
+            Some(val) => val,
+            None => return Err(format!("Content is missing the {} list", table)),
+        };
+
+        let columns = table_columns(&mut tx, table).await?;
+        for row in rows {
+            import_row(&mut tx, table, &columns, row).await?;
+        }
+    }
+
+    for (table, id_column) in CONTENT_IDS {
+        sync_ids(&mut tx, table, id_column).await?;
+    }
+
+    // Payloads and lists are decoded strictly when read, so reject broken ones up front
+    let tasks = match query("SELECT * FROM tasks").fetch_all(&mut *tx).await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to check imported tasks: {}", err)),
+    };
+    for row in tasks.iter() {
+        task_from_row(row)?;
+    }
+    let quests = match query_as::<_, QuestRow>("SELECT * FROM quests")
+        .fetch_all(&mut *tx)
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to check imported quests: {}", err)),
+    };
+    for row in quests.iter() {
+        Quest::try_from(row)?;
+    }
+
+    if let Some(game) = content.get("game") {
+        let location_radius = match game.get("location_radius").and_then(|x| x.as_f64()) {
+            Some(val) => val,
+            None => return Err("Game settings are missing location_radius".to_owned()),
+        };
+
+        if let Err(err) = query("UPDATE game SET location_radius = $1, tutorial_id = $2")
+            .bind(location_radius)
+            .bind(
+                game.get("tutorial_id")
+                    .and_then(|x| x.as_u64())
+                    .map(|x| x as i64),
+            )
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(format!("Failed to import game settings: {}", err));
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to commit the import: {}", err)),
+    }
+}
@@ -1,21 +1,31 @@
+use std::net::IpAddr;
+
 use rocket::{
     http::Status,
     serde::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
-
-use crate::{util::check_authorized_admin, DB};
+use schemars::JsonSchema;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    events::{EventBus, GameEvent},
+    util::{check_authorized_admin, get_token_user_id, Order, PageQuery},
+    DB,
+};
 
 use super::{
-    add_quest_stage, change_quest_stage_id_back, change_quest_stage_id_forward, create_quest,
-    delete_quest, delete_quest_stage, get_all_quest_stages, get_all_quests, get_quest_by_id,
-    next_quest_id, next_quest_stage_id, QuestStageContent,
+    add_quest_stage, change_quest_stage_id_back, change_quest_stage_id_forward,
+    check_content_exists, create_quest, delete_quest, delete_quest_stage, get_all_quest_stages,
+    get_quest_by_id, get_quests, last_quest_stage_id, QuestStageContent,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestAddData<'r> {
     jwt: &'r str,
@@ -30,20 +40,22 @@ pub struct QuestAddData<'r> {
 #[post("/admin/quests/add", format = "json", data = "<data>")]
 pub async fn admin_quests_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
     data: Json<QuestAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let quest_id = match next_quest_id(&mut db).await {
-        Ok(val) => val,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
 
-    if let Err(err) = create_quest(
+    let quest_id = match create_quest(
         &mut db,
-        quest_id,
         data.name,
         data.description,
         &data.unlocks,
@@ -52,14 +64,39 @@ pub async fn admin_quests_add(
         &data.rewards,
     )
     .await
+    {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "quest",
+        Some(quest_id),
+        None,
+        Some(&json!({
+            "name": data.name,
+            "desc": data.description,
+            "unlocks": data.unlocks,
+            "points": data.points,
+            "coins": data.coins,
+            "rewards": data.rewards
+        })),
+        ip,
+    )
+    .await
     {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    (Status::Ok, json!({}))
+    events.publish(GameEvent::QuestAvailable { quest_id });
+
+    (Status::Ok, json!({"quest_id": quest_id}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStageAddData<'r> {
     jwt: &'r str,
@@ -71,45 +108,69 @@ pub struct QuestStageAddData<'r> {
 #[post("/admin/quests/stages/add", format = "json", data = "<data>")]
 pub async fn admin_quests_stages_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestStageAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let quest_stage_id = match next_quest_stage_id(&mut db, data.quest_id).await {
-        Ok(val) => val,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
 
-    if let Some(task_id) = data.task_id {
-        if let Err(err) = add_quest_stage(
-            &mut db,
-            data.quest_id,
-            quest_stage_id,
-            QuestStageContent::Task(task_id),
-        )
-        .await
-        {
-            return (Status::InternalServerError, json!({"error": err}));
-        }
+    let (content, table, id) = if let Some(task_id) = data.task_id {
+        (QuestStageContent::Task(task_id), "tasks", task_id)
     } else if let Some(dialogue_id) = data.dialogue_id {
-        if let Err(err) = add_quest_stage(
-            &mut db,
-            data.quest_id,
-            quest_stage_id,
+        (
             QuestStageContent::Dialogue(dialogue_id),
+            "dialogues",
+            dialogue_id,
         )
-        .await
-        {
-            return (Status::InternalServerError, json!({"error": err}));
-        }
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"error": "either task_id or dialogue_id is required"}),
+        );
+    };
+
+    if let Some(err) = check_content_exists(&mut db, "quests", data.quest_id).await {
+        return err;
+    }
+    if let Some(err) = check_content_exists(&mut db, table, id).await {
+        return err;
+    }
+
+    let quest_stage_id = match add_quest_stage(&mut db, data.quest_id, content).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "quest_stage",
+        Some(data.quest_id),
+        None,
+        Some(&json!({
+            "stage_id": quest_stage_id,
+            "task_id": data.task_id,
+            "dialogue_id": data.dialogue_id
+        })),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
     }
 
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStageDeleteData<'r> {
     jwt: &'r str,
@@ -120,20 +181,42 @@ pub struct QuestStageDeleteData<'r> {
 #[post("/admin/quests/stages/delete", format = "json", data = "<data>")]
 pub async fn admin_quests_stages_delete(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestStageDeleteData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
     if let Err(err) = delete_quest_stage(&mut db, data.quest_id, data.position).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "delete",
+        "quest_stage",
+        Some(data.quest_id),
+        Some(&json!({"stage_id": data.position})),
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStageGetData<'r> {
     jwt: &'r str,
@@ -143,9 +226,10 @@ pub struct QuestStageGetData<'r> {
 #[post("/admin/quests/stages/get", format = "json", data = "<data>")]
 pub async fn admin_quests_stages_get(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestStageGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
@@ -157,7 +241,7 @@ pub async fn admin_quests_stages_get(
     (Status::Ok, json!(quests))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStageMoveBackData<'r> {
     jwt: &'r str,
@@ -168,12 +252,19 @@ pub struct QuestStageMoveBackData<'r> {
 #[post("/admin/quests/stages/move_back", format = "json", data = "<data>")]
 pub async fn admin_quests_stages_move_back(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestStageMoveBackData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
     if data.position == 0 {
         return (Status::BadRequest, json!({"error": "position can't be 0"}));
     }
@@ -182,10 +273,25 @@ pub async fn admin_quests_stages_move_back(
         return (Status::InternalServerError, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "move",
+        "quest_stage",
+        Some(data.quest_id),
+        Some(&json!({"stage_id": data.position})),
+        Some(&json!({"stage_id": data.position - 1})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestStageMoveForwardData<'r> {
     jwt: &'r str,
@@ -196,18 +302,25 @@ pub struct QuestStageMoveForwardData<'r> {
 #[post("/admin/quests/stages/move_forward", format = "json", data = "<data>")]
 pub async fn admin_quests_stages_move_forward(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestStageMoveForwardData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let quest_stage_id = match next_quest_stage_id(&mut db, data.quest_id).await {
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let last_stage_id = match last_quest_stage_id(&mut db, data.quest_id).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    if data.position == quest_stage_id - 1 {
+    if data.position == last_stage_id {
         return (
             Status::BadRequest,
             json!({"error": "position can't be max"}),
@@ -218,10 +331,25 @@ pub async fn admin_quests_stages_move_forward(
         return (Status::InternalServerError, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "move",
+        "quest_stage",
+        Some(data.quest_id),
+        Some(&json!({"stage_id": data.position})),
+        Some(&json!({"stage_id": data.position + 1})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestDeleteData<'r> {
     jwt: &'r str,
@@ -231,43 +359,84 @@ pub struct QuestDeleteData<'r> {
 #[post("/admin/quests/delete", format = "json", data = "<data>")]
 pub async fn admin_quests_delete(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestDeleteData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let quest = match get_quest_by_id(&mut db, data.quest_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
     if let Err(err) = delete_quest(&mut db, data.quest_id).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "delete",
+        "quest",
+        Some(data.quest_id),
+        Some(&json!(quest)),
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
     (Status::Ok, json!({}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestGetData<'r> {
     jwt: &'r str,
+    cursor: Option<u32>,
+    limit: Option<u32>,
+    order: Option<Order>,
+    search: Option<&'r str>,
 }
 
 #[post("/admin/quests/get", format = "json", data = "<data>")]
 pub async fn admin_quests_get(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<QuestGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let quests = match get_all_quests(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let quests = match get_quests(&mut db, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(quests))
+    (
+        Status::Ok,
+        json!({
+            "quests": quests.items,
+            "total": quests.total,
+            "next_cursor": quests.next_cursor
+        }),
+    )
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct QuestDuplicateData<'r> {
     jwt: &'r str,
@@ -277,15 +446,18 @@ pub struct QuestDuplicateData<'r> {
 #[post("/admin/quests/duplicate", format = "json", data = "<data>")]
 pub async fn admin_quests_duplicate(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
     data: Json<QuestDuplicateData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let quest_id = match next_quest_id(&mut db).await {
-        Ok(val) => val,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
 
     let quest = match get_quest_by_id(&mut db, data.quest_id).await {
@@ -293,20 +465,45 @@ pub async fn admin_quests_duplicate(
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    if let Err(err) = create_quest(
+    let quest_id = match create_quest(
         &mut db,
-        quest_id,
         &quest.name,
         &quest.desc,
         &quest.unlocks,
         quest.points,
-        quest.points,
+        quest.coins,
         &quest.rewards,
     )
     .await
+    {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "duplicate",
+        "quest",
+        Some(quest_id),
+        None,
+        Some(&json!({
+            "source_quest_id": quest.quest_id,
+            "name": quest.name,
+            "desc": quest.desc,
+            "unlocks": quest.unlocks,
+            "points": quest.points,
+            "coins": quest.coins,
+            "rewards": quest.rewards
+        })),
+        ip,
+    )
+    .await
     {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    (Status::Ok, json!({}))
+    events.publish(GameEvent::QuestAvailable { quest_id });
+
+    (Status::Ok, json!({"quest_id": quest_id}))
 }
@@ -1,19 +1,26 @@
+use std::net::IpAddr;
+
 use rocket::{
     http::Status,
     serde::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::{util::check_authorized_admin, DB};
-
-use super::{
-    add_choice_task, add_location_task, add_text_task, get_tasks, get_tasks_unused, next_task_id,
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    util::{check_authorized_admin, get_token_user_id, Order, PageQuery},
+    DB,
 };
 
-#[derive(Debug, Deserialize)]
+use super::{add_task, check_content_exists, get_tasks, TaskKind, TaskType};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct LocationTaskAddData<'r> {
     jwt: &'r str,
@@ -28,26 +35,56 @@ pub struct LocationTaskAddData<'r> {
 #[post("/admin/tasks/location/add", format = "json", data = "<data>")]
 pub async fn admin_tasks_location_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<LocationTaskAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let task_id = match next_task_id(&mut db).await {
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let kind = TaskKind::Location {
+        min_radius: data.min_radius,
+        max_radius: data.max_radius,
+        location_to_duplicate: data.location_to_duplicate,
+    };
+    if let Err(err) = kind.validate() {
+        return (Status::BadRequest, json!({"error": err}));
+    }
+
+    if let Some(quest_id) = data.quest_id {
+        if let Some(err) = check_content_exists(&mut db, "quests", quest_id).await {
+            return err;
+        }
+    }
+
+    let task_id = match add_task(&mut db, data.name, data.quest_id, data.desc, &kind).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    if let Err(err) = add_location_task(
+    if let Err(err) = add_audit_entry(
         &mut db,
-        task_id,
-        data.name,
-        data.quest_id,
-        data.desc,
-        data.min_radius,
-        data.max_radius,
-        data.location_to_duplicate,
+        admin_id,
+        "create",
+        "task",
+        Some(task_id),
+        None,
+        Some(&json!({
+            "type": "location",
+            "name": data.name,
+            "quest_id": data.quest_id,
+            "desc": data.desc,
+            "min_radius": data.min_radius,
+            "max_radius": data.max_radius,
+            "location_to_duplicate": data.location_to_duplicate
+        })),
+        ip,
     )
     .await
     {
@@ -57,41 +94,71 @@ pub async fn admin_tasks_location_add(
     (Status::Ok, json!({"task_id": task_id}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct MultipleChoiceTaskAddData<'r> {
     jwt: &'r str,
     quest_id: Option<u32>,
     name: &'r str,
     desc: Option<&'r str>,
-    question: &'r str,
-    answers: Vec<&'r str>,
+    question: String,
+    answers: Vec<String>,
     correct_answers: Vec<u32>,
 }
 
 #[post("/admin/tasks/multiple_choice/add", format = "json", data = "<data>")]
 pub async fn admin_tasks_multiple_choice_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<MultipleChoiceTaskAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let task_id = match next_task_id(&mut db).await {
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let kind = TaskKind::Choice {
+        question: data.question.clone(),
+        answers: data.answers.clone(),
+        correct_answers: data.correct_answers.clone(),
+    };
+    if let Err(err) = kind.validate() {
+        return (Status::BadRequest, json!({"error": err}));
+    }
+
+    if let Some(quest_id) = data.quest_id {
+        if let Some(err) = check_content_exists(&mut db, "quests", quest_id).await {
+            return err;
+        }
+    }
+
+    let task_id = match add_task(&mut db, data.name, data.quest_id, data.desc, &kind).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    if let Err(err) = add_choice_task(
+    if let Err(err) = add_audit_entry(
         &mut db,
-        task_id,
-        data.name,
-        data.quest_id,
-        data.desc,
-        data.question,
-        &data.answers,
-        &data.correct_answers,
+        admin_id,
+        "create",
+        "task",
+        Some(task_id),
+        None,
+        Some(&json!({
+            "type": "choice",
+            "name": data.name,
+            "quest_id": data.quest_id,
+            "desc": data.desc,
+            "question": data.question,
+            "answers": data.answers,
+            "correct_answers": data.correct_answers
+        })),
+        ip,
     )
     .await
     {
@@ -101,39 +168,68 @@ pub async fn admin_tasks_multiple_choice_add(
     (Status::Ok, json!({"task_id": task_id}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct TextTaskAddData<'r> {
     jwt: &'r str,
     quest_id: Option<u32>,
     name: &'r str,
     desc: Option<&'r str>,
-    question: &'r str,
-    correct_answers: Vec<&'r str>,
+    question: String,
+    correct_answers: Vec<String>,
 }
 
 #[post("/admin/tasks/text_answer/add", format = "json", data = "<data>")]
 pub async fn admin_tasks_text_answer_add(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<TextTaskAddData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let task_id = match next_task_id(&mut db).await {
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let kind = TaskKind::Text {
+        question: data.question.clone(),
+        answers: data.correct_answers.clone(),
+    };
+    if let Err(err) = kind.validate() {
+        return (Status::BadRequest, json!({"error": err}));
+    }
+
+    if let Some(quest_id) = data.quest_id {
+        if let Some(err) = check_content_exists(&mut db, "quests", quest_id).await {
+            return err;
+        }
+    }
+
+    let task_id = match add_task(&mut db, data.name, data.quest_id, data.desc, &kind).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    if let Err(err) = add_text_task(
+    if let Err(err) = add_audit_entry(
         &mut db,
-        task_id,
-        data.name,
-        data.quest_id,
-        data.desc,
-        data.question,
-        &data.correct_answers,
+        admin_id,
+        "create",
+        "task",
+        Some(task_id),
+        None,
+        Some(&json!({
+            "type": "text",
+            "name": data.name,
+            "quest_id": data.quest_id,
+            "desc": data.desc,
+            "question": data.question,
+            "correct_answers": data.correct_answers
+        })),
+        ip,
     )
     .await
     {
@@ -143,42 +239,68 @@ pub async fn admin_tasks_text_answer_add(
     (Status::Ok, json!({"task_id": task_id}))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct TaskGetData<'r> {
     jwt: &'r str,
+    quest_id: Option<u32>,
+    task_type: Option<TaskType>,
+    cursor: Option<u32>,
+    limit: Option<u32>,
+    order: Option<Order>,
+    search: Option<&'r str>,
 }
 
 #[post("/admin/tasks/get", format = "json", data = "<data>")]
 pub async fn admin_tasks_get(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<TaskGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let tasks = match get_tasks(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let tasks = match get_tasks(&mut db, data.quest_id, data.task_type, false, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(tasks))
+    (
+        Status::Ok,
+        json!({
+            "tasks": tasks.items,
+            "total": tasks.total,
+            "next_cursor": tasks.next_cursor
+        }),
+    )
 }
 
 #[post("/admin/tasks/get/unused", format = "json", data = "<data>")]
 pub async fn admin_tasks_get_unused(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<TaskGetData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let tasks = match get_tasks_unused(&mut db).await {
+    let page = PageQuery::new(data.cursor, data.limit, data.order, Order::Asc, data.search);
+
+    let tasks = match get_tasks(&mut db, data.quest_id, data.task_type, true, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     };
 
-    (Status::Ok, json!(tasks))
+    (
+        Status::Ok,
+        json!({
+            "tasks": tasks.items,
+            "total": tasks.total,
+            "next_cursor": tasks.next_cursor
+        }),
+    )
 }
@@ -0,0 +1,570 @@
+use std::{
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+use uuid::Uuid;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    user::{
+        ban_user, count_users, export::export_user_data, get_admin_count, get_user_by_id,
+        jwt::get_token, list_deleted_users, list_users, restore_deleted_user, start_session,
+        stop_all_sessions, unban_user, update_user_admin_status, UserFilter,
+    },
+    util::{check_authorized_admin, get_token_user_id},
+    DB,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserListData<'r> {
+    jwt: &'r str,
+    page: Option<u32>,
+    page_size: Option<u32>,
+    verified: Option<bool>,
+    admin: Option<bool>,
+    banned: Option<bool>,
+    search: Option<&'r str>,
+}
+
+#[post("/admin/users/get", format = "json", data = "<data>")]
+pub async fn admin_users_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserListData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let page = data.page.unwrap_or(0);
+    let page_size = data.page_size.unwrap_or(50).clamp(1, 500);
+    let offset = match page.checked_mul(page_size) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "Page out of range"})),
+    };
+
+    let filter = UserFilter {
+        verified: data.verified,
+        admin: data.admin,
+        banned: data.banned,
+        search: data.search,
+    };
+
+    let total = match count_users(&mut db, &filter).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    let users = match list_users(&mut db, &filter, page_size, offset).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    let users: Vec<Value> = users
+        .iter()
+        .map(|user| {
+            let gender = if user.gender { "m" } else { "f" };
+            json!({
+                "account_id": user.user_id,
+                "email": user.email,
+                "first_name": user.first_name,
+                "last_name": user.last_name,
+                "gender": gender,
+                "verified": user.verified,
+                "admin": user.admin,
+                "banned": user.banned,
+                "banned_until": user.banned_until
+            })
+        })
+        .collect();
+
+    (
+        Status::Ok,
+        json!({
+            "users": users,
+            "total": total,
+            "page": page,
+            "page_size": page_size
+        }),
+    )
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserAdminStatusData<'r> {
+    jwt: &'r str,
+    account_id: u32,
+}
+
+#[post("/admin/users/promote", format = "json", data = "<data>")]
+pub async fn admin_users_promote(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserAdminStatusData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let user = match get_user_by_id(&mut db, data.account_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if user.admin {
+        return (
+            Status::BadRequest,
+            json!({"error": "user is already admin"}),
+        );
+    }
+
+    if let Err(err) = update_user_admin_status(&mut db, data.account_id, true).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "promote",
+        "user",
+        Some(data.account_id),
+        Some(&json!({"admin": false})),
+        Some(&json!({"admin": true})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({}))
+}
+
+#[post("/admin/users/demote", format = "json", data = "<data>")]
+pub async fn admin_users_demote(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserAdminStatusData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let user = match get_user_by_id(&mut db, data.account_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if !user.admin {
+        return (Status::BadRequest, json!({"error": "user is not admin"}));
+    }
+
+    match get_admin_count(&mut db).await {
+        Ok(val) => {
+            if val <= 1 {
+                return (
+                    Status::BadRequest,
+                    json!({"error": "can't demote the last admin"}),
+                );
+            }
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    if let Err(err) = update_user_admin_status(&mut db, data.account_id, false).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "demote",
+        "user",
+        Some(data.account_id),
+        Some(&json!({"admin": true})),
+        Some(&json!({"admin": false})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserBanData<'r> {
+    jwt: &'r str,
+    account_id: u32,
+    reason: Option<&'r str>,
+    duration: Option<i64>,
+}
+
+#[post("/admin/users/ban", format = "json", data = "<data>")]
+pub async fn admin_users_ban(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserBanData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let user = match get_user_by_id(&mut db, data.account_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if user.admin {
+        return (
+            Status::BadRequest,
+            json!({"error": "admins can't be banned, demote them first"}),
+        );
+    }
+
+    let banned_until = match data.duration {
+        Some(duration) if duration <= 0 => {
+            return (
+                Status::BadRequest,
+                json!({"error": "duration has to be positive"}),
+            )
+        }
+        Some(duration) => {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time")
+                .as_secs() as i64;
+            Some(timestamp + duration)
+        }
+        None => None,
+    };
+
+    if let Err(err) = ban_user(
+        &mut db,
+        data.account_id,
+        admin_id,
+        data.reason,
+        banned_until,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = stop_all_sessions(&mut db, data.account_id).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "ban",
+        "user",
+        Some(data.account_id),
+        None,
+        Some(&json!({"reason": data.reason, "banned_until": banned_until})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({"banned_until": banned_until}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserUnbanData<'r> {
+    jwt: &'r str,
+    account_id: u32,
+}
+
+#[post("/admin/users/unban", format = "json", data = "<data>")]
+pub async fn admin_users_unban(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserUnbanData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    if let Err(err) = unban_user(&mut db, data.account_id).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "unban",
+        "user",
+        Some(data.account_id),
+        None,
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserImpersonateData<'r> {
+    jwt: &'r str,
+    account_id: u32,
+}
+
+#[post("/admin/users/impersonate", format = "json", data = "<data>")]
+pub async fn admin_users_impersonate(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserImpersonateData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let user = match get_user_by_id(&mut db, data.account_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if user.admin {
+        return (
+            Status::BadRequest,
+            json!({"error": "admins can't be impersonated"}),
+        );
+    }
+
+    let token = Uuid::new_v4().to_string();
+
+    let lifetime = config.tokens.impersonation_lifetime;
+
+    let jwt = match get_token(&config.secret, user.user_id, &token, lifetime) {
+        Some(val) => val,
+        None => {
+            return (
+                Status::InternalServerError,
+                json!({"error": "Failed to get token"}),
+            )
+        }
+    };
+
+    if let Err(err) = start_session(&mut db, user.user_id, &token, lifetime).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "impersonate",
+        "user",
+        Some(user.user_id),
+        None,
+        Some(&json!({"valid_until": timestamp + lifetime})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (
+        Status::Ok,
+        json!({"jwt": jwt, "valid_until": timestamp + lifetime}),
+    )
+}
+
+#[post("/admin/users/export", format = "json", data = "<data>")]
+pub async fn admin_users_export(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserAdminStatusData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    if let Err(err) = get_user_by_id(&mut db, data.account_id).await {
+        return (Status::NotFound, json!({"error": err}));
+    }
+
+    let export = match export_user_data(&mut db, data.account_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "export",
+        "user",
+        Some(data.account_id),
+        None,
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, export)
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct DeletedUserListData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/admin/users/deleted/get", format = "json", data = "<data>")]
+pub async fn admin_users_deleted_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<DeletedUserListData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let users = match list_deleted_users(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    let retention = config.janitor.deleted_account_retention;
+    let users: Vec<Value> = users
+        .iter()
+        .map(|user| {
+            let restorable_until = match (user.anonymised, user.deleted_at) {
+                (false, Some(deleted_at)) => Some(deleted_at + retention),
+                _ => None,
+            };
+
+            json!({
+                "account_id": user.account_id,
+                "email": user.email,
+                "first_name": user.first_name,
+                "last_name": user.last_name,
+                "deleted_at": user.deleted_at,
+                "anonymised": user.anonymised,
+                "restorable_until": restorable_until,
+            })
+        })
+        .collect();
+
+    (Status::Ok, json!({"users": users}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct UserRestoreData<'r> {
+    jwt: &'r str,
+    account_id: u32,
+}
+
+#[post("/admin/users/restore", format = "json", data = "<data>")]
+pub async fn admin_users_restore(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<UserRestoreData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    if let Err(err) = restore_deleted_user(
+        &mut db,
+        data.account_id,
+        config.janitor.deleted_account_retention,
+    )
+    .await
+    {
+        return (Status::BadRequest, json!({"error": err}));
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "restore",
+        "user",
+        Some(data.account_id),
+        None,
+        None,
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({}))
+}
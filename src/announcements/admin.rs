@@ -0,0 +1,236 @@
+use std::net::IpAddr;
+
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    admin::get_quest_by_id,
+    audit::add_audit_entry,
+    config::AppConfig,
+    events::EventBus,
+    logging,
+    util::{check_authorized_admin, get_token_user_id},
+    DB,
+};
+
+use super::{
+    add_announcement, expire_announcement, get_announcement_by_id, get_announcements, now,
+    publish_announcement, spawn_announcement_email, Audience, NewAnnouncement,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct AnnouncementAddData<'r> {
+    jwt: &'r str,
+    title: &'r str,
+    message: &'r str,
+    audience: Audience,
+    quest_id: Option<u32>,
+    publish_at: Option<i64>,
+    expires_at: Option<i64>,
+    send_email: Option<bool>,
+}
+
+#[post("/admin/announcements", format = "json", data = "<data>")]
+pub async fn admin_announcements_add(
+    mut db: Connection<DB>,
+    database: &State<DB>,
+    config: &State<AppConfig>,
+    events: &State<EventBus>,
+    data: Json<AnnouncementAddData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    if data.title.trim().is_empty() {
+        return (Status::BadRequest, json!({"error": "title can't be empty"}));
+    }
+
+    let quest_id = match (data.audience, data.quest_id) {
+        (Audience::QuestCompleted, Some(quest_id)) => {
+            if let Err(err) = get_quest_by_id(&mut db, quest_id).await {
+                return (Status::NotFound, json!({"error": err}));
+            }
+            Some(quest_id)
+        }
+        (Audience::QuestCompleted, None) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "quest_completed audience needs a quest_id"}),
+            )
+        }
+        (_, Some(_)) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "quest_id only applies to the quest_completed audience"}),
+            )
+        }
+        (_, None) => None,
+    };
+
+    let publish_at = data.publish_at.unwrap_or_else(now);
+
+    if data.expires_at.is_some_and(|x| x <= publish_at) {
+        return (
+            Status::BadRequest,
+            json!({"error": "expires_at has to be after publish_at"}),
+        );
+    }
+
+    let announcement = NewAnnouncement {
+        admin_id,
+        title: data.title,
+        message: data.message,
+        audience: data.audience,
+        quest_id,
+        publish_at,
+        expires_at: data.expires_at,
+        send_email: data.send_email.unwrap_or(false),
+    };
+
+    let announcement_id = match add_announcement(&mut db, &announcement).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "announcement",
+        Some(announcement_id),
+        None,
+        Some(&json!({
+            "title": data.title,
+            "audience": data.audience.as_str(),
+            "quest_id": quest_id,
+            "publish_at": publish_at,
+            "expires_at": data.expires_at,
+            "send_email": announcement.send_email,
+        })),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    // Scheduled ones are left to the periodic publisher, which also retries this one if
+    // publishing it here fails
+    if publish_at <= now() {
+        let published = match get_announcement_by_id(&mut db, announcement_id).await {
+            Ok(announcement) => publish_announcement(&mut db, events, &announcement)
+                .await
+                .map(|x| x.then_some(announcement)),
+            Err(err) => Err(err),
+        };
+
+        match published {
+            Ok(Some(announcement)) => {
+                spawn_announcement_email(database.0.clone(), (**config).clone(), announcement)
+            }
+            Ok(None) => (),
+            Err(err) => logging::warn(&format!(
+                "Announcement {} is left to the publisher: {}",
+                announcement_id, err
+            )),
+        }
+    }
+
+    (Status::Ok, json!({"announcement_id": announcement_id}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct AnnouncementGetData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/admin/announcements/search", format = "json", data = "<data>")]
+pub async fn admin_announcements_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<AnnouncementGetData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    match get_announcements(&mut db).await {
+        Ok(val) => (Status::Ok, json!(val)),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct AnnouncementExpireData<'r> {
+    jwt: &'r str,
+    announcement_id: u32,
+}
+
+#[put("/admin/announcements/expire", format = "json", data = "<data>")]
+pub async fn admin_announcements_expire(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<AnnouncementExpireData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let announcement = match get_announcement_by_id(&mut db, data.announcement_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if announcement.expires_at.is_some_and(|x| x <= now()) {
+        return (
+            Status::BadRequest,
+            json!({"error": "announcement already expired"}),
+        );
+    }
+
+    let expires_at = match expire_announcement(&mut db, data.announcement_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "expire",
+        "announcement",
+        Some(data.announcement_id),
+        Some(&json!({"expires_at": announcement.expires_at})),
+        Some(&json!({"expires_at": expires_at})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({}))
+}
@@ -0,0 +1,109 @@
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    config::AppConfig,
+    user::get_user_by_id,
+    util::{check_authorized_user, get_token_user_id},
+    DB,
+};
+
+use super::{count_unread, get_inbox, mark_read};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct InboxData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/users/me/inbox", format = "json", data = "<data>")]
+pub async fn user_inbox_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<InboxData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user = match get_token_user_id(config, data.jwt) {
+        Some(val) => match get_user_by_id(&mut db, val).await {
+            Ok(val) => val,
+            Err(err) => return (Status::BadRequest, json!({"error": err})),
+        },
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    match get_inbox(&mut db, user.user_id, user.admin).await {
+        Ok(val) => (Status::Ok, json!(val)),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
+
+// Meant to be polled, it's a single count over the announcements
+#[post("/users/me/inbox/unread", format = "json", data = "<data>")]
+pub async fn user_inbox_unread(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<InboxData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user = match get_token_user_id(config, data.jwt) {
+        Some(val) => match get_user_by_id(&mut db, val).await {
+            Ok(val) => val,
+            Err(err) => return (Status::BadRequest, json!({"error": err})),
+        },
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    match count_unread(&mut db, user.user_id, user.admin).await {
+        Ok(val) => (Status::Ok, json!({"unread": val})),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct InboxReadData<'r> {
+    jwt: &'r str,
+    announcement_id: u32,
+}
+
+#[put("/users/me/inbox/read", format = "json", data = "<data>")]
+pub async fn user_inbox_read(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<InboxReadData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user = match get_token_user_id(config, data.jwt) {
+        Some(val) => match get_user_by_id(&mut db, val).await {
+            Ok(val) => val,
+            Err(err) => return (Status::BadRequest, json!({"error": err})),
+        },
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    match mark_read(&mut db, user.user_id, user.admin, data.announcement_id).await {
+        Ok(true) => (Status::Ok, json!({})),
+        Ok(false) => (
+            Status::NotFound,
+            json!({"error": "announcement not in inbox"}),
+        ),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
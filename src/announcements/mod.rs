@@ -0,0 +1,458 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use resend_rs::{types::CreateEmailBaseOptions, Resend};
+use rocket::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{prelude::FromRow, query, query_as, Row};
+use tokio::time::interval;
+
+use crate::{
+    config::AppConfig,
+    db::{DbConnection, DbPool, NullableId},
+    events::{EventBus, GameEvent},
+    logging,
+};
+
+pub mod admin;
+pub mod inbox;
+
+// Published and not expired announcements for $1 user, $2 now and $3 whether the user is an
+// admin. quest_completed matches nobody until completed quests are stored
+const VISIBLE: &str = "published = true
+    AND (expires_at IS NULL OR expires_at > $2)
+    AND (audience = 'all' OR (audience = 'admins' AND $3))";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Audience {
+    All,
+    Admins,
+    QuestCompleted,
+}
+
+impl Audience {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Audience::All => "all",
+            Audience::Admins => "admins",
+            Audience::QuestCompleted => "quest_completed",
+        }
+    }
+
+    pub fn parse(val: &str) -> Option<Audience> {
+        match val {
+            "all" => Some(Audience::All),
+            "admins" => Some(Audience::Admins),
+            "quest_completed" => Some(Audience::QuestCompleted),
+            _ => None,
+        }
+    }
+}
+
+// ██████╗  █████╗ ████████╗ █████╗ ██████╗  █████╗ ███████╗███████╗    ███████╗██╗   ██╗███╗   ██╗ ██████╗████████╗██╗ ██████╗ ███╗   ██╗███████╗
+// ██╔══██╗██╔══██╗╚══██╔══╝██╔══██╗██╔══██╗██╔══██╗██╔════╝██╔════╝    ██╔════╝██║   ██║████╗  ██║██╔════╝╚══██╔══╝██║██╔═══██╗████╗  ██║██╔════╝
+// ██║  ██║███████║   ██║   ███████║██████╔╝███████║███████╗█████╗      █████╗  ██║   ██║██╔██╗ ██║██║        ██║   ██║██║   ██║██╔██╗ ██║███████╗
+// ██║  ██║██╔══██║   ██║   ██╔══██║██╔══██╗██╔══██║╚════██║██╔══╝      ██╔══╝  ██║   ██║██║╚██╗██║██║        ██║   ██║██║   ██║██║╚██╗██║╚════██║
+// ██████╔╝██║  ██║   ██║   ██║  ██║██████╔╝██║  ██║███████║███████╗    ██║     ╚██████╔╝██║ ╚████║╚██████╗   ██║   ██║╚██████╔╝██║ ╚████║███████║
+// ╚═════╝ ╚═╝  ╚═╝   ╚═╝   ╚═╝  ╚═╝╚═════╝ ╚═╝  ╚═╝╚══════╝╚══════╝    ╚═╝      ╚═════╝ ╚═╝  ╚═══╝ ╚═════╝   ╚═╝   ╚═╝ ╚═════╝ ╚═╝  ╚═══╝╚══════╝
+
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct Announcement {
+    #[sqlx(try_from = "i64")]
+    pub announcement_id: u32,
+    #[sqlx(try_from = "i64")]
+    pub admin_id: u32,
+    pub title: String,
+    pub message: String,
+    pub audience: String,
+    #[sqlx(try_from = "NullableId")]
+    pub quest_id: Option<u32>,
+    pub publish_at: i64,
+    pub expires_at: Option<i64>,
+    pub send_email: bool,
+    pub published: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct InboxEntry {
+    #[sqlx(try_from = "i64")]
+    pub announcement_id: u32,
+    pub title: String,
+    pub message: String,
+    pub publish_at: i64,
+    pub expires_at: Option<i64>,
+    pub read: bool,
+}
+
+pub struct NewAnnouncement<'r> {
+    pub admin_id: u32,
+    pub title: &'r str,
+    pub message: &'r str,
+    pub audience: Audience,
+    pub quest_id: Option<u32>,
+    pub publish_at: i64,
+    pub expires_at: Option<i64>,
+    pub send_email: bool,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64
+}
+
+pub async fn add_announcement(
+    db: &mut DbConnection,
+    announcement: &NewAnnouncement<'_>,
+) -> Result<u32, String> {
+    match query(
+        "INSERT INTO announcements
+        (admin_id, title, message, audience, quest_id, publish_at, expires_at, send_email, published, timestamp)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,false,$9)
+        RETURNING announcement_id",
+    )
+    .bind(i64::from(announcement.admin_id))
+    .bind(announcement.title)
+    .bind(announcement.message)
+    .bind(announcement.audience.as_str())
+    .bind(announcement.quest_id.map(i64::from))
+    .bind(announcement.publish_at)
+    .bind(announcement.expires_at)
+    .bind(announcement.send_email)
+    .bind(now())
+    .fetch_one(db)
+    .await
+    {
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to add announcement: {}", err)),
+    }
+}
+
+pub async fn get_announcements(db: &mut DbConnection) -> Result<Vec<Announcement>, String> {
+    match query_as("SELECT * FROM announcements ORDER BY announcement_id DESC")
+        .fetch_all(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get announcements: {}", err)),
+    }
+}
+
+pub async fn get_announcement_by_id(
+    db: &mut DbConnection,
+    announcement_id: u32,
+) -> Result<Announcement, String> {
+    match query_as("SELECT * FROM announcements WHERE announcement_id = $1")
+        .bind(i64::from(announcement_id))
+        .fetch_one(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(sqlx::Error::RowNotFound) => Err("Announcement not found".to_owned()),
+        Err(err) => Err(format!("Failed to get announcement: {}", err)),
+    }
+}
+
+pub async fn expire_announcement(
+    db: &mut DbConnection,
+    announcement_id: u32,
+) -> Result<i64, String> {
+    let timestamp = now();
+
+    match query("UPDATE announcements SET expires_at = $1 WHERE announcement_id = $2")
+        .bind(timestamp)
+        .bind(i64::from(announcement_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(timestamp),
+        Err(err) => Err(format!("Failed to expire announcement: {}", err)),
+    }
+}
+
+pub async fn get_inbox(
+    db: &mut DbConnection,
+    user_id: u32,
+    admin: bool,
+) -> Result<Vec<InboxEntry>, String> {
+    match query_as(&format!(
+        "SELECT announcement_id, title, message, publish_at, expires_at,
+        EXISTS (SELECT 1 FROM announcement_reads
+            WHERE announcement_reads.user_id = $1
+            AND announcement_reads.announcement_id = announcements.announcement_id) AS read
+        FROM announcements WHERE {} ORDER BY publish_at DESC, announcement_id DESC",
+        VISIBLE
+    ))
+    .bind(i64::from(user_id))
+    .bind(now())
+    .bind(admin)
+    .fetch_all(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get inbox: {}", err)),
+    }
+}
+
+pub async fn count_unread(db: &mut DbConnection, user_id: u32, admin: bool) -> Result<u32, String> {
+    match query(&format!(
+        "SELECT COUNT(*) FROM announcements WHERE {} AND NOT EXISTS (
+            SELECT 1 FROM announcement_reads
+            WHERE announcement_reads.user_id = $1
+            AND announcement_reads.announcement_id = announcements.announcement_id)",
+        VISIBLE
+    ))
+    .bind(i64::from(user_id))
+    .bind(now())
+    .bind(admin)
+    .fetch_one(db)
+    .await
+    {
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to count unread announcements: {}", err)),
+    }
+}
+
+// False when the announcement isn't in the user's inbox
+pub async fn mark_read(
+    db: &mut DbConnection,
+    user_id: u32,
+    admin: bool,
+    announcement_id: u32,
+) -> Result<bool, String> {
+    let visible: u32 = match query(&format!(
+        "SELECT COUNT(*) FROM announcements WHERE announcement_id = $4 AND {}",
+        VISIBLE
+    ))
+    .bind(i64::from(user_id))
+    .bind(now())
+    .bind(admin)
+    .bind(i64::from(announcement_id))
+    .fetch_one(&mut *db)
+    .await
+    {
+        Ok(val) => val.get::<i64, _>(0) as u32,
+        Err(err) => return Err(format!("Failed to get announcement: {}", err)),
+    };
+
+    if visible == 0 {
+        return Ok(false);
+    }
+
+    match query(
+        "INSERT INTO announcement_reads (user_id, announcement_id, timestamp) VALUES ($1,$2,$3)
+        ON CONFLICT DO NOTHING",
+    )
+    .bind(i64::from(user_id))
+    .bind(i64::from(announcement_id))
+    .bind(now())
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(true),
+        Err(err) => Err(format!("Failed to mark announcement as read: {}", err)),
+    }
+}
+
+async fn get_due_announcements(db: &mut DbConnection) -> Result<Vec<Announcement>, String> {
+    match query_as(
+        "SELECT * FROM announcements WHERE published = false AND publish_at <= $1
+        AND (expires_at IS NULL OR expires_at > $1)",
+    )
+    .bind(now())
+    .fetch_all(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get due announcements: {}", err)),
+    }
+}
+
+// False when someone else published it first
+async fn set_announcement_published(
+    db: &mut DbConnection,
+    announcement_id: u32,
+) -> Result<bool, String> {
+    match query(
+        "UPDATE announcements SET published = true WHERE announcement_id = $1 AND published = false",
+    )
+    .bind(i64::from(announcement_id))
+    .execute(db)
+    .await
+    {
+        Ok(val) => Ok(val.rows_affected() == 1),
+        Err(err) => Err(format!("Failed to publish announcement: {}", err)),
+    }
+}
+
+async fn get_audience_emails(db: &mut DbConnection, audience: &str) -> Result<Vec<String>, String> {
+    let statement = match audience {
+        "all" => "SELECT email FROM users WHERE verified = true",
+        "admins" => "SELECT email FROM users WHERE verified = true AND admin = true",
+        _ => return Ok(vec![]),
+    };
+
+    match query(statement).fetch_all(db).await {
+        Ok(val) => Ok(val.iter().map(|x| x.get(0)).collect()),
+        Err(err) => Err(format!("Failed to get announcement recipients: {}", err)),
+    }
+}
+
+// ██████╗ ██╗   ██╗██████╗ ██╗     ██╗███████╗██╗  ██╗██╗███╗   ██╗ ██████╗
+// ██╔══██╗██║   ██║██╔══██╗██║     ██║██╔════╝██║  ██║██║████╗  ██║██╔════╝
+// ██████╔╝██║   ██║██████╔╝██║     ██║███████╗███████║██║██╔██╗ ██║██║  ███╗
+// ██╔═══╝ ██║   ██║██╔══██╗██║     ██║╚════██║██╔══██║██║██║╚██╗██║██║   ██║
+// ██║     ╚██████╔╝██████╔╝███████╗██║███████║██║  ██║██║██║ ╚████║╚██████╔╝
+// ╚═╝      ╚═════╝ ╚═════╝ ╚══════╝╚═╝╚══════╝╚═╝  ╚═╝╚═╝╚═╝  ╚═══╝ ╚═════╝
+
+async fn send_announcement_email(
+    config: &AppConfig,
+    email: &str,
+    title: &str,
+    message: &str,
+) -> Result<(), String> {
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
+
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
+    };
+
+    let email =
+        CreateEmailBaseOptions::new(from, [email], title).with_html(&format!("<p>{}</p>", message));
+
+    if let Err(err) = resend.emails.send(email).await {
+        Err(format!("Failed to send email: {}", err))
+    } else {
+        Ok(())
+    }
+}
+
+// Marks an announcement published before anything is sent, so a failing mail isn't retried
+// and a concurrent run doesn't send it twice. False when someone else published it first
+pub async fn publish_announcement(
+    db: &mut DbConnection,
+    events: &EventBus,
+    announcement: &Announcement,
+) -> Result<bool, String> {
+    if !set_announcement_published(db, announcement.announcement_id).await? {
+        return Ok(false);
+    }
+
+    if let Some(audience) = Audience::parse(&announcement.audience) {
+        events.publish(GameEvent::AnnouncementPublished {
+            announcement_id: announcement.announcement_id,
+            audience,
+        });
+    }
+
+    logging::info(&format!(
+        "Published announcement {}",
+        announcement.announcement_id
+    ));
+
+    Ok(true)
+}
+
+pub async fn email_announcement(
+    db: &mut DbConnection,
+    config: &AppConfig,
+    announcement: &Announcement,
+) -> Result<(), String> {
+    if !announcement.send_email || config.mail.disabled {
+        return Ok(());
+    }
+
+    let emails = get_audience_emails(db, &announcement.audience).await?;
+    let mut failed = 0;
+
+    for email in &emails {
+        if let Err(err) =
+            send_announcement_email(config, email, &announcement.title, &announcement.message).await
+        {
+            logging::warn(&format!(
+                "Announcement {} email failed: {}",
+                announcement.announcement_id, err
+            ));
+            failed += 1;
+        }
+    }
+
+    logging::info(&format!(
+        "Announcement {} emailed to {} of {} recipients",
+        announcement.announcement_id,
+        emails.len() - failed,
+        emails.len()
+    ));
+
+    Ok(())
+}
+
+// Mails an announcement published by a request without holding the request up
+pub fn spawn_announcement_email(pool: DbPool, config: AppConfig, announcement: Announcement) {
+    if !announcement.send_email || config.mail.disabled {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let result = match pool.acquire().await {
+            Ok(mut db) => email_announcement(&mut db, &config, &announcement).await,
+            Err(err) => Err(format!("Failed to get a connection: {}", err)),
+        };
+
+        if let Err(err) = result {
+            logging::error(&format!(
+                "Announcement {} emails failed: {}",
+                announcement.announcement_id, err
+            ));
+        }
+    });
+}
+
+pub async fn publish_due_announcements(
+    db: &mut DbConnection,
+    config: &AppConfig,
+    events: &EventBus,
+) -> Result<u32, String> {
+    let due = get_due_announcements(db).await?;
+    let mut published = 0;
+
+    for announcement in &due {
+        if !publish_announcement(db, events, announcement).await? {
+            continue;
+        }
+        published += 1;
+
+        email_announcement(db, config, announcement).await?;
+    }
+
+    Ok(published)
+}
+
+// Picks up scheduled announcements, the ones due on creation are published right away
+pub async fn run_periodically(pool: DbPool, config: AppConfig, events: EventBus) {
+    let mut interval = interval(Duration::from_secs(config.announcements.publish_interval));
+
+    loop {
+        interval.tick().await;
+
+        let mut db = match pool.acquire().await {
+            Ok(val) => val,
+            Err(err) => {
+                logging::error(&format!(
+                    "Announcement publisher failed to get a connection: {}",
+                    err
+                ));
+                continue;
+            }
+        };
+
+        if let Err(err) = publish_due_announcements(&mut db, &config, &events).await {
+            logging::error(&format!("Publishing announcements failed: {}", err));
+        }
+    }
+}
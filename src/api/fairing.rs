@@ -0,0 +1,38 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+
+use super::{is_legacy, successor, LEGACY_DEPRECATED_SINCE};
+
+// Marks responses of the unversioned paths and points at their /api/v1 replacement
+pub struct DeprecationHeaders;
+
+#[rocket::async_trait]
+impl Fairing for DeprecationHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deprecation Headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if !req.route().is_some_and(is_legacy) {
+            return;
+        }
+
+        res.set_header(Header::new(
+            "Deprecation",
+            format!("@{}", LEGACY_DEPRECATED_SINCE),
+        ));
+
+        if let Some(path) = successor(req) {
+            res.set_header(Header::new(
+                "Link",
+                format!("<{}>; rel=\"successor-version\"", path),
+            ));
+        }
+    }
+}
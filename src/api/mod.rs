@@ -0,0 +1,337 @@
+use rocket::{
+    http::Method,
+    route::{BoxFuture, Outcome},
+    Data, Request, Route,
+};
+
+pub mod fairing;
+
+pub const V1_BASE: &str = "/api/v1";
+
+// Unix time the unversioned paths were deprecated, sent in the Deprecation header
+pub const LEGACY_DEPRECATED_SINCE: i64 = 1792368000;
+
+const LEGACY_PREFIX: &str = "legacy_";
+
+// Handler name, method and path under V1_BASE. Dynamic segments have to keep the index they
+// have in the handler's own path since rocket looks them up by position
+const V1_LAYOUT: &[(&str, Method, &str)] = &[
+    ("auth_register", Method::Post, "/auth/register"),
+    (
+        "auth_resend_verification",
+        Method::Post,
+        "/auth/register/<account_id>/resend",
+    ),
+    ("auth_verify", Method::Get, "/auth/verify/<token>"),
+    ("auth_login", Method::Post, "/auth/login"),
+    ("auth_login_totp", Method::Post, "/auth/login/totp"),
+    ("auth_logout", Method::Post, "/auth/logout"),
+    ("auth_password_reset", Method::Post, "/auth/password_reset"),
+    (
+        "auth_password_reset_verify",
+        Method::Get,
+        "/auth/password_reset/verify/<token>",
+    ),
+    ("auth_retrieve_user", Method::Post, "/users/me"),
+    (
+        "user_modify_first_name",
+        Method::Put,
+        "/users/me/first_name",
+    ),
+    ("user_modify_last_name", Method::Put, "/users/me/last_name"),
+    ("user_modify_gender", Method::Put, "/users/me/gender"),
+    ("user_modify_password", Method::Put, "/users/me/password"),
+    ("user_modify_email", Method::Put, "/users/me/email"),
+    (
+        "user_modify_email_verify",
+        Method::Get,
+        "/users/me/email/verify/<token>",
+    ),
+    ("delete_user", Method::Delete, "/users/me"),
+    (
+        "auth_delete_user_verify",
+        Method::Get,
+        "/users/me/delete/<token>",
+    ),
+    ("user_export", Method::Post, "/users/me/export"),
+    ("user_totp_enroll", Method::Post, "/users/me/totp/enroll"),
+    ("user_totp_confirm", Method::Post, "/users/me/totp/confirm"),
+    ("user_totp_disable", Method::Post, "/users/me/totp/disable"),
+    (
+        "user_totp_recovery_codes",
+        Method::Post,
+        "/users/me/totp/recovery_codes",
+    ),
+    ("user_totp_status", Method::Post, "/users/me/totp/status"),
+    ("user_retrieve_email", Method::Post, "/users/search/email"),
+    ("user_retrieve_name", Method::Post, "/users/search/name"),
+    ("user_retrieve_count", Method::Get, "/users/count"),
+    ("get_pause_state", Method::Get, "/game/pause_state"),
+    ("get_location_radius", Method::Get, "/game/location_radius"),
+    ("report_error", Method::Post, "/reports/errors"),
+    ("report_suggestion", Method::Post, "/reports/suggestions"),
+    ("admin_characters_add", Method::Post, "/admin/characters"),
+    (
+        "admin_characters_delete",
+        Method::Delete,
+        "/admin/characters",
+    ),
+    (
+        "admin_characters_get",
+        Method::Post,
+        "/admin/characters/search",
+    ),
+    ("admin_dialogues_add", Method::Post, "/admin/dialogues"),
+    ("admin_dialogues_delete", Method::Delete, "/admin/dialogues"),
+    (
+        "admin_dialogues_get",
+        Method::Post,
+        "/admin/dialogues/search",
+    ),
+    (
+        "admin_dialogues_get_unused",
+        Method::Post,
+        "/admin/dialogues/search/unused",
+    ),
+    (
+        "admin_tasks_location_add",
+        Method::Post,
+        "/admin/tasks/location",
+    ),
+    (
+        "admin_tasks_multiple_choice_add",
+        Method::Post,
+        "/admin/tasks/multiple_choice",
+    ),
+    (
+        "admin_tasks_text_answer_add",
+        Method::Post,
+        "/admin/tasks/text_answer",
+    ),
+    ("admin_tasks_get", Method::Post, "/admin/tasks/search"),
+    (
+        "admin_tasks_get_unused",
+        Method::Post,
+        "/admin/tasks/search/unused",
+    ),
+    ("admin_quests_add", Method::Post, "/admin/quests"),
+    ("admin_quests_delete", Method::Delete, "/admin/quests"),
+    ("admin_quests_get", Method::Post, "/admin/quests/search"),
+    (
+        "admin_quests_duplicate",
+        Method::Post,
+        "/admin/quests/duplicate",
+    ),
+    (
+        "admin_quests_stages_add",
+        Method::Post,
+        "/admin/quests/stages",
+    ),
+    (
+        "admin_quests_stages_delete",
+        Method::Delete,
+        "/admin/quests/stages",
+    ),
+    (
+        "admin_quests_stages_get",
+        Method::Post,
+        "/admin/quests/stages/search",
+    ),
+    (
+        "admin_quests_stages_move_back",
+        Method::Post,
+        "/admin/quests/stages/move_back",
+    ),
+    (
+        "admin_quests_stages_move_forward",
+        Method::Post,
+        "/admin/quests/stages/move_forward",
+    ),
+    ("admin_game_pause", Method::Post, "/admin/game/pause"),
+    ("admin_game_unpause", Method::Post, "/admin/game/unpause"),
+    (
+        "admin_game_set_location_radius",
+        Method::Put,
+        "/admin/game/location_radius",
+    ),
+    (
+        "admin_quests_select_tutorial",
+        Method::Put,
+        "/admin/game/tutorial",
+    ),
+    ("admin_users_get", Method::Post, "/admin/users/search"),
+    ("admin_users_promote", Method::Post, "/admin/users/promote"),
+    ("admin_users_demote", Method::Post, "/admin/users/demote"),
+    ("admin_users_ban", Method::Post, "/admin/users/ban"),
+    ("admin_users_unban", Method::Post, "/admin/users/unban"),
+    (
+        "admin_users_impersonate",
+        Method::Post,
+        "/admin/users/impersonate",
+    ),
+    ("admin_users_export", Method::Post, "/admin/users/export"),
+    (
+        "admin_users_deleted_get",
+        Method::Post,
+        "/admin/users/deleted/search",
+    ),
+    ("admin_users_restore", Method::Post, "/admin/users/restore"),
+    ("admin_audit_get", Method::Post, "/admin/audit/search"),
+    (
+        "admin_get_reports",
+        Method::Post,
+        "/admin/reports/errors/search",
+    ),
+    (
+        "admin_get_suggestions",
+        Method::Post,
+        "/admin/reports/suggestions/search",
+    ),
+    ("admin_reports_get", Method::Post, "/admin/reports/details"),
+    (
+        "admin_reports_set_status",
+        Method::Put,
+        "/admin/reports/status",
+    ),
+    (
+        "admin_reports_comment",
+        Method::Post,
+        "/admin/reports/comments",
+    ),
+    ("admin_get_logs", Method::Post, "/admin/logs/search"),
+    ("admin_janitor_run", Method::Post, "/admin/janitor/run"),
+];
+
+// Legacy handlers that got a new handler in v1 instead of a new path
+const V1_REPLACEMENTS: &[(&str, &str)] = &[
+    ("get_character", "/characters/<character_id>"),
+    ("user_retrieve_id", "/users/<account_id>"),
+];
+
+fn dynamic_segments(path: &str) -> Vec<(usize, &str)> {
+    path.split('/')
+        .filter(|x| !x.is_empty())
+        .enumerate()
+        .filter(|(_, x)| x.starts_with('<'))
+        .collect()
+}
+
+// Only used to build a route, remount swaps in the real handler
+fn placeholder<'r>(req: &'r Request<'_>, _: Data<'r>) -> BoxFuture<'r> {
+    Outcome::from(req, ()).pin()
+}
+
+// Copies of the handlers under the layout's methods and paths, every handler has to be listed
+pub fn remount(handlers: &[Route], layout: &[(&str, Method, &str)]) -> Vec<Route> {
+    handlers
+        .iter()
+        .map(|handler| {
+            let name = handler.name.as_deref().unwrap_or_default();
+            let (_, method, path) = layout
+                .iter()
+                .find(|(x, _, _)| *x == name)
+                .unwrap_or_else(|| panic!("Route {} is missing from the api layout", name));
+
+            if dynamic_segments(path) != dynamic_segments(handler.uri.path()) {
+                panic!("Route {} moves its dynamic segments in {}", name, path);
+            }
+
+            let mut route = Route::new(*method, path, placeholder);
+            route.handler = handler.handler.clone();
+            route.name = handler.name.clone();
+            route.format = handler.format.clone();
+            route
+        })
+        .collect()
+}
+
+pub fn v1_routes(handlers: &[Route]) -> Vec<Route> {
+    let remounted: Vec<Route> = handlers
+        .iter()
+        .filter(|x| {
+            let name = x.name.as_deref().unwrap_or_default();
+            !V1_REPLACEMENTS.iter().any(|(legacy, _)| *legacy == name)
+        })
+        .cloned()
+        .collect();
+
+    remount(&remounted, V1_LAYOUT)
+}
+
+// The unversioned paths, renamed so the deprecation fairing and the docs can tell them apart
+pub fn legacy_routes(handlers: &[Route]) -> Vec<Route> {
+    handlers
+        .iter()
+        .cloned()
+        .map(|mut route| {
+            let name = route.name.as_deref().unwrap_or_default();
+            route.name = Some(format!("{}{}", LEGACY_PREFIX, name).into());
+            route
+        })
+        .collect()
+}
+
+// Routes of an older version that a newer one doesn't replace, so /api/v2 only has to mount
+// the handlers that changed next to inherited(v1_routes(..), &v2)
+pub fn inherited(older: Vec<Route>, newer: &[Route]) -> Vec<Route> {
+    older
+        .into_iter()
+        .filter(|old| {
+            !newer
+                .iter()
+                .any(|new| new.method == old.method && new.uri.path() == old.uri.path())
+        })
+        .collect()
+}
+
+pub fn is_legacy(route: &Route) -> bool {
+    route
+        .name
+        .as_deref()
+        .is_some_and(|x| x.starts_with(LEGACY_PREFIX))
+}
+
+// Handler name with the legacy prefix stripped
+pub fn handler_name(route: &Route) -> &str {
+    let name = route.name.as_deref().unwrap_or_default();
+    name.strip_prefix(LEGACY_PREFIX).unwrap_or(name)
+}
+
+fn v1_path(name: &str) -> Option<&'static str> {
+    V1_LAYOUT
+        .iter()
+        .find(|(x, _, _)| *x == name)
+        .map(|(_, _, path)| *path)
+        .or_else(|| {
+            V1_REPLACEMENTS
+                .iter()
+                .find(|(x, _)| *x == name)
+                .map(|(_, path)| *path)
+        })
+}
+
+// Path of the v1 route replacing a legacy request, with its dynamic segments filled in
+pub fn successor(req: &Request<'_>) -> Option<String> {
+    let route = req.route()?;
+    let path = v1_path(handler_name(route))?;
+
+    let legacy_segments = dynamic_segments(route.uri.path());
+
+    let segments = path
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(|segment| {
+            if !segment.starts_with('<') {
+                return Some(segment.to_owned());
+            }
+
+            // Parameters that came from the body in the legacy route are left as a template
+            match legacy_segments.iter().find(|(_, x)| *x == segment) {
+                Some((index, _)) => req.routed_segment(*index).map(|x| x.to_owned()),
+                None => Some(format!("{{{}}}", segment.trim_matches(['<', '>']))),
+            }
+        })
+        .collect::<Option<Vec<String>>>()?;
+
+    Some(format!("{}/{}", V1_BASE, segments.join("/")))
+}
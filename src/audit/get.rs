@@ -0,0 +1,75 @@
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{config::AppConfig, util::check_authorized_admin, DB};
+
+use super::{count_audit_entries, get_audit_entries, AuditFilter};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditGetData<'r> {
+    jwt: &'r str,
+    page: Option<u32>,
+    page_size: Option<u32>,
+    actor_id: Option<u32>,
+    action: Option<&'r str>,
+    entity_type: Option<&'r str>,
+    entity_id: Option<u32>,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+#[post("/admin/audit", format = "json", data = "<data>")]
+pub async fn admin_audit_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<AuditGetData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let page = data.page.unwrap_or(0);
+    let page_size = data.page_size.unwrap_or(50).clamp(1, 500);
+    let offset = match page.checked_mul(page_size) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "Page out of range"})),
+    };
+
+    let filter = AuditFilter {
+        actor_id: data.actor_id,
+        action: data.action,
+        entity_type: data.entity_type,
+        entity_id: data.entity_id,
+        since: data.since,
+        until: data.until,
+    };
+
+    let total = match count_audit_entries(&mut db, &filter).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    let entries = match get_audit_entries(&mut db, &filter, page_size, offset).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    (
+        Status::Ok,
+        json!({
+            "entries": entries,
+            "total": total,
+            "page": page,
+            "page_size": page_size
+        }),
+    )
+}
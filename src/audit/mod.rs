@@ -0,0 +1,250 @@
+use std::{
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::serde::{
+    json::{serde_json::Map, Value},
+    Serialize,
+};
+use schemars::JsonSchema;
+use sqlx::{prelude::FromRow, query, query_as, Row};
+
+use crate::{
+    db::{DbConnection, NullableId},
+    user::hash_email,
+};
+
+pub mod get;
+
+// Actor of the changes made through the admin cli, no account has this id
+pub const CLI_ACTOR_ID: u32 = 0;
+
+#[derive(Debug, FromRow)]
+pub struct AuditEntryDB {
+    #[sqlx(try_from = "i64")]
+    pub audit_id: u32,
+    #[sqlx(try_from = "i64")]
+    pub actor_id: u32,
+    pub action: String,
+    pub entity_type: String,
+    #[sqlx(try_from = "NullableId")]
+    pub entity_id: Option<u32>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub ip: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditEntry {
+    pub audit_id: u32,
+    pub actor_id: u32,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: Option<u32>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub ip: Option<String>,
+    pub timestamp: i64,
+}
+
+impl From<AuditEntryDB> for AuditEntry {
+    fn from(value: AuditEntryDB) -> AuditEntry {
+        AuditEntry {
+            audit_id: value.audit_id,
+            actor_id: value.actor_id,
+            action: value.action,
+            entity_type: value.entity_type,
+            entity_id: value.entity_id,
+            before: value
+                .before
+                .and_then(|x| rocket::serde::json::from_str(&x).ok()),
+            after: value
+                .after
+                .and_then(|x| rocket::serde::json::from_str(&x).ok()),
+            ip: value.ip,
+            timestamp: value.timestamp,
+        }
+    }
+}
+
+fn diff(before: Option<&Value>, after: Option<&Value>) -> (Option<Value>, Option<Value>) {
+    if let (Some(Value::Object(before)), Some(Value::Object(after))) = (before, after) {
+        let mut before_diff = Map::new();
+        let mut after_diff = Map::new();
+
+        for key in before.keys().chain(after.keys()) {
+            if before.get(key) != after.get(key) {
+                if let Some(val) = before.get(key) {
+                    before_diff.insert(key.clone(), val.clone());
+                }
+                if let Some(val) = after.get(key) {
+                    after_diff.insert(key.clone(), val.clone());
+                }
+            }
+        }
+
+        return (
+            Some(Value::Object(before_diff)),
+            Some(Value::Object(after_diff)),
+        );
+    }
+
+    (before.cloned(), after.cloned())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn add_audit_entry(
+    db: &mut DbConnection,
+    actor_id: u32,
+    action: &str,
+    entity_type: &str,
+    entity_id: Option<u32>,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    ip: Option<IpAddr>,
+) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs();
+
+    let (before, after) = diff(before, after);
+
+    match query(
+        "INSERT INTO
+        audit_log
+        (actor_id, action, entity_type, entity_id, before, after, ip, timestamp)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8)",
+    )
+    .bind(i64::from(actor_id))
+    .bind(action)
+    .bind(entity_type)
+    .bind(entity_id.map(i64::from))
+    .bind(before.map(|x| x.to_string()))
+    .bind(after.map(|x| x.to_string()))
+    .bind(ip.map(|x| x.to_string()))
+    .bind(timestamp as i64)
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to add audit entry: {}", err)),
+    }
+}
+
+fn hash_audit_email(value: Option<&str>) -> Option<String> {
+    let mut value: Value = rocket::serde::json::from_str(value?).ok()?;
+    let email = value.get_mut("email")?;
+    *email = Value::String(hash_email(email.as_str()?));
+    Some(value.to_string())
+}
+
+// Entries about a user keep their email only as the same hash deleted_users does
+pub async fn anonymise_audit_entries(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    let rows = match query(
+        "SELECT audit_id, before, after FROM audit_log WHERE entity_type = 'user' AND entity_id = $1",
+    )
+    .bind(i64::from(user_id))
+    .fetch_all(&mut *db)
+    .await
+    {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to get audit entries: {}", err)),
+    };
+
+    for row in rows {
+        let audit_id: i64 = row.get(0);
+        let before: Option<String> = row.get(1);
+        let after: Option<String> = row.get(2);
+
+        let hashed_before = hash_audit_email(before.as_deref());
+        let hashed_after = hash_audit_email(after.as_deref());
+        if hashed_before.is_none() && hashed_after.is_none() {
+            continue;
+        }
+
+        if let Err(err) = query("UPDATE audit_log SET before = $1, after = $2 WHERE audit_id = $3")
+            .bind(hashed_before.or(before))
+            .bind(hashed_after.or(after))
+            .bind(audit_id)
+            .execute(&mut *db)
+            .await
+        {
+            return Err(format!(
+                "Failed to anonymise audit entry {}: {}",
+                audit_id, err
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter<'r> {
+    pub actor_id: Option<u32>,
+    pub action: Option<&'r str>,
+    pub entity_type: Option<&'r str>,
+    pub entity_id: Option<u32>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+const AUDIT_FILTER_QUERY: &str = "FROM audit_log
+    WHERE ($1 IS NULL OR actor_id = $1)
+    AND ($2 IS NULL OR action = $2)
+    AND ($3 IS NULL OR entity_type = $3)
+    AND ($4 IS NULL OR entity_id = $4)
+    AND ($5 IS NULL OR timestamp >= $5)
+    AND ($6 IS NULL OR timestamp <= $6)";
+
+pub async fn get_audit_entries(
+    db: &mut DbConnection,
+    filter: &AuditFilter<'_>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<AuditEntry>, String> {
+    match query_as::<_, AuditEntryDB>(&format!(
+        "SELECT * {} ORDER BY audit_id DESC LIMIT $7 OFFSET $8",
+        AUDIT_FILTER_QUERY
+    ))
+    .bind(filter.actor_id.map(i64::from))
+    .bind(filter.action)
+    .bind(filter.entity_type)
+    .bind(filter.entity_id.map(i64::from))
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(i64::from(limit))
+    .bind(i64::from(offset))
+    .fetch_all(db)
+    .await
+    {
+        Ok(val) => Ok(val.into_iter().map(AuditEntry::from).collect()),
+        Err(err) => Err(format!("Failed to get audit entries: {}", err)),
+    }
+}
+
+pub async fn count_audit_entries(
+    db: &mut DbConnection,
+    filter: &AuditFilter<'_>,
+) -> Result<u32, String> {
+    match query(&format!("SELECT COUNT(audit_id) {}", AUDIT_FILTER_QUERY))
+        .bind(filter.actor_id.map(i64::from))
+        .bind(filter.action)
+        .bind(filter.entity_type)
+        .bind(filter.entity_id.map(i64::from))
+        .bind(filter.since)
+        .bind(filter.until)
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(val) => Ok(val as u32),
+            Err(_) => Err("Database error".to_owned()),
+        },
+        Err(err) => Err(format!("Failed to count audit entries: {}", err)),
+    }
+}
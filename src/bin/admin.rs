@@ -0,0 +1,283 @@
+use std::{env, fs, process::ExitCode};
+
+use rocket::serde::json::{json, serde_json, Value};
+use wiedzieliscie_backend::{
+    admin::{export_content, game_set_state, get_game_state, import_content},
+    audit::{add_audit_entry, CLI_ACTOR_ID},
+    config,
+    db::{connect as connect_db, create_tables, DbConnection, DbPool},
+    janitor::run_janitor,
+    user::{
+        create_user, email_taken, get_user_by_email, get_user_by_id,
+        remove_password_reset_by_user_id, stop_all_sessions, update_user_admin_status,
+        update_user_password, update_user_verification_status, UserDB,
+    },
+};
+
+const USAGE: &str = "Usage: wiedzieliscie-admin <command> [args]
+
+Commands:
+    create-admin <email> <password> <first_name> <last_name> <m|f>
+    promote <account_id|email>
+    reset-password <account_id|email> <password>
+    pause
+    unpause
+    export-content [file]       writes to stdout without a file
+    import-content <file>       replaces all media, characters, dialogues, tasks and quests
+    migrate                     creates missing tables
+    purge-expired               removes unverified accounts, expired sessions, tokens and bans";
+
+async fn connect() -> Result<DbPool, String> {
+    let url: String = match config::figment().extract_inner("databases.db.url") {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Database url not found: {}", err)),
+    };
+
+    connect_db(&url).await
+}
+
+async fn find_user(db: &mut DbConnection, account: &str) -> Result<UserDB, String> {
+    match account.parse::<u32>() {
+        Ok(id) => get_user_by_id(db, id).await,
+        Err(_) => get_user_by_email(db, account).await,
+    }
+}
+
+async fn audit(
+    db: &mut DbConnection,
+    action: &str,
+    entity_type: &str,
+    entity_id: Option<u32>,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) -> Result<(), String> {
+    add_audit_entry(
+        db,
+        CLI_ACTOR_ID,
+        action,
+        entity_type,
+        entity_id,
+        before,
+        after,
+        None,
+    )
+    .await
+}
+
+async fn create_admin(
+    db: &mut DbConnection,
+    email: &str,
+    password: &str,
+    first_name: &str,
+    last_name: &str,
+    gender: &str,
+) -> Result<(), String> {
+    let gender = match gender {
+        "m" => 'm',
+        "f" => 'f',
+        _ => return Err("Gender has to be m or f".to_owned()),
+    };
+
+    if email_taken(db, email).await? {
+        return Err("Email already in use".to_owned());
+    }
+
+    let user_id = create_user(db, first_name, last_name, email, password, gender).await?;
+    update_user_verification_status(db, user_id).await?;
+    update_user_admin_status(db, user_id, true).await?;
+
+    audit(
+        db,
+        "create_admin",
+        "user",
+        Some(user_id),
+        None,
+        Some(&json!({"email": email, "admin": true})),
+    )
+    .await?;
+
+    println!("Created admin {} with account id {}", email, user_id);
+    Ok(())
+}
+
+async fn promote(db: &mut DbConnection, account: &str) -> Result<(), String> {
+    let user = find_user(db, account).await?;
+
+    if user.admin {
+        return Err("User is already admin".to_owned());
+    }
+
+    update_user_admin_status(db, user.user_id, true).await?;
+
+    audit(
+        db,
+        "promote",
+        "user",
+        Some(user.user_id),
+        Some(&json!({"admin": false})),
+        Some(&json!({"admin": true})),
+    )
+    .await?;
+
+    println!("Promoted {} to admin", user.email);
+    Ok(())
+}
+
+async fn reset_password(
+    db: &mut DbConnection,
+    account: &str,
+    password: &str,
+) -> Result<(), String> {
+    let user = find_user(db, account).await?;
+
+    update_user_password(db, user.user_id, password).await?;
+    remove_password_reset_by_user_id(db, user.user_id).await?;
+    stop_all_sessions(db, user.user_id).await?;
+
+    audit(db, "reset_password", "user", Some(user.user_id), None, None).await?;
+
+    println!(
+        "Reset the password of {} and ended all of their sessions",
+        user.email
+    );
+    Ok(())
+}
+
+async fn set_paused(db: &mut DbConnection, paused: bool) -> Result<(), String> {
+    let before = get_game_state(db).await?;
+
+    game_set_state(db, paused).await?;
+
+    audit(
+        db,
+        if paused { "pause" } else { "unpause" },
+        "game",
+        None,
+        Some(&json!({"paused": before.paused})),
+        Some(&json!({"paused": paused})),
+    )
+    .await?;
+
+    println!("Game {}", if paused { "paused" } else { "unpaused" });
+    Ok(())
+}
+
+async fn export(db: &mut DbConnection, file: Option<&str>) -> Result<(), String> {
+    let content = export_content(db).await?;
+
+    let text = match serde_json::to_string_pretty(&content) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to serialize content: {}", err)),
+    };
+
+    match file {
+        Some(file) => match fs::write(file, text) {
+            Ok(_) => {
+                println!("Exported content to {}", file);
+                Ok(())
+            }
+            Err(err) => Err(format!("Failed to write {}: {}", file, err)),
+        },
+        None => {
+            println!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+async fn import(db: &mut DbConnection, file: &str) -> Result<(), String> {
+    let text = match fs::read_to_string(file) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read {}: {}", file, err)),
+    };
+
+    let content: Value = match serde_json::from_str(&text) {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to parse {}: {}", file, err)),
+    };
+
+    import_content(db, &content).await?;
+
+    audit(db, "import_content", "content", None, None, None).await?;
+
+    println!("Imported content from {}", file);
+    Ok(())
+}
+
+async fn purge(db: &mut DbConnection) -> Result<(), String> {
+    let config = match config::load_from(&config::figment()) {
+        Ok(val) => val,
+        Err(problems) => return Err(format!("Invalid configuration: {}", problems.join(", "))),
+    };
+
+    let counts = run_janitor(db, &config.janitor).await?;
+
+    println!("Removed {} unverified users", counts.unverified_users);
+    println!("Removed {} verifications", counts.verifications);
+
+    println!("Removed {} expired sessions", counts.sessions);
+    println!("Removed {} expired password resets", counts.password_resets);
+    println!("Removed {} expired email updates", counts.email_updates);
+    println!("Removed {} expired delete requests", counts.delete_requests);
+    println!("Removed {} expired bans", counts.bans);
+    println!(
+        "Removed {} expired login challenges",
+        counts.login_challenges
+    );
+    println!("Anonymised {} deleted users", counts.anonymised_users);
+    Ok(())
+}
+
+async fn run(pool: &DbPool, args: &[&str]) -> Result<(), String> {
+    let mut db = match pool.acquire().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to get a database connection: {}", err)),
+    };
+
+    match args {
+        ["create-admin", email, password, first_name, last_name, gender] => {
+            create_admin(&mut db, email, password, first_name, last_name, gender).await
+        }
+        ["promote", account] => promote(&mut db, account).await,
+        ["reset-password", account, password] => reset_password(&mut db, account, password).await,
+        ["pause"] => set_paused(&mut db, true).await,
+        ["unpause"] => set_paused(&mut db, false).await,
+        ["export-content"] => export(&mut db, None).await,
+        ["export-content", file] => export(&mut db, Some(file)).await,
+        ["import-content", file] => import(&mut db, file).await,
+        ["migrate"] => {
+            create_tables(db, false).await?;
+            println!("Database is up to date");
+            Ok(())
+        }
+        ["purge-expired"] => purge(&mut db).await,
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    if let ["help" | "--help" | "-h"] = args.as_slice() {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let pool = match connect().await {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&pool, &args).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
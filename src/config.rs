@@ -0,0 +1,501 @@
+use std::{env, fs, net::IpAddr};
+
+use rocket::{
+    fairing::AdHoc,
+    figment::{providers::Serialized, value::Value, Figment},
+    serde::{de, Deserialize, Deserializer, Serialize},
+    Build, Rocket,
+};
+
+use crate::{
+    logging::{self, Level},
+    user::jwt::check_secret,
+};
+
+const ENV_PREFIX: &str = "WIEDZIELISCIE_BACKEND_";
+const ENV_FILE: &str = ".env";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AppConfig {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub reset_db: bool,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub tokens: TokenConfig,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub logging: LogConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub janitor: JanitorConfig,
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    #[serde(default)]
+    pub totp: TotpConfig,
+    #[serde(default)]
+    pub announcements: AnnouncementConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MailConfig {
+    pub disabled: bool,
+    pub from: Option<String>,
+    pub resend_api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TokenConfig {
+    pub session_lifetime: i64,
+    pub impersonation_lifetime: i64,
+    pub password_reset_lifetime: i64,
+    pub email_update_lifetime: i64,
+    pub delete_request_lifetime: i64,
+}
+
+impl Default for TokenConfig {
+    fn default() -> TokenConfig {
+        TokenConfig {
+            session_lifetime: 2592000,
+            impersonation_lifetime: 900,
+            password_reset_lifetime: 300,
+            email_update_lifetime: 3600,
+            delete_request_lifetime: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SessionConfig {
+    pub max_per_user: u32,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig { max_per_user: 32 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LogConfig {
+    pub level: Level,
+    pub file: String,
+    pub max_size: u64,
+    pub max_files: u32,
+    pub buffer_size: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: Level::Info,
+            file: "logs/wiedzieliscie-backend.log".to_owned(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+            buffer_size: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MetricsConfig {
+    #[serde(deserialize_with = "ip_list")]
+    pub allowed_ips: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct JanitorConfig {
+    pub interval: u64,
+    pub unverified_account_age: i64,
+    pub deleted_account_retention: i64,
+}
+
+impl Default for JanitorConfig {
+    fn default() -> JanitorConfig {
+        JanitorConfig {
+            interval: 3600,
+            unverified_account_age: 604800,
+            deleted_account_retention: 2592000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AnnouncementConfig {
+    pub publish_interval: u64,
+}
+
+impl Default for AnnouncementConfig {
+    fn default() -> AnnouncementConfig {
+        AnnouncementConfig {
+            publish_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MediaConfig {
+    pub directory: String,
+    pub max_size: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub thumbnail_size: u32,
+}
+
+impl Default for MediaConfig {
+    fn default() -> MediaConfig {
+        MediaConfig {
+            directory: "media".to_owned(),
+            max_size: 5242880,
+            max_width: 4096,
+            max_height: 4096,
+            thumbnail_size: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TotpConfig {
+    pub required_for_admins: bool,
+    pub issuer: String,
+    pub challenge_lifetime: i64,
+    pub skew: u64,
+}
+
+impl Default for TotpConfig {
+    fn default() -> TotpConfig {
+        TotpConfig {
+            required_for_admins: false,
+            issuer: "WiedzieLISCIE".to_owned(),
+            challenge_lifetime: 300,
+            skew: 1,
+        }
+    }
+}
+
+// A bucket holds up to capacity requests and gets one back every refill_every seconds,
+// capacity 0 turns the bucket off
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BucketConfig {
+    pub capacity: u32,
+    pub refill_every: u64,
+}
+
+impl BucketConfig {
+    const fn new(capacity: u32, refill_every: u64) -> BucketConfig {
+        BucketConfig {
+            capacity,
+            refill_every,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RouteLimitConfig {
+    pub ip: BucketConfig,
+    pub account: BucketConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LockoutConfig {
+    pub max_failures: u32,
+    pub window: u64,
+    pub duration: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> LockoutConfig {
+        LockoutConfig {
+            max_failures: 5,
+            window: 900,
+            duration: 900,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub login: RouteLimitConfig,
+    pub register: RouteLimitConfig,
+    pub resend_verification: RouteLimitConfig,
+    pub password_reset: RouteLimitConfig,
+    pub delete_user: RouteLimitConfig,
+    pub lockout: LockoutConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            login: RouteLimitConfig {
+                ip: BucketConfig::new(20, 6),
+                account: BucketConfig::new(10, 30),
+            },
+            register: RouteLimitConfig {
+                ip: BucketConfig::new(10, 60),
+                account: BucketConfig::default(),
+            },
+            resend_verification: RouteLimitConfig {
+                ip: BucketConfig::new(5, 60),
+                account: BucketConfig::new(3, 300),
+            },
+            password_reset: RouteLimitConfig {
+                ip: BucketConfig::new(5, 60),
+                account: BucketConfig::new(3, 300),
+            },
+            delete_user: RouteLimitConfig {
+                ip: BucketConfig::new(5, 60),
+                account: BucketConfig::new(3, 300),
+            },
+            lockout: LockoutConfig::default(),
+        }
+    }
+}
+
+// Accepts both a list and a comma separated string, so the ips can be set from a single env var
+fn ip_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<IpAddr>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde", untagged)]
+    enum IpList {
+        List(Vec<String>),
+        Joined(String),
+    }
+
+    let ips = match IpList::deserialize(deserializer)? {
+        IpList::List(val) => val,
+        IpList::Joined(val) => val.split(',').map(|x| x.to_owned()).collect(),
+    };
+
+    ips.iter()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+impl AppConfig {
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.url.is_empty() {
+            problems.push("url can't be empty".to_owned());
+        }
+
+        if let Err(err) = check_secret(&self.secret) {
+            problems.push(format!("secret has to be valid base64: {}", err));
+        }
+
+        if !self.mail.disabled {
+            if self.mail.from.is_none() {
+                problems.push("mail.from is required unless mail is disabled".to_owned());
+            }
+            if self.mail.resend_api_key.is_none() {
+                problems.push("mail.resend_api_key is required unless mail is disabled".to_owned());
+            }
+        }
+
+        let lifetimes = [
+            ("tokens.session_lifetime", self.tokens.session_lifetime),
+            (
+                "tokens.impersonation_lifetime",
+                self.tokens.impersonation_lifetime,
+            ),
+            (
+                "tokens.password_reset_lifetime",
+                self.tokens.password_reset_lifetime,
+            ),
+            (
+                "tokens.email_update_lifetime",
+                self.tokens.email_update_lifetime,
+            ),
+            (
+                "tokens.delete_request_lifetime",
+                self.tokens.delete_request_lifetime,
+            ),
+        ];
+        for (name, lifetime) in lifetimes {
+            if lifetime <= 0 {
+                problems.push(format!("{} has to be positive", name));
+            }
+        }
+
+        if self.sessions.max_per_user == 0 {
+            problems.push("sessions.max_per_user has to be positive".to_owned());
+        }
+
+        if self.logging.max_size == 0 {
+            problems.push("logging.max_size has to be positive".to_owned());
+        }
+
+        if self.janitor.interval == 0 {
+            problems.push("janitor.interval has to be positive".to_owned());
+        }
+
+        if self.janitor.unverified_account_age <= 0 {
+            problems.push("janitor.unverified_account_age has to be positive".to_owned());
+        }
+
+        if self.janitor.deleted_account_retention <= 0 {
+            problems.push("janitor.deleted_account_retention has to be positive".to_owned());
+        }
+
+        let routes = [
+            ("login", &self.rate_limits.login),
+            ("register", &self.rate_limits.register),
+            ("resend_verification", &self.rate_limits.resend_verification),
+            ("password_reset", &self.rate_limits.password_reset),
+            ("delete_user", &self.rate_limits.delete_user),
+        ];
+        for (route, limit) in routes {
+            for (kind, bucket) in [("ip", limit.ip), ("account", limit.account)] {
+                if bucket.capacity > 0 && bucket.refill_every == 0 {
+                    problems.push(format!(
+                        "rate_limits.{}.{}.refill_every has to be positive",
+                        route, kind
+                    ));
+                }
+            }
+        }
+
+        if self.totp.issuer.is_empty() || self.totp.issuer.contains(':') {
+            problems.push("totp.issuer can't be empty or contain ':'".to_owned());
+        }
+
+        if self.totp.challenge_lifetime <= 0 {
+            problems.push("totp.challenge_lifetime has to be positive".to_owned());
+        }
+
+        if self.totp.skew > 10 {
+            problems.push("totp.skew can't be larger than 10".to_owned());
+        }
+
+        if self.announcements.publish_interval == 0 {
+            problems.push("announcements.publish_interval has to be positive".to_owned());
+        }
+
+        if self.media.directory.is_empty() {
+            problems.push("media.directory can't be empty".to_owned());
+        }
+
+        if self.media.max_size == 0 || self.media.max_width == 0 || self.media.max_height == 0 {
+            problems
+                .push("media.max_size, max_width and max_height have to be positive".to_owned());
+        }
+
+        if self.media.thumbnail_size == 0 {
+            problems.push("media.thumbnail_size has to be positive".to_owned());
+        }
+
+        if self.rate_limits.lockout.max_failures > 0 && self.rate_limits.lockout.duration == 0 {
+            problems.push("rate_limits.lockout.duration has to be positive".to_owned());
+        }
+
+        problems
+    }
+}
+
+// Maps env names onto config keys, the old flat names are kept so existing .env files still work
+fn config_key(var: &str) -> Option<String> {
+    if var == "RESEND_API_KEY" {
+        return Some("mail.resend_api_key".to_owned());
+    }
+
+    let key = var.strip_prefix(ENV_PREFIX)?.to_lowercase();
+    let key = match key.as_str() {
+        "from_mail" => "mail.from",
+        "kein_mail" => "mail.disabled",
+        "log_level" => "logging.level",
+        "log_file" => "logging.file",
+        "log_max_size" => "logging.max_size",
+        "log_max_files" => "logging.max_files",
+        "log_buffer_size" => "logging.buffer_size",
+        "metrics_allowed_ips" => "metrics.allowed_ips",
+        _ => return Some(key.replace("__", ".")),
+    };
+
+    Some(key.to_owned())
+}
+
+fn vars_figment(vars: impl Iterator<Item = (String, String)>) -> Figment {
+    vars.filter_map(|(var, val)| Some((config_key(&var)?, val)))
+        .fold(Figment::new(), |figment, (key, val)| {
+            let val: Value = val.parse().unwrap_or_else(|err| match err {});
+            figment.merge(Serialized::global(&key, val))
+        })
+}
+
+fn env_file_vars() -> Vec<(String, String)> {
+    match fs::read_to_string(ENV_FILE) {
+        Ok(val) => val
+            .lines()
+            .filter_map(|x| x.trim().split_once(' '))
+            .map(|(var, val)| (var.to_owned(), val.trim().to_owned()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// The rate limit defaults are joined in so that overriding one bucket keeps the others
+pub fn figment() -> Figment {
+    rocket::Config::figment()
+        .join(Serialized::default(
+            "rate_limits",
+            RateLimitConfig::default(),
+        ))
+        .merge(vars_figment(env_file_vars().into_iter()))
+        .merge(vars_figment(env::vars()))
+}
+
+pub fn load_from(figment: &Figment) -> Result<AppConfig, Vec<String>> {
+    let config: AppConfig = match figment.extract_lossy() {
+        Ok(val) => val,
+        Err(err) => return Err(err.into_iter().map(|x| x.to_string()).collect()),
+    };
+
+    let problems = config.validate();
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    Ok(config)
+}
+
+async fn ignite(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    match load_from(rocket.figment()) {
+        Ok(config) => {
+            logging::init(&config.logging);
+            Ok(rocket.manage(config))
+        }
+        Err(problems) => {
+            for problem in problems {
+                error!("Invalid configuration: {}", problem);
+            }
+            Err(rocket)
+        }
+    }
+}
+
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("App Config", ignite)
+}
@@ -1,17 +1,86 @@
-use std::env::{self, VarError};
+use std::{
+    num::TryFromIntError,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use sqlx::{pool::PoolConnection, query, Sqlite, SqliteConnection};
+use sqlx::{
+    database::{HasArguments, HasValueRef},
+    error::BoxDynError,
+    pool::PoolConnection,
+    query, Connection, Database, Decode, Row, Type, ValueRef,
+};
 
-async fn create_user_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE users").execute(&mut *db).await.ok();
+use rocket::serde::json::serde_json;
+
+use crate::{admin::TaskKind, error::ReportStatus};
+
+// SQLite unless the crate is built with the postgres feature
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "postgres")]
+use postgres as backend;
+#[cfg(not(feature = "postgres"))]
+mod sqlite;
+#[cfg(not(feature = "postgres"))]
+use sqlite as backend;
+
+pub use backend::{connect, json_text, sync_ids, table_columns, Backend};
+
+pub type DbConnection = <Backend as Database>::Connection;
+pub type DbRow = <Backend as Database>::Row;
+pub type DbPool = sqlx::Pool<Backend>;
+pub type DbArguments<'q> = <Backend as HasArguments<'q>>::Arguments;
+
+// Postgres has no unsigned integers, so ids are bigint columns read through i64 on both backends,
+// #[sqlx(try_from = "i64")] for u32 fields and #[sqlx(try_from = "NullableId")] for Option<u32>
+pub struct NullableId(Option<i64>);
+
+impl Type<Backend> for NullableId {
+    fn type_info() -> <Backend as Database>::TypeInfo {
+        <i64 as Type<Backend>>::type_info()
+    }
+
+    fn compatible(ty: &<Backend as Database>::TypeInfo) -> bool {
+        <i64 as Type<Backend>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Backend> for NullableId {
+    fn decode(value: <Backend as HasValueRef<'r>>::ValueRef) -> Result<Self, BoxDynError> {
+        if value.is_null() {
+            return Ok(NullableId(None));
         }
+        Ok(NullableId(Some(<i64 as Decode<Backend>>::decode(value)?)))
     }
+}
 
-    match query(
-        "CREATE TABLE users (
-        user_id int,
+impl TryFrom<NullableId> for Option<u32> {
+    type Error = TryFromIntError;
+
+    fn try_from(id: NullableId) -> Result<Self, Self::Error> {
+        id.0.map(u32::try_from).transpose()
+    }
+}
+
+async fn drop_table(db: &mut DbConnection, table: &str) {
+    query(&format!(
+        "DROP TABLE IF EXISTS {}{}",
+        table,
+        backend::DROP_CASCADE
+    ))
+    .execute(db)
+    .await
+    .ok();
+}
+
+async fn create_user_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "users").await;
+    }
+
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS users (
+        user_id {},
         first_name varchar(255),
         last_name varchar(255),
         email varchar(255),
@@ -20,548 +89,1155 @@ async fn create_user_table(db: &mut SqliteConnection) -> Result<(), String> {
         verified bool,
         admin bool
     )",
-    )
+        backend::USER_ID
+    ))
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table users already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create users table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create users table: {}", err)),
     }
 }
 
-async fn create_deleted_user_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE deleted_users")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_deleted_user_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "deleted_users").await;
     }
 
     match query(
-        "CREATE TABLE deleted_users (
-        user_id int,
+        "CREATE TABLE IF NOT EXISTS deleted_users (
+        user_id bigint primary key,
         first_name varchar(255),
         last_name varchar(255),
         email varchar(255),
         password varchar(255),
         gender bool,
         verified bool,
-        admin bool
+        admin bool,
+        deleted_at bigint,
+        anonymised bool
         )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table deleted_users already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create deleted_users table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create deleted_users table: {}", err)),
     }
 }
 
-async fn create_delete_request_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE delete_requests")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+// Databases created before a column was added get it here, existing rows keep NULL
+async fn add_missing_column(
+    db: &mut DbConnection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), String> {
+    let columns = backend::table_columns(db, table).await?;
+
+    if columns.iter().any(|(name, _)| name == column) {
+        return Ok(());
+    }
+
+    match query(&format!(
+        "ALTER TABLE {} ADD COLUMN {} {}",
+        table, column, definition
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to add {}.{}: {}", table, column, err)),
+    }
+}
+
+async fn create_delete_request_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "delete_requests").await;
     }
 
     match query(
-        "CREATE TABLE delete_requests (
-        user_id int,
+        "CREATE TABLE IF NOT EXISTS delete_requests (
+        user_id bigint references users(user_id) on delete cascade,
         delete_token varchar(255),
-        timestamp int,
-        valid_until int
+        timestamp bigint,
+        valid_until bigint
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table delete_requests already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create delete_requests table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create delete_requests table: {}", err)),
     }
 }
 
-async fn create_verification_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE verifications")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_verification_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "verifications").await;
     }
 
     match query(
-        "CREATE TABLE verifications (
-        user_id int,
-        timestamp int,
+        "CREATE TABLE IF NOT EXISTS verifications (
+        user_id bigint references users(user_id) on delete cascade,
+        timestamp bigint,
         verification_token varchar(255)
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table verifications already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create verifications table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create verifications table: {}", err)),
     }
 }
 
-async fn create_session_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE sessions").execute(&mut *db).await.ok();
-        }
+async fn create_session_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "sessions").await;
     }
 
     match query(
-        "CREATE TABLE sessions (
-        user_id int,
+        "CREATE TABLE IF NOT EXISTS sessions (
+        user_id bigint references users(user_id) on delete cascade,
         session_token varchar(255),
-        timestamp int,
-        valid_until int
+        timestamp bigint,
+        valid_until bigint
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table sessions already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create sessions table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create sessions table: {}", err)),
     }
 }
 
-async fn create_password_reser_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE password_resets")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_password_reser_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "password_resets").await;
     }
 
     match query(
-        "CREATE TABLE password_resets (
-        user_id int,
+        "CREATE TABLE IF NOT EXISTS password_resets (
+        user_id bigint references users(user_id) on delete cascade,
         reset_token varchar(255),
         password varchar(255), 
-        timestamp int,
-        valid_until int
+        timestamp bigint,
+        valid_until bigint
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table password_resets already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create password_resets table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create password_resets table: {}", err)),
     }
 }
 
-async fn create_email_update_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE email_updates")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_email_update_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "email_updates").await;
     }
 
     match query(
-        "CREATE TABLE email_updates (
-        user_id int,
+        "CREATE TABLE IF NOT EXISTS email_updates (
+        user_id bigint references users(user_id) on delete cascade,
         update_token varchar(255),
         email varchar(255), 
-        timestamp int,
-        valid_until int
+        timestamp bigint,
+        valid_until bigint
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table email_updates already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create email_updates table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create email_updates table: {}", err)),
     }
 }
 
-async fn create_character_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE characters").execute(&mut *db).await.ok();
-        }
+async fn create_character_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "characters").await;
     }
 
-    match query(
-        "CREATE TABLE characters (
-        character_id int,
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS characters (
+        character_id {},
         name varchar(255),
         short_desc varchar(255),
         full_desc varchar(255),
-        image varchar(255)
+        image varchar(255),
+        media_id bigint references media(media_id) on delete restrict
     )",
-    )
+        backend::ID
+    ))
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table characters already exists"
-            {
-                Ok(())
-            } else {
-                return Err(format!("Failed to create characters table: {}", err));
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create characters table: {}", err)),
     }
 }
 
-async fn create_dialogue_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE dialogues").execute(&mut *db).await.ok();
-        }
+async fn create_dialogue_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "dialogues").await;
     }
 
-    match query(
-        "CREATE TABLE dialogues (
-        dialogue_id int,
-        quest_id int,
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS dialogues (
+        dialogue_id {},
+        quest_id bigint references quests(quest_id) on delete set null,
         name varchar(255),
         is_skippable bool
     )",
-    )
+        backend::ID
+    ))
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table dialogues already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create dialogues table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create dialogues table: {}", err)),
     }
 }
 
-async fn create_dialogue_part_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE dialogue_parts")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_dialogue_part_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "dialogue_parts").await;
     }
 
     match query(
-        "CREATE TABLE dialogue_parts (
-        dialogue_id int,
-        part_id int,
-        character_id int,
-        text varchar(65536)
+        "CREATE TABLE IF NOT EXISTS dialogue_parts (
+        dialogue_id bigint references dialogues(dialogue_id) on delete cascade,
+        part_id bigint,
+        character_id bigint references characters(character_id) on delete restrict,
+        text text,
+        primary key (dialogue_id, part_id)
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table dialogue_parts already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create dialogue_parts table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create dialogue_parts table: {}", err)),
     }
 }
 
-async fn create_task_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE tasks").execute(&mut *db).await.ok();
-        }
+async fn create_task_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "tasks").await;
     }
 
-    match query(
-        "CREATE TABLE tasks (
-        task_id int,
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS tasks (
+        task_id {},
         type varchar(255),
         name varchar(255),
-        quest_id int,
-        desc varchar(65536),
-        min_radius real,
-        max_radius real,
-        location_to_duplicate int,
-        question varchar(65536),
-        answers varchar(65536),
-        choice_answers varchar(32),
-        text_answers varchar(65536)
+        quest_id bigint references quests(quest_id) on delete set null,
+        \"desc\" text,
+        payload text
     )",
-    )
+        backend::ID
+    ))
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table tasks already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create tasks table: {}", err))
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create tasks table: {}", err)),
+    }
+}
+
+// Legacy answers were stored one per line, each followed by a newline
+fn split_legacy_answers(answers: &str) -> Vec<String> {
+    if answers.is_empty() {
+        return vec![];
+    }
+    answers
+        .strip_suffix('\n')
+        .unwrap_or(answers)
+        .split('\n')
+        .map(|x| x.to_owned())
+        .collect()
+}
+
+fn legacy_task_kind(row: &DbRow) -> Result<TaskKind, String> {
+    let task_type: Option<String> = row.try_get("type").map_err(|err| err.to_string())?;
+    let min_radius: Option<f32> = row.try_get("min_radius").map_err(|err| err.to_string())?;
+    let max_radius: Option<f32> = row.try_get("max_radius").map_err(|err| err.to_string())?;
+    let location_to_duplicate: Option<i64> = row
+        .try_get("location_to_duplicate")
+        .map_err(|err| err.to_string())?;
+    let question: Option<String> = row.try_get("question").map_err(|err| err.to_string())?;
+    let answers: Option<String> = row.try_get("answers").map_err(|err| err.to_string())?;
+    let choice_answers: Option<String> = row
+        .try_get("choice_answers")
+        .map_err(|err| err.to_string())?;
+    let text_answers: Option<String> =
+        row.try_get("text_answers").map_err(|err| err.to_string())?;
+
+    match (task_type.as_deref(), question) {
+        (Some("location"), _) => match (min_radius, max_radius) {
+            (Some(min_radius), Some(max_radius)) => Ok(TaskKind::Location {
+                min_radius,
+                max_radius,
+                location_to_duplicate: location_to_duplicate.map(|x| x as u32),
+            }),
+            _ => Err("location task without a radius".to_owned()),
+        },
+        (Some("choice"), Some(question)) => match (answers, choice_answers) {
+            (Some(answers), Some(choice_answers)) => Ok(TaskKind::Choice {
+                question,
+                answers: split_legacy_answers(&answers),
+                correct_answers: choice_answers
+                    .trim()
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, x)| *x == '1')
+                    .map(|(i, _)| i as u32)
+                    .collect(),
+            }),
+            _ => Err("choice task without answers".to_owned()),
+        },
+        (Some("text"), Some(question)) => match text_answers {
+            Some(answers) => Ok(TaskKind::Text {
+                question,
+                answers: split_legacy_answers(&answers),
+            }),
+            None => Err("text task without answers".to_owned()),
+        },
+        (task_type, _) => Err(format!(
+            "{} task doesn't match any task shape",
+            task_type.unwrap_or("untyped")
+        )),
+    }
+}
+
+// Moves tasks from the old per-type columns into payload, all or nothing
+async fn migrate_legacy_tasks(db: &mut DbConnection) -> Result<(), String> {
+    let pending: i64 = match query("SELECT COUNT(*) FROM tasks WHERE payload IS NULL")
+        .fetch_one(&mut *db)
+        .await
+    {
+        Ok(row) => row.get(0),
+        Err(err) => return Err(format!("Failed to count legacy tasks: {}", err)),
+    };
+    if pending == 0 {
+        return Ok(());
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(err) => return Err(format!("Failed to start task migration: {}", err)),
+    };
+
+    let rows = match query(
+        "SELECT task_id, type, min_radius, max_radius, location_to_duplicate, question, answers, choice_answers, text_answers
+        FROM tasks WHERE payload IS NULL",
+    )
+    .fetch_all(&mut *tx)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(err) => return Err(format!("Failed to read legacy tasks: {}", err)),
+    };
+
+    let mut failed = vec![];
+    for row in rows.iter() {
+        let task_id: i64 = row.get("task_id");
+        let payload = match legacy_task_kind(row).and_then(|kind| kind.to_json()) {
+            Ok(val) => val,
+            Err(err) => {
+                failed.push(format!("task {}: {}", task_id, err));
+                continue;
             }
+        };
+        if let Err(err) =
+            query("UPDATE tasks SET payload = $1 WHERE task_id = $2 AND payload IS NULL")
+                .bind(payload)
+                .bind(task_id)
+                .execute(&mut *tx)
+                .await
+        {
+            return Err(format!("Failed to migrate task {}: {}", task_id, err));
         }
-        _ => Ok(()),
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "Failed to migrate legacy tasks, fix or delete them and restart: {}",
+            failed.join("; ")
+        ));
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to finish task migration: {}", err)),
     }
 }
 
-async fn create_quest_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE quests").execute(&mut *db).await.ok();
-        }
+async fn create_quest_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "quests").await;
     }
 
-    match query(
-        "CREATE TABLE quests (
-        quest_id int,
-        quest_name varchar(65536),
-        desc varchar(65536),
-        unlocks varchar(65536),
-        points int,
-        coins int,
-        rewards varchar(65536)
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS quests (
+        quest_id {},
+        quest_name text,
+        \"desc\" text,
+        unlocks text,
+        points bigint,
+        coins bigint,
+        rewards text
     )",
-    )
+        backend::ID
+    ))
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table quests already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create quests table: {}", err))
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create quests table: {}", err)),
+    }
+}
+
+// Legacy id lists were newline terminated numbers
+fn parse_legacy_ids(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Ok("[]".to_owned());
+    }
+    let ids = value
+        .strip_suffix('\n')
+        .unwrap_or(value)
+        .split('\n')
+        .map(|x| {
+            x.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("{:?} isn't an id", x))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    serde_json::to_string(&ids).map_err(|err| err.to_string())
+}
+
+// Rewrites newline joined unlocks and rewards as json arrays, all or nothing
+async fn migrate_legacy_quest_lists(db: &mut DbConnection) -> Result<(), String> {
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(err) => return Err(format!("Failed to start quest migration: {}", err)),
+    };
+
+    let rows = match query(
+        "SELECT quest_id, unlocks, rewards FROM quests
+        WHERE unlocks IS NULL OR rewards IS NULL OR unlocks NOT LIKE '[%' OR rewards NOT LIKE '[%'",
+    )
+    .fetch_all(&mut *tx)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(err) => return Err(format!("Failed to read legacy quests: {}", err)),
+    };
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut failed = vec![];
+    for row in rows.iter() {
+        let quest_id: i64 = row.get("quest_id");
+        let mut lists = vec![];
+        for column in ["unlocks", "rewards"] {
+            let value = match row.try_get::<Option<String>, _>(column) {
+                Ok(Some(val)) => val,
+                _ => {
+                    failed.push(format!("quest {} {}: missing", quest_id, column));
+                    continue;
+                }
+            };
+            if value.starts_with('[') {
+                lists.push(value);
+                continue;
+            }
+            match parse_legacy_ids(&value) {
+                Ok(val) => lists.push(val),
+                Err(err) => failed.push(format!("quest {} {}: {}", quest_id, column, err)),
             }
         }
-        _ => Ok(()),
+        if lists.len() != 2 {
+            continue;
+        }
+
+        if let Err(err) = query("UPDATE quests SET unlocks = $1, rewards = $2 WHERE quest_id = $3")
+            .bind(&lists[0])
+            .bind(&lists[1])
+            .bind(quest_id)
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(format!("Failed to migrate quest {}: {}", quest_id, err));
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "Failed to migrate legacy quests, fix or delete them and restart: {}",
+            failed.join("; ")
+        ));
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to finish quest migration: {}", err)),
     }
 }
 
-async fn create_quest_stage_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE quest_stages")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+async fn create_quest_stage_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "quest_stages").await;
     }
 
     match query(
-        "CREATE TABLE quest_stages (
-        quest_id int,
-        stage_id int,
-        task_id int,
-        dialogue_id int
+        "CREATE TABLE IF NOT EXISTS quest_stages (
+        quest_id bigint references quests(quest_id) on delete cascade,
+        stage_id bigint,
+        task_id bigint references tasks(task_id) on delete cascade,
+        dialogue_id bigint references dialogues(dialogue_id) on delete cascade,
+        primary key (quest_id, stage_id),
+        check ((task_id IS NULL) != (dialogue_id IS NULL))
     )",
     )
     .execute(db)
     .await
     {
-        Err(err) => {
-            if &format!("{}", err)
-                == "error returned from database: (code: 1) table quest_stages already exists"
-            {
-                Ok(())
-            } else {
-                Err(format!("Failed to create quest_stages table: {}", err))
-            }
-        }
-        _ => Ok(()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create quest_stages table: {}", err)),
     }
 }
 
-async fn create_game_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE game").execute(&mut *db).await.ok();
-        }
+async fn create_game_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "game").await;
     }
 
     if let Err(err) = query(
-        "CREATE TABLE game (
+        "CREATE TABLE IF NOT EXISTS game (
         paused bool,
         location_radius real,
-        tutorial_id int
+        tutorial_id bigint references quests(quest_id) on delete set null
     )",
     )
     .execute(db)
     .await
     {
-        if &format!("{}", err)
-            != "error returned from database: (code: 1) table game already exists"
-        {
-            return Err(format!("Failed to create quest_stages table: {}", err));
-        }
+        return Err(format!("Failed to create quest_stages table: {}", err));
+    }
+
+    Ok(())
+}
+
+async fn prepare_game_table(db: &mut DbConnection) -> Result<(), String> {
+    if let Err(err) = query(
+        "INSERT INTO game (paused, location_radius)
+        SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM game)",
+    )
+    .bind(false)
+    .bind(10.0)
+    .execute(db)
+    .await
+    {
+        return Err(format!("Failed to prepare game table: {}", err));
     };
+    Ok(())
+}
+
+async fn create_error_report_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "error_report").await;
+    }
+
+    if let Err(err) = query(&format!(
+        "CREATE TABLE IF NOT EXISTS error_report (
+        report_id {},
+        user_id bigint,
+        title text,
+        message text,
+        app_version varchar(255),
+        device_info text,
+        status varchar(255),
+        timestamp bigint,
+        updated bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        return Err(format!("Failed to create error_report table: {}", err));
+    }
 
     Ok(())
 }
 
-async fn prepare_game_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            if let Err(err) = query("INSERT INTO game (paused, location_radius) VALUES (?, ?)")
-                .bind(false)
-                .bind(10.0)
-                .execute(db)
-                .await
-            {
-                return Err(format!("Failed to prepare game table: {}", err));
-            };
-        }
+async fn create_suggestion_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "suggestion").await;
+    }
+
+    if let Err(err) = query(&format!(
+        "CREATE TABLE IF NOT EXISTS suggestion (
+        report_id {},
+        user_id bigint,
+        title text,
+        message text,
+        app_version varchar(255),
+        device_info text,
+        status varchar(255),
+        timestamp bigint,
+        updated bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        return Err(format!("Failed to create suggestion table: {}", err));
     }
 
     Ok(())
 }
 
-async fn create_error_report_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE error_report")
-                .execute(&mut *db)
-                .await
-                .ok();
-        }
+// Reports from before triage only had a title and a message. The generated report_id can't be
+// added to an existing table, so the table is recreated and the old reports come back as open,
+// without a reporter and dated to the migration
+async fn migrate_legacy_reports(db: &mut DbConnection, table: &str) -> Result<(), String> {
+    let columns = backend::table_columns(db, table).await?;
+    if columns.iter().any(|(name, _)| name == "report_id") {
+        return Ok(());
     }
 
-    if let Err(err) = query(
-        "CREATE TABLE error_report (
-        title varchar(65536),
-        message varchar(65536)
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+    let old = format!("{}_legacy", table);
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(err) => return Err(format!("Failed to start {} migration: {}", table, err)),
+    };
+
+    if let Err(err) = query(&format!("ALTER TABLE {} RENAME TO {}", table, old))
+        .execute(&mut *tx)
+        .await
+    {
+        return Err(format!("Failed to migrate {}: {}", table, err));
+    }
+    match table {
+        "error_report" => create_error_report_table(&mut tx, false).await?,
+        _ => create_suggestion_table(&mut tx, false).await?,
+    }
+    if let Err(err) = query(&format!(
+        "INSERT INTO {} (title, message, status, timestamp, updated)
+        SELECT title, message, $1, $2, $2 FROM {}",
+        table, old
+    ))
+    .bind(ReportStatus::Open.as_str())
+    .bind(timestamp)
+    .execute(&mut *tx)
+    .await
+    {
+        return Err(format!("Failed to migrate {}: {}", table, err));
+    }
+    if let Err(err) = query(&format!("DROP TABLE {}", old))
+        .execute(&mut *tx)
+        .await
+    {
+        return Err(format!("Failed to migrate {}: {}", table, err));
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to finish {} migration: {}", table, err)),
+    }
+}
+
+async fn create_ban_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "bans").await;
+    }
+
+    match query(
+        "CREATE TABLE IF NOT EXISTS bans (
+        user_id bigint,
+        admin_id bigint,
+        reason text,
+        timestamp bigint,
+        banned_until bigint
     )",
     )
     .execute(db)
     .await
     {
-        if &format!("{}", err)
-            != "error returned from database: (code: 1) table error_report already exists"
-        {
-            return Err(format!("Failed to create quest_stages table: {}", err));
-        }
-    };
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create bans table: {}", err)),
+    }
+}
 
-    Ok(())
+async fn create_audit_log_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "audit_log").await;
+    }
+
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+        audit_id {},
+        actor_id bigint,
+        action varchar(255),
+        entity_type varchar(255),
+        entity_id bigint,
+        before text,
+        after text,
+        ip varchar(255),
+        timestamp bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create audit_log table: {}", err)),
+    }
 }
 
-async fn create_suggestion_table(db: &mut SqliteConnection) -> Result<(), String> {
-    if let Ok(var) = env::var("WIEDZIELISCIE_BACKEND_RESET_DB") {
-        if var.to_lowercase() == "true" || var == "1" {
-            query("DROP TABLE suggestion").execute(&mut *db).await.ok();
-        }
+async fn create_report_comment_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "report_comments").await;
     }
 
-    if let Err(err) = query(
-        "CREATE TABLE suggestion (
-        title varchar(65536),
-        message varchar(65536)
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS report_comments (
+        comment_id {},
+        report_kind varchar(255),
+        report_id bigint,
+        admin_id bigint,
+        message text,
+        timestamp bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create report_comments table: {}", err)),
+    }
+}
+
+async fn create_totp_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "totp").await;
+    }
+
+    match query(
+        "CREATE TABLE IF NOT EXISTS totp (
+        user_id bigint primary key references users(user_id) on delete cascade,
+        secret varchar(255),
+        enabled bool,
+        last_step bigint,
+        timestamp bigint
     )",
     )
     .execute(db)
     .await
     {
-        if &format!("{}", err)
-            != "error returned from database: (code: 1) table suggestion already exists"
-        {
-            return Err(format!("Failed to create quest_stages table: {}", err));
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create totp table: {}", err)),
+    }
+}
+
+async fn create_recovery_code_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "recovery_codes").await;
+    }
+
+    match query(
+        "CREATE TABLE IF NOT EXISTS recovery_codes (
+        user_id bigint references users(user_id) on delete cascade,
+        code_hash varchar(255)
+    )",
+    )
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create recovery_codes table: {}", err)),
+    }
+}
+
+async fn create_login_challenge_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "login_challenges").await;
+    }
+
+    match query(
+        "CREATE TABLE IF NOT EXISTS login_challenges (
+        user_id bigint references users(user_id) on delete cascade,
+        challenge_token varchar(255),
+        valid_until bigint
+    )",
+    )
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create login_challenges table: {}", err)),
+    }
+}
+
+async fn create_announcement_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "announcements").await;
+    }
+
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS announcements (
+        announcement_id {},
+        admin_id bigint,
+        title varchar(255),
+        message text,
+        audience varchar(255),
+        quest_id bigint,
+        publish_at bigint,
+        expires_at bigint,
+        send_email bool,
+        published bool,
+        timestamp bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create announcements table: {}", err)),
+    }
+}
+
+// The primary key keeps marking as read idempotent and makes unread counts an index lookup
+async fn create_announcement_read_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "announcement_reads").await;
+    }
+
+    match query(
+        "CREATE TABLE IF NOT EXISTS announcement_reads (
+        user_id bigint references users(user_id) on delete cascade,
+        announcement_id bigint references announcements(announcement_id) on delete cascade,
+        timestamp bigint,
+        PRIMARY KEY (user_id, announcement_id)
+    )",
+    )
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to create announcement_reads table: {}",
+            err
+        )),
+    }
+}
+
+// Files live in the media directory under their hash, a repeated upload gets the same row
+async fn create_media_table(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    if reset {
+        drop_table(db, "media").await;
+    }
+
+    match query(&format!(
+        "CREATE TABLE IF NOT EXISTS media (
+        media_id {},
+        hash varchar(64),
+        format varchar(255),
+        width bigint,
+        height bigint,
+        size bigint,
+        admin_id bigint,
+        timestamp bigint
+    )",
+        backend::ID
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to create media table: {}", err)),
+    }
+}
+
+// (table, key columns)
+const KEYS: [(&str, &str); 9] = [
+    ("users", "user_id"),
+    ("deleted_users", "user_id"),
+    ("characters", "character_id"),
+    ("dialogues", "dialogue_id"),
+    ("tasks", "task_id"),
+    ("quests", "quest_id"),
+    ("totp", "user_id"),
+    ("dialogue_parts", "dialogue_id, part_id"),
+    ("quest_stages", "quest_id, stage_id"),
+];
+
+// (table, column, referenced table, referenced column)
+const REFERENCES: [(&str, &str, &str, &str); 19] = [
+    ("delete_requests", "user_id", "users", "user_id"),
+    ("verifications", "user_id", "users", "user_id"),
+    ("sessions", "user_id", "users", "user_id"),
+    ("password_resets", "user_id", "users", "user_id"),
+    ("email_updates", "user_id", "users", "user_id"),
+    ("totp", "user_id", "users", "user_id"),
+    ("recovery_codes", "user_id", "users", "user_id"),
+    ("login_challenges", "user_id", "users", "user_id"),
+    ("announcement_reads", "user_id", "users", "user_id"),
+    (
+        "announcement_reads",
+        "announcement_id",
+        "announcements",
+        "announcement_id",
+    ),
+    ("characters", "media_id", "media", "media_id"),
+    ("dialogues", "quest_id", "quests", "quest_id"),
+    ("dialogue_parts", "dialogue_id", "dialogues", "dialogue_id"),
+    (
+        "dialogue_parts",
+        "character_id",
+        "characters",
+        "character_id",
+    ),
+    ("tasks", "quest_id", "quests", "quest_id"),
+    ("quest_stages", "quest_id", "quests", "quest_id"),
+    ("quest_stages", "task_id", "tasks", "task_id"),
+    ("quest_stages", "dialogue_id", "dialogues", "dialogue_id"),
+    ("game", "tutorial_id", "quests", "quest_id"),
+];
+
+// (name, table, columns, unique), covering the lookups done by user and admin queries
+const INDICES: [(&str, &str, &str, bool); 24] = [
+    ("users_email", "users", "lower(email)", true),
+    ("users_names", "users", "first_name, last_name", false),
+    ("sessions_token", "sessions", "session_token", true),
+    ("sessions_user", "sessions", "user_id", false),
+    (
+        "verifications_token",
+        "verifications",
+        "verification_token",
+        true,
+    ),
+    ("verifications_user", "verifications", "user_id", false),
+    (
+        "password_resets_token",
+        "password_resets",
+        "reset_token",
+        true,
+    ),
+    ("password_resets_user", "password_resets", "user_id", false),
+    ("email_updates_token", "email_updates", "update_token", true),
+    ("email_updates_user", "email_updates", "user_id", false),
+    (
+        "delete_requests_token",
+        "delete_requests",
+        "delete_token",
+        true,
+    ),
+    ("delete_requests_user", "delete_requests", "user_id", false),
+    (
+        "login_challenges_token",
+        "login_challenges",
+        "challenge_token",
+        true,
+    ),
+    (
+        "login_challenges_user",
+        "login_challenges",
+        "user_id",
+        false,
+    ),
+    ("recovery_codes_user", "recovery_codes", "user_id", false),
+    ("bans_user", "bans", "user_id", false),
+    ("error_report_user", "error_report", "user_id", false),
+    ("suggestion_user", "suggestion", "user_id", false),
+    ("media_hash", "media", "hash", true),
+    ("dialogues_quest", "dialogues", "quest_id", false),
+    (
+        "dialogue_parts_character",
+        "dialogue_parts",
+        "character_id",
+        false,
+    ),
+    ("tasks_quest", "tasks", "quest_id", false),
+    ("quest_stages_task", "quest_stages", "task_id", false),
+    (
+        "quest_stages_dialogue",
+        "quest_stages",
+        "dialogue_id",
+        false,
+    ),
+];
+
+async fn fetch_texts(db: &mut DbConnection, sql: &str) -> Result<Vec<String>, String> {
+    match query(sql).fetch_all(db).await {
+        Ok(rows) => Ok(rows
+            .iter()
+            .map(|x| x.get::<Option<String>, _>(0).unwrap_or("null".to_owned()))
+            .collect()),
+        Err(err) => Err(format!("Failed to check the schema: {}", err)),
+    }
+}
+
+fn as_text(columns: &str) -> String {
+    columns
+        .split(", ")
+        .map(|x| format!("COALESCE(CAST({} AS TEXT), 'null')", x))
+        .collect::<Vec<String>>()
+        .join(" || '/' || ")
+}
+
+// Lists rows that would break the keys, references and unique indices, empty when it's safe to migrate
+pub async fn schema_violations(db: &mut DbConnection) -> Result<Vec<String>, String> {
+    let mut violations = vec![];
+    let mut report = |problem: String, values: Vec<String>| {
+        if !values.is_empty() {
+            violations.push(format!("{}: {}", problem, values.join(", ")));
         }
     };
 
+    for (table, columns) in KEYS {
+        let values = fetch_texts(
+            db,
+            &format!(
+                "SELECT {} FROM {} GROUP BY {} HAVING COUNT(*) > 1",
+                as_text(columns),
+                table,
+                columns
+            ),
+        )
+        .await?;
+        report(format!("duplicate {} ({})", table, columns), values);
+    }
+
+    for (_, table, columns, unique) in INDICES {
+        if !unique {
+            continue;
+        }
+        let values = fetch_texts(
+            db,
+            &format!(
+                "SELECT {0} FROM {1} WHERE {0} IS NOT NULL GROUP BY {0} HAVING COUNT(*) > 1",
+                columns, table
+            ),
+        )
+        .await?;
+        report(format!("duplicate {} ({})", table, columns), values);
+    }
+
+    for (table, column, parent, parent_column) in REFERENCES {
+        let values = fetch_texts(
+            db,
+            &format!(
+                "SELECT DISTINCT CAST({1} AS TEXT) FROM {0} WHERE {1} IS NOT NULL
+                AND {1} NOT IN (SELECT {3} FROM {2} WHERE {3} IS NOT NULL)",
+                table, column, parent, parent_column
+            ),
+        )
+        .await?;
+        report(
+            format!("{}.{} points at missing {}", table, column, parent),
+            values,
+        );
+    }
+
+    let values = fetch_texts(
+        db,
+        &format!(
+            "SELECT {} FROM quest_stages WHERE (task_id IS NULL) = (dialogue_id IS NULL)",
+            as_text("quest_id, stage_id")
+        ),
+    )
+    .await?;
+    report(
+        "quest_stages (quest_id, stage_id) without exactly one of task_id and dialogue_id"
+            .to_owned(),
+        values,
+    );
+
+    Ok(violations)
+}
+
+pub async fn create_indices(db: &mut DbConnection) -> Result<(), String> {
+    for (name, table, columns, unique) in INDICES {
+        if let Err(err) = query(&format!(
+            "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+            if unique { "UNIQUE " } else { "" },
+            name,
+            table,
+            columns
+        ))
+        .execute(&mut *db)
+        .await
+        {
+            return Err(format!("Failed to create index {}: {}", name, err));
+        }
+    }
     Ok(())
 }
 
-pub async fn create_tables(mut db: PoolConnection<Sqlite>) {
-    create_user_table(&mut db).await.unwrap();
-    create_verification_table(&mut db).await.unwrap();
-    create_session_table(&mut db).await.unwrap();
-    create_password_reser_table(&mut db).await.unwrap();
-    create_email_update_table(&mut db).await.unwrap();
-    create_character_table(&mut db).await.unwrap();
-    create_dialogue_table(&mut db).await.unwrap();
-    create_dialogue_part_table(&mut db).await.unwrap();
-    create_task_table(&mut db).await.unwrap();
-    create_quest_table(&mut db).await.unwrap();
-    create_quest_stage_table(&mut db).await.unwrap();
-    create_game_table(&mut db).await.unwrap();
-    prepare_game_table(&mut db).await.unwrap();
-    create_deleted_user_table(&mut db).await.unwrap();
-    create_delete_request_table(&mut db).await.unwrap();
-    create_error_report_table(&mut db).await.unwrap();
-    create_suggestion_table(&mut db).await.unwrap();
+// References are left alone while tables are dropped, recreated and rebuilt
+pub async fn create_tables(mut db: PoolConnection<Backend>, reset: bool) -> Result<(), String> {
+    backend::set_foreign_keys(&mut db, false).await?;
+    let result = create_all_tables(&mut db, reset).await;
+    backend::set_foreign_keys(&mut db, true).await?;
+    result
+}
+
+// Tables are created after the ones they reference
+async fn create_all_tables(db: &mut DbConnection, reset: bool) -> Result<(), String> {
+    create_user_table(db, reset).await?;
+    create_verification_table(db, reset).await?;
+    create_session_table(db, reset).await?;
+    create_password_reser_table(db, reset).await?;
+    create_email_update_table(db, reset).await?;
+    create_media_table(db, reset).await?;
+    create_character_table(db, reset).await?;
+    add_missing_column(db, "characters", "media_id", "bigint").await?;
+    create_quest_table(db, reset).await?;
+    migrate_legacy_quest_lists(db).await?;
+    create_dialogue_table(db, reset).await?;
+    create_dialogue_part_table(db, reset).await?;
+    create_task_table(db, reset).await?;
+    add_missing_column(db, "tasks", "payload", "text").await?;
+    migrate_legacy_tasks(db).await?;
+    create_quest_stage_table(db, reset).await?;
+    create_game_table(db, reset).await?;
+    prepare_game_table(db).await?;
+    create_deleted_user_table(db, reset).await?;
+    add_missing_column(db, "deleted_users", "deleted_at", "bigint").await?;
+    add_missing_column(db, "deleted_users", "anonymised", "bool").await?;
+    create_delete_request_table(db, reset).await?;
+    create_error_report_table(db, reset).await?;
+    migrate_legacy_reports(db, "error_report").await?;
+    create_suggestion_table(db, reset).await?;
+    migrate_legacy_reports(db, "suggestion").await?;
+    create_report_comment_table(db, reset).await?;
+    create_ban_table(db, reset).await?;
+    create_audit_log_table(db, reset).await?;
+    create_totp_table(db, reset).await?;
+    create_recovery_code_table(db, reset).await?;
+    create_login_challenge_table(db, reset).await?;
+    create_announcement_table(db, reset).await?;
+    create_announcement_read_table(db, reset).await?;
+    backend::add_constraints(db).await?;
+    create_indices(db).await?;
+    backend::reserve_deleted_user_ids(db).await?;
+
+    Ok(())
 }
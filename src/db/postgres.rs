@@ -0,0 +1,86 @@
+use sqlx::{query, Row};
+
+use super::{DbConnection, DbPool};
+
+pub type Backend = sqlx::Postgres;
+
+// Explicitly inserted ids don't move the sequence, sync_ids and reserve_deleted_user_ids do
+pub const ID: &str = "bigint generated by default as identity primary key";
+pub const USER_ID: &str = ID;
+pub const DROP_CASCADE: &str = " CASCADE";
+
+pub async fn connect(url: &str) -> Result<DbPool, String> {
+    match DbPool::connect(url).await {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to open the database: {}", err)),
+    }
+}
+
+pub async fn table_columns(
+    db: &mut DbConnection,
+    table: &str,
+) -> Result<Vec<(String, String)>, String> {
+    match query(
+        "SELECT column_name::text, data_type::text FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = $1
+        ORDER BY ordinal_position",
+    )
+    .bind(table)
+    .fetch_all(db)
+    .await
+    {
+        Ok(rows) => Ok(rows
+            .iter()
+            .map(|x| (x.get::<String, _>(0), x.get::<String, _>(1)))
+            .collect()),
+        Err(err) => Err(format!("Failed to get columns of {}: {}", table, err)),
+    }
+}
+
+pub fn json_text(column: &str, key: &str) -> String {
+    format!("({}::json ->> '{}')", column, key)
+}
+
+// Continues the id sequence after the largest id, e.g. once imported rows brought their own
+pub async fn sync_ids(db: &mut DbConnection, table: &str, id_column: &str) -> Result<(), String> {
+    match query(&format!(
+        "SELECT setval(pg_get_serial_sequence('{0}', '{1}'), MAX({1})) FROM {0}
+        HAVING MAX({1}) IS NOT NULL",
+        table, id_column
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to update the ids of {}: {}", table, err)),
+    }
+}
+
+// Tables are dropped with cascade and created with their references in place
+pub async fn set_foreign_keys(_db: &mut DbConnection, _enabled: bool) -> Result<(), String> {
+    Ok(())
+}
+
+// Deleted accounts keep their id so they can be restored, new accounts have to start above them
+pub async fn reserve_deleted_user_ids(db: &mut DbConnection) -> Result<(), String> {
+    match query(
+        "SELECT setval(pg_get_serial_sequence('users', 'user_id'), reserved) FROM (
+            SELECT GREATEST(
+                (SELECT COALESCE(MAX(user_id), 0) FROM deleted_users),
+                (SELECT COALESCE(MAX(user_id), 0) FROM users),
+                COALESCE(pg_sequence_last_value(pg_get_serial_sequence('users', 'user_id')::regclass), 0)
+            ) AS reserved
+        ) AS ids WHERE reserved > 0",
+    )
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to reserve deleted user ids: {}", err)),
+    }
+}
+
+// Postgres support came after the keys, its tables are always created with them
+pub async fn add_constraints(_db: &mut DbConnection) -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use sqlx::{query, sqlite::SqliteConnectOptions, Connection, Row};
+
+use super::{add_missing_column, create_indices, schema_violations, DbConnection, DbPool};
+
+pub type Backend = sqlx::Sqlite;
+
+// Generated ids are rowid aliases, users never reuse the id of a deleted row
+pub const ID: &str = "integer primary key";
+pub const USER_ID: &str = "integer primary key autoincrement";
+pub const DROP_CASCADE: &str = "";
+
+// The admin cli creates the database file like the server does
+pub async fn connect(url: &str) -> Result<DbPool, String> {
+    let options = match SqliteConnectOptions::from_str(url) {
+        Ok(val) => val.create_if_missing(true),
+        Err(err) => return Err(format!("Invalid database url: {}", err)),
+    };
+
+    match DbPool::connect_with(options).await {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to open the database: {}", err)),
+    }
+}
+
+pub async fn table_columns(
+    db: &mut DbConnection,
+    table: &str,
+) -> Result<Vec<(String, String)>, String> {
+    match query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(db)
+        .await
+    {
+        Ok(rows) => Ok(rows
+            .iter()
+            .map(|x| (x.get::<String, _>("name"), x.get::<String, _>("type")))
+            .collect()),
+        Err(err) => Err(format!("Failed to get columns of {}: {}", table, err)),
+    }
+}
+
+pub fn json_text(column: &str, key: &str) -> String {
+    format!("json_extract({}, '$.{}')", column, key)
+}
+
+// Rowids already continue after the largest imported id
+pub async fn sync_ids(
+    _db: &mut DbConnection,
+    _table: &str,
+    _id_column: &str,
+) -> Result<(), String> {
+    Ok(())
+}
+
+pub async fn set_foreign_keys(db: &mut DbConnection, enabled: bool) -> Result<(), String> {
+    match query(&format!(
+        "PRAGMA foreign_keys = {}",
+        if enabled { "ON" } else { "OFF" }
+    ))
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to set foreign keys: {}", err)),
+    }
+}
+
+// Deleted accounts keep their id so they can be restored, new accounts have to start above them
+pub async fn reserve_deleted_user_ids(db: &mut DbConnection) -> Result<(), String> {
+    for statement in [
+        "INSERT INTO sqlite_sequence (name, seq) SELECT 'users', 0
+        WHERE NOT EXISTS (SELECT 1 FROM sqlite_sequence WHERE name = 'users')",
+        "UPDATE sqlite_sequence
+        SET seq = MAX(seq, (SELECT COALESCE(MAX(user_id), 0) FROM deleted_users))
+        WHERE name = 'users'",
+    ] {
+        if let Err(err) = query(statement).execute(&mut *db).await {
+            return Err(format!("Failed to reserve deleted user ids: {}", err));
+        }
+    }
+    Ok(())
+}
+
+// Bumped whenever existing tables have to be rebuilt to pick up new keys and constraints
+const SCHEMA_VERSION: i64 = 1;
+
+// Tables whose definition gained keys, references or checks, rebuilt once on older databases
+const CONSTRAINED_TABLES: [&str; 18] = [
+    "users",
+    "deleted_users",
+    "delete_requests",
+    "verifications",
+    "sessions",
+    "password_resets",
+    "email_updates",
+    "characters",
+    "dialogues",
+    "dialogue_parts",
+    "tasks",
+    "quests",
+    "quest_stages",
+    "game",
+    "totp",
+    "recovery_codes",
+    "login_challenges",
+    "announcement_reads",
+];
+
+async fn create_table(db: &mut DbConnection, table: &str) -> Result<(), String> {
+    match table {
+        "users" => super::create_user_table(db, false).await,
+        "deleted_users" => super::create_deleted_user_table(db, false).await,
+        "delete_requests" => super::create_delete_request_table(db, false).await,
+        "verifications" => super::create_verification_table(db, false).await,
+        "sessions" => super::create_session_table(db, false).await,
+        "password_resets" => super::create_password_reser_table(db, false).await,
+        "email_updates" => super::create_email_update_table(db, false).await,
+        "characters" => super::create_character_table(db, false).await,
+        "dialogues" => super::create_dialogue_table(db, false).await,
+        "dialogue_parts" => super::create_dialogue_part_table(db, false).await,
+        "tasks" => super::create_task_table(db, false).await,
+        "quests" => super::create_quest_table(db, false).await,
+        "quest_stages" => super::create_quest_stage_table(db, false).await,
+        "game" => super::create_game_table(db, false).await,
+        "totp" => super::create_totp_table(db, false).await,
+        "recovery_codes" => super::create_recovery_code_table(db, false).await,
+        "login_challenges" => super::create_login_challenge_table(db, false).await,
+        "announcement_reads" => super::create_announcement_read_table(db, false).await,
+        _ => Err(format!("Unknown table {}", table)),
+    }
+}
+
+// Renames the old table away, creates the current definition and copies every column over
+async fn rebuild_table(db: &mut DbConnection, table: &str) -> Result<(), String> {
+    let old = format!("{}_old", table);
+    if let Err(err) = query(&format!("ALTER TABLE {} RENAME TO {}", table, old))
+        .execute(&mut *db)
+        .await
+    {
+        return Err(format!("Failed to rebuild {}: {}", table, err));
+    }
+    create_table(db, table).await?;
+
+    let columns = table_columns(db, &old).await?;
+    // Columns the current definition dropped, like the legacy task ones, are kept
+    for (name, kind) in columns.iter() {
+        add_missing_column(db, table, name, kind).await?;
+    }
+
+    let names = columns
+        .iter()
+        .map(|(name, _)| format!("\"{}\"", name))
+        .collect::<Vec<String>>()
+        .join(", ");
+    for statement in [
+        format!(
+            "INSERT INTO {} ({1}) SELECT {1} FROM {2}",
+            table, names, old
+        ),
+        format!("DROP TABLE {}", old),
+    ] {
+        if let Err(err) = query(&statement).execute(&mut *db).await {
+            return Err(format!("Failed to rebuild {}: {}", table, err));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn add_constraints(db: &mut DbConnection) -> Result<(), String> {
+    let version: i64 = match query("PRAGMA user_version").fetch_one(&mut *db).await {
+        Ok(row) => row.get(0),
+        Err(err) => return Err(format!("Failed to get the schema version: {}", err)),
+    };
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let violations = schema_violations(db).await?;
+    if !violations.is_empty() {
+        return Err(format!(
+            "Can't add keys and constraints, fix or delete these rows and restart:\n{}",
+            violations.join("\n")
+        ));
+    }
+
+    // Keeps references in other tables from following the renamed old tables
+    if let Err(err) = query("PRAGMA legacy_alter_table = ON")
+        .execute(&mut *db)
+        .await
+    {
+        return Err(format!("Failed to prepare the schema migration: {}", err));
+    }
+    let result = rebuild_tables(db).await;
+    query("PRAGMA legacy_alter_table = OFF")
+        .execute(&mut *db)
+        .await
+        .ok();
+    result
+}
+
+async fn rebuild_tables(db: &mut DbConnection) -> Result<(), String> {
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(err) => return Err(format!("Failed to start the schema migration: {}", err)),
+    };
+
+    for table in CONSTRAINED_TABLES {
+        rebuild_table(&mut tx, table).await?;
+    }
+
+    match query("PRAGMA foreign_key_check").fetch_all(&mut *tx).await {
+        Ok(rows) if rows.is_empty() => (),
+        Ok(rows) => {
+            return Err(format!(
+                "Schema migration left {} rows with missing references",
+                rows.len()
+            ))
+        }
+        Err(err) => return Err(format!("Failed to check references: {}", err)),
+    }
+
+    // A failed index leaves the tables as they were so the next start checks them again
+    create_indices(&mut tx).await?;
+
+    if let Err(err) = query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .execute(&mut *tx)
+        .await
+    {
+        return Err(format!("Failed to set the schema version: {}", err));
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to finish the schema migration: {}", err)),
+    }
+}
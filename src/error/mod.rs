@@ -1,69 +1,266 @@
-use rocket::serde::Serialize;
-use sqlx::{prelude::FromRow, query, query_as, SqliteConnection};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::{prelude::FromRow, query, query_as, Row};
+
+use crate::{
+    db::{DbConnection, NullableId},
+    util::{fetch_page, Condition, Page, PageQuery},
+};
 
 pub mod report;
+pub mod triage;
 
-pub async fn add_error(
-    db: &mut SqliteConnection,
-    title: &str,
-    message: &str,
-) -> Result<(), String> {
-    match query(
-        "INSERT INTO 
-                error_report 
-                (title, message)
-                VALUES (?,?)",
-    )
-    .bind(title)
-    .bind(message)
-    .execute(db)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ReportKind {
+    Error,
+    Suggestion,
+}
+
+impl ReportKind {
+    pub fn table(&self) -> &'static str {
+        match self {
+            ReportKind::Error => "error_report",
+            ReportKind::Suggestion => "suggestion",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportKind::Error => "error",
+            ReportKind::Suggestion => "suggestion",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    Acknowledged,
+    Resolved,
+    WontFix,
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Acknowledged => "acknowledged",
+            ReportStatus::Resolved => "resolved",
+            ReportStatus::WontFix => "wont_fix",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Acknowledged => "acknowledged",
+            ReportStatus::Resolved => "resolved",
+            ReportStatus::WontFix => "won't fix",
+        }
+    }
+}
+
+pub struct NewReport<'r> {
+    pub user_id: u32,
+    pub title: &'r str,
+    pub message: &'r str,
+    pub app_version: Option<&'r str>,
+    pub device_info: Option<&'r str>,
+}
+
+pub async fn add_report(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    report: &NewReport<'_>,
+) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query(&format!(
+        "INSERT INTO
+                {}
+                (user_id, title, message, app_version, device_info, status, timestamp, updated)
+                VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+        RETURNING report_id",
+        kind.table()
+    ))
+    .bind(i64::from(report.user_id))
+    .bind(report.title)
+    .bind(report.message)
+    .bind(report.app_version)
+    .bind(report.device_info)
+    .bind(ReportStatus::Open.as_str())
+    .bind(timestamp)
+    .bind(timestamp)
+    .fetch_one(db)
     .await
     {
-        Ok(_) => Ok(()),
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
         Err(err) => Err(format!(
-            "Failed to insert error report into the database: {}",
+            "Failed to insert {} report into the database: {}",
+            kind.as_str(),
             err
         )),
     }
 }
 
-pub async fn add_suggestion(
-    db: &mut SqliteConnection,
-    title: &str,
-    message: &str,
-) -> Result<(), String> {
-    match query(
-        "INSERT INTO 
-                suggestion 
-                (title, message)
-                VALUES (?,?)",
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorReport {
+    #[sqlx(try_from = "i64")]
+    pub report_id: u32,
+    #[sqlx(try_from = "NullableId")]
+    pub user_id: Option<u32>,
+    pub title: String,
+    pub message: String,
+    pub app_version: Option<String>,
+    pub device_info: Option<String>,
+    pub status: String,
+    pub timestamp: i64,
+    pub updated: i64,
+}
+
+// Newest first unless the page asks otherwise
+pub async fn get_reports(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    status: Option<ReportStatus>,
+    page: &PageQuery<'_>,
+) -> Result<Page<ErrorReport>, String> {
+    let mut conditions = vec![];
+    if let Some(status) = status {
+        conditions.push(Condition::Matches("status", status.as_str()));
+    }
+
+    fetch_page(
+        db,
+        kind.table(),
+        "report_id",
+        &["title", "message"],
+        &conditions,
+        page,
     )
-    .bind(title)
-    .bind(message)
+    .await?
+    .try_map(|row| {
+        ErrorReport::from_row(row).map_err(|err| format!("Failed to read report: {}", err))
+    })
+}
+
+pub async fn get_report_by_id(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    report_id: u32,
+) -> Result<ErrorReport, String> {
+    match query_as::<_, ErrorReport>(&format!(
+        "SELECT * FROM {} WHERE report_id = $1",
+        kind.table()
+    ))
+    .bind(i64::from(report_id))
+    .fetch_one(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(sqlx::Error::RowNotFound) => Err(format!("{} report not found", kind.as_str())),
+        Err(err) => Err(format!("Failed to get {} report: {}", kind.as_str(), err)),
+    }
+}
+
+pub async fn set_report_status(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    report_id: u32,
+    status: ReportStatus,
+) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query(&format!(
+        "UPDATE {} SET status = $1, updated = $2 WHERE report_id = $3",
+        kind.table()
+    ))
+    .bind(status.as_str())
+    .bind(timestamp)
+    .bind(i64::from(report_id))
     .execute(db)
     .await
     {
         Ok(_) => Ok(()),
         Err(err) => Err(format!(
-            "Failed to insert suggestion into the database: {}",
+            "Failed to set {} report status: {}",
+            kind.as_str(),
             err
         )),
     }
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
-pub struct ErrorReport {
-    title: String,
-    message: String,
+pub struct ReportComment {
+    #[sqlx(try_from = "i64")]
+    pub comment_id: u32,
+    #[sqlx(try_from = "i64")]
+    pub admin_id: u32,
+    pub message: String,
+    pub timestamp: i64,
+}
+
+pub async fn add_report_comment(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    report_id: u32,
+    admin_id: u32,
+    message: &str,
+) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query(
+        "INSERT INTO
+                report_comments
+                (report_kind, report_id, admin_id, message, timestamp)
+                VALUES ($1,$2,$3,$4,$5)
+        RETURNING comment_id",
+    )
+    .bind(kind.as_str())
+    .bind(i64::from(report_id))
+    .bind(i64::from(admin_id))
+    .bind(message)
+    .bind(timestamp)
+    .fetch_one(db)
+    .await
+    {
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to add report comment: {}", err)),
+    }
 }
 
-pub async fn get_reports(db: &mut SqliteConnection) -> Result<Vec<ErrorReport>, String> {
-    match query_as::<_, ErrorReport>("SELECT * FROM error_report")
-        .fetch_all(db)
-        .await
+pub async fn get_report_comments(
+    db: &mut DbConnection,
+    kind: ReportKind,
+    report_id: u32,
+) -> Result<Vec<ReportComment>, String> {
+    match query_as::<_, ReportComment>(
+        "SELECT comment_id, admin_id, message, timestamp
+        FROM report_comments
+        WHERE report_kind = $1 AND report_id = $2
+        ORDER BY comment_id",
+    )
+    .bind(kind.as_str())
+    .bind(i64::from(report_id))
+    .fetch_all(db)
+    .await
     {
         Ok(val) => Ok(val),
-        Err(err) => return Err(err.to_string()),
+        Err(err) => Err(format!("Failed to get report comments: {}", err)),
     }
 }
@@ -4,93 +4,149 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
 use crate::{
-    util::{check_authorized_admin, check_authorized_user, is_paused},
+    config::AppConfig,
+    util::{check_authorized_admin, check_authorized_user, get_token_user_id, Order, PageQuery},
     DB,
 };
 
-use super::{add_error, add_suggestion, get_reports};
+use super::{add_report, get_reports, NewReport, ReportKind, ReportStatus};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GetReportsData<'r> {
     jwt: &'r str,
+    status: Option<ReportStatus>,
+    cursor: Option<u32>,
+    limit: Option<u32>,
+    order: Option<Order>,
+    search: Option<&'r str>,
 }
 
 #[post("/admin/get_reports", format = "json", data = "<data>")]
 pub async fn admin_get_reports(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<GetReportsData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    if is_paused(&mut db).await {
-        return (Status::Unauthorized, json!({"error": "Game paused"}))
-    }
+    let page = PageQuery::new(
+        data.cursor,
+        data.limit,
+        data.order,
+        Order::Desc,
+        data.search,
+    );
 
-    let reports = match get_reports(&mut db).await {
+    let reports = match get_reports(&mut db, ReportKind::Error, data.status, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::BadRequest, json!({"error": err})),
     };
 
-    (Status::Ok, json!(reports))
+    (
+        Status::Ok,
+        json!({
+            "reports": reports.items,
+            "total": reports.total,
+            "next_cursor": reports.next_cursor
+        }),
+    )
 }
 
-#[post("/admin/get_logs", format = "json", data = "<data>")]
-pub async fn admin_get_logs(
+#[post("/admin/get_suggestions", format = "json", data = "<data>")]
+pub async fn admin_get_suggestions(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<GetReportsData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_admin(&mut db, data.jwt).await {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
         return err;
     }
 
-    let reports = match get_reports(&mut db).await {
+    let page = PageQuery::new(
+        data.cursor,
+        data.limit,
+        data.order,
+        Order::Desc,
+        data.search,
+    );
+
+    let suggestions = match get_reports(&mut db, ReportKind::Suggestion, data.status, &page).await {
         Ok(val) => val,
         Err(err) => return (Status::BadRequest, json!({"error": err})),
     };
 
-    (Status::Ok, json!(reports))
+    (
+        Status::Ok,
+        json!({
+            "suggestions": suggestions.items,
+            "total": suggestions.total,
+            "next_cursor": suggestions.next_cursor
+        }),
+    )
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct ReportData<'r> {
     jwt: &'r str,
     title: &'r str,
     message: &'r str,
+    app_version: Option<&'r str>,
+    device_info: Option<&'r str>,
 }
 
-#[post("/report/error", format = "json", data = "<data>")]
-pub async fn report_error(mut db: Connection<DB>, data: Json<ReportData<'_>>) -> (Status, Value) {
-    if let Some(err) = check_authorized_user(&mut db, data.jwt).await {
+async fn report(
+    db: &mut Connection<DB>,
+    config: &AppConfig,
+    kind: ReportKind,
+    data: &ReportData<'_>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(db, config, data.jwt).await {
         return err;
     }
 
-    if let Err(err) = add_error(&mut db, data.title, data.message).await {
-        return (Status::InternalServerError, json!({"error": err}));
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let report = NewReport {
+        user_id,
+        title: data.title,
+        message: data.message,
+        app_version: data.app_version,
+        device_info: data.device_info,
+    };
+
+    match add_report(db, kind, &report).await {
+        Ok(report_id) => (Status::Ok, json!({"report_id": report_id})),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
     }
+}
 
-    (Status::Ok, json!({}))
+#[post("/report/error", format = "json", data = "<data>")]
+pub async fn report_error(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<ReportData<'_>>,
+) -> (Status, Value) {
+    report(&mut db, config, ReportKind::Error, &data).await
 }
 
 #[post("/report/suggestion", format = "json", data = "<data>")]
 pub async fn report_suggestion(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<ReportData<'_>>,
 ) -> (Status, Value) {
-    if let Some(err) = check_authorized_user(&mut db, data.jwt).await {
-        return err;
-    }
-
-    if let Err(err) = add_suggestion(&mut db, data.title, data.message).await {
-        return (Status::InternalServerError, json!({"error": err}));
-    }
-
-    (Status::Ok, json!({}))
+    report(&mut db, config, ReportKind::Suggestion, &data).await
 }
@@ -0,0 +1,251 @@
+use std::net::IpAddr;
+
+use resend_rs::{types::CreateEmailBaseOptions, Resend};
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    logging,
+    user::get_user_by_id,
+    util::{check_authorized_admin, escape_html, get_token_user_id},
+    DB,
+};
+
+use super::{
+    add_report_comment, get_report_by_id, get_report_comments, set_report_status, ReportKind,
+    ReportStatus,
+};
+
+async fn send_report_status_email(
+    config: &AppConfig,
+    email: &str,
+    title: &str,
+    status: ReportStatus,
+    comment: Option<&str>,
+) -> Result<(), String> {
+    if config.mail.disabled {
+        return Ok(());
+    }
+
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
+
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
+    };
+    let subject = "Your report has been updated";
+
+    let comment = match comment {
+        Some(val) => format!("<p>{}</p>", escape_html(val)),
+        None => String::new(),
+    };
+
+    let email = CreateEmailBaseOptions::new(from, [email], subject).with_html(&format!(
+        "
+            <p>The status of your report \"{}\" has been changed to: {}</p>
+            {}
+        ",
+        escape_html(title),
+        status.description(),
+        comment
+    ));
+
+    if let Err(err) = resend.emails.send(email).await {
+        Err(format!("Failed to send email: {}", err))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ReportGetData<'r> {
+    jwt: &'r str,
+    kind: ReportKind,
+    report_id: u32,
+}
+
+#[post("/admin/reports/get", format = "json", data = "<data>")]
+pub async fn admin_reports_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<ReportGetData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let report = match get_report_by_id(&mut db, data.kind, data.report_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    let comments = match get_report_comments(&mut db, data.kind, data.report_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    (Status::Ok, json!({"report": report, "comments": comments}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ReportSetStatusData<'r> {
+    jwt: &'r str,
+    kind: ReportKind,
+    report_id: u32,
+    status: ReportStatus,
+    comment: Option<&'r str>,
+}
+
+#[post("/admin/reports/set_status", format = "json", data = "<data>")]
+pub async fn admin_reports_set_status(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<ReportSetStatusData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let report = match get_report_by_id(&mut db, data.kind, data.report_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::NotFound, json!({"error": err})),
+    };
+
+    if report.status == data.status.as_str() {
+        return (
+            Status::BadRequest,
+            json!({"error": "report already has this status"}),
+        );
+    }
+
+    if let Err(err) = set_report_status(&mut db, data.kind, data.report_id, data.status).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Some(comment) = data.comment {
+        if let Err(err) =
+            add_report_comment(&mut db, data.kind, data.report_id, admin_id, comment).await
+        {
+            return (Status::InternalServerError, json!({"error": err}));
+        }
+    }
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "set_status",
+        data.kind.table(),
+        Some(data.report_id),
+        Some(&json!({"status": report.status})),
+        Some(&json!({"status": data.status.as_str()})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    // The reporter might have deleted their account since, anonymised reports have no user_id
+    let user = match report.user_id {
+        Some(user_id) => get_user_by_id(&mut db, user_id).await.ok(),
+        None => None,
+    };
+
+    // The status is already changed, a failed notification doesn't undo it
+    if let Some(user) = user {
+        if let Err(err) = send_report_status_email(
+            config,
+            &user.email,
+            &report.title,
+            data.status,
+            data.comment,
+        )
+        .await
+        {
+            logging::warn(&format!(
+                "Report {} status email failed: {}",
+                data.report_id, err
+            ));
+        }
+    }
+
+    (Status::Ok, json!({}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ReportCommentData<'r> {
+    jwt: &'r str,
+    kind: ReportKind,
+    report_id: u32,
+    message: &'r str,
+}
+
+#[post("/admin/reports/comment", format = "json", data = "<data>")]
+pub async fn admin_reports_comment(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<ReportCommentData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    if let Err(err) = get_report_by_id(&mut db, data.kind, data.report_id).await {
+        return (Status::NotFound, json!({"error": err}));
+    }
+
+    let comment_id = match add_report_comment(
+        &mut db,
+        data.kind,
+        data.report_id,
+        admin_id,
+        data.message,
+    )
+    .await
+    {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "comment",
+        data.kind.table(),
+        Some(data.report_id),
+        None,
+        Some(&json!({"comment_id": comment_id, "message": data.message})),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({"comment_id": comment_id}))
+}
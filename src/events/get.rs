@@ -0,0 +1,116 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::{
+    http::Status,
+    response::stream::{Event, EventStream},
+    serde::json::{json, Value},
+    tokio::{
+        select,
+        sync::broadcast::error::RecvError,
+        time::{interval_at, sleep_until, Instant},
+    },
+    Shutdown, State,
+};
+use rocket_db_pools::Connection;
+
+use crate::{
+    config::AppConfig,
+    db::{DbConnection, DbPool},
+    user::{get_active_ban, get_session_by_token, get_user_by_id, jwt::verify_token},
+    util::check_authorized_user,
+    DB,
+};
+
+use super::EventBus;
+
+// How often an open stream checks that its session still exists and the user isn't banned
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Whether the user is an admin, None once the stream should end
+async fn stream_user_admin(db: &mut DbConnection, token: &str, user_id: u32) -> Option<bool> {
+    get_session_by_token(db, token).await.ok()?;
+
+    match get_active_ban(db, user_id).await {
+        Ok(None) => get_user_by_id(db, user_id).await.ok().map(|x| x.admin),
+        _ => None,
+    }
+}
+
+async fn check_stream_user(pool: &DbPool, token: &str, user_id: u32) -> Option<bool> {
+    match pool.acquire().await {
+        Ok(mut db) => stream_user_admin(&mut db, token, user_id).await,
+        Err(_) => None,
+    }
+}
+
+// EventSource can't set headers, so the jwt comes in the query. The stream ends with the session,
+// also when it's ended early by a logout, ban, password reset or deletion
+#[get("/events?<jwt>")]
+pub async fn events(
+    mut db: Connection<DB>,
+    database: &State<DB>,
+    config: &State<AppConfig>,
+    bus: &State<EventBus>,
+    jwt: &str,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], (Status, Value)> {
+    if let Some(err) = check_authorized_user(&mut db, config, jwt).await {
+        return Err(err);
+    }
+
+    let claims = match verify_token(&config.secret, jwt) {
+        Ok(val) => val.claims,
+        Err(_) => return Err((Status::BadRequest, json!({"error": "invalid token"}))),
+    };
+
+    let session = match get_session_by_token(&mut db, &claims.token).await {
+        Ok(val) => val,
+        Err(err) => return Err((Status::BadRequest, json!({"error": err}))),
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+    let expires_at = Instant::now() + Duration::from_secs((session.valid_until - timestamp) as u64);
+
+    let user_id = claims.uid;
+    let token = claims.token;
+    let mut admin = match stream_user_admin(&mut db, &token, user_id).await {
+        Some(val) => val,
+        None => return Err((Status::BadRequest, json!({"error": "invalid session"}))),
+    };
+    let pool = database.0.clone();
+    let mut events = bus.subscribe();
+    let mut session_checks = interval_at(
+        Instant::now() + SESSION_CHECK_INTERVAL,
+        SESSION_CHECK_INTERVAL,
+    );
+
+    Ok(EventStream! {
+        loop {
+            let event = select! {
+                event = events.recv() => event,
+                _ = session_checks.tick() => {
+                    match check_stream_user(&pool, &token, user_id).await {
+                        Some(val) => admin = val,
+                        None => break,
+                    }
+                    continue;
+                }
+                _ = sleep_until(expires_at) => break,
+                _ = &mut shutdown => break,
+            };
+
+            match event {
+                Ok(event) if event.is_visible_to(user_id, admin) => {
+                    yield Event::json(&event).event(event.name());
+                }
+                Ok(_) => (),
+                // Missed events can't be replayed, the client has to fetch the state again
+                Err(RecvError::Lagged(_)) => yield Event::empty().event("resync"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
@@ -0,0 +1,92 @@
+use rocket::{
+    serde::Serialize,
+    tokio::sync::broadcast::{self, Receiver, Sender},
+};
+use schemars::JsonSchema;
+
+use crate::announcements::Audience;
+
+pub mod get;
+
+// Subscribers that fall further behind than this get a resync event instead of the missed ones
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    GamePaused,
+    GameUnpaused,
+    LocationRadiusChanged {
+        radius: f32,
+    },
+    QuestAvailable {
+        quest_id: u32,
+    },
+    AnnouncementPublished {
+        announcement_id: u32,
+        audience: Audience,
+    },
+    // Player progress isn't stored yet, these are for the code that will change it
+    ProgressChanged {
+        user_id: u32,
+        quest_id: u32,
+    },
+    PointsChanged {
+        user_id: u32,
+        points: u32,
+    },
+    InventoryChanged {
+        user_id: u32,
+    },
+}
+
+impl GameEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameEvent::GamePaused => "game_paused",
+            GameEvent::GameUnpaused => "game_unpaused",
+            GameEvent::LocationRadiusChanged { .. } => "location_radius_changed",
+            GameEvent::QuestAvailable { .. } => "quest_available",
+            GameEvent::AnnouncementPublished { .. } => "announcement_published",
+            GameEvent::ProgressChanged { .. } => "progress_changed",
+            GameEvent::PointsChanged { .. } => "points_changed",
+            GameEvent::InventoryChanged { .. } => "inventory_changed",
+        }
+    }
+
+    // Events about one player only go to that player's streams, announcements only to their
+    // audience the same way the inbox shows them
+    pub fn is_visible_to(&self, user_id: u32, admin: bool) -> bool {
+        match self {
+            GameEvent::ProgressChanged { user_id: x, .. }
+            | GameEvent::PointsChanged { user_id: x, .. }
+            | GameEvent::InventoryChanged { user_id: x } => *x == user_id,
+            GameEvent::AnnouncementPublished { audience, .. } => match audience {
+                Audience::All => true,
+                Audience::Admins => admin,
+                Audience::QuestCompleted => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus(Sender<GameEvent>);
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus(broadcast::channel(CHANNEL_CAPACITY).0)
+    }
+}
+
+impl EventBus {
+    // Nobody listening isn't an error, the event is just dropped
+    pub fn publish(&self, event: GameEvent) {
+        self.0.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> Receiver<GameEvent> {
+        self.0.subscribe()
+    }
+}
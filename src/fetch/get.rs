@@ -6,10 +6,11 @@ use rocket::{
     },
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::DB;
+use crate::{db::DbConnection, DB};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct GetCharacterData {
     character_id: u32,
@@ -20,7 +21,17 @@ pub async fn get_character(
     mut db: Connection<DB>,
     data: Json<GetCharacterData>,
 ) -> (Status, Value) {
-    let character = match super::get_character(&mut db, data.character_id).await {
+    character(&mut db, data.character_id).await
+}
+
+// Replaces get_character in /api/v1
+#[get("/characters/<character_id>")]
+pub async fn characters_get(mut db: Connection<DB>, character_id: u32) -> (Status, Value) {
+    character(&mut db, character_id).await
+}
+
+async fn character(db: &mut DbConnection, character_id: u32) -> (Status, Value) {
+    let character = match super::get_character(db, character_id).await {
         Ok(val) => val,
         Err(err) => return (Status::BadRequest, json!({"error": err})),
     };
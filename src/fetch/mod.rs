@@ -1,13 +1,14 @@
 use rocket::serde::Serialize;
-use sqlx::{prelude::FromRow, query_as, SqliteConnection};
+use schemars::JsonSchema;
+use sqlx::{prelude::FromRow, query_as};
 
 pub mod get;
 
-use crate::admin::Character;
+use crate::{admin::Character, db::DbConnection};
 
-pub async fn get_character(db: &mut SqliteConnection, id: u32) -> Result<Character, String> {
-    match query_as::<_, Character>("SELECT * FROM characters WHERE character_id = ?")
-        .bind(id)
+pub async fn get_character(db: &mut DbConnection, id: u32) -> Result<Character, String> {
+    match query_as::<_, Character>("SELECT * FROM characters WHERE character_id = $1")
+        .bind(i64::from(id))
         .fetch_one(db)
         .await
     {
@@ -16,13 +17,13 @@ pub async fn get_character(db: &mut SqliteConnection, id: u32) -> Result<Charact
     }
 }
 
-#[derive(Debug, Serialize, FromRow)]
+#[derive(Debug, Serialize, FromRow, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct PauseState {
     paused: bool,
 }
 
-pub async fn get_pause(db: &mut SqliteConnection) -> Result<PauseState, String> {
+pub async fn get_pause(db: &mut DbConnection) -> Result<PauseState, String> {
     match query_as::<_, PauseState>("SELECT paused FROM game")
         .fetch_one(db)
         .await
@@ -32,13 +33,13 @@ pub async fn get_pause(db: &mut SqliteConnection) -> Result<PauseState, String>
     }
 }
 
-#[derive(Debug, Serialize, FromRow)]
+#[derive(Debug, Serialize, FromRow, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct LocationRadius {
     radius: f32,
 }
 
-pub async fn get_location_radius(db: &mut SqliteConnection) -> Result<LocationRadius, String> {
+pub async fn get_location_radius(db: &mut DbConnection) -> Result<LocationRadius, String> {
     match query_as::<_, LocationRadius>("SELECT location_radius FROM game")
         .fetch_one(db)
         .await
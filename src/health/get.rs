@@ -0,0 +1,50 @@
+use rocket::{
+    http::Status,
+    serde::json::{json, Value},
+    State,
+};
+use rocket_db_pools::Connection;
+
+use crate::{config::AppConfig, DB};
+
+use super::{check_database, check_game_row, check_jwt_secret, check_mailer, Check};
+
+// Sending mail is best effort, so a mailer outage is reported here instead of failing /ready
+#[get("/health")]
+pub async fn health(config: &State<AppConfig>) -> (Status, Value) {
+    (
+        Status::Ok,
+        json!({"status": "ok", "checks": [check_mailer(config).await]}),
+    )
+}
+
+#[get("/ready")]
+pub async fn ready(db: Option<Connection<DB>>, config: &State<AppConfig>) -> (Status, Value) {
+    let mut checks = Vec::new();
+
+    match db {
+        Some(mut db) => {
+            checks.push(check_database(&mut db).await);
+            checks.push(check_game_row(&mut db).await);
+        }
+        None => checks.push(Check {
+            name: "database",
+            ok: false,
+            error: Some("Failed to get a connection from the pool".to_owned()),
+            details: None,
+        }),
+    }
+
+    checks.push(check_jwt_secret(config));
+
+    let problems: Vec<&Check> = checks.iter().filter(|x| !x.ok).collect();
+
+    if problems.is_empty() {
+        (Status::Ok, json!({"status": "ready", "checks": checks}))
+    } else {
+        (
+            Status::ServiceUnavailable,
+            json!({"status": "not_ready", "problems": problems, "checks": checks}),
+        )
+    }
+}
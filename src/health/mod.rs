@@ -0,0 +1,108 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rocket::serde::{json::Value, Serialize};
+use schemars::JsonSchema;
+use sqlx::{query, Row};
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{config::AppConfig, db::DbConnection, user::jwt::check_secret};
+
+pub mod get;
+
+const MAILER_HOST: &str = "api.resend.com:443";
+const MAILER_TIMEOUT: Duration = Duration::from_secs(3);
+const MAILER_CHECK_LIFETIME: Duration = Duration::from_secs(60);
+
+// Probes don't need auth, so they reuse the last connection attempt instead of opening a new one each time
+static MAILER_CHECK: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::new(None);
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl Check {
+    fn from_result(name: &'static str, result: Result<(), String>) -> Check {
+        Check {
+            name,
+            ok: result.is_ok(),
+            error: result.err(),
+            details: None,
+        }
+    }
+}
+
+pub async fn check_database(db: &mut DbConnection) -> Check {
+    let result = match query("SELECT 1").fetch_one(db).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Database doesn't answer: {}", err)),
+    };
+
+    Check::from_result("database", result)
+}
+
+pub async fn check_game_row(db: &mut DbConnection) -> Check {
+    let result = match query("SELECT COUNT(*) FROM game").fetch_one(db).await {
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(0) => Err("Game row is missing".to_owned()),
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Failed to read game row count: {}", err)),
+        },
+        Err(err) => Err(format!("Failed to query the game table: {}", err)),
+    };
+
+    Check::from_result("game", result)
+}
+
+pub fn check_jwt_secret(config: &AppConfig) -> Check {
+    Check::from_result("secret", check_secret(&config.secret))
+}
+
+pub async fn check_mailer(config: &AppConfig) -> Check {
+    if config.mail.disabled {
+        let mut check = Check::from_result("mailer", Ok(()));
+        check.details = Some(rocket::serde::json::json!({"disabled": true}));
+        return check;
+    }
+
+    if config.mail.from.is_none() {
+        return Check::from_result("mailer", Err("From mail not found".to_owned()));
+    }
+
+    if config.mail.resend_api_key.is_none() {
+        return Check::from_result("mailer", Err("Resend api key not found".to_owned()));
+    }
+
+    Check::from_result("mailer", mailer_reachable().await)
+}
+
+async fn mailer_reachable() -> Result<(), String> {
+    if let Ok(cached) = MAILER_CHECK.lock() {
+        if let Some((checked_at, result)) = cached.as_ref() {
+            if checked_at.elapsed() < MAILER_CHECK_LIFETIME {
+                return result.clone();
+            }
+        }
+    }
+
+    let result = match timeout(MAILER_TIMEOUT, TcpStream::connect(MAILER_HOST)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(format!("Mailer is unreachable: {}", err)),
+        Err(_) => Err("Mailer connection timed out".to_owned()),
+    };
+
+    if let Ok(mut cached) = MAILER_CHECK.lock() {
+        *cached = Some((Instant::now(), result.clone()));
+    }
+
+    result
+}
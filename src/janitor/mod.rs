@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use tokio::time::interval;
+
+use crate::{
+    config::JanitorConfig,
+    db::{DbConnection, DbPool},
+    logging,
+    user::{purge_expired, PurgeCounts},
+};
+
+pub mod run;
+
+pub async fn run_janitor(
+    db: &mut DbConnection,
+    config: &JanitorConfig,
+) -> Result<PurgeCounts, String> {
+    let counts = purge_expired(
+        db,
+        config.unverified_account_age,
+        config.deleted_account_retention,
+    )
+    .await?;
+
+    let total = counts.unverified_users
+        + counts.verifications
+        + counts.sessions
+        + counts.password_resets
+        + counts.email_updates
+        + counts.delete_requests
+        + counts.bans
+        + counts.login_challenges
+        + counts.anonymised_users;
+
+    let message = format!(
+        "Janitor removed {} unverified users, {} verifications, {} sessions, {} password resets, {} email updates, {} delete requests, {} bans and {} login challenges, and anonymised {} deleted users",
+        counts.unverified_users,
+        counts.verifications,
+        counts.sessions,
+        counts.password_resets,
+        counts.email_updates,
+        counts.delete_requests,
+        counts.bans,
+        counts.login_challenges,
+        counts.anonymised_users
+    );
+
+    if total > 0 {
+        logging::info(&message);
+    } else {
+        logging::debug(&message);
+    }
+
+    Ok(counts)
+}
+
+// Runs until the runtime shuts down, the first run happens right after liftoff
+pub async fn run_periodically(pool: DbPool, config: JanitorConfig) {
+    let mut interval = interval(Duration::from_secs(config.interval));
+
+    loop {
+        interval.tick().await;
+
+        let mut db = match pool.acquire().await {
+            Ok(val) => val,
+            Err(err) => {
+                logging::error(&format!("Janitor failed to get a connection: {}", err));
+                continue;
+            }
+        };
+
+        if let Err(err) = run_janitor(&mut db, &config).await {
+            logging::error(&format!("Janitor run failed: {}", err));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    util::{check_authorized_admin, get_token_user_id},
+    DB,
+};
+
+use super::run_janitor;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct JanitorRunData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/admin/janitor/run", format = "json", data = "<data>")]
+pub async fn admin_janitor_run(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<JanitorRunData<'_>>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let counts = match run_janitor(&mut db, &config.janitor).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "run",
+        "janitor",
+        None,
+        None,
+        Some(&json!(counts)),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!(counts))
+}
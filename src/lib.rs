@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate rocket;
+
+pub mod admin;
+pub mod announcements;
+pub mod api;
+pub mod audit;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod events;
+pub mod fetch;
+pub mod health;
+pub mod janitor;
+pub mod logging;
+pub mod media;
+pub mod metrics;
+pub mod openapi;
+pub mod ratelimit;
+pub mod totp;
+pub mod user;
+pub mod util;
+
+use rocket_db_pools::Database;
+
+#[derive(Database)]
+#[database("db")]
+pub struct DB(pub db::DbPool);
@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Data, Request, Response,
+};
+use uuid::Uuid;
+
+use crate::{config::AppConfig, util::get_token_user_id};
+
+use super::{log, Level, LogEntry};
+
+const PEEK_SIZE: usize = 512;
+
+struct RequestInfo {
+    request_id: String,
+    start: Instant,
+    user_id: Option<u32>,
+}
+
+// Finds the "jwt" field in the beginning of a json body without parsing all of it,
+// since only the peeked part of the body is available before the route runs
+fn find_jwt(body: &[u8]) -> Option<&str> {
+    let body = std::str::from_utf8(body).ok()?;
+    let rest = &body[body.find("\"jwt\"")? + 5..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    Some(&rest[..rest.find('"')?])
+}
+
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        let config = req.rocket().state::<AppConfig>();
+        let jwt = find_jwt(data.peek(PEEK_SIZE).await);
+        let user_id = match (config, jwt) {
+            (Some(config), Some(jwt)) => get_token_user_id(config, jwt),
+            _ => None,
+        };
+
+        req.local_cache(|| RequestInfo {
+            request_id: Uuid::new_v4().to_string(),
+            start: Instant::now(),
+            user_id,
+        });
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let info = req.local_cache(|| RequestInfo {
+            request_id: Uuid::new_v4().to_string(),
+            start: Instant::now(),
+            user_id: None,
+        });
+
+        let status = res.status();
+        let level = match status.code {
+            500.. => Level::Error,
+            400..=499 => Level::Warn,
+            _ => Level::Info,
+        };
+
+        let route = match req.route() {
+            Some(route) => route.uri.to_string(),
+            None => req.uri().path().to_string(),
+        };
+
+        let mut entry = LogEntry::new(level, &format!("{} {} {}", req.method(), route, status));
+        entry.request_id = Some(info.request_id.clone());
+        entry.method = Some(req.method().to_string());
+        entry.route = Some(route);
+        entry.status = Some(status.code);
+        entry.user_id = info.user_id;
+        entry.latency_ms = Some(info.start.elapsed().as_millis() as u64);
+        log(entry);
+
+        res.set_header(Header::new("X-Request-Id", info.request_id.clone()));
+    }
+}
@@ -0,0 +1,47 @@
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{config::AppConfig, util::check_authorized_admin, DB};
+
+use super::{get_logs, Level, LogFilter};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct GetLogsData<'r> {
+    jwt: &'r str,
+    level: Option<Level>,
+    since: Option<i64>,
+    until: Option<i64>,
+    route: Option<&'r str>,
+    limit: Option<usize>,
+}
+
+#[post("/admin/get_logs", format = "json", data = "<data>")]
+pub async fn admin_get_logs(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<GetLogsData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let filter = LogFilter {
+        level: data.level,
+        since: data.since,
+        until: data.until,
+        route: data.route,
+    };
+
+    let logs = get_logs(&filter, data.limit.unwrap_or(100).clamp(1, 1000));
+
+    (Status::Ok, json!(logs))
+}
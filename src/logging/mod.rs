@@ -0,0 +1,236 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::serde::{json::serde_json, Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use crate::config::LogConfig;
+
+pub mod fairing;
+pub mod get;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct LogEntry {
+    pub timestamp: i64,
+    pub level: Level,
+    pub message: String,
+    pub request_id: Option<String>,
+    pub method: Option<String>,
+    pub route: Option<String>,
+    pub status: Option<u16>,
+    pub user_id: Option<u32>,
+    pub latency_ms: Option<u64>,
+}
+
+impl LogEntry {
+    pub fn new(level: Level, message: &str) -> LogEntry {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time")
+            .as_secs() as i64;
+
+        LogEntry {
+            timestamp,
+            level,
+            message: message.to_owned(),
+            request_id: None,
+            method: None,
+            route: None,
+            status: None,
+            user_id: None,
+            latency_ms: None,
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> RotatingFile {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok();
+        let size = fs::metadata(&path).map(|x| x.len()).unwrap_or(0);
+
+        RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        }
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+
+        for index in (1..self.max_files).rev() {
+            fs::rename(self.rotated_path(index), self.rotated_path(index + 1)).ok();
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, self.rotated_path(1)).ok();
+        } else {
+            fs::remove_file(&self.path).ok();
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .ok();
+        self.size = 0;
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 + 1 > self.max_size && self.size > 0 {
+            self.rotate();
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            if writeln!(file, "{}", line).is_ok() {
+                self.size += line.len() as u64 + 1;
+            }
+        }
+    }
+}
+
+struct Logger {
+    min_level: Level,
+    capacity: usize,
+    buffer: Mutex<VecDeque<LogEntry>>,
+    file: Mutex<RotatingFile>,
+}
+
+impl Logger {
+    fn new(config: &LogConfig) -> Logger {
+        Logger {
+            min_level: config.level,
+            capacity: config.buffer_size,
+            buffer: Mutex::new(VecDeque::with_capacity(config.buffer_size)),
+            file: Mutex::new(RotatingFile::open(
+                config.file.clone().into(),
+                config.max_size,
+                config.max_files,
+            )),
+        }
+    }
+
+    fn log(&self, entry: LogEntry) {
+        if entry.level < self.min_level {
+            return;
+        }
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            if let Ok(mut file) = self.file.lock() {
+                file.write_line(&line);
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Ok(mut buffer) = self.buffer.lock() {
+            while buffer.len() >= self.capacity {
+                buffer.pop_front();
+            }
+            buffer.push_back(entry);
+        }
+    }
+}
+
+pub fn init(config: &LogConfig) {
+    LOGGER.set(Logger::new(config)).ok();
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger::new(&LogConfig::default()))
+}
+
+pub fn log(entry: LogEntry) {
+    logger().log(entry);
+}
+
+pub fn debug(message: &str) {
+    log(LogEntry::new(Level::Debug, message));
+}
+
+pub fn info(message: &str) {
+    log(LogEntry::new(Level::Info, message));
+}
+
+pub fn warn(message: &str) {
+    log(LogEntry::new(Level::Warn, message));
+}
+
+pub fn error(message: &str) {
+    log(LogEntry::new(Level::Error, message));
+}
+
+#[derive(Debug, Default)]
+pub struct LogFilter<'r> {
+    pub level: Option<Level>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub route: Option<&'r str>,
+}
+
+pub fn get_logs(filter: &LogFilter<'_>, limit: usize) -> Vec<LogEntry> {
+    let buffer = match logger().buffer.lock() {
+        Ok(val) => val,
+        Err(_) => return Vec::new(),
+    };
+
+    buffer
+        .iter()
+        .rev()
+        .filter(|x| filter.level.is_none_or(|level| x.level >= level))
+        .filter(|x| filter.since.is_none_or(|since| x.timestamp >= since))
+        .filter(|x| filter.until.is_none_or(|until| x.timestamp <= until))
+        .filter(|x| {
+            filter
+                .route
+                .is_none_or(|route| x.route.as_deref() == Some(route))
+        })
+        .take(limit)
+        .cloned()
+        .collect()
+}
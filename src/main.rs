@@ -1,88 +1,177 @@
-use db::create_tables;
 use rocket::fairing::AdHoc;
 use rocket_db_pools::{Database, Pool};
+use wiedzieliscie_backend::{
+    admin, announcements, api, audit, config, config::AppConfig, db::create_tables, error, events, fetch, health, janitor,
+    logging, media, metrics, openapi, ratelimit, totp, user, DB,
+};
 
 #[macro_use]
 extern crate rocket;
 
-pub mod admin;
-pub mod db;
-pub mod error;
-pub mod fetch;
-pub mod user;
-pub mod util;
-
-#[derive(Database)]
-#[database("db")]
-pub struct DB(sqlx::SqlitePool);
-
 #[launch]
 fn rocket() -> _ {
-    util::load_env().unwrap();
     let db = DB::init();
-    rocket::build()
+    // Mounted as deprecated aliases at / and under /api/v1, see src/api/mod.rs
+    let handlers = routes![
+        user::register::auth_register,
+        user::register::auth_resend_verification,
+        user::register::auth_verify,
+        user::login::auth_login,
+        totp::login::auth_login_totp,
+        user::reset::auth_password_reset,
+        user::reset::auth_password_reset_verify,
+        user::logout::auth_logout,
+        user::retrieve::auth_retrieve_user,
+        user::verifyless_updates::user_modify_first_name,
+        user::verifyless_updates::user_modify_last_name,
+        user::verifyless_updates::user_modify_gender,
+        user::verifyless_updates::user_modify_password,
+        user::update_email::user_modify_email,
+        user::update_email::user_modify_email_verify,
+        user::delete_user::delete_user,
+        user::delete_user::auth_delete_user_verify,
+        user::export::user_export,
+        totp::enroll::user_totp_enroll,
+        totp::enroll::user_totp_confirm,
+        totp::enroll::user_totp_disable,
+        totp::enroll::user_totp_recovery_codes,
+        totp::enroll::user_totp_status,
+        admin::character::admin_characters_add,
+        admin::character::admin_characters_delete,
+        admin::character::admin_characters_get,
+        admin::dialogue::admin_dialogues_add,
+        admin::dialogue::admin_dialogues_delete,
+        admin::dialogue::admin_dialogues_get,
+        admin::dialogue::admin_dialogues_get_unused,
+        admin::task::admin_tasks_location_add,
+        admin::task::admin_tasks_multiple_choice_add,
+        admin::task::admin_tasks_text_answer_add,
+        admin::task::admin_tasks_get,
+        admin::task::admin_tasks_get_unused,
+        admin::quest::admin_quests_add,
+        admin::quest::admin_quests_delete,
+        admin::quest::admin_quests_get,
+        admin::quest::admin_quests_duplicate,
+        admin::quest::admin_quests_stages_add,
+        admin::quest::admin_quests_stages_delete,
+        admin::quest::admin_quests_stages_get,
+        admin::quest::admin_quests_stages_move_back,
+        admin::quest::admin_quests_stages_move_forward,
+        admin::game::admin_game_pause,
+        admin::game::admin_game_unpause,
+        admin::game::admin_game_set_location_radius,
+        admin::game::admin_quests_select_tutorial,
+        admin::user::admin_users_get,
+        admin::user::admin_users_promote,
+        admin::user::admin_users_demote,
+        admin::user::admin_users_ban,
+        admin::user::admin_users_unban,
+        admin::user::admin_users_impersonate,
+        admin::user::admin_users_export,
+        admin::user::admin_users_deleted_get,
+        admin::user::admin_users_restore,
+        audit::get::admin_audit_get,
+        error::report::report_error,
+        error::report::report_suggestion,
+        error::report::admin_get_reports,
+        error::report::admin_get_suggestions,
+        error::triage::admin_reports_get,
+        error::triage::admin_reports_set_status,
+        error::triage::admin_reports_comment,
+        logging::get::admin_get_logs,
+        janitor::run::admin_janitor_run,
+        fetch::get::get_location_radius,
+        fetch::get::get_pause_state,
+        fetch::get::get_character,
+        user::retrieve::user_retrieve_email,
+        user::retrieve::user_retrieve_id,
+        user::retrieve::user_retrieve_name,
+        user::retrieve::user_retrieve_count,
+    ];
+
+    rocket::custom(config::figment())
+        .attach(config::fairing())
         .attach(db)
-        .attach(AdHoc::on_liftoff("Startup Check", |rocket| {
+        .attach(logging::fairing::RequestLogger)
+        .attach(metrics::fairing::MetricsCollector)
+        .attach(ratelimit::fairing::RetryAfterHeader)
+        .attach(api::fairing::DeprecationHeaders)
+        .manage(ratelimit::RateLimiter::default())
+        .manage(events::EventBus::default())
+        // A database that can't be created or migrated aborts the launch
+        .attach(AdHoc::try_on_ignite("Database Setup", |rocket| async move {
+            let reset_db = match rocket.state::<AppConfig>() {
+                Some(config) => config.reset_db,
+                None => return Err(rocket),
+            };
+            let result = match DB::fetch(&rocket) {
+                Some(DB(db)) => match db.get().await {
+                    Ok(connection) => create_tables(connection, reset_db).await,
+                    Err(err) => Err(format!("Failed to get a database connection: {}", err)),
+                },
+                None => Err("Failed to init the database".to_owned()),
+            };
+
+            match result {
+                Ok(_) => Ok(rocket),
+                Err(err) => {
+                    error!("Database setup failed: {}", err);
+                    logging::error(&format!("Database setup failed: {}", err));
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_liftoff("Startup", |rocket| {
             Box::pin(async move {
                 let DB(db) = DB::fetch(rocket).expect("Failed to init the database");
-                let connection = db.get().await.expect("Failed to init the database");
-                create_tables(connection).await;
+                let config = rocket
+                    .state::<AppConfig>()
+                    .expect("Failed to load the config");
+                let events = rocket
+                    .state::<events::EventBus>()
+                    .expect("Failed to get the event bus");
+
+                logging::info("Server started");
+                rocket::tokio::spawn(janitor::run_periodically(
+                    db.clone(),
+                    config.janitor.clone(),
+                ));
+                rocket::tokio::spawn(announcements::run_periodically(
+                    db.clone(),
+                    config.clone(),
+                    events.clone(),
+                ));
             })
         }))
+        .mount("/", api::legacy_routes(&handlers))
+        .mount(api::V1_BASE, api::v1_routes(&handlers))
+        .mount(
+            api::V1_BASE,
+            routes![
+                fetch::get::characters_get,
+                user::retrieve::users_get,
+                events::get::events,
+                announcements::admin::admin_announcements_add,
+                announcements::admin::admin_announcements_get,
+                announcements::admin::admin_announcements_expire,
+                announcements::inbox::user_inbox_get,
+                announcements::inbox::user_inbox_unread,
+                announcements::inbox::user_inbox_read,
+                media::admin::admin_media_upload,
+                media::admin::admin_media_get,
+                media::get::media_get,
+                media::get::media_thumbnail_get,
+            ],
+        )
         .mount(
             "/",
             routes![
-                user::register::auth_register,
-                user::register::auth_resend_verification,
-                user::register::auth_verify,
-                user::login::auth_login,
-                user::reset::auth_password_reset,
-                user::reset::auth_password_reset_verify,
-                user::logout::auth_logout,
-                user::retrieve::auth_retrieve_user,
-                user::verifyless_updates::user_modify_first_name,
-                user::verifyless_updates::user_modify_last_name,
-                user::verifyless_updates::user_modify_gender,
-                user::verifyless_updates::user_modify_password,
-                user::update_email::user_modify_email,
-                user::update_email::user_modify_email_verify,
-                user::delete_user::delete_user,
-                admin::character::admin_characters_add,
-                admin::character::admin_characters_delete,
-                admin::character::admin_characters_get,
-                admin::dialogue::admin_dialogues_add,
-                admin::dialogue::admin_dialogues_delete,
-                admin::dialogue::admin_dialogues_get,
-                admin::dialogue::admin_dialogues_get_unused,
-                admin::task::admin_tasks_location_add,
-                admin::task::admin_tasks_multiple_choice_add,
-                admin::task::admin_tasks_text_answer_add,
-                admin::task::admin_tasks_get,
-                admin::task::admin_tasks_get_unused,
-                admin::quest::admin_quests_add,
-                admin::quest::admin_quests_delete,
-                admin::quest::admin_quests_get,
-                admin::quest::admin_quests_duplicate,
-                admin::quest::admin_quests_stages_add,
-                admin::quest::admin_quests_stages_delete,
-                admin::quest::admin_quests_stages_get,
-                admin::quest::admin_quests_stages_move_back,
-                admin::quest::admin_quests_stages_move_forward,
-                admin::game::admin_game_pause,
-                admin::game::admin_game_unpause,
-                admin::game::admin_game_set_location_radius,
-                admin::game::admin_quests_select_tutorial,
-                error::report::report_error,
-                error::report::report_suggestion,
-                error::report::admin_get_reports,
-                error::report::admin_get_logs,
-                fetch::get::get_location_radius,
-                fetch::get::get_pause_state,
-                fetch::get::get_character,
-                user::retrieve::user_retrieve_email,
-                user::retrieve::user_retrieve_id,
-                user::retrieve::user_retrieve_name,
-                user::retrieve::user_retrieve_count,
+                metrics::get::metrics,
+                health::get::health,
+                health::get::ready,
+                openapi::get::openapi_json,
+                openapi::get::openapi_docs,
+                openapi::get::openapi_docs_asset,
             ],
         )
 }
@@ -0,0 +1,142 @@
+use std::net::IpAddr;
+
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    tokio::task::spawn_blocking,
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    audit::add_audit_entry,
+    config::AppConfig,
+    metrics::get::BearerToken,
+    util::{check_authorized_admin, get_token_user_id},
+    DB,
+};
+
+use super::{add_media, get_all_media, get_media_by_hash, prepare_image, store_image};
+
+// The body is the image itself, so the jwt comes in the Authorization header. Not in the
+// query, where request logs and proxies would record it
+#[post("/admin/media", data = "<data>")]
+pub async fn admin_media_upload(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    token: BearerToken<'_>,
+    data: Data<'_>,
+    ip: Option<IpAddr>,
+) -> (Status, Value) {
+    let jwt = match token.0 {
+        Some(val) => val,
+        None => {
+            return (
+                Status::Unauthorized,
+                json!({"error": "missing bearer token"}),
+            )
+        }
+    };
+
+    if let Some(err) = check_authorized_admin(&mut db, config, jwt).await {
+        return err;
+    }
+
+    let admin_id = match get_token_user_id(config, jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let bytes = match data.open(config.media.max_size.bytes()).into_bytes().await {
+        Ok(val) if val.is_complete() => val.into_inner(),
+        Ok(_) => {
+            return (
+                Status::PayloadTooLarge,
+                json!({"error": format!("Images can't be larger than {} bytes", config.media.max_size)}),
+            )
+        }
+        Err(err) => return (Status::BadRequest, json!({"error": err.to_string()})),
+    };
+
+    if bytes.is_empty() {
+        return (Status::BadRequest, json!({"error": "No image in the body"}));
+    }
+
+    let media_config = config.media.clone();
+    let image = match spawn_blocking(move || prepare_image(&media_config, bytes)).await {
+        Ok(Ok(val)) => val,
+        Ok(Err(err)) => return (Status::BadRequest, json!({"error": err})),
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"error": err.to_string()}),
+            )
+        }
+    };
+
+    if let Err(err) = store_image(&config.media, &image).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    // Uploading the same file again returns the existing media
+    match get_media_by_hash(&mut db, &image.hash).await {
+        Ok(Some(media)) => return (Status::Ok, json!({"media_id": media.media_id})),
+        Ok(None) => (),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    let media_id = match add_media(&mut db, &image, admin_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if let Err(err) = add_audit_entry(
+        &mut db,
+        admin_id,
+        "create",
+        "media",
+        Some(media_id),
+        None,
+        Some(&json!({
+            "hash": image.hash,
+            "format": image.format,
+            "width": image.width,
+            "height": image.height,
+            "size": image.size,
+        })),
+        ip,
+    )
+    .await
+    {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({"media_id": media_id}))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct MediaGetData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/admin/media/search", format = "json", data = "<data>")]
+pub async fn admin_media_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<MediaGetData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_admin(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    match get_all_media(&mut db).await {
+        Ok(val) => (Status::Ok, json!(val)),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
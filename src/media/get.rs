@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use rocket::{
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder, Response},
+    serde::json::{json, Value},
+    tokio::fs,
+    State,
+};
+use rocket_db_pools::Connection;
+
+use crate::{config::AppConfig, DB};
+
+use super::{content_type, file_path, get_media_by_id};
+
+// Files are named after their content, so whatever is under a media ID never changes
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+pub struct IfNoneMatch<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match")))
+    }
+}
+
+impl IfNoneMatch<'_> {
+    fn matches(&self, etag: &str) -> bool {
+        self.0
+            .is_some_and(|x| x.split(',').any(|x| x.trim() == etag || x.trim() == "*"))
+    }
+}
+
+// Without a body it's a 304 for a client that already has the file
+pub struct MediaFile {
+    content_type: ContentType,
+    etag: String,
+    body: Option<Vec<u8>>,
+}
+
+impl<'r> Responder<'r, 'static> for MediaFile {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .raw_header("Cache-Control", CACHE_CONTROL)
+            .raw_header("ETag", self.etag);
+
+        match self.body {
+            Some(body) => response
+                .header(self.content_type)
+                .sized_body(body.len(), Cursor::new(body)),
+            None => response.status(Status::NotModified),
+        };
+
+        response.ok()
+    }
+}
+
+async fn media_file(
+    db: &mut Connection<DB>,
+    config: &AppConfig,
+    media_id: u32,
+    thumbnail: bool,
+    cached: IfNoneMatch<'_>,
+) -> Result<MediaFile, (Status, Value)> {
+    let media = match get_media_by_id(db, media_id).await {
+        Ok(val) => val,
+        Err(err) => return Err((Status::NotFound, json!({"error": err}))),
+    };
+
+    let etag = match thumbnail {
+        true => format!("\"{}.thumb\"", media.hash),
+        false => format!("\"{}\"", media.hash),
+    };
+
+    if cached.matches(&etag) {
+        return Ok(MediaFile {
+            content_type: content_type(&media.format),
+            etag,
+            body: None,
+        });
+    }
+
+    let path = file_path(&config.media, &media.hash, &media.format, thumbnail);
+    let body = match fs::read(&path).await {
+        Ok(val) => val,
+        Err(err) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"error": format!("Failed to read {}: {}", path.display(), err)}),
+            ))
+        }
+    };
+
+    Ok(MediaFile {
+        content_type: content_type(&media.format),
+        etag,
+        body: Some(body),
+    })
+}
+
+#[get("/media/<media_id>")]
+pub async fn media_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    media_id: u32,
+    cached: IfNoneMatch<'_>,
+) -> Result<MediaFile, (Status, Value)> {
+    media_file(&mut db, config, media_id, false, cached).await
+}
+
+#[get("/media/<media_id>/thumbnail")]
+pub async fn media_thumbnail_get(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    media_id: u32,
+    cached: IfNoneMatch<'_>,
+) -> Result<MediaFile, (Status, Value)> {
+    media_file(&mut db, config, media_id, true, cached).await
+}
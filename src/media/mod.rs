@@ -0,0 +1,272 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use rocket::{http::ContentType, serde::Serialize, tokio::fs};
+use schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+use sqlx::{prelude::FromRow, query, query_as, Row};
+
+use crate::{config::MediaConfig, db::DbConnection};
+
+pub mod admin;
+pub mod get;
+
+// The only formats accepted for upload, as (format, name stored in the database)
+const FORMATS: [(ImageFormat, &str); 3] = [
+    (ImageFormat::Png, "png"),
+    (ImageFormat::Jpeg, "jpeg"),
+    (ImageFormat::WebP, "webp"),
+];
+
+// ██████╗  █████╗ ████████╗ █████╗ ██████╗  █████╗ ███████╗███████╗    ███████╗██╗   ██╗███╗   ██╗ ██████╗████████╗██╗ ██████╗ ███╗   ██╗███████╗
+// ██╔══██╗██╔══██╗╚══██╔══╝██╔══██╗██╔══██╗██╔══██╗██╔════╝██╔════╝    ██╔════╝██║   ██║████╗  ██║██╔════╝╚══██╔══╝██║██╔═══██╗████╗  ██║██╔════╝
+// ██║  ██║███████║   ██║   ███████║██████╔╝███████║███████╗█████╗      █████╗  ██║   ██║██╔██╗ ██║██║        ██║   ██║██║   ██║██╔██╗ ██║███████╗
+// ██║  ██║██╔══██║   ██║   ██╔══██║██╔══██╗██╔══██║╚════██║██╔══╝      ██╔══╝  ██║   ██║██║╚██╗██║██║        ██║   ██║██║   ██║██║╚██╗██║╚════██║
+// ██████╔╝██║  ██║   ██║   ██║  ██║██████╔╝██║  ██║███████║███████╗    ██║     ╚██████╔╝██║ ╚████║╚██████╗   ██║   ██║╚██████╔╝██║ ╚████║███████║
+// ╚═════╝ ╚═╝  ╚═╝   ╚═╝   ╚═╝  ╚═╝╚═════╝ ╚═╝  ╚═╝╚══════╝╚══════╝    ╚═╝      ╚═════╝ ╚═╝  ╚═══╝ ╚═════╝   ╚═╝   ╚═╝ ╚═════╝ ╚═╝  ╚═══╝╚══════╝
+
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct Media {
+    #[sqlx(try_from = "i64")]
+    pub media_id: u32,
+    pub hash: String,
+    pub format: String,
+    #[sqlx(try_from = "i64")]
+    pub width: u32,
+    #[sqlx(try_from = "i64")]
+    pub height: u32,
+    pub size: i64,
+    #[sqlx(try_from = "i64")]
+    pub admin_id: u32,
+    pub timestamp: i64,
+}
+
+pub async fn add_media(
+    db: &mut DbConnection,
+    image: &StoredImage,
+    admin_id: u32,
+) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query(
+        "INSERT INTO media
+        (hash, format, width, height, size, admin_id, timestamp)
+        VALUES ($1,$2,$3,$4,$5,$6,$7)
+        RETURNING media_id",
+    )
+    .bind(&image.hash)
+    .bind(image.format)
+    .bind(i64::from(image.width))
+    .bind(i64::from(image.height))
+    .bind(image.size as i64)
+    .bind(i64::from(admin_id))
+    .bind(timestamp)
+    .fetch_one(db)
+    .await
+    {
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to add media: {}", err)),
+    }
+}
+
+pub async fn get_media_by_id(db: &mut DbConnection, media_id: u32) -> Result<Media, String> {
+    match query_as("SELECT * FROM media WHERE media_id = $1")
+        .bind(i64::from(media_id))
+        .fetch_one(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(sqlx::Error::RowNotFound) => Err("Media not found".to_owned()),
+        Err(err) => Err(format!("Failed to get media: {}", err)),
+    }
+}
+
+pub async fn get_media_by_hash(db: &mut DbConnection, hash: &str) -> Result<Option<Media>, String> {
+    match query_as("SELECT * FROM media WHERE hash = $1")
+        .bind(hash)
+        .fetch_optional(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get media: {}", err)),
+    }
+}
+
+pub async fn get_all_media(db: &mut DbConnection) -> Result<Vec<Media>, String> {
+    match query_as("SELECT * FROM media ORDER BY media_id")
+        .fetch_all(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get media: {}", err)),
+    }
+}
+
+// ███████╗████████╗ ██████╗ ██████╗  █████╗  ██████╗ ███████╗
+// ██╔════╝╚══██╔══╝██╔═══██╗██╔══██╗██╔══██╗██╔════╝ ██╔════╝
+// ███████╗   ██║   ██║   ██║██████╔╝███████║██║  ███╗█████╗
+// ╚════██║   ██║   ██║   ██║██╔══██╗██╔══██║██║   ██║██╔══╝
+// ███████║   ██║   ╚██████╔╝██║  ██║██║  ██║╚██████╔╝███████╗
+// ╚══════╝   ╚═╝    ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝ ╚═════╝ ╚══════╝
+
+pub struct StoredImage {
+    pub hash: String,
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+    bytes: Vec<u8>,
+    thumbnail: Vec<u8>,
+}
+
+fn image_format(name: &str) -> Option<ImageFormat> {
+    FORMATS.iter().find(|(_, x)| *x == name).map(|(x, _)| *x)
+}
+
+pub fn content_type(format: &str) -> ContentType {
+    match image_format(format) {
+        Some(ImageFormat::Png) => ContentType::PNG,
+        Some(ImageFormat::Jpeg) => ContentType::JPEG,
+        Some(ImageFormat::WebP) => ContentType::WEBP,
+        _ => ContentType::Binary,
+    }
+}
+
+pub fn file_path(config: &MediaConfig, hash: &str, format: &str, thumbnail: bool) -> PathBuf {
+    let extension = match image_format(format) {
+        Some(format) => format.extensions_str()[0],
+        None => "bin",
+    };
+
+    let name = match thumbnail {
+        true => format!("{}.thumb.{}", hash, extension),
+        false => format!("{}.{}", hash, extension),
+    };
+
+    Path::new(&config.directory).join(name)
+}
+
+// The format comes from the bytes, not from what the client claims. Dimensions are checked
+// from the header before anything gets decoded
+fn decode(config: &MediaConfig, bytes: &[u8]) -> Result<(ImageFormat, DynamicImage), String> {
+    let reader = match ImageReader::new(Cursor::new(bytes)).with_guessed_format() {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read the image: {}", err)),
+    };
+
+    let format = match reader.format() {
+        Some(format) if FORMATS.iter().any(|(x, _)| *x == format) => format,
+        _ => return Err("Only png, jpeg and webp images are accepted".to_owned()),
+    };
+
+    let (width, height) = match reader.into_dimensions() {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to read the image: {}", err)),
+    };
+
+    if width > config.max_width || height > config.max_height {
+        return Err(format!(
+            "Images can't be larger than {}x{}",
+            config.max_width, config.max_height
+        ));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_width);
+    limits.max_image_height = Some(config.max_height);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+
+    match reader.decode() {
+        Ok(image) => Ok((format, image)),
+        Err(err) => Err(format!("Failed to decode the image: {}", err)),
+    }
+}
+
+// Thumbnails keep the format of the original, jpeg has no alpha channel to keep
+fn thumbnail(
+    config: &MediaConfig,
+    format: ImageFormat,
+    image: &DynamicImage,
+) -> Result<Vec<u8>, String> {
+    let thumbnail = image.thumbnail(config.thumbnail_size, config.thumbnail_size);
+    let thumbnail = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+        ImageFormat::WebP => DynamicImage::ImageRgba8(thumbnail.to_rgba8()),
+        _ => thumbnail,
+    };
+
+    let mut bytes = Cursor::new(Vec::new());
+    match thumbnail.write_to(&mut bytes, format) {
+        Ok(_) => Ok(bytes.into_inner()),
+        Err(err) => Err(format!("Failed to encode the thumbnail: {}", err)),
+    }
+}
+
+// Decoding is slow, so this is meant for spawn_blocking. Errors are about the upload itself
+pub fn prepare_image(config: &MediaConfig, bytes: Vec<u8>) -> Result<StoredImage, String> {
+    let (format, image) = decode(config, &bytes)?;
+    let thumbnail = thumbnail(config, format, &image)?;
+
+    let hash = Sha256::digest(&bytes);
+
+    Ok(StoredImage {
+        hash: hash.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        format: FORMATS
+            .iter()
+            .find(|(x, _)| *x == format)
+            .map(|(_, x)| *x)
+            .unwrap_or_default(),
+        width: image.width(),
+        height: image.height(),
+        size: bytes.len(),
+        bytes,
+        thumbnail,
+    })
+}
+
+// Written under a temporary name and renamed, so a file under its hash is always complete
+async fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    if fs::try_exists(path).await.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let temporary = path.with_extension("tmp");
+    if let Err(err) = fs::write(&temporary, bytes).await {
+        return Err(format!("Failed to write {}: {}", temporary.display(), err));
+    }
+
+    match fs::rename(&temporary, path).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to write {}: {}", path.display(), err)),
+    }
+}
+
+pub async fn store_image(config: &MediaConfig, image: &StoredImage) -> Result<(), String> {
+    if let Err(err) = fs::create_dir_all(&config.directory).await {
+        return Err(format!(
+            "Failed to create the media directory {}: {}",
+            config.directory, err
+        ));
+    }
+
+    write_file(
+        &file_path(config, &image.hash, image.format, false),
+        &image.bytes,
+    )
+    .await?;
+    write_file(
+        &file_path(config, &image.hash, image.format, true),
+        &image.thumbnail,
+    )
+    .await
+}
@@ -0,0 +1,53 @@
+use std::time::Instant;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+
+use crate::api::handler_name;
+
+use super::{login_failed, record_request, registered};
+
+struct RequestStart(Instant);
+
+pub struct MetricsCollector;
+
+#[rocket::async_trait]
+impl Fairing for MetricsCollector {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics Collector",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let RequestStart(start) = req.local_cache(|| RequestStart(Instant::now()));
+
+        // Unmatched requests are counted together so random paths can't blow up the label set
+        let route = match req.route() {
+            Some(route) => route.uri.to_string(),
+            None => "unmatched".to_owned(),
+        };
+        let status = res.status();
+
+        record_request(
+            req.method().as_str(),
+            &route,
+            status.code,
+            start.elapsed().as_secs_f64(),
+        );
+
+        // By handler so the legacy paths and /api/v1 count the same
+        match req.route().map(handler_name) {
+            Some("auth_register") if status.class().is_success() => registered(),
+            Some("auth_login") if !status.class().is_success() => login_failed(),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::net::IpAddr;
+
+use rocket::{
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome},
+    Request, State,
+};
+use rocket_db_pools::Connection;
+
+use crate::{config::AppConfig, user::get_active_session_count, util::check_authorized_admin, DB};
+
+use super::{render, PoolStats};
+
+pub struct BearerToken<'r>(pub Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "));
+
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+fn ip_allowed(config: &AppConfig, ip: Option<IpAddr>) -> bool {
+    match ip {
+        Some(val) => config.metrics.allowed_ips.contains(&val),
+        None => false,
+    }
+}
+
+#[get("/metrics")]
+pub async fn metrics(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    pool: &State<DB>,
+    token: BearerToken<'_>,
+    ip: Option<IpAddr>,
+) -> (Status, (ContentType, String)) {
+    if !ip_allowed(config, ip) {
+        let jwt = match token.0 {
+            Some(val) => val,
+            None => {
+                return (
+                    Status::Unauthorized,
+                    (ContentType::Plain, "missing bearer token".to_owned()),
+                )
+            }
+        };
+
+        if check_authorized_admin(&mut db, config, jwt).await.is_some() {
+            return (
+                Status::Unauthorized,
+                (ContentType::Plain, "user is not authorized".to_owned()),
+            );
+        }
+    }
+
+    let active_sessions = match get_active_session_count(&mut db).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, (ContentType::Plain, err)),
+    };
+
+    let DB(pool) = pool.inner();
+    let pool = PoolStats {
+        size: pool.size(),
+        idle: pool.num_idle(),
+        max: pool.options().get_max_connections(),
+    };
+
+    (
+        Status::Ok,
+        (ContentType::Plain, render(&pool, active_sessions)),
+    )
+}
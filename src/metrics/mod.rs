@@ -0,0 +1,203 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+pub mod fairing;
+pub mod get;
+
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[index] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+struct Metrics {
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+    registrations: AtomicU64,
+    failed_logins: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            requests: Mutex::new(BTreeMap::new()),
+            latencies: Mutex::new(BTreeMap::new()),
+            registrations: AtomicU64::new(0),
+            failed_logins: AtomicU64::new(0),
+        }
+    }
+}
+
+pub fn record_request(method: &str, route: &str, status: u16, latency: f64) {
+    if let Ok(mut requests) = METRICS.requests.lock() {
+        *requests
+            .entry((method.to_owned(), route.to_owned(), status))
+            .or_default() += 1;
+    }
+
+    if let Ok(mut latencies) = METRICS.latencies.lock() {
+        latencies
+            .entry((method.to_owned(), route.to_owned()))
+            .or_default()
+            .observe(latency);
+    }
+}
+
+pub fn registered() {
+    METRICS.registrations.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn login_failed() {
+    METRICS.failed_logins.fetch_add(1, Ordering::Relaxed);
+}
+
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} counter", name).ok();
+    writeln!(out, "{} {}", name, value).ok();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} gauge", name).ok();
+    writeln!(out, "{} {}", name, value).ok();
+}
+
+pub fn render(pool: &PoolStats, active_sessions: u32) -> String {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "# HELP http_requests_total Handled requests by route and status"
+    )
+    .ok();
+    writeln!(out, "# TYPE http_requests_total counter").ok();
+    if let Ok(requests) = METRICS.requests.lock() {
+        for ((method, route, status), count) in requests.iter() {
+            writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape(route),
+                status,
+                count
+            )
+            .ok();
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP http_request_duration_seconds Request latency by route"
+    )
+    .ok();
+    writeln!(out, "# TYPE http_request_duration_seconds histogram").ok();
+    if let Ok(latencies) = METRICS.latencies.lock() {
+        for ((method, route), histogram) in latencies.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                )
+                .ok();
+            }
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )
+            .ok();
+            writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )
+            .ok();
+            writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )
+            .ok();
+        }
+    }
+
+    gauge(
+        &mut out,
+        "db_pool_connections",
+        "Open connections in the database pool",
+        pool.size as u64,
+    );
+    gauge(
+        &mut out,
+        "db_pool_idle_connections",
+        "Idle connections in the database pool",
+        pool.idle as u64,
+    );
+    gauge(
+        &mut out,
+        "db_pool_max_connections",
+        "Maximum connections in the database pool",
+        pool.max as u64,
+    );
+    gauge(
+        &mut out,
+        "active_sessions",
+        "Sessions that haven't expired yet",
+        active_sessions as u64,
+    );
+    counter(
+        &mut out,
+        "registrations_total",
+        "Successful registrations",
+        METRICS.registrations.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "failed_logins_total",
+        "Rejected login attempts",
+        METRICS.failed_logins.load(Ordering::Relaxed),
+    );
+
+    out
+}
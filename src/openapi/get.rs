@@ -0,0 +1,60 @@
+use rocket::{
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome, Request},
+    response::content::RawHtml,
+    serde::json::Value,
+    Orbit, Rocket,
+};
+
+use super::spec;
+
+// Swagger UI 5.17.14, see static/swagger-ui/LICENSE
+const SWAGGER_UI_BUNDLE: &str = include_str!("../../static/swagger-ui/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &str = include_str!("../../static/swagger-ui/swagger-ui.css");
+
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>wiedzieliscie-backend API</title>
+    <link rel="stylesheet" href="/docs/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="/docs/swagger-ui-bundle.js"></script>
+    <script>
+        window.ui = SwaggerUIBundle({url: "/openapi.json", dom_id: "#swagger-ui"});
+    </script>
+</body>
+</html>"##;
+
+pub struct MountedRoutes<'r>(&'r Rocket<Orbit>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MountedRoutes<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(MountedRoutes(request.rocket()))
+    }
+}
+
+// Built from the mounted routes, so the document can't fall behind main.rs
+#[get("/openapi.json")]
+pub async fn openapi_json(mounted: MountedRoutes<'_>) -> (Status, Value) {
+    (Status::Ok, spec(mounted.0.routes()))
+}
+
+#[get("/docs")]
+pub async fn openapi_docs() -> RawHtml<&'static str> {
+    RawHtml(DOCS_PAGE)
+}
+
+#[get("/docs/<file>")]
+pub async fn openapi_docs_asset(file: &str) -> Option<(ContentType, &'static str)> {
+    match file {
+        "swagger-ui-bundle.js" => Some((ContentType::JavaScript, SWAGGER_UI_BUNDLE)),
+        "swagger-ui.css" => Some((ContentType::CSS, SWAGGER_UI_CSS)),
+        _ => None,
+    }
+}
@@ -0,0 +1,976 @@
+use rocket::{
+    serde::json::{json, serde_json::Map, Value},
+    Route,
+};
+use schemars::{gen::SchemaGenerator, gen::SchemaSettings, JsonSchema};
+
+use crate::{
+    admin::{
+        character::{CharacterAddData, CharacterDeleteData, CharacterGetData},
+        dialogue::{DialogueAddData, DialogueDeleteData, DialogueGetData},
+        game::{GamePauseData, GameSetLocationRadiusData, GameSetTutorialData, GameUnpauseData},
+        quest::{
+            QuestAddData, QuestDeleteData, QuestDuplicateData, QuestGetData, QuestStageAddData,
+            QuestStageDeleteData, QuestStageGetData, QuestStageMoveBackData,
+            QuestStageMoveForwardData,
+        },
+        task::{LocationTaskAddData, MultipleChoiceTaskAddData, TaskGetData, TextTaskAddData},
+        user::{
+            DeletedUserListData, UserAdminStatusData, UserBanData, UserImpersonateData,
+            UserListData, UserRestoreData, UserUnbanData,
+        },
+        Character, Dialogue, Quest, QuestStage, Task,
+    },
+    announcements::{
+        admin::{AnnouncementAddData, AnnouncementExpireData, AnnouncementGetData},
+        inbox::{InboxData, InboxReadData},
+        Announcement, InboxEntry,
+    },
+    api::{handler_name, is_legacy},
+    audit::{get::AuditGetData, AuditEntry},
+    error::{
+        report::{GetReportsData, ReportData},
+        triage::{ReportCommentData, ReportGetData, ReportSetStatusData},
+        ErrorReport, ReportComment,
+    },
+    events::GameEvent,
+    fetch::get::GetCharacterData,
+    health::Check,
+    janitor::run::JanitorRunData,
+    logging::{get::GetLogsData, LogEntry},
+    media::{admin::MediaGetData, Media},
+    totp::{
+        enroll::{TotpCodeData, TotpEnrollData},
+        login::LoginTotpData,
+    },
+    user::{
+        delete_user::DeleteData, export::ExportData, login::LoginData, logout::LogoutData,
+        register::RegisterData, reset::ResetData, retrieve::RetrieveUserData,
+        retrieve::RetrieveUserEmailData, retrieve::RetrieveUserIdData,
+        retrieve::RetrieveUserNamesData, update_email::EmailUpdateData,
+        verifyless_updates::SimpleModifyData, PurgeCounts,
+    },
+};
+
+pub mod get;
+
+// Error codes every handler can answer with, on top of the ones listed per route
+const PUBLIC: &[u16] = &[400, 500];
+const LIMITED: &[u16] = &[400, 429, 500];
+const AUTHORIZED: &[u16] = &[400, 403, 500];
+const ADMIN: &[u16] = &[400, 403, 404, 500];
+
+pub struct Operation {
+    pub tag: &'static str,
+    pub summary: &'static str,
+    pub request: Option<Value>,
+    pub request_types: &'static [&'static str],
+    pub responses: Vec<(u16, &'static str, Value)>,
+    pub errors: &'static [u16],
+}
+
+fn op(
+    tag: &'static str,
+    summary: &'static str,
+    request: Option<Value>,
+    responses: Vec<(u16, &'static str, Value)>,
+    errors: &'static [u16],
+) -> Option<Operation> {
+    Some(Operation {
+        tag,
+        summary,
+        request,
+        request_types: &["application/json"],
+        responses,
+        errors,
+    })
+}
+
+// Image uploads and downloads, the body is the file itself
+const IMAGE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/webp"];
+
+fn image() -> Vec<(u16, &'static str, Value)> {
+    IMAGE_TYPES
+        .iter()
+        .map(|x| (200, *x, json!({"type": "string", "format": "binary"})))
+        .collect()
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    json!(gen.subschema_for::<T>())
+}
+
+fn ok(schema: Value) -> Vec<(u16, &'static str, Value)> {
+    vec![(200, "application/json", schema)]
+}
+
+fn html() -> Vec<(u16, &'static str, Value)> {
+    vec![(200, "text/html", json!({"type": "string"}))]
+}
+
+fn object(properties: &[(&str, Value)]) -> Value {
+    let required: Vec<&str> = properties.iter().map(|(name, _)| *name).collect();
+    let properties: Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+fn empty() -> Value {
+    json!({"type": "object"})
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn integer() -> Value {
+    json!({"type": "integer", "format": "int64"})
+}
+
+fn boolean() -> Value {
+    json!({"type": "boolean"})
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+fn nullable(mut schema: Value) -> Value {
+    schema["nullable"] = json!(true);
+    schema
+}
+
+// Cursor paginated lists, next_cursor is null on the last page
+fn page(key: &str, items: Value) -> Value {
+    object(&[
+        (key, array(items)),
+        ("total", integer()),
+        ("next_cursor", nullable(integer())),
+    ])
+}
+
+fn user() -> Value {
+    object(&[
+        ("account_id", integer()),
+        ("email", string()),
+        ("first_name", string()),
+        ("last_name", string()),
+        ("gender", json!({"type": "string", "enum": ["m", "f"]})),
+    ])
+}
+
+fn user_with_points() -> Value {
+    let mut user = user();
+    user["properties"]["points"] = integer();
+    user["required"]
+        .as_array_mut()
+        .expect("Required")
+        .push(json!("points"));
+    user
+}
+
+// Keyed by the handler name, which is the route name rocket reports
+pub fn operation(gen: &mut SchemaGenerator, name: &str) -> Option<Operation> {
+    match name {
+        "auth_register" => op(
+            "auth",
+            "Register a new account and send the verification email",
+            Some(schema::<RegisterData>(gen)),
+            vec![(
+                201,
+                "application/json",
+                object(&[("account_id", integer())]),
+            )],
+            LIMITED,
+        ),
+        "auth_resend_verification" => op(
+            "auth",
+            "Send the verification email again",
+            None,
+            ok(empty()),
+            LIMITED,
+        ),
+        "auth_verify" => op("auth", "Verify an account", None, html(), &[]),
+        "auth_login" => op(
+            "auth",
+            "Log in, accounts with two-factor authentication get a challenge instead of a jwt",
+            Some(schema::<LoginData>(gen)),
+            vec![
+                (200, "application/json", object(&[("jwt", string())])),
+                (
+                    202,
+                    "application/json",
+                    object(&[("totp_required", boolean()), ("challenge", string())]),
+                ),
+            ],
+            &[400, 401, 403, 429, 500],
+        ),
+        "auth_login_totp" => op(
+            "auth",
+            "Finish a login with a totp or recovery code",
+            Some(schema::<LoginTotpData>(gen)),
+            ok(object(&[("jwt", string())])),
+            &[400, 403, 429, 500],
+        ),
+        "auth_password_reset" => op(
+            "auth",
+            "Request a password reset email",
+            Some(schema::<ResetData>(gen)),
+            ok(empty()),
+            LIMITED,
+        ),
+        "auth_password_reset_verify" => op("auth", "Confirm a password reset", None, html(), &[]),
+        "auth_logout" => op(
+            "auth",
+            "End the session of the jwt",
+            Some(schema::<LogoutData>(gen)),
+            ok(empty()),
+            PUBLIC,
+        ),
+        "auth_retrieve_user" => op(
+            "user",
+            "Get the account of the jwt",
+            Some(schema::<RetrieveUserData>(gen)),
+            ok(user()),
+            PUBLIC,
+        ),
+        "user_modify_first_name" => op(
+            "user",
+            "Change the first name",
+            Some(schema::<SimpleModifyData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_modify_last_name" => op(
+            "user",
+            "Change the last name",
+            Some(schema::<SimpleModifyData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_modify_gender" => op(
+            "user",
+            "Change the gender, new_value is m or f",
+            Some(schema::<SimpleModifyData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_modify_password" => op(
+            "user",
+            "Change the password and end all sessions",
+            Some(schema::<SimpleModifyData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_modify_email" => op(
+            "user",
+            "Request an email change, the new address has to confirm it",
+            Some(schema::<EmailUpdateData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_modify_email_verify" => op("user", "Confirm an email change", None, html(), &[]),
+        "delete_user" => op(
+            "user",
+            "Request an account deletion email",
+            Some(schema::<DeleteData>(gen)),
+            ok(empty()),
+            LIMITED,
+        ),
+        "auth_delete_user_verify" => op("user", "Confirm an account deletion", None, html(), &[]),
+        "user_export" => op(
+            "user",
+            "Export everything stored about the account of the jwt",
+            Some(schema::<ExportData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_totp_enroll" => op(
+            "totp",
+            "Start enrolling two-factor authentication",
+            Some(schema::<TotpEnrollData>(gen)),
+            ok(object(&[("secret", string()), ("otpauth_uri", string())])),
+            AUTHORIZED,
+        ),
+        "user_totp_confirm" => op(
+            "totp",
+            "Enable two-factor authentication with a code from the new secret",
+            Some(schema::<TotpCodeData>(gen)),
+            ok(object(&[("recovery_codes", array(string()))])),
+            AUTHORIZED,
+        ),
+        "user_totp_disable" => op(
+            "totp",
+            "Disable two-factor authentication",
+            Some(schema::<TotpCodeData>(gen)),
+            ok(empty()),
+            AUTHORIZED,
+        ),
+        "user_totp_recovery_codes" => op(
+            "totp",
+            "Replace the recovery codes",
+            Some(schema::<TotpCodeData>(gen)),
+            ok(object(&[("recovery_codes", array(string()))])),
+            AUTHORIZED,
+        ),
+        "user_totp_status" => op(
+            "totp",
+            "Get the two-factor authentication status",
+            Some(schema::<TotpEnrollData>(gen)),
+            ok(object(&[
+                ("enabled", boolean()),
+                ("recovery_codes_left", integer()),
+            ])),
+            AUTHORIZED,
+        ),
+        "user_retrieve_email" => op(
+            "user",
+            "Find a user by email",
+            Some(schema::<RetrieveUserEmailData>(gen)),
+            ok(user_with_points()),
+            &[404, 500],
+        ),
+        "user_retrieve_id" => op(
+            "user",
+            "Find a user by id",
+            Some(schema::<RetrieveUserIdData>(gen)),
+            ok(user_with_points()),
+            &[404],
+        ),
+        "users_get" => op("user", "Get a user", None, ok(user_with_points()), &[404]),
+        "user_retrieve_name" => op(
+            "user",
+            "Find a user by first and last name",
+            Some(schema::<RetrieveUserNamesData>(gen)),
+            ok(user_with_points()),
+            &[404, 500],
+        ),
+        "user_retrieve_count" => op("user", "Count users", None, ok(integer()), &[404]),
+        "admin_characters_add" => op(
+            "admin",
+            "Add a character",
+            Some(schema::<CharacterAddData>(gen)),
+            ok(object(&[("character_id", integer())])),
+            ADMIN,
+        ),
+        "admin_characters_delete" => op(
+            "admin",
+            "Delete a character",
+            Some(schema::<CharacterDeleteData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_characters_get" => op(
+            "admin",
+            "List characters",
+            Some(schema::<CharacterGetData>(gen)),
+            ok(page("characters", schema::<Character>(gen))),
+            ADMIN,
+        ),
+        "admin_dialogues_add" => op(
+            "admin",
+            "Add a dialogue",
+            Some(schema::<DialogueAddData>(gen)),
+            ok(object(&[("dialogue_id", integer())])),
+            ADMIN,
+        ),
+        "admin_dialogues_delete" => op(
+            "admin",
+            "Delete a dialogue",
+            Some(schema::<DialogueDeleteData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_dialogues_get" => op(
+            "admin",
+            "List dialogues",
+            Some(schema::<DialogueGetData>(gen)),
+            ok(page("dialogues", schema::<Dialogue>(gen))),
+            ADMIN,
+        ),
+        "admin_dialogues_get_unused" => op(
+            "admin",
+            "List dialogues that aren't part of a quest",
+            Some(schema::<DialogueGetData>(gen)),
+            ok(page("dialogues", schema::<Dialogue>(gen))),
+            ADMIN,
+        ),
+        "admin_tasks_location_add" => op(
+            "admin",
+            "Add a location task",
+            Some(schema::<LocationTaskAddData>(gen)),
+            ok(object(&[("task_id", integer())])),
+            ADMIN,
+        ),
+        "admin_tasks_multiple_choice_add" => op(
+            "admin",
+            "Add a multiple choice task",
+            Some(schema::<MultipleChoiceTaskAddData>(gen)),
+            ok(object(&[("task_id", integer())])),
+            ADMIN,
+        ),
+        "admin_tasks_text_answer_add" => op(
+            "admin",
+            "Add a text answer task",
+            Some(schema::<TextTaskAddData>(gen)),
+            ok(object(&[("task_id", integer())])),
+            ADMIN,
+        ),
+        "admin_tasks_get" => op(
+            "admin",
+            "List tasks",
+            Some(schema::<TaskGetData>(gen)),
+            ok(page("tasks", schema::<Task>(gen))),
+            ADMIN,
+        ),
+        "admin_tasks_get_unused" => op(
+            "admin",
+            "List tasks that aren't part of a quest",
+            Some(schema::<TaskGetData>(gen)),
+            ok(page("tasks", schema::<Task>(gen))),
+            ADMIN,
+        ),
+        "admin_quests_add" => op(
+            "admin",
+            "Add a quest",
+            Some(schema::<QuestAddData>(gen)),
+            ok(object(&[("quest_id", integer())])),
+            ADMIN,
+        ),
+        "admin_quests_delete" => op(
+            "admin",
+            "Delete a quest",
+            Some(schema::<QuestDeleteData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_quests_get" => op(
+            "admin",
+            "List quests",
+            Some(schema::<QuestGetData>(gen)),
+            ok(page("quests", schema::<Quest>(gen))),
+            ADMIN,
+        ),
+        "admin_quests_duplicate" => op(
+            "admin",
+            "Copy a quest with its stages",
+            Some(schema::<QuestDuplicateData>(gen)),
+            ok(object(&[("quest_id", integer())])),
+            ADMIN,
+        ),
+        "admin_quests_stages_add" => op(
+            "admin",
+            "Add a task or dialogue stage to a quest",
+            Some(schema::<QuestStageAddData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_quests_stages_delete" => op(
+            "admin",
+            "Delete a quest stage",
+            Some(schema::<QuestStageDeleteData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_quests_stages_get" => op(
+            "admin",
+            "List the stages of a quest",
+            Some(schema::<QuestStageGetData>(gen)),
+            ok(array(schema::<QuestStage>(gen))),
+            ADMIN,
+        ),
+        "admin_quests_stages_move_back" => op(
+            "admin",
+            "Move a quest stage one place back",
+            Some(schema::<QuestStageMoveBackData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_quests_stages_move_forward" => op(
+            "admin",
+            "Move a quest stage one place forward",
+            Some(schema::<QuestStageMoveForwardData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_game_pause" => op(
+            "admin",
+            "Pause the game",
+            Some(schema::<GamePauseData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_game_unpause" => op(
+            "admin",
+            "Unpause the game",
+            Some(schema::<GameUnpauseData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_game_set_location_radius" => op(
+            "admin",
+            "Set the location radius",
+            Some(schema::<GameSetLocationRadiusData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_quests_select_tutorial" => op(
+            "admin",
+            "Select the tutorial quest",
+            Some(schema::<GameSetTutorialData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_users_get" => op(
+            "admin",
+            "List users",
+            Some(schema::<UserListData>(gen)),
+            ok(object(&[
+                ("users", array(empty())),
+                ("total", integer()),
+                ("page", integer()),
+                ("page_size", integer()),
+            ])),
+            ADMIN,
+        ),
+        "admin_users_promote" => op(
+            "admin",
+            "Make a user an admin",
+            Some(schema::<UserAdminStatusData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_users_demote" => op(
+            "admin",
+            "Take admin rights away",
+            Some(schema::<UserAdminStatusData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_users_ban" => op(
+            "admin",
+            "Ban a user, without a duration the ban is permanent",
+            Some(schema::<UserBanData>(gen)),
+            ok(object(&[("banned_until", nullable(integer()))])),
+            ADMIN,
+        ),
+        "admin_users_unban" => op(
+            "admin",
+            "Lift a ban",
+            Some(schema::<UserUnbanData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_users_impersonate" => op(
+            "admin",
+            "Get a short lived jwt for a user",
+            Some(schema::<UserImpersonateData>(gen)),
+            ok(object(&[("jwt", string()), ("valid_until", integer())])),
+            ADMIN,
+        ),
+        "admin_users_export" => op(
+            "admin",
+            "Export everything stored about a user",
+            Some(schema::<UserAdminStatusData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_users_deleted_get" => op(
+            "admin",
+            "List deleted accounts",
+            Some(schema::<DeletedUserListData>(gen)),
+            ok(object(&[("users", array(empty()))])),
+            ADMIN,
+        ),
+        "admin_users_restore" => op(
+            "admin",
+            "Restore a deleted account that wasn't anonymised yet",
+            Some(schema::<UserRestoreData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_audit_get" => op(
+            "admin",
+            "Browse the audit log",
+            Some(schema::<AuditGetData>(gen)),
+            ok(object(&[
+                ("entries", array(schema::<AuditEntry>(gen))),
+                ("total", integer()),
+                ("page", integer()),
+                ("page_size", integer()),
+            ])),
+            ADMIN,
+        ),
+        "report_error" => op(
+            "reports",
+            "Report an error",
+            Some(schema::<ReportData>(gen)),
+            ok(object(&[("report_id", integer())])),
+            AUTHORIZED,
+        ),
+        "report_suggestion" => op(
+            "reports",
+            "Send a suggestion",
+            Some(schema::<ReportData>(gen)),
+            ok(object(&[("report_id", integer())])),
+            AUTHORIZED,
+        ),
+        "admin_get_reports" => op(
+            "reports",
+            "List error reports",
+            Some(schema::<GetReportsData>(gen)),
+            ok(page("reports", schema::<ErrorReport>(gen))),
+            ADMIN,
+        ),
+        "admin_get_suggestions" => op(
+            "reports",
+            "List suggestions",
+            Some(schema::<GetReportsData>(gen)),
+            ok(page("suggestions", schema::<ErrorReport>(gen))),
+            ADMIN,
+        ),
+        "admin_reports_get" => op(
+            "reports",
+            "Get a report with its comments",
+            Some(schema::<ReportGetData>(gen)),
+            ok(object(&[
+                ("report", schema::<ErrorReport>(gen)),
+                ("comments", array(schema::<ReportComment>(gen))),
+            ])),
+            ADMIN,
+        ),
+        "admin_reports_set_status" => op(
+            "reports",
+            "Change the status of a report and tell the reporter",
+            Some(schema::<ReportSetStatusData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "admin_reports_comment" => op(
+            "reports",
+            "Comment on a report",
+            Some(schema::<ReportCommentData>(gen)),
+            ok(object(&[("comment_id", integer())])),
+            ADMIN,
+        ),
+        "admin_get_logs" => op(
+            "operations",
+            "Read recent log entries",
+            Some(schema::<GetLogsData>(gen)),
+            ok(array(schema::<LogEntry>(gen))),
+            ADMIN,
+        ),
+        "admin_janitor_run" => op(
+            "operations",
+            "Run the janitor now",
+            Some(schema::<JanitorRunData>(gen)),
+            ok(schema::<PurgeCounts>(gen)),
+            ADMIN,
+        ),
+        "admin_announcements_add" => op(
+            "announcements",
+            "Create an announcement, published right away unless publish_at is in the future",
+            Some(schema::<AnnouncementAddData>(gen)),
+            ok(object(&[("announcement_id", integer())])),
+            ADMIN,
+        ),
+        "admin_announcements_get" => op(
+            "announcements",
+            "List all announcements",
+            Some(schema::<AnnouncementGetData>(gen)),
+            ok(array(schema::<Announcement>(gen))),
+            ADMIN,
+        ),
+        "admin_announcements_expire" => op(
+            "announcements",
+            "Expire an announcement now",
+            Some(schema::<AnnouncementExpireData>(gen)),
+            ok(empty()),
+            ADMIN,
+        ),
+        "user_inbox_get" => op(
+            "announcements",
+            "List the announcements in the user's inbox",
+            Some(schema::<InboxData>(gen)),
+            ok(array(schema::<InboxEntry>(gen))),
+            AUTHORIZED,
+        ),
+        "user_inbox_unread" => op(
+            "announcements",
+            "Count the unread announcements",
+            Some(schema::<InboxData>(gen)),
+            ok(object(&[("unread", integer())])),
+            AUTHORIZED,
+        ),
+        "user_inbox_read" => op(
+            "announcements",
+            "Mark an announcement as read",
+            Some(schema::<InboxReadData>(gen)),
+            ok(empty()),
+            &[400, 403, 404, 500],
+        ),
+        "admin_media_upload" => Some(Operation {
+            tag: "media",
+            summary: "Upload a png, jpeg or webp image with the admin jwt as a bearer token, the same file uploaded again keeps its media_id",
+            request: Some(json!({"type": "string", "format": "binary"})),
+            request_types: IMAGE_TYPES,
+            responses: ok(object(&[("media_id", integer())])),
+            errors: &[400, 401, 403, 413, 500],
+        }),
+        "admin_media_get" => op(
+            "media",
+            "List uploaded media",
+            Some(schema::<MediaGetData>(gen)),
+            ok(array(schema::<Media>(gen))),
+            ADMIN,
+        ),
+        "media_get" => op(
+            "media",
+            "Get an uploaded image, 304 when If-None-Match has its ETag",
+            None,
+            image(),
+            &[404, 500],
+        ),
+        "media_thumbnail_get" => op(
+            "media",
+            "Get the thumbnail of an uploaded image",
+            None,
+            image(),
+            &[404, 500],
+        ),
+        "metrics" => op(
+            "operations",
+            "Prometheus metrics",
+            None,
+            vec![(200, "text/plain", string())],
+            &[401],
+        ),
+        "health" => op(
+            "operations",
+            "Liveness check",
+            None,
+            ok(object(&[
+                ("status", string()),
+                ("checks", array(schema::<Check>(gen))),
+            ])),
+            &[],
+        ),
+        "ready" => op(
+            "operations",
+            "Readiness check",
+            None,
+            vec![
+                (
+                    200,
+                    "application/json",
+                    object(&[
+                        ("status", string()),
+                        ("checks", array(schema::<Check>(gen))),
+                    ]),
+                ),
+                (
+                    503,
+                    "application/json",
+                    object(&[
+                        ("status", string()),
+                        ("problems", array(string())),
+                        ("checks", array(schema::<Check>(gen))),
+                    ]),
+                ),
+            ],
+            &[],
+        ),
+        "get_location_radius" => op(
+            "game",
+            "Get the location radius",
+            None,
+            ok(json!({"type": "number"})),
+            &[500],
+        ),
+        "get_pause_state" => op(
+            "game",
+            "Check if the game is paused",
+            None,
+            ok(boolean()),
+            &[500],
+        ),
+        "get_character" => op(
+            "game",
+            "Get a character",
+            Some(schema::<GetCharacterData>(gen)),
+            ok(schema::<Character>(gen)),
+            &[400],
+        ),
+        "characters_get" => op(
+            "game",
+            "Get a character",
+            None,
+            ok(schema::<Character>(gen)),
+            &[400],
+        ),
+        "events" => op(
+            "game",
+            "Server-sent events about game changes, events about a player only reach their own stream",
+            None,
+            vec![(200, "text/event-stream", schema::<GameEvent>(gen))],
+            &[400, 403],
+        ),
+        "openapi_json" => op("operations", "This document", None, ok(empty()), &[]),
+        "openapi_docs" => op(
+            "operations",
+            "Swagger UI for this document",
+            None,
+            html(),
+            &[],
+        ),
+        "openapi_docs_asset" => op(
+            "operations",
+            "Swagger UI assets",
+            None,
+            vec![(200, "text/plain", string())],
+            &[404],
+        ),
+        _ => None,
+    }
+}
+
+fn error_response(code: u16) -> Value {
+    let schema = if code == 429 {
+        "#/components/schemas/RateLimited"
+    } else {
+        "#/components/schemas/Error"
+    };
+
+    json!({
+        "description": error_description(code),
+        "content": {"application/json": {"schema": {"$ref": schema}}}
+    })
+}
+
+fn error_description(code: u16) -> &'static str {
+    match code {
+        400 => "Invalid request, token or data",
+        401 => "Missing or invalid bearer token",
+        403 => "Banned, not allowed or two-factor authentication required",
+        404 => "Not found",
+        429 => "Rate limited, see the Retry-After header",
+        500 => "Server error",
+        _ => "Error",
+    }
+}
+
+// <param> and <param..> become {param}
+pub fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix('<') {
+            Some(param) => format!("{{{}}}", param.trim_end_matches('>').trim_end_matches("..")),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn parameter(name: &str, location: &str) -> Value {
+    let schema = if name.ends_with("_id") {
+        integer()
+    } else {
+        string()
+    };
+    json!({"name": name, "in": location, "required": true, "schema": schema})
+}
+
+fn parameters(path: &str, query: Option<&str>) -> Vec<Value> {
+    let path_parameters = path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| parameter(name, "path"));
+
+    let query_parameters = query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|segment| segment.strip_prefix('<')?.strip_suffix('>'))
+        .map(|name| parameter(name, "query"));
+
+    path_parameters.chain(query_parameters).collect()
+}
+
+pub fn spec<'a>(routes: impl Iterator<Item = &'a Route>) -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+
+    for route in routes {
+        let name = route.name.as_deref().unwrap_or_default();
+        let path = openapi_path(route.uri.path());
+        let method = route.method.as_str().to_lowercase();
+
+        let mut operation = json!({"operationId": name});
+
+        if is_legacy(route) {
+            operation["deprecated"] = json!(true);
+        }
+
+        match self::operation(&mut gen, handler_name(route)) {
+            Some(doc) => {
+                let mut responses = Map::new();
+                for (code, content_type, schema) in doc.responses {
+                    let response = responses
+                        .entry(code.to_string())
+                        .or_insert_with(|| json!({"description": "Success", "content": {}}));
+                    response["content"][content_type] = json!({"schema": schema});
+                }
+                for code in doc.errors {
+                    responses.insert(code.to_string(), error_response(*code));
+                }
+
+                operation["tags"] = json!([doc.tag]);
+                operation["summary"] = json!(doc.summary);
+                operation["responses"] = Value::Object(responses);
+
+                if let Some(request) = doc.request {
+                    let content: Map<String, Value> = doc
+                        .request_types
+                        .iter()
+                        .map(|x| (x.to_string(), json!({"schema": request})))
+                        .collect();
+                    operation["requestBody"] = json!({"required": true, "content": content});
+                }
+            }
+            None => {
+                operation["x-undocumented"] = json!(true);
+                operation["responses"] = json!({"default": {"description": "Undocumented"}});
+            }
+        }
+
+        let parameters = parameters(&path, route.uri.query());
+        if !parameters.is_empty() {
+            operation["parameters"] = json!(parameters);
+        }
+
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[method] = operation;
+    }
+
+    let mut schemas: Map<String, Value> = gen
+        .definitions()
+        .iter()
+        .map(|(name, schema)| (name.clone(), json!(schema)))
+        .collect();
+    schemas.insert("Error".to_owned(), object(&[("error", string())]));
+    schemas.insert(
+        "RateLimited".to_owned(),
+        object(&[("error", string()), ("retry_after", integer())]),
+    );
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "wiedzieliscie-backend",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths,
+        "components": {"schemas": schemas}
+    })
+}
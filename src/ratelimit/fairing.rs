@@ -0,0 +1,31 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Status},
+    Request, Response,
+};
+
+use super::RetryAfter;
+
+// Handlers only return a status and a body, so the header is added here
+pub struct RetryAfterHeader;
+
+#[rocket::async_trait]
+impl Fairing for RetryAfterHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Retry-After Header",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.status() != Status::TooManyRequests {
+            return;
+        }
+
+        let retry_after = req.local_cache(|| RetryAfter(Default::default()));
+        if let Some(val) = retry_after.get() {
+            res.set_header(Header::new("Retry-After", val.to_string()));
+        }
+    }
+}
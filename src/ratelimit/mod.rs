@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    serde::json::{json, Value},
+    Request,
+};
+
+use crate::{
+    config::{AppConfig, BucketConfig, LockoutConfig, RateLimitConfig, RouteLimitConfig},
+    logging,
+};
+
+pub mod fairing;
+
+// Above this many entries the ones that behave the same as missing ones are dropped
+const MAX_BUCKETS: usize = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitedRoute {
+    Login,
+    Register,
+    ResendVerification,
+    PasswordReset,
+    DeleteUser,
+}
+
+impl LimitedRoute {
+    fn config<'r>(&self, config: &'r RateLimitConfig) -> &'r RouteLimitConfig {
+        match self {
+            LimitedRoute::Login => &config.login,
+            LimitedRoute::Register => &config.register,
+            LimitedRoute::ResendVerification => &config.resend_verification,
+            LimitedRoute::PasswordReset => &config.password_reset,
+            LimitedRoute::DeleteUser => &config.delete_user,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Ip(LimitedRoute, IpAddr),
+    Account(LimitedRoute, String),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    config: BucketConfig,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let refilled =
+            now.duration_since(self.updated).as_secs_f64() / self.config.refill_every as f64;
+        (self.tokens + refilled).min(self.config.capacity as f64)
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(now) >= self.config.capacity as f64
+    }
+
+    // Returns the seconds until a request would be let through if there are no tokens left
+    fn take(&mut self, now: Instant) -> Result<(), u64> {
+        self.tokens = self.tokens_at(now);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) * self.config.refill_every as f64).ceil() as u64)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lockout {
+    failures: Vec<Instant>,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+    lockouts: Mutex<HashMap<String, Lockout>>,
+}
+
+fn retry_after(until: Instant, now: Instant) -> u64 {
+    until.saturating_duration_since(now).as_secs_f64().ceil() as u64
+}
+
+impl RateLimiter {
+    fn take(&self, key: BucketKey, config: BucketConfig) -> Result<(), u64> {
+        if config.capacity == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("Rate limit buckets");
+
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, x| !x.is_full(now));
+        }
+
+        buckets
+            .entry(key)
+            .or_insert(Bucket {
+                config,
+                tokens: config.capacity as f64,
+                updated: now,
+            })
+            .take(now)
+    }
+
+    pub fn check(
+        &self,
+        config: &RateLimitConfig,
+        route: LimitedRoute,
+        ip: Option<IpAddr>,
+        account: Option<&str>,
+    ) -> Result<(), u64> {
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let limits = route.config(config);
+
+        if let Some(ip) = ip {
+            self.take(BucketKey::Ip(route, ip), limits.ip)?;
+        }
+
+        if let Some(account) = account {
+            self.take(
+                BucketKey::Account(route, account.to_lowercase()),
+                limits.account,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn check_lockout(&self, config: &RateLimitConfig, account: &str) -> Result<(), u64> {
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let lockouts = self.lockouts.lock().expect("Lockouts");
+
+        match lockouts
+            .get(&account.to_lowercase())
+            .and_then(|x| x.locked_until)
+        {
+            Some(until) if until > now => Err(retry_after(until, now)),
+            _ => Ok(()),
+        }
+    }
+
+    // Locks the account once max_failures happen within the window
+    pub fn login_failed(&self, config: &LockoutConfig, account: &str) {
+        if config.max_failures == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(config.window);
+        let mut lockouts = self.lockouts.lock().expect("Lockouts");
+
+        if lockouts.len() > MAX_BUCKETS {
+            lockouts.retain(|_, x| {
+                x.locked_until.is_some_and(|x| x > now)
+                    || x.failures.iter().any(|x| now.duration_since(*x) < window)
+            });
+        }
+
+        let lockout = lockouts.entry(account.to_lowercase()).or_insert(Lockout {
+            failures: Vec::new(),
+            locked_until: None,
+        });
+
+        lockout.failures.retain(|x| now.duration_since(*x) < window);
+        lockout.failures.push(now);
+
+        if lockout.failures.len() >= config.max_failures as usize {
+            lockout.failures.clear();
+            lockout.locked_until = Some(now + Duration::from_secs(config.duration));
+            logging::warn(&format!(
+                "Locked {} for {} seconds after {} failed logins",
+                account, config.duration, config.max_failures
+            ));
+        }
+    }
+
+    pub fn login_succeeded(&self, account: &str) {
+        self.lockouts
+            .lock()
+            .expect("Lockouts")
+            .remove(&account.to_lowercase());
+    }
+}
+
+pub struct RetryAfter(AtomicU64);
+
+impl RetryAfter {
+    pub fn get(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            val => Some(val),
+        }
+    }
+}
+
+pub struct RateLimit<'r> {
+    limiter: &'r RateLimiter,
+    config: &'r AppConfig,
+    ip: Option<IpAddr>,
+    retry_after: &'r RetryAfter,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = req.rocket().state::<RateLimiter>();
+        let config = req.rocket().state::<AppConfig>();
+
+        match (limiter, config) {
+            (Some(limiter), Some(config)) => Outcome::Success(RateLimit {
+                limiter,
+                config,
+                // The peer address unless ip_header names a trusted proxy header
+                ip: req.client_ip(),
+                retry_after: req.local_cache(|| RetryAfter(AtomicU64::new(0))),
+            }),
+            _ => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+impl RateLimit<'_> {
+    fn too_many_requests(&self, retry_after: u64) -> (Status, Value) {
+        // Retry-After of 0 would mean the header isn't set, so wait at least a second
+        let retry_after = retry_after.max(1);
+        self.retry_after.0.store(retry_after, Ordering::Relaxed);
+
+        (
+            Status::TooManyRequests,
+            json!({"error": "Too many requests", "retry_after": retry_after}),
+        )
+    }
+
+    pub fn check(&self, route: LimitedRoute, account: Option<&str>) -> Option<(Status, Value)> {
+        match self
+            .limiter
+            .check(&self.config.rate_limits, route, self.ip, account)
+        {
+            Ok(_) => None,
+            Err(retry_after) => Some(self.too_many_requests(retry_after)),
+        }
+    }
+
+    pub fn check_lockout(&self, account: &str) -> Option<(Status, Value)> {
+        match self
+            .limiter
+            .check_lockout(&self.config.rate_limits, account)
+        {
+            Ok(_) => None,
+            Err(retry_after) => Some(self.too_many_requests(retry_after)),
+        }
+    }
+
+    pub fn login_failed(&self, account: &str) {
+        if self.config.rate_limits.enabled {
+            self.limiter
+                .login_failed(&self.config.rate_limits.lockout, account);
+        }
+    }
+
+    pub fn login_succeeded(&self, account: &str) {
+        self.limiter.login_succeeded(account);
+    }
+}
@@ -0,0 +1,257 @@
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    config::AppConfig,
+    logging,
+    user::get_user_by_id,
+    util::{check_authorized_user, get_token_user_id},
+    DB,
+};
+
+use super::{
+    delete_totp, enable_totp, generate_recovery_codes, generate_secret, get_recovery_code_count,
+    get_totp, otpauth_uri, replace_recovery_codes, set_totp_secret, verify_totp_code,
+    verify_totp_or_recovery_code,
+};
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpEnrollData<'r> {
+    jwt: &'r str,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpCodeData<'r> {
+    jwt: &'r str,
+    code: &'r str,
+}
+
+#[post("/user/totp/enroll", format = "json", data = "<data>")]
+pub async fn user_totp_enroll(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<TotpEnrollData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let user = match get_user_by_id(&mut db, user_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::BadRequest, json!({"error": err})),
+    };
+
+    match get_totp(&mut db, user_id).await {
+        Ok(Some(totp)) if totp.enabled => {
+            return (
+                Status::BadRequest,
+                json!({"error": "two-factor authentication already enabled"}),
+            )
+        }
+        Ok(_) => (),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    let secret = generate_secret();
+
+    if let Err(err) = set_totp_secret(&mut db, user_id, &secret).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (
+        Status::Ok,
+        json!({
+            "secret": secret,
+            "otpauth_uri": otpauth_uri(&config.totp, &user.email, &secret),
+        }),
+    )
+}
+
+#[post("/user/totp/confirm", format = "json", data = "<data>")]
+pub async fn user_totp_confirm(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<TotpCodeData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let totp = match get_totp(&mut db, user_id).await {
+        Ok(Some(val)) => val,
+        Ok(None) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "two-factor authentication not enrolled"}),
+            )
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    if totp.enabled {
+        return (
+            Status::BadRequest,
+            json!({"error": "two-factor authentication already enabled"}),
+        );
+    }
+
+    match verify_totp_code(&mut db, &config.totp, &totp, data.code).await {
+        Ok(true) => (),
+        Ok(false) => return (Status::BadRequest, json!({"error": "invalid code"})),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    let codes = generate_recovery_codes();
+
+    if let Err(err) = replace_recovery_codes(&mut db, user_id, &codes).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    if let Err(err) = enable_totp(&mut db, user_id).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    logging::info(&format!(
+        "User {} enabled two-factor authentication",
+        user_id
+    ));
+
+    (Status::Ok, json!({"recovery_codes": codes}))
+}
+
+#[post("/user/totp/disable", format = "json", data = "<data>")]
+pub async fn user_totp_disable(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<TotpCodeData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let totp = match get_totp(&mut db, user_id).await {
+        Ok(Some(val)) if val.enabled => val,
+        Ok(_) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "two-factor authentication not enabled"}),
+            )
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    match verify_totp_or_recovery_code(&mut db, &config.totp, &totp, data.code).await {
+        Ok(true) => (),
+        Ok(false) => return (Status::BadRequest, json!({"error": "invalid code"})),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    if let Err(err) = delete_totp(&mut db, user_id).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    logging::info(&format!(
+        "User {} disabled two-factor authentication",
+        user_id
+    ));
+
+    (Status::Ok, json!({}))
+}
+
+// Invalidates the old codes, only a code from the authenticator is accepted here
+#[post("/user/totp/recovery_codes", format = "json", data = "<data>")]
+pub async fn user_totp_recovery_codes(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<TotpCodeData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let totp = match get_totp(&mut db, user_id).await {
+        Ok(Some(val)) if val.enabled => val,
+        Ok(_) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "two-factor authentication not enabled"}),
+            )
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    match verify_totp_code(&mut db, &config.totp, &totp, data.code).await {
+        Ok(true) => (),
+        Ok(false) => return (Status::BadRequest, json!({"error": "invalid code"})),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    let codes = generate_recovery_codes();
+
+    if let Err(err) = replace_recovery_codes(&mut db, user_id, &codes).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    (Status::Ok, json!({"recovery_codes": codes}))
+}
+
+#[post("/user/totp/status", format = "json", data = "<data>")]
+pub async fn user_totp_status(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<TotpEnrollData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    let enabled = match get_totp(&mut db, user_id).await {
+        Ok(val) => val.is_some_and(|val| val.enabled),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    let recovery_codes_left = match get_recovery_code_count(&mut db, user_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    (
+        Status::Ok,
+        json!({"enabled": enabled, "recovery_codes_left": recovery_codes_left}),
+    )
+}
@@ -0,0 +1,90 @@
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+
+use crate::{
+    config::AppConfig,
+    ratelimit::{LimitedRoute, RateLimit},
+    user::{get_user_by_id, login::issue_session},
+    util::check_not_banned,
+    DB,
+};
+
+use super::{delete_login_challenge, get_login_challenge, get_totp, verify_totp_or_recovery_code};
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct LoginTotpData<'r> {
+    challenge: &'r str,
+    code: &'r str,
+}
+
+// Second step of auth_login, the challenge stays valid until it expires or a code is accepted
+#[post("/auth/login/totp", format = "json", data = "<data>")]
+pub async fn auth_login_totp(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
+    data: Json<LoginTotpData<'_>>,
+) -> (Status, Value) {
+    let user_id = match get_login_challenge(&mut db, data.challenge).await {
+        Ok(val) => val,
+        Err(err) => return (Status::BadRequest, json!({"error": err})),
+    };
+
+    let user = match get_user_by_id(&mut db, user_id).await {
+        Ok(val) => val,
+        Err(err) => return (Status::BadRequest, json!({"error": err})),
+    };
+
+    if let Some(err) = limit.check(LimitedRoute::Login, Some(&user.email)) {
+        return err;
+    }
+
+    if let Some(err) = limit.check_lockout(&user.email) {
+        return err;
+    }
+
+    if let Some(err) = check_not_banned(&mut db, user_id).await {
+        return err;
+    }
+
+    let totp = match get_totp(&mut db, user_id).await {
+        Ok(Some(val)) if val.enabled => val,
+        Ok(_) => {
+            return (
+                Status::BadRequest,
+                json!({"error": "two-factor authentication not enabled"}),
+            )
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
+
+    match verify_totp_or_recovery_code(&mut db, &config.totp, &totp, data.code).await {
+        Ok(true) => (),
+        Ok(false) => {
+            limit.login_failed(&user.email);
+            return (Status::BadRequest, json!({"error": "invalid code"}));
+        }
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    if let Err(err) = delete_login_challenge(&mut db, data.challenge).await {
+        return (Status::InternalServerError, json!({"error": err}));
+    }
+
+    let result = issue_session(&mut db, config, user_id).await;
+
+    if result.0 == Status::Ok {
+        limit.login_succeeded(&user.email);
+    }
+
+    result
+}
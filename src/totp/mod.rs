@@ -0,0 +1,420 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng, RngCore};
+use rocket::http::RawStr;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::{prelude::FromRow, query, query_as, Row};
+use uuid::Uuid;
+
+use crate::{config::TotpConfig, db::DbConnection};
+
+pub mod enroll;
+pub mod login;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const SECRET_LENGTH: usize = 20;
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// ████████╗ ██████╗ ████████╗██████╗
+// ╚══██╔══╝██╔═══██╗╚══██╔══╝██╔══██╗
+//    ██║   ██║   ██║   ██║   ██████╔╝
+//    ██║   ██║   ██║   ██║   ██╔═══╝
+//    ██║   ╚██████╔╝   ██║   ██║
+//    ╚═╝    ╚═════╝    ╚═╝   ╚═╝
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    result
+}
+
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in data.trim_end_matches('=').chars() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|val| *val as char == c.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(result)
+}
+
+// RFC 4226 with the dynamic truncation from section 5.3
+pub fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    code % 10u32.pow(DIGITS)
+}
+
+pub fn current_step() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs()
+        / STEP
+}
+
+// Returns the step the code belongs to, steps up to last_step were already used
+pub fn find_step(secret: &str, code: &str, skew: u64, last_step: u64) -> Option<u64> {
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let code: u32 = code.parse().ok()?;
+    let key = base32_decode(secret)?;
+    let now = current_step();
+
+    (now.saturating_sub(skew)..=now + skew)
+        .filter(|step| *step > last_step)
+        .find(|step| hotp(&key, *step) == code)
+}
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+pub fn otpauth_uri(config: &TotpConfig, email: &str, secret: &str) -> String {
+    let issuer = RawStr::new(&config.issuer).percent_encode();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        RawStr::new(email).percent_encode(),
+        secret,
+        issuer,
+        DIGITS,
+        STEP
+    )
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = OsRng
+                .sample_iter(&Alphanumeric)
+                .take(8)
+                .map(|c| (c as char).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    let hash = Sha256::digest(code.trim().to_ascii_lowercase().as_bytes());
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// ██████╗  █████╗ ████████╗ █████╗ ██████╗  █████╗ ███████╗███████╗    ███████╗██╗   ██╗███╗   ██╗ ██████╗████████╗██╗ ██████╗ ███╗   ██╗███████╗
+// ██╔══██╗██╔══██╗╚══██╔══╝██╔══██╗██╔══██╗██╔══██╗██╔════╝██╔════╝    ██╔════╝██║   ██║████╗  ██║██╔════╝╚══██╔══╝██║██╔═══██╗████╗  ██║██╔════╝
+// ██║  ██║███████║   ██║   ███████║██████╔╝███████║███████╗█████╗      █████╗  ██║   ██║██╔██╗ ██║██║        ██║   ██║██║   ██║██╔██╗ ██║███████╗
+// ██║  ██║██╔══██║   ██║   ██╔══██║██╔══██╗██╔══██║╚════██║██╔══╝      ██╔══╝  ██║   ██║██║╚██╗██║██║        ██║   ██║██║   ██║██║╚██╗██║╚════██║
+// ██████╔╝██║  ██║   ██║   ██║  ██║██████╔╝██║  ██║███████║███████╗    ██║     ╚██████╔╝██║ ╚████║╚██████╗   ██║   ██║╚██████╔╝██║ ╚████║███████║
+// ╚═════╝ ╚═╝  ╚═╝   ╚═╝   ╚═╝  ╚═╝╚═════╝ ╚═╝  ╚═╝╚══════╝╚══════╝    ╚═╝      ╚═════╝ ╚═╝  ╚═══╝ ╚═════╝   ╚═╝   ╚═╝ ╚═════╝ ╚═╝  ╚═══╝╚══════╝
+
+#[derive(Debug, FromRow)]
+pub struct TotpDB {
+    #[sqlx(try_from = "i64")]
+    pub user_id: u32,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: i64,
+    pub timestamp: i64,
+}
+
+pub async fn get_totp(db: &mut DbConnection, user_id: u32) -> Result<Option<TotpDB>, String> {
+    match query_as("SELECT * FROM totp WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .fetch_optional(db)
+        .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get two-factor authentication: {}", err)),
+    }
+}
+
+pub async fn is_totp_enabled(db: &mut DbConnection, user_id: u32) -> Result<bool, String> {
+    Ok(get_totp(db, user_id).await?.is_some_and(|val| val.enabled))
+}
+
+// Replaces a pending enrolment, enabled secrets have to be disabled first
+pub async fn set_totp_secret(
+    db: &mut DbConnection,
+    user_id: u32,
+    secret: &str,
+) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    if let Err(err) = query("DELETE FROM totp WHERE user_id = $1 AND enabled = false")
+        .bind(i64::from(user_id))
+        .execute(&mut *db)
+        .await
+    {
+        return Err(format!(
+            "Failed to enrol two-factor authentication: {}",
+            err
+        ));
+    }
+
+    match query(
+        "INSERT INTO
+                totp
+                (user_id, secret, enabled, last_step, timestamp)
+                VALUES ($1,$2,false,0,$3)",
+    )
+    .bind(i64::from(user_id))
+    .bind(secret)
+    .bind(timestamp)
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to enrol two-factor authentication: {}",
+            err
+        )),
+    }
+}
+
+pub async fn enable_totp(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    match query("UPDATE totp SET enabled = true WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to enable two-factor authentication: {}",
+            err
+        )),
+    }
+}
+
+pub async fn delete_totp(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    if let Err(err) = query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(&mut *db)
+        .await
+    {
+        return Err(format!("Failed to delete recovery codes: {}", err));
+    }
+
+    match query("DELETE FROM totp WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to disable two-factor authentication: {}",
+            err
+        )),
+    }
+}
+
+async fn update_last_step(db: &mut DbConnection, user_id: u32, step: u64) -> Result<(), String> {
+    match query("UPDATE totp SET last_step = $1 WHERE user_id = $2")
+        .bind(step as i64)
+        .bind(i64::from(user_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to update two-factor authentication: {}",
+            err
+        )),
+    }
+}
+
+pub async fn replace_recovery_codes(
+    db: &mut DbConnection,
+    user_id: u32,
+    codes: &[String],
+) -> Result<(), String> {
+    if let Err(err) = query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(&mut *db)
+        .await
+    {
+        return Err(format!("Failed to replace recovery codes: {}", err));
+    }
+
+    for code in codes {
+        if let Err(err) = query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1,$2)")
+            .bind(i64::from(user_id))
+            .bind(hash_recovery_code(code))
+            .execute(&mut *db)
+            .await
+        {
+            return Err(format!("Failed to replace recovery codes: {}", err));
+        }
+    }
+
+    Ok(())
+}
+
+async fn use_recovery_code(
+    db: &mut DbConnection,
+    user_id: u32,
+    code: &str,
+) -> Result<bool, String> {
+    match query("DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2")
+        .bind(i64::from(user_id))
+        .bind(hash_recovery_code(code))
+        .execute(db)
+        .await
+    {
+        Ok(val) => Ok(val.rows_affected() > 0),
+        Err(err) => Err(format!("Failed to check the recovery code: {}", err)),
+    }
+}
+
+pub async fn get_recovery_code_count(db: &mut DbConnection, user_id: u32) -> Result<u32, String> {
+    match query("SELECT COUNT(*) FROM recovery_codes WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => Ok(row.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to count recovery codes: {}", err)),
+    }
+}
+
+// A code is only accepted once, every accepted totp code moves last_step forward
+pub async fn verify_totp_code(
+    db: &mut DbConnection,
+    config: &TotpConfig,
+    totp: &TotpDB,
+    code: &str,
+) -> Result<bool, String> {
+    match find_step(
+        &totp.secret,
+        code.trim(),
+        config.skew,
+        totp.last_step as u64,
+    ) {
+        Some(step) => {
+            update_last_step(db, totp.user_id, step).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub async fn verify_totp_or_recovery_code(
+    db: &mut DbConnection,
+    config: &TotpConfig,
+    totp: &TotpDB,
+    code: &str,
+) -> Result<bool, String> {
+    if verify_totp_code(db, config, totp, code).await? {
+        return Ok(true);
+    }
+
+    use_recovery_code(db, totp.user_id, code).await
+}
+
+pub async fn create_login_challenge(
+    db: &mut DbConnection,
+    user_id: u32,
+    lifetime: i64,
+) -> Result<String, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+    let token = Uuid::new_v4().to_string();
+
+    match query(
+        "INSERT INTO
+                login_challenges
+                (user_id, challenge_token, valid_until)
+                VALUES ($1,$2,$3)",
+    )
+    .bind(i64::from(user_id))
+    .bind(&token)
+    .bind(timestamp + lifetime)
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(token),
+        Err(err) => Err(format!("Failed to create the login challenge: {}", err)),
+    }
+}
+
+pub async fn get_login_challenge(db: &mut DbConnection, token: &str) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query(
+        "SELECT user_id FROM login_challenges WHERE challenge_token = $1 AND valid_until > $2",
+    )
+    .bind(token)
+    .bind(timestamp)
+    .fetch_optional(db)
+    .await
+    {
+        Ok(Some(row)) => Ok(row.get::<i64, _>(0) as u32),
+        Ok(None) => Err("invalid or expired challenge".to_owned()),
+        Err(err) => Err(format!("Failed to get the login challenge: {}", err)),
+    }
+}
+
+pub async fn delete_login_challenge(db: &mut DbConnection, token: &str) -> Result<(), String> {
+    match query("DELETE FROM login_challenges WHERE challenge_token = $1")
+        .bind(token)
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to delete the login challenge: {}", err)),
+    }
+}
@@ -1,7 +1,4 @@
-use std::{
-    env,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use resend_rs::{types::CreateEmailBaseOptions, Resend};
 use rocket::{
@@ -11,12 +8,19 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
 use crate::{
-    user::{delete_user_db, get_delete_request_by_token, get_user_by_id}, util::is_paused, DB
+    api::V1_BASE,
+    config::AppConfig,
+    ratelimit::{LimitedRoute, RateLimit},
+    user::{delete_user_db, get_delete_request_by_token, get_user_by_id},
+    util::is_paused,
+    DB,
 };
 
 use super::{
@@ -24,28 +28,29 @@ use super::{
     remove_delete_request_by_user_id, start_delete,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct DeleteData<'r> {
     email: &'r str,
 }
 
-async fn send_delete_user_email(email: &str, delete_token: &str) -> Result<(), String> {
-    if env::var("WIEDZIELISCIE_BACKEND_KEIN_MAIL").is_ok() {
+async fn send_delete_user_email(
+    config: &AppConfig,
+    email: &str,
+    delete_token: &str,
+) -> Result<(), String> {
+    if config.mail.disabled {
         return Ok(());
     }
 
-    let resend = Resend::default();
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
 
-    let from = match env::var("WIEDZIELISCIE_BACKEND_FROM_MAIL") {
-        Ok(val) => val,
-        Err(_) => return Err("From mail not found".to_owned()),
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
     };
     let subject = "Confirm your account deletion request";
-    let password_reset_link = match env::var("WIEDZIELISCIE_BACKEND_URL") {
-        Ok(val) => val + "/auth/delete_user/verify/" + delete_token,
-        Err(_) => return Err("Url not found".to_owned()),
-    };
+    let password_reset_link = config.url.clone() + V1_BASE + "/users/me/delete/" + delete_token;
 
     let email = CreateEmailBaseOptions::new(from, [email], subject).with_html(&format!( "
             <a href=\"{}\">Click this to confirm account deletion</a>
@@ -62,9 +67,18 @@ async fn send_delete_user_email(email: &str, delete_token: &str) -> Result<(), S
 }
 
 #[post("/auth/delete_user", format = "json", data = "<data>")]
-pub async fn delete_user(mut db: Connection<DB>, data: Json<DeleteData<'_>>) -> (Status, Value) {
+pub async fn delete_user(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
+    data: Json<DeleteData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = limit.check(LimitedRoute::DeleteUser, Some(data.email)) {
+        return err;
+    }
+
     if is_paused(&mut db).await {
-        return (Status::Unauthorized, json!({"error": "Game paused"}))
+        return (Status::Unauthorized, json!({"error": "Game paused"}));
     }
 
     let user = match get_user_by_email(&mut db, data.email).await {
@@ -86,9 +100,9 @@ pub async fn delete_user(mut db: Connection<DB>, data: Json<DeleteData<'_>>) ->
                 };
 
                 if timestamp > reset.valid_until {
-                    match remove_delete_request_by_user_id(&mut db, user.user_id).await {
-                        Err(err) => return (Status::InternalServerError, json!({"error": err})),
-                        _ => {}
+                    if let Err(err) = remove_delete_request_by_user_id(&mut db, user.user_id).await
+                    {
+                        return (Status::InternalServerError, json!({"error": err}));
                     }
                 } else {
                     return (
@@ -103,11 +117,18 @@ pub async fn delete_user(mut db: Connection<DB>, data: Json<DeleteData<'_>>) ->
 
     let token = Uuid::new_v4().to_string();
 
-    if let Err(err) = start_delete(&mut db, user.user_id, &token).await {
+    if let Err(err) = start_delete(
+        &mut db,
+        user.user_id,
+        &token,
+        config.tokens.delete_request_lifetime,
+    )
+    .await
+    {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if let Err(err) = send_delete_user_email(&user.email, &token).await {
+    if let Err(err) = send_delete_user_email(config, &user.email, &token).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
@@ -133,15 +154,15 @@ pub fn get_delete_user_page(title: &str, message: &str) -> String {
 }
 
 #[get("/auth/delete_user/verify/<token>")]
-pub async fn auth_password_reset_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String> {
+pub async fn auth_delete_user_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String> {
     let reset = match get_delete_request_by_token(&mut db, token).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_delete_user_page(&"Password reset failed", &err)),
+        Err(err) => return RawHtml(get_delete_user_page("Account deletion failed", &err)),
     };
 
     let user = match get_user_by_id(&mut db, reset.user_id).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_delete_user_page(&"Password reset failed", &err)),
+        Err(err) => return RawHtml(get_delete_user_page("Account deletion failed", &err)),
     };
 
     let timestamp = SystemTime::now()
@@ -151,17 +172,17 @@ pub async fn auth_password_reset_verify(mut db: Connection<DB>, token: &str) ->
 
     if timestamp > reset.valid_until {
         return RawHtml(get_delete_user_page(
-            &"Account deletion failed",
-            &"Account deletion expired",
+            "Account deletion failed",
+            "Account deletion expired",
         ));
     }
 
     if let Err(err) = delete_user_db(&mut db, user.user_id).await {
-        RawHtml(get_delete_user_page(&"Password reset failed", &err));
+        return RawHtml(get_delete_user_page("Account deletion failed", &err));
     }
 
     RawHtml(get_delete_user_page(
-        &"Account deletion successful",
-        &"You can now close this page",
+        "Account deletion successful",
+        "You can now close this page",
     ))
 }
@@ -0,0 +1,171 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, serde_json::Map, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use rocket_db_pools::Connection;
+use schemars::JsonSchema;
+use sqlx::query;
+
+use crate::{
+    admin::row_to_json,
+    config::AppConfig,
+    db::DbConnection,
+    util::{check_authorized_user, get_token_user_id},
+    DB,
+};
+
+use super::get_user_by_id;
+
+// Every query takes the user id for each ?, tokens and passwords are left out on purpose
+// Progress and ledger entries get their own sections once they are stored
+const EXPORT_SECTIONS: [(&str, &str, usize); 13] = [
+    (
+        "sessions",
+        "SELECT timestamp, valid_until FROM sessions WHERE user_id = $1",
+        1,
+    ),
+    (
+        "verifications",
+        "SELECT timestamp FROM verifications WHERE user_id = $1",
+        1,
+    ),
+    (
+        "password_resets",
+        "SELECT timestamp, valid_until FROM password_resets WHERE user_id = $1",
+        1,
+    ),
+    (
+        "email_updates",
+        "SELECT email, timestamp, valid_until FROM email_updates WHERE user_id = $1",
+        1,
+    ),
+    (
+        "delete_requests",
+        "SELECT timestamp, valid_until FROM delete_requests WHERE user_id = $1",
+        1,
+    ),
+    (
+        "bans",
+        "SELECT reason, timestamp, banned_until FROM bans WHERE user_id = $1",
+        1,
+    ),
+    (
+        "error_reports",
+        "SELECT report_id, title, message, app_version, device_info, status, timestamp, updated
+        FROM error_report WHERE user_id = $1",
+        1,
+    ),
+    (
+        "suggestions",
+        "SELECT report_id, title, message, app_version, device_info, status, timestamp, updated
+        FROM suggestion WHERE user_id = $1",
+        1,
+    ),
+    (
+        "report_comments",
+        "SELECT report_kind, report_id, message, timestamp FROM report_comments
+        WHERE (report_kind = 'error' AND report_id IN (SELECT report_id FROM error_report WHERE user_id = $1))
+        OR (report_kind = 'suggestion' AND report_id IN (SELECT report_id FROM suggestion WHERE user_id = $2))
+        ORDER BY comment_id",
+        2,
+    ),
+    (
+        "two_factor",
+        "SELECT enabled, timestamp FROM totp WHERE user_id = $1",
+        1,
+    ),
+    (
+        "recovery_codes",
+        "SELECT COUNT(*) AS remaining FROM recovery_codes WHERE user_id = $1",
+        1,
+    ),
+    (
+        "audit_log",
+        "SELECT action, entity_type, entity_id, ip, timestamp FROM audit_log
+        WHERE actor_id = $1 ORDER BY audit_id",
+        1,
+    ),
+    (
+        "announcement_reads",
+        "SELECT announcement_id, timestamp FROM announcement_reads WHERE user_id = $1",
+        1,
+    ),
+];
+
+pub async fn export_user_data(db: &mut DbConnection, user_id: u32) -> Result<Value, String> {
+    let user = get_user_by_id(db, user_id).await?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    let mut export = Map::new();
+    export.insert("exported_at".to_owned(), json!(timestamp));
+    export.insert(
+        "profile".to_owned(),
+        json!({
+            "account_id": user.user_id,
+            "email": user.email,
+            "first_name": user.first_name,
+            "last_name": user.last_name,
+            "gender": if user.gender { "m" } else { "f" },
+            "verified": user.verified,
+            "admin": user.admin,
+        }),
+    );
+
+    for (section, statement, binds) in EXPORT_SECTIONS {
+        let mut section_query = query(statement);
+        for _ in 0..binds {
+            section_query = section_query.bind(i64::from(user_id));
+        }
+
+        let rows = match section_query.fetch_all(&mut *db).await {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Failed to export {}: {}", section, err)),
+        };
+
+        let rows = rows
+            .iter()
+            .map(|x| row_to_json(x).map(Value::Object))
+            .collect::<Result<Vec<Value>, String>>()?;
+
+        export.insert(section.to_owned(), Value::Array(rows));
+    }
+
+    Ok(Value::Object(export))
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct ExportData<'r> {
+    jwt: &'r str,
+}
+
+#[post("/user/export", format = "json", data = "<data>")]
+pub async fn user_export(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<ExportData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = check_authorized_user(&mut db, config, data.jwt).await {
+        return err;
+    }
+
+    let user_id = match get_token_user_id(config, data.jwt) {
+        Some(val) => val,
+        None => return (Status::BadRequest, json!({"error": "invalid token"})),
+    };
+
+    match export_user_data(&mut db, user_id).await {
+        Ok(val) => (Status::Ok, val),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
+    }
+}
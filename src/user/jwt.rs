@@ -2,61 +2,51 @@ use jsonwebtoken::{
     decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
 };
 use rocket::serde::{Deserialize, Serialize};
-use std::{
-    env,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Claims {
     pub uid: u32,
     exp: u64,
-    pub token: String
+    pub token: String,
 }
 
-fn get_secret() -> Option<String> {
-    env::var("WIEDZIELISCIE_BACKEND_SECRET").ok()
+pub fn check_secret(secret: &str) -> Result<(), String> {
+    match DecodingKey::from_base64_secret(secret) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Unable to decode secret: {}", err)),
+    }
 }
 
-pub fn get_token(user_id: u32, token: &str) -> Option<String> {
+pub fn get_token(secret: &str, user_id: u32, token: &str, lifetime: i64) -> Option<String> {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs();
-    let jwt_secret = match get_secret() {
-        Some(val) => val,
-        None => return None,
-    };
-    let expiration = timestamp + 2592000;
+    let expiration = timestamp + lifetime as u64;
 
     let claims = Claims {
         uid: user_id,
         exp: expiration,
-        token: token.to_owned()
+        token: token.to_owned(),
     };
     let header = Header::new(Algorithm::HS256);
-    match encode(
+    encode(
         &header,
         &claims,
-        &EncodingKey::from_base64_secret(&jwt_secret).expect("Unable to decode secret"),
-    ) {
-        Ok(val) => Some(val),
-        Err(_) => None,
-    }
+        &EncodingKey::from_base64_secret(secret).expect("Unable to decode secret"),
+    )
+    .ok()
 }
 
-pub fn verify_token(token: &str) -> Result<TokenData<Claims>, String> {
-    let jwt_secret = match get_secret() {
-        Some(val) => val,
-        None => return Err("Unable to get the secret".to_owned()),
-    };
+pub fn verify_token(secret: &str, token: &str) -> Result<TokenData<Claims>, String> {
     match decode::<Claims>(
         token,
-        &DecodingKey::from_base64_secret(&jwt_secret).expect("Unable to decode secret"),
+        &DecodingKey::from_base64_secret(secret).expect("Unable to decode secret"),
         &Validation::new(Algorithm::HS256),
     ) {
         Ok(val) => Ok(val),
-        Err(err) => Err(err.to_string())
+        Err(err) => Err(err.to_string()),
     }
 }
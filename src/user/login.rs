@@ -4,15 +4,24 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
-use crate::{util::is_paused, DB};
+use crate::{
+    config::AppConfig,
+    db::DbConnection,
+    ratelimit::{LimitedRoute, RateLimit},
+    totp::{create_login_challenge, is_totp_enabled},
+    util::{check_not_banned, is_paused},
+    DB,
+};
 
 use super::{get_session_count, get_user_by_email, jwt::get_token, start_session};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct LoginData<'r> {
     email: &'r str,
@@ -20,23 +29,82 @@ pub struct LoginData<'r> {
 }
 
 #[post("/auth/login", format = "json", data = "<data>")]
-pub async fn auth_login(mut db: Connection<DB>, data: Json<LoginData<'_>>) -> (Status, Value) {
+pub async fn auth_login(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
+    data: Json<LoginData<'_>>,
+) -> (Status, Value) {
+    if let Some(err) = limit.check(LimitedRoute::Login, Some(data.email)) {
+        return err;
+    }
+
+    if let Some(err) = limit.check_lockout(data.email) {
+        return err;
+    }
+
     let user = match get_user_by_email(&mut db, data.email).await {
         Ok(val) => val,
         Err(err) => return (Status::BadRequest, json!({"error": err})),
     };
 
     if is_paused(&mut db).await && !user.admin {
-        return (Status::Unauthorized, json!({"error": "Game paused and user isn't admin"}))
+        return (
+            Status::Unauthorized,
+            json!({"error": "Game paused and user isn't admin"}),
+        );
     }
-    
+
     if !user.verified {
         return (Status::BadRequest, json!({"error": "User not verified"}));
     }
 
-    match get_session_count(&mut db, user.user_id).await {
+    if let Some(err) = check_not_banned(&mut db, user.user_id).await {
+        return err;
+    }
+
+    if data.plaintext_password != user.password {
+        limit.login_failed(data.email);
+        return (Status::BadRequest, json!({"error": "Wrong password"}));
+    }
+
+    match is_totp_enabled(&mut db, user.user_id).await {
+        Ok(true) => {
+            return match create_login_challenge(
+                &mut db,
+                user.user_id,
+                config.totp.challenge_lifetime,
+            )
+            .await
+            {
+                Ok(challenge) => (
+                    Status::Accepted,
+                    json!({"totp_required": true, "challenge": challenge}),
+                ),
+                Err(err) => (Status::InternalServerError, json!({"error": err})),
+            };
+        }
+        Ok(false) => (),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
+    let result = issue_session(&mut db, config, user.user_id).await;
+
+    if result.0 == Status::Ok {
+        limit.login_succeeded(data.email);
+    }
+
+    result
+}
+
+pub async fn issue_session(
+    db: &mut DbConnection,
+    config: &AppConfig,
+    user_id: u32,
+) -> (Status, Value) {
+    match get_session_count(db, user_id).await {
         Ok(val) => {
-            if val > 32 {
+            if val > config.sessions.max_per_user {
                 return (
                     Status::BadRequest,
                     json!({"error": "Session limit exceeded"}),
@@ -50,7 +118,9 @@ pub async fn auth_login(mut db: Connection<DB>, data: Json<LoginData<'_>>) -> (S
 
     let token = Uuid::new_v4().to_string();
 
-    let jwt = match get_token(user.user_id, &token) {
+    let lifetime = config.tokens.session_lifetime;
+
+    let jwt = match get_token(&config.secret, user_id, &token, lifetime) {
         Some(val) => val,
         None => {
             return (
@@ -60,13 +130,9 @@ pub async fn auth_login(mut db: Connection<DB>, data: Json<LoginData<'_>>) -> (S
         }
     };
 
-    if let Err(err) = start_session(&mut db, user.user_id, &token).await {
+    if let Err(err) = start_session(db, user_id, &token, lifetime).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if data.plaintext_password == user.password {
-        (Status::Ok, json!({"jwt": jwt}))
-    } else {
-        (Status::BadRequest, json!({"error": "Wrong password"}))
-    }
+    (Status::Ok, json!({"jwt": jwt}))
 }
@@ -6,22 +6,28 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::{util::is_paused, DB};
+use crate::{config::AppConfig, DB};
 
 use super::{get_session_by_token, get_user_by_id, jwt::verify_token, stop_session};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct LogoutData<'r> {
     jwt: &'r str,
 }
 
 #[post("/auth/logout", format = "json", data = "<data>")]
-pub async fn auth_logout(mut db: Connection<DB>, data: Json<LogoutData<'_>>) -> (Status, Value) {
-    let claims = match verify_token(data.jwt) {
+pub async fn auth_logout(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    data: Json<LogoutData<'_>>,
+) -> (Status, Value) {
+    let claims = match verify_token(&config.secret, data.jwt) {
         Ok(val) => val.claims,
         Err(_) => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
@@ -1,9 +1,14 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use rocket::serde::Serialize;
-use sqlx::{prelude::FromRow, query, query_as, Row, SqliteConnection};
+use schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+use sqlx::{prelude::FromRow, query, query_as, Connection, Row};
+
+use crate::{audit::anonymise_audit_entries, db::DbConnection, logging, totp::delete_totp};
 
 pub mod delete_user;
+pub mod export;
 pub mod jwt;
 pub mod login;
 pub mod logout;
@@ -29,6 +34,7 @@ pub mod verifyless_updates;
 
 #[derive(Debug, FromRow)]
 pub struct UserDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub first_name: String,
     pub last_name: String,
@@ -39,19 +45,21 @@ pub struct UserDB {
     pub admin: bool,
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct User {
+    #[sqlx(try_from = "i64")]
     pub account_id: u32,
     pub email: String,
     pub first_name: String,
     pub last_name: String,
     pub gender: bool,
+    #[sqlx(try_from = "i64")]
     pub points: u32,
 }
 
-async fn email_taken(db: &mut SqliteConnection, email: &str) -> Result<bool, String> {
-    match query("SELECT user_id FROM users WHERE ? = email")
+pub async fn email_taken(db: &mut DbConnection, email: &str) -> Result<bool, String> {
+    match query("SELECT user_id FROM users WHERE lower(email) = lower($1)")
         .bind(email)
         .fetch_optional(db)
         .await
@@ -64,55 +72,38 @@ async fn email_taken(db: &mut SqliteConnection, email: &str) -> Result<bool, Str
     }
 }
 
-async fn next_user_id(db: &mut SqliteConnection) -> Result<u32, String> {
-    match query("SELECT MAX(user_id) FROM users")
-        .fetch_optional(db)
-        .await
-    {
-        Ok(val) => match val {
-            Some(row) => match row.try_get::<u32, _>(0) {
-                Ok(id) => Ok(id + 1),
-                Err(_) => Err("Database error".to_owned()),
-            },
-            None => Ok(1),
-        },
-        Err(_) => Err("Failed to perform a database query".to_owned()),
-    }
-}
-
-async fn create_user(
-    db: &mut SqliteConnection,
-    id: u32,
+pub async fn create_user(
+    db: &mut DbConnection,
     first_name: &str,
     last_name: &str,
     email: &str,
     password: &str,
     gender: char,
-) -> Result<(), String> {
+) -> Result<u32, String> {
     match query(
         "INSERT INTO 
                 users 
-                (user_id, first_name, last_name, email, 
+                (first_name, last_name, email, 
                 password, gender, verified, admin) 
-                VALUES (?,?,?,?,?,?,0,0)",
+                VALUES ($1,$2,$3,$4,$5,false,false)
+        RETURNING user_id",
     )
-    .bind(id)
     .bind(first_name)
     .bind(last_name)
     .bind(email)
     .bind(password)
     .bind(gender == 'm')
-    .execute(db)
+    .fetch_one(db)
     .await
     {
-        Ok(_) => Ok(()),
+        Ok(val) => Ok(val.get::<i64, _>(0) as u32),
         Err(err) => Err(format!("Failed to insert user into the database: {}", err)),
     }
 }
 
-pub async fn get_user_by_id(db: &mut SqliteConnection, user_id: u32) -> Result<UserDB, String> {
-    let user: UserDB = match query_as("SELECT * FROM users WHERE user_id = ?")
-        .bind(user_id)
+pub async fn get_user_by_id(db: &mut DbConnection, user_id: u32) -> Result<UserDB, String> {
+    let user: UserDB = match query_as("SELECT * FROM users WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .fetch_optional(db)
         .await
     {
@@ -126,8 +117,8 @@ pub async fn get_user_by_id(db: &mut SqliteConnection, user_id: u32) -> Result<U
     Ok(user)
 }
 
-pub async fn get_user_by_email(db: &mut SqliteConnection, email: &str) -> Result<UserDB, String> {
-    let user: UserDB = match query_as("SELECT * FROM users WHERE email = ?")
+pub async fn get_user_by_email(db: &mut DbConnection, email: &str) -> Result<UserDB, String> {
+    let user: UserDB = match query_as("SELECT * FROM users WHERE lower(email) = lower($1)")
         .bind(email)
         .fetch_optional(db)
         .await
@@ -143,11 +134,11 @@ pub async fn get_user_by_email(db: &mut SqliteConnection, email: &str) -> Result
 }
 
 pub async fn update_user_verification_status(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<(), String> {
-    match query("UPDATE users SET verified = 1 WHERE user_id = ?")
-        .bind(user_id)
+    match query("UPDATE users SET verified = true WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -160,13 +151,13 @@ pub async fn update_user_verification_status(
 }
 
 pub async fn update_user_password(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     password: &str,
 ) -> Result<(), String> {
-    match query("UPDATE users SET password = ? WHERE user_id = ?")
+    match query("UPDATE users SET password = $1 WHERE user_id = $2")
         .bind(password)
-        .bind(user_id)
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -176,13 +167,13 @@ pub async fn update_user_password(
 }
 
 pub async fn update_user_email(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     email: &str,
 ) -> Result<(), String> {
-    match query("UPDATE users SET email = ? WHERE user_id = ?")
+    match query("UPDATE users SET email = $1 WHERE user_id = $2")
         .bind(email)
-        .bind(user_id)
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -191,21 +182,42 @@ pub async fn update_user_email(
     }
 }
 
-pub async fn delete_user_db(db: &mut SqliteConnection, user_id: u32) -> Result<(), String> {
+// Rows that only make sense while the account exists, reports and bans stay until anonymisation
+const DELETED_USER_TABLES: [&str; 7] = [
+    "sessions",
+    "verifications",
+    "password_resets",
+    "email_updates",
+    "delete_requests",
+    "login_challenges",
+    "announcement_reads",
+];
+
+pub async fn delete_user_db(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
     let user: UserDB = match get_user_by_id(db, user_id).await {
         Ok(val) => val,
         Err(err) => return Err(format!("Failed to get user: {}", err)),
     };
-    println!("DELETE CALLED");
+    logging::info(&format!("Deleting user {}", user_id));
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+    };
 
     match query(
         "INSERT INTO 
                 deleted_users 
                 (user_id, first_name, last_name, email, 
-                password, gender, verified, admin) 
-                VALUES (?,?,?,?,?,?,?,?)",
+                password, gender, verified, admin, deleted_at, anonymised) 
+                VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,false)",
     )
-    .bind(user.user_id)
+    .bind(i64::from(user.user_id))
     .bind(user.first_name)
     .bind(user.last_name)
     .bind(user.email)
@@ -213,59 +225,75 @@ pub async fn delete_user_db(db: &mut SqliteConnection, user_id: u32) -> Result<(
     .bind(user.gender)
     .bind(user.verified)
     .bind(user.admin)
-    .execute(&mut *db)
+    .bind(timestamp)
+    .execute(&mut *tx)
     .await
     {
         Ok(_) => (),
         Err(err) => return Err(format!("Failed to delete the user: {}", err)),
     }
 
-    match query("DELETE FROM users WHERE user_id = ?")
-        .bind(user.user_id)
-        .execute(db)
+    for table in DELETED_USER_TABLES {
+        if let Err(err) = query(&format!("DELETE FROM {} WHERE user_id = $1", table))
+            .bind(i64::from(user.user_id))
+            .execute(&mut *tx)
+            .await
+        {
+            return Err(format!("Failed to delete the user's {}: {}", table, err));
+        }
+    }
+
+    if let Err(err) = delete_totp(&mut tx, user.user_id).await {
+        return Err(format!("Failed to delete the user: {}", err));
+    }
+
+    if let Err(err) = query("DELETE FROM users WHERE user_id = $1")
+        .bind(i64::from(user.user_id))
+        .execute(&mut *tx)
         .await
     {
+        return Err(format!("Failed to delete the user: {}", err));
+    }
+
+    match tx.commit().await {
         Ok(_) => Ok(()),
         Err(err) => Err(format!("Failed to delete the user: {}", err)),
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum UserField {
+    FirstName,
+    LastName,
+    Gender,
+}
+
 pub async fn update_user_name_or_gender(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
-    field: &str,
+    field: UserField,
     data: &str,
 ) -> Result<(), String> {
-    if field == "gender" {
-        match query(&format!("UPDATE users SET {} = ? WHERE user_id = ?", field))
-            .bind(data == "m")
-            .bind(user_id)
-            .execute(db)
-            .await
-        {
-            Ok(_) => Ok(()),
-            Err(err) => Err(format!("Failed to update user's data: {}", err)),
+    let statement = match field {
+        UserField::FirstName => {
+            query("UPDATE users SET first_name = $1 WHERE user_id = $2").bind(data)
         }
-    } else if field == "last_name" || field == "first_name" {
-        match query(&format!("UPDATE users SET {} = ? WHERE user_id = ?", field))
-            .bind(data)
-            .bind(user_id)
-            .execute(db)
-            .await
-        {
-            Ok(_) => Ok(()),
-            Err(err) => Err(format!("Failed to update user's data: {}", err)),
+        UserField::LastName => {
+            query("UPDATE users SET last_name = $1 WHERE user_id = $2").bind(data)
+        }
+        UserField::Gender => {
+            query("UPDATE users SET gender = $1 WHERE user_id = $2").bind(data == "m")
         }
-    } else {
-        Err("Invalid field".to_owned())
+    };
+
+    match statement.bind(i64::from(user_id)).execute(db).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to update user's data: {}", err)),
     }
 }
 
-pub async fn retrieve_user_by_email(
-    db: &mut SqliteConnection,
-    email: &str,
-) -> Result<User, String> {
-    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, 0 as points FROM users WHERE users.email = ?")
+pub async fn retrieve_user_by_email(db: &mut DbConnection, email: &str) -> Result<User, String> {
+    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, CAST(0 AS bigint) as points FROM users WHERE lower(users.email) = lower($1)")
         .bind(email)
         .fetch_optional(db)
         .await
@@ -280,9 +308,9 @@ pub async fn retrieve_user_by_email(
     Ok(user)
 }
 
-pub async fn retrieve_user_by_id(db: &mut SqliteConnection, id: u32) -> Result<User, String> {
-    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, 0 as points FROM users WHERE users.user_id = ?")
-        .bind(id)
+pub async fn retrieve_user_by_id(db: &mut DbConnection, id: u32) -> Result<User, String> {
+    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, CAST(0 AS bigint) as points FROM users WHERE users.user_id = $1")
+        .bind(i64::from(id))
         .fetch_optional(db)
         .await
     {
@@ -297,11 +325,11 @@ pub async fn retrieve_user_by_id(db: &mut SqliteConnection, id: u32) -> Result<U
 }
 
 pub async fn retrieve_user_by_names(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     first_name: &str,
     last_name: &str,
 ) -> Result<User, String> {
-    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, 0 as points FROM users WHERE users.first_name = ? AND users.last_name = ?")
+    let user: User = match query_as("SELECT users.user_id as account_id, users.email, users.first_name, users.last_name, users.gender, CAST(0 AS bigint) as points FROM users WHERE users.first_name = $1 AND users.last_name = $2")
         .bind(first_name)
         .bind(last_name)
         .fetch_optional(db)
@@ -317,6 +345,126 @@ pub async fn retrieve_user_by_names(
     Ok(user)
 }
 
+pub async fn update_user_admin_status(
+    db: &mut DbConnection,
+    user_id: u32,
+    admin: bool,
+) -> Result<(), String> {
+    match query("UPDATE users SET admin = $1 WHERE user_id = $2")
+        .bind(admin)
+        .bind(i64::from(user_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to update user's admin status: {}", err)),
+    }
+}
+
+pub async fn get_admin_count(db: &mut DbConnection) -> Result<u32, String> {
+    match query("SELECT COUNT(user_id) FROM users WHERE admin = true")
+        .fetch_one(db)
+        .await
+    {
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(val) => Ok(val as u32),
+            Err(_) => Err("Database error".to_owned()),
+        },
+        Err(err) => Err(format!("Failed to get admin count: {}", err)),
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserSummaryDB {
+    #[sqlx(try_from = "i64")]
+    pub user_id: u32,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub gender: bool,
+    pub verified: bool,
+    pub admin: bool,
+    pub banned: bool,
+    pub banned_until: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+pub struct UserFilter<'r> {
+    pub verified: Option<bool>,
+    pub admin: Option<bool>,
+    pub banned: Option<bool>,
+    pub search: Option<&'r str>,
+}
+
+const USER_FILTER_QUERY: &str = "FROM users
+    LEFT JOIN bans ON bans.user_id = users.user_id
+        AND (bans.banned_until IS NULL OR bans.banned_until > $1)
+    WHERE ($2 IS NULL OR users.verified = $2)
+    AND ($3 IS NULL OR users.admin = $3)
+    AND ($4 IS NULL OR (bans.user_id IS NOT NULL) = $4)
+    AND ($5 IS NULL OR lower(users.email) LIKE lower($5) OR lower(users.first_name) LIKE lower($5)
+        OR lower(users.last_name) LIKE lower($5))";
+
+pub async fn list_users(
+    db: &mut DbConnection,
+    filter: &UserFilter<'_>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<UserSummaryDB>, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs();
+
+    match query_as::<_, UserSummaryDB>(&format!(
+        "SELECT users.user_id, users.first_name, users.last_name, users.email, users.gender,
+        users.verified, users.admin, bans.user_id IS NOT NULL as banned, bans.banned_until
+        {}
+        ORDER BY users.user_id
+        LIMIT $6 OFFSET $7",
+        USER_FILTER_QUERY
+    ))
+    .bind(timestamp as i64)
+    .bind(filter.verified)
+    .bind(filter.admin)
+    .bind(filter.banned)
+    .bind(filter.search.map(|x| format!("%{}%", x)))
+    .bind(i64::from(limit))
+    .bind(i64::from(offset))
+    .fetch_all(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to list users: {}", err)),
+    }
+}
+
+pub async fn count_users(db: &mut DbConnection, filter: &UserFilter<'_>) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs();
+
+    match query(&format!(
+        "SELECT COUNT(users.user_id) {}",
+        USER_FILTER_QUERY
+    ))
+    .bind(timestamp as i64)
+    .bind(filter.verified)
+    .bind(filter.admin)
+    .bind(filter.banned)
+    .bind(filter.search.map(|x| format!("%{}%", x)))
+    .fetch_one(db)
+    .await
+    {
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(val) => Ok(val as u32),
+            Err(_) => Err("Database error".to_owned()),
+        },
+        Err(err) => Err(format!("Failed to count users: {}", err)),
+    }
+}
+
 // ██╗   ██╗███████╗██████╗ ██╗███████╗██╗ ██████╗ █████╗ ████████╗██╗ ██████╗ ███╗   ██╗
 // ██║   ██║██╔════╝██╔══██╗██║██╔════╝██║██╔════╝██╔══██╗╚══██╔══╝██║██╔═══██╗████╗  ██║
 // ██║   ██║█████╗  ██████╔╝██║█████╗  ██║██║     ███████║   ██║   ██║██║   ██║██╔██╗ ██║
@@ -326,18 +474,19 @@ pub async fn retrieve_user_by_names(
 
 #[derive(Debug, FromRow)]
 pub struct VerificationDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub timestamp: i64,
     pub verification_token: String,
 }
 
 pub async fn get_verification_by_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<VerificationDB, String> {
     let verification: VerificationDB =
-        match query_as("SELECT * FROM verifications WHERE user_id = ?")
-            .bind(user_id)
+        match query_as("SELECT * FROM verifications WHERE user_id = $1")
+            .bind(i64::from(user_id))
             .fetch_optional(db)
             .await
         {
@@ -352,11 +501,11 @@ pub async fn get_verification_by_id(
 }
 
 pub async fn get_verification_by_token(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     token: &str,
 ) -> Result<VerificationDB, String> {
     let verification: VerificationDB =
-        match query_as("SELECT * FROM verifications WHERE verification_token = ?")
+        match query_as("SELECT * FROM verifications WHERE verification_token = $1")
             .bind(token)
             .fetch_optional(db)
             .await
@@ -372,7 +521,7 @@ pub async fn get_verification_by_token(
 }
 
 pub async fn add_verification(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     token: &str,
 ) -> Result<(), String> {
@@ -385,9 +534,9 @@ pub async fn add_verification(
         "INSERT INTO 
                 verifications
                 (user_id, timestamp, verification_token) 
-                VALUES (?,?,?)",
+                VALUES ($1,$2,$3)",
     )
-    .bind(user_id)
+    .bind(i64::from(user_id))
     .bind(timestamp as i64)
     .bind(token)
     .execute(db)
@@ -401,9 +550,9 @@ pub async fn add_verification(
     }
 }
 
-pub async fn remove_verification(db: &mut SqliteConnection, user_id: u32) -> Result<(), String> {
-    match query("DELETE FROM verifications WHERE user_id = ?")
-        .bind(user_id)
+pub async fn remove_verification(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    match query("DELETE FROM verifications WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -424,6 +573,7 @@ pub async fn remove_verification(db: &mut SqliteConnection, user_id: u32) -> Res
 
 #[derive(Debug, FromRow)]
 pub struct SessionDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub session_token: String,
     pub timestamp: i64,
@@ -431,9 +581,10 @@ pub struct SessionDB {
 }
 
 pub async fn start_session(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     token: &str,
+    lifetime: i64,
 ) -> Result<(), String> {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -444,12 +595,12 @@ pub async fn start_session(
         "INSERT INTO 
                 sessions
                 (user_id, session_token, timestamp, valid_until) 
-                VALUES (?,?,?,?)",
+                VALUES ($1,$2,$3,$4)",
     )
-    .bind(user_id)
+    .bind(i64::from(user_id))
     .bind(token)
     .bind(timestamp as i64)
-    .bind(timestamp as i64 + 2592000)
+    .bind(timestamp as i64 + lifetime)
     .execute(db)
     .await
     {
@@ -461,9 +612,9 @@ pub async fn start_session(
     }
 }
 
-pub async fn stop_all_sessions(db: &mut SqliteConnection, user_id: u32) -> Result<(), String> {
-    match query("DELETE FROM sessions WHERE user_id = ?")
-        .bind(user_id)
+pub async fn stop_all_sessions(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    match query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -472,8 +623,8 @@ pub async fn stop_all_sessions(db: &mut SqliteConnection, user_id: u32) -> Resul
     }
 }
 
-pub async fn stop_session(db: &mut SqliteConnection, token: &str) -> Result<(), String> {
-    match query("DELETE FROM sessions WHERE session_token = ?")
+pub async fn stop_session(db: &mut DbConnection, token: &str) -> Result<(), String> {
+    match query("DELETE FROM sessions WHERE session_token = $1")
         .bind(token)
         .execute(db)
         .await
@@ -483,16 +634,16 @@ pub async fn stop_session(db: &mut SqliteConnection, token: &str) -> Result<(),
     }
 }
 
-pub async fn get_session_count(db: &mut SqliteConnection, user_id: u32) -> Result<u32, String> {
-    let query = query("SELECT COUNT(session_token) FROM sessions WHERE user_id = ?")
-        .bind(user_id)
+pub async fn get_session_count(db: &mut DbConnection, user_id: u32) -> Result<u32, String> {
+    let query = query("SELECT COUNT(session_token) FROM sessions WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .fetch_optional(db)
         .await;
 
     let val: u32 = match query {
         Ok(row) => {
             let row = row.unwrap();
-            row.get(0)
+            row.get::<i64, _>(0) as u32
         }
         Err(err) => return Err(format!("Failed to get session count: {}", err)),
     };
@@ -500,21 +651,35 @@ pub async fn get_session_count(db: &mut SqliteConnection, user_id: u32) -> Resul
     Ok(val)
 }
 
-pub async fn get_session_by_token(
-    db: &mut SqliteConnection,
-    token: &str,
-) -> Result<SessionDB, String> {
-    let password_reset: SessionDB = match query_as("SELECT * FROM sessions WHERE session_token = ?")
-        .bind(token)
-        .fetch_optional(db)
+pub async fn get_active_session_count(db: &mut DbConnection) -> Result<u32, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    match query("SELECT COUNT(session_token) FROM sessions WHERE valid_until > $1")
+        .bind(timestamp)
+        .fetch_one(db)
         .await
     {
-        Ok(row) => match row {
-            Some(val) => val,
-            None => return Err("Session not found".to_owned()),
-        },
-        Err(err) => return Err(format!("Failed to get session by token: {}", err)),
-    };
+        Ok(row) => Ok(row.get::<i64, _>(0) as u32),
+        Err(err) => Err(format!("Failed to get active session count: {}", err)),
+    }
+}
+
+pub async fn get_session_by_token(db: &mut DbConnection, token: &str) -> Result<SessionDB, String> {
+    let password_reset: SessionDB =
+        match query_as("SELECT * FROM sessions WHERE session_token = $1")
+            .bind(token)
+            .fetch_optional(db)
+            .await
+        {
+            Ok(row) => match row {
+                Some(val) => val,
+                None => return Err("Session not found".to_owned()),
+            },
+            Err(err) => return Err(format!("Failed to get session by token: {}", err)),
+        };
 
     Ok(password_reset)
 }
@@ -523,15 +688,16 @@ pub async fn get_session_by_token(
 
 #[derive(Debug, FromRow)]
 pub struct AccountDeleteRequestDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub delete_token: String,
     pub timestamp: i64,
     pub valid_until: i64,
 }
 
-pub async fn deletion_in_progress(db: &mut SqliteConnection, user_id: u32) -> Result<bool, String> {
-    match query("SELECT user_id FROM delete_requests WHERE user_id = ?")
-        .bind(user_id)
+pub async fn deletion_in_progress(db: &mut DbConnection, user_id: u32) -> Result<bool, String> {
+    match query("SELECT user_id FROM delete_requests WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .fetch_optional(db)
         .await
     {
@@ -544,9 +710,10 @@ pub async fn deletion_in_progress(db: &mut SqliteConnection, user_id: u32) -> Re
 }
 
 pub async fn start_delete(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     token: &str,
+    lifetime: i64,
 ) -> Result<(), String> {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -557,12 +724,12 @@ pub async fn start_delete(
         "INSERT INTO 
                 delete_requests
                 (user_id, delete_token, timestamp, valid_until) 
-                VALUES (?,?,?,?,?)",
+                VALUES ($1,$2,$3,$4)",
     )
-    .bind(user_id)
+    .bind(i64::from(user_id))
     .bind(token)
     .bind(timestamp as i64)
-    .bind(timestamp as i64 + 300)
+    .bind(timestamp as i64 + lifetime)
     .execute(db)
     .await
     {
@@ -575,11 +742,11 @@ pub async fn start_delete(
 }
 
 pub async fn get_delete_request_by_token(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     token: &str,
 ) -> Result<AccountDeleteRequestDB, String> {
     let delete_request: AccountDeleteRequestDB =
-        match query_as("SELECT * FROM delete_requests WHERE delete_token = ?")
+        match query_as("SELECT * FROM delete_requests WHERE delete_token = $1")
             .bind(token)
             .fetch_optional(db)
             .await
@@ -595,12 +762,12 @@ pub async fn get_delete_request_by_token(
 }
 
 pub async fn get_delete_request_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<AccountDeleteRequestDB, String> {
     let delete_request: AccountDeleteRequestDB =
-        match query_as("SELECT * FROM delete_requests WHERE user_id = ?")
-            .bind(user_id)
+        match query_as("SELECT * FROM delete_requests WHERE user_id = $1")
+            .bind(i64::from(user_id))
             .fetch_optional(db)
             .await
         {
@@ -615,11 +782,11 @@ pub async fn get_delete_request_by_user_id(
 }
 
 pub async fn remove_delete_request_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<(), String> {
-    match query("DELETE FROM delete_requests user_id = ?")
-        .bind(user_id)
+    match query("DELETE FROM delete_requests user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -637,6 +804,7 @@ pub async fn remove_delete_request_by_user_id(
 
 #[derive(Debug, FromRow)]
 pub struct PasswordResetDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub reset_token: String,
     pub password: String,
@@ -644,9 +812,9 @@ pub struct PasswordResetDB {
     pub valid_until: i64,
 }
 
-pub async fn reset_in_progress(db: &mut SqliteConnection, user_id: u32) -> Result<bool, String> {
-    match query("SELECT user_id FROM password_resets WHERE user_id = ?")
-        .bind(user_id)
+pub async fn reset_in_progress(db: &mut DbConnection, user_id: u32) -> Result<bool, String> {
+    match query("SELECT user_id FROM password_resets WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .fetch_optional(db)
         .await
     {
@@ -659,10 +827,11 @@ pub async fn reset_in_progress(db: &mut SqliteConnection, user_id: u32) -> Resul
 }
 
 pub async fn start_reset(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     password: &str,
     token: &str,
+    lifetime: i64,
 ) -> Result<(), String> {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -673,13 +842,13 @@ pub async fn start_reset(
         "INSERT INTO 
                 password_resets
                 (user_id, reset_token, password, timestamp, valid_until) 
-                VALUES (?,?,?,?,?)",
+                VALUES ($1,$2,$3,$4,$5)",
     )
-    .bind(user_id)
+    .bind(i64::from(user_id))
     .bind(token)
     .bind(password)
     .bind(timestamp as i64)
-    .bind(timestamp as i64 + 300)
+    .bind(timestamp as i64 + lifetime)
     .execute(db)
     .await
     {
@@ -692,11 +861,11 @@ pub async fn start_reset(
 }
 
 pub async fn get_reset_by_token(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     token: &str,
 ) -> Result<PasswordResetDB, String> {
     let password_reset: PasswordResetDB =
-        match query_as("SELECT * FROM password_resets WHERE reset_token = ?")
+        match query_as("SELECT * FROM password_resets WHERE reset_token = $1")
             .bind(token)
             .fetch_optional(db)
             .await
@@ -712,12 +881,12 @@ pub async fn get_reset_by_token(
 }
 
 pub async fn get_reset_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<PasswordResetDB, String> {
     let password_reset: PasswordResetDB =
-        match query_as("SELECT * FROM password_resets WHERE user_id = ?")
-            .bind(user_id)
+        match query_as("SELECT * FROM password_resets WHERE user_id = $1")
+            .bind(i64::from(user_id))
             .fetch_optional(db)
             .await
         {
@@ -732,11 +901,11 @@ pub async fn get_reset_by_user_id(
 }
 
 pub async fn remove_password_reset_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<(), String> {
-    match query("DELETE FROM password_resets WHERE user_id = ?")
-        .bind(user_id)
+    match query("DELETE FROM password_resets WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -754,6 +923,7 @@ pub async fn remove_password_reset_by_user_id(
 
 #[derive(Debug, FromRow)]
 pub struct EmailUpdateDB {
+    #[sqlx(try_from = "i64")]
     pub user_id: u32,
     pub update_token: String,
     pub email: String,
@@ -761,12 +931,9 @@ pub struct EmailUpdateDB {
     pub valid_until: i64,
 }
 
-pub async fn email_update_in_progress(
-    db: &mut SqliteConnection,
-    user_id: u32,
-) -> Result<bool, String> {
-    match query("SELECT user_id FROM email_updates WHERE user_id = ?")
-        .bind(user_id)
+pub async fn email_update_in_progress(db: &mut DbConnection, user_id: u32) -> Result<bool, String> {
+    match query("SELECT user_id FROM email_updates WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .fetch_optional(db)
         .await
     {
@@ -779,10 +946,11 @@ pub async fn email_update_in_progress(
 }
 
 pub async fn start_email_update(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
     email: &str,
     token: &str,
+    lifetime: i64,
 ) -> Result<(), String> {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -793,13 +961,13 @@ pub async fn start_email_update(
         "INSERT INTO 
                 email_updates
                 (user_id, update_token, email, timestamp, valid_until) 
-                VALUES (?,?,?,?,?)",
+                VALUES ($1,$2,$3,$4,$5)",
     )
-    .bind(user_id)
+    .bind(i64::from(user_id))
     .bind(token)
     .bind(email)
     .bind(timestamp as i64)
-    .bind(timestamp as i64 + 3600)
+    .bind(timestamp as i64 + lifetime)
     .execute(db)
     .await
     {
@@ -812,11 +980,11 @@ pub async fn start_email_update(
 }
 
 pub async fn get_email_update_by_token(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     token: &str,
 ) -> Result<EmailUpdateDB, String> {
     let password_reset: EmailUpdateDB =
-        match query_as("SELECT * FROM email_updates WHERE update_token = ?")
+        match query_as("SELECT * FROM email_updates WHERE update_token = $1")
             .bind(token)
             .fetch_optional(db)
             .await
@@ -832,12 +1000,12 @@ pub async fn get_email_update_by_token(
 }
 
 pub async fn get_email_update_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<EmailUpdateDB, String> {
     let password_reset: EmailUpdateDB =
-        match query_as("SELECT * FROM email_updates WHERE user_id = ?")
-            .bind(user_id)
+        match query_as("SELECT * FROM email_updates WHERE user_id = $1")
+            .bind(i64::from(user_id))
             .fetch_optional(db)
             .await
         {
@@ -852,11 +1020,11 @@ pub async fn get_email_update_by_user_id(
 }
 
 pub async fn remove_email_updates_by_user_id(
-    db: &mut SqliteConnection,
+    db: &mut DbConnection,
     user_id: u32,
 ) -> Result<(), String> {
-    match query("DELETE FROM email_updates WHERE user_id = ?")
-        .bind(user_id)
+    match query("DELETE FROM email_updates WHERE user_id = $1")
+        .bind(i64::from(user_id))
         .execute(db)
         .await
     {
@@ -864,3 +1032,362 @@ pub async fn remove_email_updates_by_user_id(
         Err(err) => Err(format!("Failed to delete email update: {}", err)),
     }
 }
+
+// ██████╗  █████╗ ███╗   ██╗
+// ██╔══██╗██╔══██╗████╗  ██║
+// ██████╔╝███████║██╔██╗ ██║
+// ██╔══██╗██╔══██║██║╚██╗██║
+// ██████╔╝██║  ██║██║ ╚████║
+// ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═══╝
+
+#[derive(Debug, FromRow)]
+pub struct BanDB {
+    #[sqlx(try_from = "i64")]
+    pub user_id: u32,
+    #[sqlx(try_from = "i64")]
+    pub admin_id: u32,
+    pub reason: Option<String>,
+    pub timestamp: i64,
+    pub banned_until: Option<i64>,
+}
+
+pub async fn ban_user(
+    db: &mut DbConnection,
+    user_id: u32,
+    admin_id: u32,
+    reason: Option<&str>,
+    banned_until: Option<i64>,
+) -> Result<(), String> {
+    unban_user(&mut *db, user_id).await?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs();
+
+    match query(
+        "INSERT INTO
+                bans
+                (user_id, admin_id, reason, timestamp, banned_until)
+                VALUES ($1,$2,$3,$4,$5)",
+    )
+    .bind(i64::from(user_id))
+    .bind(i64::from(admin_id))
+    .bind(reason)
+    .bind(timestamp as i64)
+    .bind(banned_until)
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to insert ban into the database: {}", err)),
+    }
+}
+
+pub async fn unban_user(db: &mut DbConnection, user_id: u32) -> Result<(), String> {
+    match query("DELETE FROM bans WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(db)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to delete ban: {}", err)),
+    }
+}
+
+pub async fn get_active_ban(db: &mut DbConnection, user_id: u32) -> Result<Option<BanDB>, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs();
+
+    match query_as(
+        "SELECT * FROM bans WHERE user_id = $1 AND (banned_until IS NULL OR banned_until > $2)",
+    )
+    .bind(i64::from(user_id))
+    .bind(timestamp as i64)
+    .fetch_optional(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get ban by user id: {}", err)),
+    }
+}
+
+// ██████╗ ███████╗██╗     ███████╗████████╗███████╗██████╗     ██╗   ██╗███████╗███████╗██████╗ ███████╗
+// ██╔══██╗██╔════╝██║     ██╔════╝╚══██╔══╝██╔════╝██╔══██╗    ██║   ██║██╔════╝██╔════╝██╔══██╗██╔════╝
+// ██║  ██║█████╗  ██║     █████╗     ██║   █████╗  ██║  ██║    ██║   ██║███████╗█████╗  ██████╔╝███████╗
+// ██║  ██║██╔══╝  ██║     ██╔══╝     ██║   ██╔══╝  ██║  ██║    ██║   ██║╚════██║██╔══╝  ██╔══██╗╚════██║
+// ██████╔╝███████╗███████╗███████╗   ██║   ███████╗██████╔╝    ╚██████╔╝███████║███████╗██║  ██║███████║
+// ╚═════╝ ╚══════╝╚══════╝╚══════╝   ╚═╝   ╚══════╝╚═════╝      ╚═════╝ ╚══════╝╚══════╝╚═╝  ╚═╝╚══════╝
+
+#[derive(Debug, FromRow, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct DeletedUser {
+    #[sqlx(try_from = "i64")]
+    pub account_id: u32,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub deleted_at: Option<i64>,
+    pub anonymised: bool,
+}
+
+pub async fn list_deleted_users(db: &mut DbConnection) -> Result<Vec<DeletedUser>, String> {
+    match query_as(
+        "SELECT user_id AS account_id, email, first_name, last_name, deleted_at,
+        COALESCE(anonymised, false) AS anonymised
+        FROM deleted_users ORDER BY deleted_at DESC",
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(val) => Ok(val),
+        Err(err) => Err(format!("Failed to get deleted users: {}", err)),
+    }
+}
+
+// The account comes back verified or not as it was, without sessions or two-factor authentication
+// Past the retention the account is due for anonymisation even if the janitor hasn't run yet
+pub async fn restore_deleted_user(
+    db: &mut DbConnection,
+    user_id: u32,
+    retention: i64,
+) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    let row = match query(
+        "SELECT email, COALESCE(anonymised, false), COALESCE(deleted_at, 0)
+        FROM deleted_users WHERE user_id = $1",
+    )
+    .bind(i64::from(user_id))
+    .fetch_optional(&mut *db)
+    .await
+    {
+        Ok(Some(val)) => val,
+        Ok(None) => return Err("deleted user not found".to_owned()),
+        Err(err) => return Err(format!("Failed to get the deleted user: {}", err)),
+    };
+
+    if row.get::<bool, _>(1) {
+        return Err("deleted user was already anonymised".to_owned());
+    }
+
+    if row.get::<i64, _>(2) <= timestamp - retention {
+        return Err("deleted user can't be restored anymore".to_owned());
+    }
+
+    if get_user_by_id(db, user_id).await.is_ok() {
+        return Err("account id is taken".to_owned());
+    }
+
+    if email_taken(db, &row.get::<String, _>(0)).await? {
+        return Err("email is taken".to_owned());
+    }
+
+    let mut tx = match db.begin().await {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+    };
+
+    if let Err(err) = query(
+        "INSERT INTO users
+        (user_id, first_name, last_name, email, password, gender, verified, admin)
+        SELECT user_id, first_name, last_name, email, password, gender, verified, admin
+        FROM deleted_users WHERE user_id = $1",
+    )
+    .bind(i64::from(user_id))
+    .execute(&mut *tx)
+    .await
+    {
+        return Err(format!("Failed to restore the user: {}", err));
+    }
+
+    if let Err(err) = query("DELETE FROM deleted_users WHERE user_id = $1")
+        .bind(i64::from(user_id))
+        .execute(&mut *tx)
+        .await
+    {
+        return Err(format!("Failed to restore the user: {}", err));
+    }
+
+    match tx.commit().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to restore the user: {}", err)),
+    }
+}
+
+pub fn hash_email(email: &str) -> String {
+    let hash = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Accounts deleted before deleted_at existed have it NULL and are anonymised on the first run
+pub async fn anonymise_deleted_users(db: &mut DbConnection, retention: i64) -> Result<u64, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    let rows = match query(
+        "SELECT user_id, email FROM deleted_users
+        WHERE COALESCE(anonymised, false) = false AND COALESCE(deleted_at, 0) <= $1",
+    )
+    .bind(timestamp - retention)
+    .fetch_all(&mut *db)
+    .await
+    {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to get deleted users: {}", err)),
+    };
+
+    for row in &rows {
+        let user_id = row.get::<i64, _>(0) as u32;
+        let email: String = row.get(1);
+
+        let mut tx = match db.begin().await {
+            Ok(val) => val,
+            Err(err) => return Err(format!("Failed to start a transaction: {}", err)),
+        };
+
+        if let Err(err) = query(
+            "UPDATE deleted_users SET email = $1, first_name = '', last_name = '',
+            password = NULL, anonymised = true WHERE user_id = $2",
+        )
+        .bind(hash_email(&email))
+        .bind(i64::from(user_id))
+        .execute(&mut *tx)
+        .await
+        {
+            return Err(format!("Failed to anonymise user {}: {}", user_id, err));
+        }
+
+        for statement in [
+            "UPDATE error_report SET user_id = NULL WHERE user_id = $1",
+            "UPDATE suggestion SET user_id = NULL WHERE user_id = $1",
+            "DELETE FROM bans WHERE user_id = $1",
+        ] {
+            if let Err(err) = query(statement)
+                .bind(i64::from(user_id))
+                .execute(&mut *tx)
+                .await
+            {
+                return Err(format!("Failed to anonymise user {}: {}", user_id, err));
+            }
+        }
+
+        anonymise_audit_entries(&mut tx, user_id).await?;
+
+        if let Err(err) = tx.commit().await {
+            return Err(format!("Failed to anonymise user {}: {}", user_id, err));
+        }
+
+        logging::info(&format!("Anonymised deleted user {}", user_id));
+    }
+
+    Ok(rows.len() as u64)
+}
+
+// ██████╗ ██╗   ██╗██████╗  ██████╗ ███████╗
+// ██╔══██╗██║   ██║██╔══██╗██╔════╝ ██╔════╝
+// ██████╔╝██║   ██║██████╔╝██║  ███╗█████╗
+// ██╔═══╝ ██║   ██║██╔══██╗██║   ██║██╔══╝
+// ██║     ╚██████╔╝██║  ██║╚██████╔╝███████╗
+// ╚═╝      ╚═════╝ ╚═╝  ╚═╝ ╚═════╝ ╚══════╝
+
+#[derive(Debug, Default, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct PurgeCounts {
+    pub unverified_users: u64,
+    pub verifications: u64,
+    pub sessions: u64,
+    pub password_resets: u64,
+    pub email_updates: u64,
+    pub delete_requests: u64,
+    pub bans: u64,
+    pub login_challenges: u64,
+    pub anonymised_users: u64,
+}
+
+async fn purge_table(
+    db: &mut DbConnection,
+    statement: &str,
+    timestamp: i64,
+) -> Result<u64, String> {
+    match query(statement).bind(timestamp).execute(db).await {
+        Ok(val) => Ok(val.rows_affected()),
+        Err(err) => Err(format!("Failed to purge expired rows: {}", err)),
+    }
+}
+
+// Unverified accounts are removed once their last verification email is older than unverified_age
+pub async fn purge_expired(
+    db: &mut DbConnection,
+    unverified_age: i64,
+    deleted_retention: i64,
+) -> Result<PurgeCounts, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time")
+        .as_secs() as i64;
+
+    Ok(PurgeCounts {
+        unverified_users: purge_table(
+            &mut *db,
+            "DELETE FROM users WHERE verified = false AND user_id IN
+            (SELECT user_id FROM verifications WHERE timestamp <= $1)",
+            timestamp - unverified_age,
+        )
+        .await?,
+        verifications: match query(
+            "DELETE FROM verifications WHERE user_id NOT IN
+            (SELECT user_id FROM users WHERE verified = false)",
+        )
+        .execute(&mut *db)
+        .await
+        {
+            Ok(val) => val.rows_affected(),
+            Err(err) => return Err(format!("Failed to purge verifications: {}", err)),
+        },
+        sessions: purge_table(
+            &mut *db,
+            "DELETE FROM sessions WHERE valid_until <= $1",
+            timestamp,
+        )
+        .await?,
+        password_resets: purge_table(
+            &mut *db,
+            "DELETE FROM password_resets WHERE valid_until <= $1",
+            timestamp,
+        )
+        .await?,
+        email_updates: purge_table(
+            &mut *db,
+            "DELETE FROM email_updates WHERE valid_until <= $1",
+            timestamp,
+        )
+        .await?,
+        delete_requests: purge_table(
+            &mut *db,
+            "DELETE FROM delete_requests WHERE valid_until <= $1",
+            timestamp,
+        )
+        .await?,
+        bans: purge_table(
+            &mut *db,
+            "DELETE FROM bans WHERE banned_until IS NOT NULL AND banned_until <= $1",
+            timestamp,
+        )
+        .await?,
+        login_challenges: purge_table(
+            &mut *db,
+            "DELETE FROM login_challenges WHERE valid_until <= $1",
+            timestamp,
+        )
+        .await?,
+        anonymised_users: anonymise_deleted_users(&mut *db, deleted_retention).await?,
+    })
+}
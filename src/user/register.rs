@@ -1,7 +1,4 @@
-use std::{
-    env,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use resend_rs::{types::CreateEmailBaseOptions, Resend};
 use rocket::{
@@ -11,18 +8,26 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
-use crate::{util::is_paused, DB};
+use crate::{
+    api::V1_BASE,
+    config::AppConfig,
+    ratelimit::{LimitedRoute, RateLimit},
+    util::is_paused,
+    DB,
+};
 
 use super::{
     add_verification, create_user, email_taken, get_user_by_id, get_verification_by_id,
-    get_verification_by_token, next_user_id, remove_verification, update_user_verification_status,
+    get_verification_by_token, remove_verification, update_user_verification_status,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct RegisterData<'r> {
     email: &'r str,
@@ -32,22 +37,23 @@ pub struct RegisterData<'r> {
     gender: char,
 }
 
-async fn send_registration_email(email: &str, verification_token: &str) -> Result<(), String> {
-    if env::var("WIEDZIELISCIE_BACKEND_KEIN_MAIL").is_ok() {
+async fn send_registration_email(
+    config: &AppConfig,
+    email: &str,
+    verification_token: &str,
+) -> Result<(), String> {
+    if config.mail.disabled {
         return Ok(());
     }
 
-    let resend = Resend::default();
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
 
-    let from = match env::var("WIEDZIELISCIE_BACKEND_FROM_MAIL") {
-        Ok(val) => val,
-        Err(_) => return Err("From mail not found".to_owned()),
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
     };
     let subject = "Confirm your registration to WiedzieLIŚCIE";
-    let verification_link = match env::var("WIEDZIELISCIE_BACKEND_URL") {
-        Ok(val) => val + "/auth/verify/" + verification_token,
-        Err(_) => return Err("Url not found".to_owned()),
-    };
+    let verification_link = config.url.clone() + V1_BASE + "/auth/verify/" + verification_token;
 
     let email = CreateEmailBaseOptions::new(from, [email], subject).with_html(&format!(
         "
@@ -67,10 +73,16 @@ async fn send_registration_email(email: &str, verification_token: &str) -> Resul
 #[post("/auth/register", format = "json", data = "<data>")]
 pub async fn auth_register(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
     data: Json<RegisterData<'_>>,
 ) -> (Status, Value) {
+    if let Some(err) = limit.check(LimitedRoute::Register, None) {
+        return err;
+    }
+
     if is_paused(&mut db).await {
-        return (Status::Unauthorized, json!({"error": "Game paused"}))
+        return (Status::Unauthorized, json!({"error": "Game paused"}));
     }
 
     let data = data.into_inner();
@@ -84,16 +96,10 @@ pub async fn auth_register(
         Err(err) => return (Status::InternalServerError, json!({"error": err})),
     }
 
-    let user_id = match next_user_id(&mut db).await {
-        Ok(id) => id,
-        Err(err) => return (Status::InternalServerError, json!({"error": err})),
-    };
-
     let token = Uuid::new_v4().to_string();
 
-    if let Err(err) = create_user(
+    let user_id = match create_user(
         &mut db,
-        user_id,
         data.first_name,
         data.last_name,
         data.email,
@@ -102,14 +108,15 @@ pub async fn auth_register(
     )
     .await
     {
-        return (Status::InternalServerError, json!({"error": err}));
-    }
+        Ok(val) => val,
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    };
 
     if let Err(err) = add_verification(&mut db, user_id, &token).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if let Err(err) = send_registration_email(data.email, &token).await {
+    if let Err(err) = send_registration_email(config, data.email, &token).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
@@ -117,9 +124,21 @@ pub async fn auth_register(
 }
 
 #[post("/auth/resend_verification/<account_id>")]
-pub async fn auth_resend_verification(mut db: Connection<DB>, account_id: u32) -> (Status, Value) {
+pub async fn auth_resend_verification(
+    mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
+    account_id: u32,
+) -> (Status, Value) {
+    if let Some(err) = limit.check(
+        LimitedRoute::ResendVerification,
+        Some(&account_id.to_string()),
+    ) {
+        return err;
+    }
+
     if is_paused(&mut db).await {
-        return (Status::Unauthorized, json!({"error": "Game paused"}))
+        return (Status::Unauthorized, json!({"error": "Game paused"}));
     }
 
     let user = match get_user_by_id(&mut db, account_id).await {
@@ -151,7 +170,7 @@ pub async fn auth_resend_verification(mut db: Connection<DB>, account_id: u32) -
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if let Err(err) = send_registration_email(&user.email, &token).await {
+    if let Err(err) = send_registration_email(config, &user.email, &token).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
@@ -180,12 +199,12 @@ pub fn get_verification_page(title: &str, message: &str) -> String {
 pub async fn auth_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String> {
     let verification = match get_verification_by_token(&mut db, token).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_verification_page(&"Verification failed", &err)),
+        Err(err) => return RawHtml(get_verification_page("Verification failed", &err)),
     };
 
     let user = match get_user_by_id(&mut db, verification.user_id).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_verification_page(&"Verification failed", &err)),
+        Err(err) => return RawHtml(get_verification_page("Verification failed", &err)),
     };
 
     let timestamp = SystemTime::now()
@@ -197,22 +216,22 @@ pub async fn auth_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String>
         match remove_verification(&mut db, user.user_id).await {
             Ok(_) => {
                 return RawHtml(get_verification_page(
-                    &"Verification failed",
-                    &"Token invalid",
+                    "Verification failed",
+                    "Token invalid",
                 ));
             }
             Err(err) => {
-                return RawHtml(get_verification_page(&"Verification failed", &err));
+                return RawHtml(get_verification_page("Verification failed", &err));
             }
         }
     }
 
     if let Err(err) = update_user_verification_status(&mut db, user.user_id).await {
-        return RawHtml(get_verification_page(&"Verification failed", &err));
+        return RawHtml(get_verification_page("Verification failed", &err));
     }
 
     RawHtml(get_verification_page(
-        &"Verification successful",
-        &"You can now close this page and return to the app",
+        "Verification successful",
+        "You can now close this page and return to the app",
     ))
 }
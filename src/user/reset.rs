@@ -1,7 +1,4 @@
-use std::{
-    env,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use resend_rs::{types::CreateEmailBaseOptions, Resend};
 use rocket::{
@@ -11,11 +8,19 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
-use crate::{util::is_paused, DB};
+use crate::{
+    api::V1_BASE,
+    config::AppConfig,
+    ratelimit::{LimitedRoute, RateLimit},
+    util::is_paused,
+    DB,
+};
 
 use super::{
     get_reset_by_token, get_reset_by_user_id, get_user_by_email, get_user_by_id,
@@ -23,29 +28,31 @@ use super::{
     update_user_password,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct ResetData<'r> {
     email: &'r str,
     plaintext_password: &'r str,
 }
 
-async fn send_password_reset_email(email: &str, reset_token: &str) -> Result<(), String> {
-    if env::var("WIEDZIELISCIE_BACKEND_KEIN_MAIL").is_ok() {
+async fn send_password_reset_email(
+    config: &AppConfig,
+    email: &str,
+    reset_token: &str,
+) -> Result<(), String> {
+    if config.mail.disabled {
         return Ok(());
     }
 
-    let resend = Resend::default();
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
 
-    let from = match env::var("WIEDZIELISCIE_BACKEND_FROM_MAIL") {
-        Ok(val) => val,
-        Err(_) => return Err("From mail not found".to_owned()),
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
     };
     let subject = "Confirm your password reset";
-    let password_reset_link = match env::var("WIEDZIELISCIE_BACKEND_URL") {
-        Ok(val) => val + "/auth/password_reset/verify/" + reset_token,
-        Err(_) => return Err("Url not found".to_owned()),
-    };
+    let password_reset_link =
+        config.url.clone() + V1_BASE + "/auth/password_reset/verify/" + reset_token;
 
     let email = CreateEmailBaseOptions::new(from, [email], subject).with_html(&format!( "
             <a href=\"{}\">Click this to confirm password change</a>
@@ -64,13 +71,19 @@ async fn send_password_reset_email(email: &str, reset_token: &str) -> Result<(),
 #[post("/auth/password_reset", format = "json", data = "<data>")]
 pub async fn auth_password_reset(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
+    limit: RateLimit<'_>,
     data: Json<ResetData<'_>>,
 ) -> (Status, Value) {
+    if let Some(err) = limit.check(LimitedRoute::PasswordReset, Some(data.email)) {
+        return err;
+    }
+
     if is_paused(&mut db).await {
-        return (Status::Unauthorized, json!({"error": "Game paused"}))
+        return (Status::Unauthorized, json!({"error": "Game paused"}));
     }
 
-    let user = match get_user_by_email(&mut db, &data.email).await {
+    let user = match get_user_by_email(&mut db, data.email).await {
         Ok(val) => val,
         Err(_) => return (Status::BadRequest, json!({"error": "User not found"})),
     };
@@ -89,9 +102,9 @@ pub async fn auth_password_reset(
                 };
 
                 if timestamp > reset.valid_until {
-                    match remove_password_reset_by_user_id(&mut db, user.user_id).await {
-                        Err(err) => return (Status::InternalServerError, json!({"error": err})),
-                        _ => {}
+                    if let Err(err) = remove_password_reset_by_user_id(&mut db, user.user_id).await
+                    {
+                        return (Status::InternalServerError, json!({"error": err}));
                     }
                 } else {
                     return (Status::BadRequest, json!({"error": "reset in progress"}));
@@ -103,11 +116,19 @@ pub async fn auth_password_reset(
 
     let token = Uuid::new_v4().to_string();
 
-    if let Err(err) = start_reset(&mut db, user.user_id, data.plaintext_password, &token).await {
+    if let Err(err) = start_reset(
+        &mut db,
+        user.user_id,
+        data.plaintext_password,
+        &token,
+        config.tokens.password_reset_lifetime,
+    )
+    .await
+    {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
-    if let Err(err) = send_password_reset_email(&user.email, &token).await {
+    if let Err(err) = send_password_reset_email(config, &user.email, &token).await {
         return (Status::InternalServerError, json!({"error": err}));
     }
 
@@ -136,12 +157,12 @@ pub fn get_password_reset_page(title: &str, message: &str) -> String {
 pub async fn auth_password_reset_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String> {
     let reset = match get_reset_by_token(&mut db, token).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_password_reset_page(&"Password reset failed", &err)),
+        Err(err) => return RawHtml(get_password_reset_page("Password reset failed", &err)),
     };
 
     let user = match get_user_by_id(&mut db, reset.user_id).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_password_reset_page(&"Password reset failed", &err)),
+        Err(err) => return RawHtml(get_password_reset_page("Password reset failed", &err)),
     };
 
     let timestamp = SystemTime::now()
@@ -151,21 +172,21 @@ pub async fn auth_password_reset_verify(mut db: Connection<DB>, token: &str) ->
 
     if timestamp > reset.valid_until {
         return RawHtml(get_password_reset_page(
-            &"Password reset failed",
-            &"Password reset expired",
+            "Password reset failed",
+            "Password reset expired",
         ));
     }
 
     if let Err(err) = stop_all_sessions(&mut db, user.user_id).await {
-        RawHtml(get_password_reset_page(&"Password reset failed", &err));
+        return RawHtml(get_password_reset_page("Password reset failed", &err));
     }
 
     if let Err(err) = update_user_password(&mut db, user.user_id, &reset.password).await {
-        RawHtml(get_password_reset_page(&"Password reset failed", &err));
+        return RawHtml(get_password_reset_page("Password reset failed", &err));
     }
 
     RawHtml(get_password_reset_page(
-        &"Password reset successful",
-        &"You can now close this page and log into the app using your new password",
+        "Password reset successful",
+        "You can now close this page and log into the app using your new password",
     ))
 }
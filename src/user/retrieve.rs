@@ -6,17 +6,24 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 
-use crate::{util::is_paused, DB};
+use crate::{
+    config::AppConfig,
+    db::DbConnection,
+    util::{check_not_banned, is_paused},
+    DB,
+};
 
 use super::{
-    get_session_by_token, get_user_by_email, get_user_by_id, jwt::verify_token, next_user_id,
-    retrieve_user_by_email, retrieve_user_by_id, retrieve_user_by_names,
+    count_users, get_session_by_token, get_user_by_id, jwt::verify_token, retrieve_user_by_email,
+    retrieve_user_by_id, retrieve_user_by_names, UserFilter,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct RetrieveUserData<'r> {
     jwt: &'r str,
@@ -25,9 +32,10 @@ pub struct RetrieveUserData<'r> {
 #[post("/auth/retrieve_user", format = "json", data = "<data>")]
 pub async fn auth_retrieve_user(
     mut db: Connection<DB>,
+    config: &State<AppConfig>,
     data: Json<RetrieveUserData<'_>>,
 ) -> (Status, Value) {
-    let claims = match verify_token(data.jwt) {
+    let claims = match verify_token(&config.secret, data.jwt) {
         Ok(val) => val.claims,
         Err(_) => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
@@ -39,11 +47,17 @@ pub async fn auth_retrieve_user(
         Ok(val) => val,
         Err(_) => return (Status::BadRequest, json!({"error": "user not found"})),
     };
-    
+
     if is_paused(&mut db).await && !user.admin {
-        return (Status::Unauthorized, json!({"error": "Game paused and user is not admin"}))
+        return (
+            Status::Unauthorized,
+            json!({"error": "Game paused and user is not admin"}),
+        );
     }
 
+    if let Some(err) = check_not_banned(&mut db, user_id).await {
+        return err;
+    }
 
     let sessions = match get_session_by_token(&mut db, &session_token).await {
         Ok(val) => val,
@@ -73,7 +87,7 @@ pub async fn auth_retrieve_user(
     )
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct RetrieveUserEmailData<'r> {
     email: &'r str,
@@ -103,7 +117,7 @@ pub async fn user_retrieve_email(
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct RetrieveUserIdData {
     account_id: u32,
@@ -114,7 +128,17 @@ pub async fn user_retrieve_id(
     mut db: Connection<DB>,
     data: Json<RetrieveUserIdData>,
 ) -> (Status, Value) {
-    match retrieve_user_by_id(&mut db, data.account_id).await {
+    retrieve_id(&mut db, data.account_id).await
+}
+
+// Replaces user_retrieve_id in /api/v1
+#[get("/users/<account_id>")]
+pub async fn users_get(mut db: Connection<DB>, account_id: u32) -> (Status, Value) {
+    retrieve_id(&mut db, account_id).await
+}
+
+async fn retrieve_id(db: &mut DbConnection, account_id: u32) -> (Status, Value) {
+    match retrieve_user_by_id(db, account_id).await {
         Ok(user) => {
             let gender = if user.gender { "m" } else { "f" };
             (
@@ -133,7 +157,7 @@ pub async fn user_retrieve_id(
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
 pub struct RetrieveUserNamesData<'r> {
     first_name: &'r str,
@@ -166,8 +190,8 @@ pub async fn user_retrieve_name(
 
 #[get("/user/retrieve/count")]
 pub async fn user_retrieve_count(mut db: Connection<DB>) -> (Status, Value) {
-    match next_user_id(&mut db).await {
-        Ok(user) => (Status::Ok, json!(user - 1)),
-        Err(_) => (Status::NotFound, json!({})),
+    match count_users(&mut db, &UserFilter::default()).await {
+        Ok(count) => (Status::Ok, json!(count)),
+        Err(err) => (Status::InternalServerError, json!({"error": err})),
     }
 }
@@ -1,5 +1,5 @@
 use std::{
-    env,
+    net::IpAddr,
     time::{SystemTime, UNIX_EPOCH},
 };
 
@@ -11,42 +11,52 @@ use rocket::{
         json::{json, Json, Value},
         Deserialize,
     },
+    State,
 };
 use rocket_db_pools::Connection;
+use schemars::JsonSchema;
 use uuid::Uuid;
 
-use crate::{util::{check_authorized_admin, check_authorized_user_or_admin, is_paused}, DB};
+use crate::{
+    api::V1_BASE,
+    audit::add_audit_entry,
+    config::AppConfig,
+    util::{check_authorized_admin, check_authorized_user_or_admin, is_paused},
+    DB,
+};
 
 use super::{
-    email_update_in_progress, get_email_update_by_token, get_email_update_by_user_id,
+    email_taken, email_update_in_progress, get_email_update_by_token, get_email_update_by_user_id,
     get_user_by_id, jwt::verify_token, remove_email_updates_by_user_id, start_email_update,
     stop_all_sessions, update_user_email,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(crate = "rocket::serde")]
-pub struct ResetData<'r> {
+pub struct EmailUpdateData<'r> {
     jwt: &'r str,
     new_value: &'r str,
     account_id: u32,
 }
 
-async fn send_email_update_email(email: &str, change_token: &str) -> Result<(), String> {
-    if env::var("WIEDZIELISCIE_BACKEND_KEIN_MAIL").is_ok() {
+async fn send_email_update_email(
+    config: &AppConfig,
+    email: &str,
+    change_token: &str,
+) -> Result<(), String> {
+    if config.mail.disabled {
         return Ok(());
     }
 
-    let resend = Resend::default();
+    let resend = Resend::new(config.mail.resend_api_key.as_deref().unwrap_or_default());
 
-    let from = match env::var("WIEDZIELISCIE_BACKEND_FROM_MAIL") {
-        Ok(val) => val,
-        Err(_) => return Err("From mail not found".to_owned()),
+    let from = match &config.mail.from {
+        Some(val) => val,
+        None => return Err("From mail not found".to_owned()),
     };
     let subject = "Confirm email change";
-    let password_reset_link = match env::var("WIEDZIELISCIE_BACKEND_URL") {
-        Ok(val) => val + "/user/modify/email/verify/" + change_token,
-        Err(_) => return Err("Url not found".to_owned()),
-    };
+    let password_reset_link =
+        config.url.clone() + V1_BASE + "/users/me/email/verify/" + change_token;
 
     let email = CreateEmailBaseOptions::new(from, [email], subject).with_html(&format!( "
             <a href=\"{}\">Click this to confirm email change</a>
@@ -65,17 +75,28 @@ async fn send_email_update_email(email: &str, change_token: &str) -> Result<(),
 #[post("/user/modify/email", format = "json", data = "<data>")]
 pub async fn user_modify_email(
     mut db: Connection<DB>,
-    data: Json<ResetData<'_>>,
+    config: &State<AppConfig>,
+    data: Json<EmailUpdateData<'_>>,
+    ip: Option<IpAddr>,
 ) -> (Status, Value) {
-    if is_paused(&mut db).await && check_authorized_admin(&mut db, data.jwt).await.is_some() {
-        return (Status::Unauthorized, json!({"error": "Game paused and user isn't admin"}))
+    if is_paused(&mut db).await
+        && check_authorized_admin(&mut db, config, data.jwt)
+            .await
+            .is_some()
+    {
+        return (
+            Status::Unauthorized,
+            json!({"error": "Game paused and user isn't admin"}),
+        );
     }
 
-    if let Some(err) = check_authorized_user_or_admin(&mut db, data.jwt, data.account_id).await {
+    if let Some(err) =
+        check_authorized_user_or_admin(&mut db, config, data.jwt, data.account_id).await
+    {
         return err;
     }
 
-    let claims = match verify_token(data.jwt) {
+    let claims = match verify_token(&config.secret, data.jwt) {
         Ok(val) => val.claims,
         Err(_) => return (Status::BadRequest, json!({"error": "invalid token"})),
     };
@@ -86,6 +107,12 @@ pub async fn user_modify_email(
         Err(err) => return (Status::BadRequest, json!({"error": err})),
     };
 
+    match email_taken(&mut db, data.new_value).await {
+        Ok(true) => return (Status::BadRequest, json!({"error": "Email already in use"})),
+        Ok(false) => (),
+        Err(err) => return (Status::InternalServerError, json!({"error": err})),
+    }
+
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time")
@@ -100,9 +127,10 @@ pub async fn user_modify_email(
                 };
 
                 if timestamp > update.valid_until {
-                    match remove_email_updates_by_user_id(&mut db, data.account_id).await {
-                        Err(err) => return (Status::InternalServerError, json!({"error": err})),
-                        _ => {}
+                    if let Err(err) =
+                        remove_email_updates_by_user_id(&mut db, data.account_id).await
+                    {
+                        return (Status::InternalServerError, json!({"error": err}));
                     }
                 } else {
                     return (Status::BadRequest, json!({"error": "update in progress"}));
@@ -113,21 +141,49 @@ pub async fn user_modify_email(
     }
 
     if user.admin && data.account_id != user_id {
+        let target = match get_user_by_id(&mut db, data.account_id).await {
+            Ok(val) => val,
+            Err(err) => return (Status::NotFound, json!({"error": err})),
+        };
+
         if let Err(err) = stop_all_sessions(&mut db, data.account_id).await {
-            RawHtml(get_email_update_page(&"Email update failed", &err));
+            return (Status::InternalServerError, json!({"error": err}));
         }
 
         if let Err(err) = update_user_email(&mut db, data.account_id, data.new_value).await {
-            RawHtml(get_email_update_page(&"Email update failed", &err));
+            return (Status::InternalServerError, json!({"error": err}));
+        }
+
+        if let Err(err) = add_audit_entry(
+            &mut db,
+            user_id,
+            "update_email",
+            "user",
+            Some(data.account_id),
+            Some(&json!({"email": target.email})),
+            Some(&json!({"email": data.new_value})),
+            ip,
+        )
+        .await
+        {
+            return (Status::InternalServerError, json!({"error": err}));
         }
     } else {
         let token = Uuid::new_v4().to_string();
 
-        if let Err(err) = start_email_update(&mut db, user_id, data.new_value, &token).await {
+        if let Err(err) = start_email_update(
+            &mut db,
+            user_id,
+            data.new_value,
+            &token,
+            config.tokens.email_update_lifetime,
+        )
+        .await
+        {
             return (Status::InternalServerError, json!({"error": err}));
         }
 
-        if let Err(err) = send_email_update_email(data.new_value, &token).await {
+        if let Err(err) = send_email_update_email(config, data.new_value, &token).await {
             return (Status::InternalServerError, json!({"error": err}));
         }
     }
@@ -157,12 +213,12 @@ pub fn get_email_update_page(title: &str, message: &str) -> String {
 pub async fn user_modify_email_verify(mut db: Connection<DB>, token: &str) -> RawHtml<String> {
     let update = match get_email_update_by_token(&mut db, token).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_email_update_page(&"Email update failed", &err)),
+        Err(err) => return RawHtml(get_email_update_page("Email update failed", &err)),
     };
 
     let user = match get_user_by_id(&mut db, update.user_id).await {
         Ok(val) => val,
-        Err(err) => return RawHtml(get_email_update_page(&"Email update failed", &err)),
+        Err(err) => return RawHtml(get_email_update_page("Email update failed", &err)),
     };
 
     let timestamp = SystemTime::now()
@@ -172,21 +228,32 @@ pub async fn user_modify_email_verify(mut db: Connection<DB>, token: &str) -> Ra
 
     if timestamp > update.valid_until {
         return RawHtml(get_email_update_page(
-            &"Email update failed",
-            &"Email update expired",
+            "Email update failed",
+            "Email update expired",
         ));
     }
 
+    match email_taken(&mut db, &update.email).await {
+        Ok(true) => {
+            return RawHtml(get_email_update_page(
+                "Email update failed",
+                "Email already in use",
+            ))
+        }
+        Ok(false) => (),
+        Err(err) => return RawHtml(get_email_update_page("Email update failed", &err)),
+    }
+
     if let Err(err) = stop_all_sessions(&mut db, user.user_id).await {
-        RawHtml(get_email_update_page(&"Email update failed", &err));
+        return RawHtml(get_email_update_page("Email update failed", &err));
     }
 
     if let Err(err) = update_user_email(&mut db, user.user_id, &update.email).await {
-        RawHtml(get_email_update_page(&"Email update failed", &err));
+        return RawHtml(get_email_update_page("Email update failed", &err));
     }
 
     RawHtml(get_email_update_page(
-        &"Email reset successful",
-        &"You can now close this page and log into the app using your new email",
+        "Email reset successful",
+        "You can now close this page and log into the app using your new email",
     ))
 }
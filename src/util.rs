@@ -1,23 +1,30 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{
-    user::{get_session_by_token, get_user_by_id, jwt::verify_token},
+    config::AppConfig,
+    db::{Backend, DbArguments, DbConnection, DbRow},
+    totp::is_totp_enabled,
+    user::{get_active_ban, get_session_by_token, get_user_by_id, jwt::verify_token},
     DB,
 };
 
 use rocket::{
     http::Status,
-    serde::json::{json, Value},
+    serde::{
+        json::{json, Value},
+        Deserialize,
+    },
 };
 use rocket_db_pools::Connection;
-use sqlx::{query, SqliteConnection, Row};
-use std::{fs::File, io::Read};
+use schemars::JsonSchema;
+use sqlx::{query, query::Query, Row};
 
 pub async fn check_authorized_user(
-    mut db: &mut Connection<DB>,
+    db: &mut Connection<DB>,
+    config: &AppConfig,
     jwt: &str,
 ) -> Option<(Status, Value)> {
-    let claims = match verify_token(jwt) {
+    let claims = match verify_token(&config.secret, jwt) {
         Ok(val) => val.claims,
         Err(_) => return Some((Status::BadRequest, json!({"error": "invalid token"}))),
     };
@@ -25,11 +32,15 @@ pub async fn check_authorized_user(
     let user_id = claims.uid;
     let session_token = claims.token;
 
-    if let Err(err) = get_user_by_id(&mut db, user_id).await {
+    if let Err(err) = get_user_by_id(db, user_id).await {
         return Some((Status::BadRequest, json!({"error": err})));
     };
 
-    let sessions = match get_session_by_token(&mut db, &session_token).await {
+    if let Some(err) = check_not_banned(db, user_id).await {
+        return Some(err);
+    }
+
+    let sessions = match get_session_by_token(db, &session_token).await {
         Ok(val) => val,
         Err(err) => return Some((Status::BadRequest, json!({"error": err}))),
     };
@@ -47,10 +58,11 @@ pub async fn check_authorized_user(
 }
 
 pub async fn check_authorized_admin(
-    mut db: &mut Connection<DB>,
+    db: &mut Connection<DB>,
+    config: &AppConfig,
     jwt: &str,
 ) -> Option<(Status, Value)> {
-    let claims = match verify_token(jwt) {
+    let claims = match verify_token(&config.secret, jwt) {
         Ok(val) => val.claims,
         Err(_) => return Some((Status::BadRequest, json!({"error": "invalid token"}))),
     };
@@ -58,7 +70,7 @@ pub async fn check_authorized_admin(
     let user_id = claims.uid;
     let session_token = claims.token;
 
-    let user = match get_user_by_id(&mut db, user_id).await {
+    let user = match get_user_by_id(db, user_id).await {
         Ok(val) => val,
         Err(err) => return Some((Status::BadRequest, json!({"error": err}))),
     };
@@ -67,7 +79,15 @@ pub async fn check_authorized_admin(
         return Some((Status::BadRequest, json!({"error": "user is not admin"})));
     }
 
-    let sessions = match get_session_by_token(&mut db, &session_token).await {
+    if let Some(err) = check_admin_totp(db, config, user_id).await {
+        return Some(err);
+    }
+
+    if let Some(err) = check_not_banned(db, user_id).await {
+        return Some(err);
+    }
+
+    let sessions = match get_session_by_token(db, &session_token).await {
         Ok(val) => val,
         Err(err) => return Some((Status::BadRequest, json!({"error": err}))),
     };
@@ -85,11 +105,12 @@ pub async fn check_authorized_admin(
 }
 
 pub async fn check_authorized_user_or_admin(
-    mut db: &mut Connection<DB>,
+    db: &mut Connection<DB>,
+    config: &AppConfig,
     jwt: &str,
     account_id: u32,
 ) -> Option<(Status, Value)> {
-    let claims = match verify_token(jwt) {
+    let claims = match verify_token(&config.secret, jwt) {
         Ok(val) => val.claims,
         Err(_) => return Some((Status::BadRequest, json!({"error": "invalid token"}))),
     };
@@ -97,7 +118,7 @@ pub async fn check_authorized_user_or_admin(
     let user_id = claims.uid;
     let session_token = claims.token;
 
-    let user = match get_user_by_id(&mut db, user_id).await {
+    let user = match get_user_by_id(db, user_id).await {
         Ok(val) => val,
         Err(err) => return Some((Status::BadRequest, json!({"error": err}))),
     };
@@ -109,7 +130,17 @@ pub async fn check_authorized_user_or_admin(
         ));
     }
 
-    let sessions = match get_session_by_token(&mut db, &session_token).await {
+    if user_id != account_id {
+        if let Some(err) = check_admin_totp(db, config, user_id).await {
+            return Some(err);
+        }
+    }
+
+    if let Some(err) = check_not_banned(db, user_id).await {
+        return Some(err);
+    }
+
+    let sessions = match get_session_by_token(db, &session_token).await {
         Ok(val) => val,
         Err(err) => return Some((Status::BadRequest, json!({"error": err}))),
     };
@@ -126,33 +157,250 @@ pub async fn check_authorized_user_or_admin(
     None
 }
 
-pub async fn is_paused(db: &mut SqliteConnection) -> bool {
-    match query("SELECT paused FROM game")
-        .fetch_one(db)
-        .await {
-        Ok(row) => {
-            row.try_get(0).unwrap_or(true)
-        },
-        Err(_) => true
+// Only enforced when totp.required_for_admins is set, enrolment itself goes through user routes
+pub async fn check_admin_totp(
+    db: &mut DbConnection,
+    config: &AppConfig,
+    user_id: u32,
+) -> Option<(Status, Value)> {
+    if !config.totp.required_for_admins {
+        return None;
+    }
+
+    match is_totp_enabled(db, user_id).await {
+        Ok(true) => None,
+        Ok(false) => Some((
+            Status::Forbidden,
+            json!({"error": "admin has to enable two-factor authentication"}),
+        )),
+        Err(err) => Some((Status::InternalServerError, json!({"error": err}))),
     }
 }
 
-pub fn load_env() -> Result<(), String> {
-    let mut env_file = match File::open(".env") {
-        Ok(val) => val,
-        Err(err) => return Err(err.to_string())
-    };
+pub async fn check_not_banned(db: &mut DbConnection, user_id: u32) -> Option<(Status, Value)> {
+    match get_active_ban(db, user_id).await {
+        Ok(Some(ban)) => Some((
+            Status::Forbidden,
+            json!({"error": "user is banned", "banned_until": ban.banned_until, "reason": ban.reason}),
+        )),
+        Ok(None) => None,
+        Err(err) => Some((Status::InternalServerError, json!({"error": err}))),
+    }
+}
+
+pub fn get_token_user_id(config: &AppConfig, jwt: &str) -> Option<u32> {
+    verify_token(&config.secret, jwt)
+        .ok()
+        .map(|val| val.claims.uid)
+}
+
+pub async fn is_paused(db: &mut DbConnection) -> bool {
+    match query("SELECT paused FROM game").fetch_one(db).await {
+        Ok(row) => row.try_get(0).unwrap_or(true),
+        Err(_) => true,
+    }
+}
+
+// User input put into email html
+pub fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|x| match x {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            '"' => "&quot;".to_owned(),
+            '\'' => "&#39;".to_owned(),
+            _ => x.to_string(),
+        })
+        .collect()
+}
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+// A cursor is the last id of the previous page, so pages stay stable while rows are added
+pub struct PageQuery<'r> {
+    pub cursor: Option<u32>,
+    pub limit: u32,
+    pub order: Order,
+    pub search: Option<&'r str>,
+}
 
-    let mut env = String::new();
-    if let Err(err) = env_file.read_to_string(&mut env) {
-        return Err(err.to_string());
+impl<'r> PageQuery<'r> {
+    pub fn new(
+        cursor: Option<u32>,
+        limit: Option<u32>,
+        order: Option<Order>,
+        default_order: Order,
+        search: Option<&'r str>,
+    ) -> PageQuery<'r> {
+        PageQuery {
+            cursor,
+            limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+            order: order.unwrap_or(default_order),
+            search: search.filter(|x| !x.trim().is_empty()),
+        }
+    }
+}
+
+pub enum Condition<'r> {
+    Equals(&'static str, u32),
+    Matches(&'static str, &'r str),
+    IsNull(&'static str),
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u32,
+    pub next_cursor: Option<u32>,
+}
+
+impl<T> Page<T> {
+    pub fn try_map<U>(self, f: impl FnMut(&T) -> Result<U, String>) -> Result<Page<U>, String> {
+        Ok(Page {
+            items: self
+                .items
+                .iter()
+                .map(f)
+                .collect::<Result<Vec<U>, String>>()?,
+            total: self.total,
+            next_cursor: self.next_cursor,
+        })
+    }
+}
+
+// Returns the clause and how many placeholders it used, numbered in the order bind_conditions
+// binds them. Searching ignores case on both backends
+fn where_clause(
+    conditions: &[Condition<'_>],
+    search_columns: &[&str],
+    searching: bool,
+) -> (String, usize) {
+    let mut placeholders = 0;
+    let mut clauses: Vec<String> = conditions
+        .iter()
+        .map(|condition| match condition {
+            Condition::Equals(column, _) | Condition::Matches(column, _) => {
+                placeholders += 1;
+                format!("{} = ${}", column, placeholders)
+            }
+            Condition::IsNull(column) => format!("{} IS NULL", column),
+        })
+        .collect();
+
+    if searching {
+        placeholders += 1;
+        let search = search_columns
+            .iter()
+            .map(|x| format!("lower({}) LIKE lower(${}) ESCAPE '\\'", x, placeholders))
+            .collect::<Vec<String>>()
+            .join(" OR ");
+        clauses.push(format!("({})", search));
+    }
+
+    match clauses.is_empty() {
+        true => ("1 = 1".to_owned(), placeholders),
+        false => (clauses.join(" AND "), placeholders),
+    }
+}
+
+fn bind_conditions<'q>(
+    mut statement: Query<'q, Backend, DbArguments<'q>>,
+    conditions: &[Condition<'q>],
+    search: Option<&str>,
+) -> Query<'q, Backend, DbArguments<'q>> {
+    for condition in conditions {
+        statement = match condition {
+            Condition::Equals(_, val) => statement.bind(i64::from(*val)),
+            Condition::Matches(_, val) => statement.bind(*val),
+            Condition::IsNull(_) => statement,
+        };
     }
 
-    let vars: Vec<_> = env.lines().filter_map(|x| x.split_once(" ")).collect();
-    
-    for (var, val) in vars.iter() {
-        std::env::set_var(var, val);
+    // % and _ in the search are meant literally
+    if let Some(search) = search {
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        statement = statement.bind(format!("%{}%", escaped));
     }
 
-    Ok(())
+    statement
+}
+
+// Table and column names come from the callers, only values are bound. The total counts every
+// row matching the conditions and search, not only the ones after the cursor
+pub async fn fetch_page<'q>(
+    db: &mut DbConnection,
+    table: &str,
+    id_column: &str,
+    search_columns: &[&str],
+    conditions: &[Condition<'q>],
+    page: &PageQuery<'_>,
+) -> Result<Page<DbRow>, String> {
+    let (filter, placeholders) = where_clause(conditions, search_columns, page.search.is_some());
+
+    let count_sql = format!("SELECT COUNT(*) FROM {} WHERE {}", table, filter);
+    let total = match bind_conditions(query(&count_sql), conditions, page.search)
+        .fetch_one(&mut *db)
+        .await
+    {
+        Ok(row) => match row.try_get::<i64, _>(0) {
+            Ok(val) => val as u32,
+            Err(_) => return Err("Database error".to_owned()),
+        },
+        Err(err) => return Err(format!("Failed to count {}: {}", table, err)),
+    };
+
+    let (comparison, direction) = match page.order {
+        Order::Asc => (">", "ASC"),
+        Order::Desc => ("<", "DESC"),
+    };
+
+    let page_sql = format!(
+        "SELECT * FROM {0} WHERE {1} AND (${2} IS NULL OR {3} {4} ${2}) ORDER BY {3} {5} LIMIT ${6}",
+        table,
+        filter,
+        placeholders + 1,
+        id_column,
+        comparison,
+        direction,
+        placeholders + 2
+    );
+
+    // One row more than asked for tells whether there's a next page
+    let mut rows = match bind_conditions(query(&page_sql), conditions, page.search)
+        .bind(page.cursor.map(i64::from))
+        .bind(i64::from(page.limit + 1))
+        .fetch_all(db)
+        .await
+    {
+        Ok(val) => val,
+        Err(err) => return Err(format!("Failed to get {}: {}", table, err)),
+    };
+
+    let next_cursor = match rows.len() > page.limit as usize {
+        true => {
+            rows.truncate(page.limit as usize);
+            match rows.last().map(|x| x.try_get::<i64, _>(id_column)) {
+                Some(Ok(val)) => Some(val as u32),
+                _ => return Err("Database error".to_owned()),
+            }
+        }
+        false => None,
+    };
+
+    Ok(Page {
+        items: rows,
+        total,
+        next_cursor,
+    })
 }
@@ -5,10 +5,103 @@ import time
 import socket
 import sqlite3
 import re
+import os
+import hmac
+import hashlib
+import base64
+import struct
+import zlib
+from concurrent.futures import ThreadPoolExecutor
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+import threading
 
 addr = ""
 port = 0
 
+# A postgres url runs the server and the admin cli built with the postgres feature against it
+database = os.environ.get("WIEDZIELISCIE_BACKEND_TEST_DATABASE", "db.sqlite")
+postgres = database.startswith("postgres")
+cargo_features = ["--features", "postgres"] if postgres else []
+if postgres:
+    os.environ["WIEDZIELISCIE_BACKEND_DATABASES__DB__URL"] = database
+
+# Enough of sqlite3's connection for the tests, statements go through psql and commit right away
+class PostgresConnection:
+    def __init__(self, url):
+        self.url = url
+        self.rows = []
+
+    def literal(self, value):
+        if value is None:
+            return "NULL"
+        if isinstance(value, bool):
+            return "TRUE" if value else "FALSE"
+        if isinstance(value, (int, float)):
+            return str(value)
+        return "'" + str(value).replace("'", "''") + "'"
+
+    def execute(self, sql, params=()):
+        params = iter(params)
+        sql = re.sub(r"\?", lambda _: self.literal(next(params)), sql)
+        select = sql.lstrip().upper().startswith("SELECT")
+        if select:
+            sql = f"SELECT COALESCE(json_agg(q), '[]') FROM ({sql}) q"
+        result = subprocess.run(["psql", self.url, "-v", "ON_ERROR_STOP=1", "-Atc", sql], capture_output=True, text=True)
+        if result.returncode != 0:
+            raise Exception(result.stderr.strip())
+        self.rows = json.loads(result.stdout, object_pairs_hook=lambda x: tuple(v for (_, v) in x)) if select else []
+        return self
+
+    def cursor(self):
+        return self
+
+    def fetchall(self):
+        return self.rows
+
+    def fetchone(self):
+        return self.rows[0] if self.rows else None
+
+    def commit(self):
+        pass
+
+    def close(self):
+        pass
+
+def connect_db():
+    return PostgresConnection(database) if postgres else sqlite3.connect(database)
+
+# Stands in for the resend api, tests that send mail point RESEND_BASE_URL at it
+mail_server = {"sent": [], "failing": False}
+
+class MailHandler(BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        if mail_server["failing"]:
+            response = {"statusCode": 500, "name": "internal_server_error", "message": "mail is down"}
+            self.send_response(500)
+        else:
+            mail_server["sent"].append(body)
+            response = {"id": str(len(mail_server["sent"]))}
+            self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.end_headers()
+        self.wfile.write(json.dumps(response).encode())
+
+    def log_message(self, *args):
+        pass
+
+def start_mail_server():
+    server = ThreadingHTTPServer((addr, port + 2), MailHandler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+
+def mail_env():
+    return {
+        "WIEDZIELISCIE_BACKEND_MAIL__DISABLED": "false",
+        "WIEDZIELISCIE_BACKEND_MAIL__FROM": "game@wiedzieliscie.io",
+        "RESEND_API_KEY": "re_test",
+        "RESEND_BASE_URL": f"http://{addr}:{port + 2}",
+    }
+
 def set_env():
     env_file = open(".env")
     env_txt = env_file.read().strip()
@@ -38,6 +131,61 @@ def expect_pattern(t, n, a, b):
         print(f"{t}: Expected {n} to contain {b}, but it is {a}")
         stop = True
 
+def register_user(email, password, first_name, last_name):
+    register_url = f"http://{addr}:{port}/auth/register"
+    register_data = {
+        "email": email,
+        "plaintext_password": password,
+        "first_name": first_name,
+        "last_name": last_name,
+        "gender": "m"
+    }
+    register_request = requests.post(register_url, json=register_data)
+    expect("register", "status", register_request.status_code, 201)
+    account_id = json.loads(register_request.text)["account_id"]
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT verification_token FROM verifications WHERE user_id = ?", (account_id,))
+    ver_token = cur.fetchall()[0][0]
+    conn.close()
+
+    requests.get(f"http://{addr}:{port}/auth/verify/{ver_token}")
+    return account_id
+
+def login_user(email, password):
+    login_url = f"http://{addr}:{port}/auth/login"
+    login_data = {
+        "email": email,
+        "plaintext_password": password,
+    }
+    return requests.post(login_url, json=login_data)
+
+def make_admin(account_id):
+    conn = connect_db()
+    conn.execute("UPDATE users SET admin = true WHERE user_id = ?", (account_id,))
+    conn.commit()
+    conn.close()
+
+def make_png(width, height, color=(255, 0, 0)):
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    rows = b"".join(b"\x00" + bytes(color) * width for _ in range(height))
+    header = struct.pack(">IIBBBBB", width, height, 8, 2, 0, 0, 0)
+    return b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", header) + chunk(b"IDAT", zlib.compress(rows)) + chunk(b"IEND", b"")
+
+def upload_image(jwt, image):
+    return requests.post(f"http://{addr}:{port}/api/v1/admin/media", data=image,
+                         headers={"Content-Type": "image/png", "Authorization": f"Bearer {jwt}"})
+
+def setup_admin_and_user():
+    admin_id = register_user("admin.mail@user.io", "admin_passwd", "admin", "admin")
+    user_id = register_user("user.mail.2@user.io", "user_2_passwd", "user", "number2")
+    make_admin(admin_id)
+    admin_jwt = json.loads(login_user("admin.mail@user.io", "admin_passwd").text)["jwt"]
+    return (admin_id, user_id, admin_jwt)
+
 def test_register():
     register_url = f"http://{addr}:{port}/auth/register"
     register_data = {
@@ -57,7 +205,7 @@ def test_register():
     if stop:
         return
 
-    conn = sqlite3.connect("db.sqlite")
+    conn = connect_db()
     cur = conn.cursor()
 
     cur.execute("SELECT verification_token FROM verifications WHERE user_id = ?", str(response["account_id"]))
@@ -162,7 +310,7 @@ def test_password_reset():
     reset_response = requests.post(reset_url, json=reset_data)
     expect("reset", "status", reset_response.status_code, 200)
     
-    conn = sqlite3.connect("db.sqlite")
+    conn = connect_db()
     cur = conn.cursor()
 
     cur.execute("SELECT reset_token FROM password_resets WHERE user_id = ?", "1")
@@ -253,7 +401,1609 @@ def test_retrieve_user_count():
     user_data = 1
     expect("retrieve", "response", response, user_data)
 
+def test_admin_users_list():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    list_url = f"http://{addr}:{port}/admin/users/get"
+    list_response = requests.post(list_url, json={"jwt": admin_jwt})
+    expect("users list", "status", list_response.status_code, 200)
+    expect("users list", "total", json.loads(list_response.text)["total"], 2)
+
+    list_response = requests.post(list_url, json={"jwt": admin_jwt, "admin": True})
+    response = json.loads(list_response.text)
+    expect("users list", "admin total", response["total"], 1)
+    expect("users list", "admin id", response["users"][0]["account_id"], admin_id)
+
+    list_response = requests.post(list_url, json={"jwt": admin_jwt, "search": "number2"})
+    response = json.loads(list_response.text)
+    expect("users list", "search id", response["users"][0]["account_id"], user_id)
+
+    overflow_response = requests.post(list_url, json={"jwt": admin_jwt, "page": 4294967295})
+    expect("users list", "overflowing page status", overflow_response.status_code, 400)
+
+def test_admin_users_ban():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    ban_url = f"http://{addr}:{port}/admin/users/ban"
+    ban_data = {"jwt": admin_jwt, "account_id": user_id, "reason": "spam", "duration": 3600}
+    ban_response = requests.post(ban_url, json=ban_data)
+    expect("ban", "status", ban_response.status_code, 200)
+
+    login_response = login_user("user.mail.2@user.io", "user_2_passwd")
+    expect("ban", "login status", login_response.status_code, 403)
+
+    list_url = f"http://{addr}:{port}/admin/users/get"
+    list_response = requests.post(list_url, json={"jwt": admin_jwt, "banned": True})
+    expect("ban", "banned total", json.loads(list_response.text)["total"], 1)
+
+    unban_url = f"http://{addr}:{port}/admin/users/unban"
+    unban_response = requests.post(unban_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("unban", "status", unban_response.status_code, 200)
+
+    login_response = login_user("user.mail.2@user.io", "user_2_passwd")
+    expect("unban", "login status", login_response.status_code, 200)
+
+def test_admin_users_promote_demote():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    demote_url = f"http://{addr}:{port}/admin/users/demote"
+    demote_response = requests.post(demote_url, json={"jwt": admin_jwt, "account_id": admin_id})
+    expect("demote", "last admin status", demote_response.status_code, 400)
+
+    promote_url = f"http://{addr}:{port}/admin/users/promote"
+    promote_response = requests.post(promote_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("promote", "status", promote_response.status_code, 200)
+
+    demote_response = requests.post(demote_url, json={"jwt": admin_jwt, "account_id": admin_id})
+    expect("demote", "status", demote_response.status_code, 200)
+
+def test_admin_users_impersonate():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    impersonate_url = f"http://{addr}:{port}/admin/users/impersonate"
+    impersonate_data = {"jwt": admin_jwt, "account_id": user_id}
+    impersonate_response = requests.post(impersonate_url, json=impersonate_data)
+    expect("impersonate", "status", impersonate_response.status_code, 200)
+
+    if stop:
+        return
+
+    jwt = json.loads(impersonate_response.text)["jwt"]
+    retrieve_url = f"http://{addr}:{port}/auth/retrieve_user"
+    retrieve_response = requests.post(retrieve_url, json={"jwt": jwt})
+    expect("impersonate", "account_id", json.loads(retrieve_response.text)["account_id"], user_id)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT actor_id, entity_id FROM audit_log WHERE action = 'impersonate'")
+    expect("impersonate", "audit entry", cur.fetchall(), [(admin_id, user_id)])
+
+def test_admin_audit():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    audit_url = f"http://{addr}:{port}/admin/audit"
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    forbidden_response = requests.post(audit_url, json={"jwt": user_jwt})
+    expect("audit", "non admin status", forbidden_response.status_code, 400)
+
+    radius_url = f"http://{addr}:{port}/admin/game/set_location_radius"
+    radius_response = requests.post(radius_url, json={"jwt": admin_jwt, "distance": 25.0})
+    expect("audit", "radius status", radius_response.status_code, 200)
+
+    pause_url = f"http://{addr}:{port}/admin/game/pause"
+    pause_response = requests.post(pause_url, json={"jwt": admin_jwt})
+    expect("audit", "pause status", pause_response.status_code, 200)
+
+    if stop:
+        return
+
+    audit_response = requests.post(audit_url, json={"jwt": admin_jwt, "entity_type": "game"})
+    expect("audit", "status", audit_response.status_code, 200)
+
+    if stop:
+        return
+
+    response = json.loads(audit_response.text)
+    expect("audit", "total", response["total"], 2)
+    expect("audit", "actions", [x["action"] for x in response["entries"]], ["pause", "set_location_radius"])
+    expect("audit", "actor", response["entries"][0]["actor_id"], admin_id)
+    expect("audit", "after", response["entries"][1]["after"], {"location_radius": 25.0})
+
+    overflow_response = requests.post(audit_url, json={"jwt": admin_jwt, "page": 4294967295})
+    expect("audit", "overflowing page status", overflow_response.status_code, 400)
+
+    unpause_url = f"http://{addr}:{port}/admin/game/unpause"
+    requests.post(unpause_url, json={"jwt": admin_jwt})
+
+def test_report_triage():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+
+    report_url = f"http://{addr}:{port}/report/error"
+    report_data = {
+        "jwt": user_jwt,
+        "title": "crash",
+        "message": "app crashes on start",
+        "app_version": "1.2.3",
+        "device_info": "pixel 7"
+    }
+    report_response = requests.post(report_url, json=report_data)
+    expect("triage", "report status", report_response.status_code, 200)
+
+    suggestion_url = f"http://{addr}:{port}/report/suggestion"
+    suggestion_data = {"jwt": user_jwt, "title": "dark mode", "message": "please"}
+    suggestion_response = requests.post(suggestion_url, json=suggestion_data)
+    expect("triage", "suggestion status", suggestion_response.status_code, 200)
+
+    if stop:
+        return
+
+    report_id = json.loads(report_response.text)["report_id"]
+    suggestion_id = json.loads(suggestion_response.text)["report_id"]
+
+    suggestions_url = f"http://{addr}:{port}/admin/get_suggestions"
+    suggestions_response = requests.post(suggestions_url, json={"jwt": admin_jwt})
+    suggestions = json.loads(suggestions_response.text)["suggestions"]
+    expect("triage", "suggestions", [(x["report_id"], x["user_id"], x["status"]) for x in suggestions], [(suggestion_id, user_id, "open")])
+
+    set_status_url = f"http://{addr}:{port}/admin/reports/set_status"
+    set_status_data = {
+        "jwt": admin_jwt,
+        "kind": "error",
+        "report_id": report_id,
+        "status": "wont_fix",
+        "comment": "works as intended"
+    }
+    set_status_response = requests.post(set_status_url, json=set_status_data)
+    expect("triage", "set status", set_status_response.status_code, 200)
+
+    repeat_response = requests.post(set_status_url, json=set_status_data)
+    expect("triage", "repeated set status", repeat_response.status_code, 400)
+
+    reports_url = f"http://{addr}:{port}/admin/get_reports"
+    open_response = requests.post(reports_url, json={"jwt": admin_jwt, "status": "open"})
+    expect("triage", "open reports", json.loads(open_response.text), {"reports": [], "total": 0, "next_cursor": None})
+
+    get_url = f"http://{addr}:{port}/admin/reports/get"
+    get_response = requests.post(get_url, json={"jwt": admin_jwt, "kind": "error", "report_id": report_id})
+    expect("triage", "get status", get_response.status_code, 200)
+
+    if stop:
+        return
+
+    response = json.loads(get_response.text)
+    expect("triage", "report", (response["report"]["status"], response["report"]["app_version"], response["report"]["device_info"]), ("wont_fix", "1.2.3", "pixel 7"))
+    expect("triage", "comments", [(x["admin_id"], x["message"]) for x in response["comments"]], [(admin_id, "works as intended")])
+
+def test_report_status_email():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    report_url = f"http://{addr}:{port}/report/error"
+    report_data = {"jwt": user_jwt, "title": "<b>crash</b>", "message": "app crashes"}
+    expect("report email", "report status", requests.post(report_url, json=report_data).status_code, 200)
+
+    mail_server["sent"].clear()
+    set_status_url = f"http://{addr}:{port}/admin/reports/set_status"
+    set_status_data = {"jwt": admin_jwt, "kind": "error", "report_id": 1, "status": "acknowledged", "comment": "<script>alert(1)</script>"}
+    expect("report email", "set status", requests.post(set_status_url, json=set_status_data).status_code, 200)
+    expect("report email", "recipients", [x["to"] for x in mail_server["sent"]], [["user.mail.2@user.io"]])
+
+    if stop:
+        return
+
+    html = mail_server["sent"][0]["html"]
+    expect("report email", "escaped title", "&lt;b&gt;crash&lt;/b&gt;" in html, True)
+    expect("report email", "escaped comment", "&lt;script&gt;alert(1)&lt;/script&gt;" in html and "<script>" not in html, True)
+
+    mail_server["failing"] = True
+    set_status_data = {"jwt": admin_jwt, "kind": "error", "report_id": 1, "status": "resolved"}
+    failed_mail_response = requests.post(set_status_url, json=set_status_data)
+    mail_server["failing"] = False
+    expect("report email", "failed mail status", failed_mail_response.status_code, 200)
+
+    get_url = f"http://{addr}:{port}/admin/reports/get"
+    get_response = requests.post(get_url, json={"jwt": admin_jwt, "kind": "error", "report_id": 1})
+    expect("report email", "status kept", json.loads(get_response.text)["report"]["status"], "resolved")
+
+def test_admin_get_logs():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    audit_url = f"http://{addr}:{port}/admin/audit"
+    audit_response = requests.post(audit_url, json={"jwt": admin_jwt})
+    expect("logs", "audit status", audit_response.status_code, 200)
+    expect("logs", "request id header", "X-Request-Id" in audit_response.headers, True)
+
+    missing_url = f"http://{addr}:{port}/missing/route"
+    missing_response = requests.get(missing_url)
+    expect("logs", "missing status", missing_response.status_code, 404)
+
+    if stop:
+        return
+
+    logs_url = f"http://{addr}:{port}/admin/get_logs"
+    logs_response = requests.post(logs_url, json={"jwt": admin_jwt, "route": "/admin/audit"})
+    expect("logs", "status", logs_response.status_code, 200)
+
+    if stop:
+        return
+
+    logs = json.loads(logs_response.text)
+    expect("logs", "audit entries", len(logs), 1)
+
+    if stop:
+        return
+
+    entry = logs[0]
+    expect("logs", "entry", (entry["level"], entry["method"], entry["status"], entry["user_id"]), ("info", "POST", 200, admin_id))
+    expect("logs", "request id", entry["request_id"], audit_response.headers["X-Request-Id"])
+    expect("logs", "latency", entry["latency_ms"] is not None, True)
+
+    warn_response = requests.post(logs_url, json={"jwt": admin_jwt, "level": "warn"})
+    warn_logs = json.loads(warn_response.text)
+    expect("logs", "warn entries", [(x["route"], x["status"]) for x in warn_logs], [("/missing/route", 404)])
+
+    future_response = requests.post(logs_url, json={"jwt": admin_jwt, "since": int(time.time()) + 3600})
+    expect("logs", "future entries", json.loads(future_response.text), [])
+
+def test_metrics():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    login_url = f"http://{addr}:{port}/auth/login"
+    requests.post(login_url, json={"email": "user.mail.2@user.io", "plaintext_password": "wrong"})
+
+    metrics_url = f"http://{addr}:{port}/metrics"
+    anonymous_response = requests.get(metrics_url)
+    expect("metrics", "anonymous status", anonymous_response.status_code, 401)
+
+    spoofed_response = requests.get(metrics_url, headers={"X-Real-IP": "10.9.9.9"})
+    expect("metrics", "spoofed ip status", spoofed_response.status_code, 401)
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    user_response = requests.get(metrics_url, headers={"Authorization": f"Bearer {user_jwt}"})
+    expect("metrics", "user status", user_response.status_code, 401)
+
+    metrics_response = requests.get(metrics_url, headers={"Authorization": f"Bearer {admin_jwt}"})
+    expect("metrics", "status", metrics_response.status_code, 200)
+
+    if stop:
+        return
+
+    lines = metrics_response.text.splitlines()
+    expect("metrics", "registrations", "registrations_total 2" in lines, True)
+    expect("metrics", "failed logins", "failed_logins_total 1" in lines, True)
+    expect("metrics", "active sessions", "active_sessions 2" in lines, True)
+    expect("metrics", "register count", 'http_requests_total{method="POST",route="/auth/register",status="201"} 2' in lines, True)
+    expect_pattern("metrics", "latency histogram", metrics_response.text, '(?s).*http_request_duration_seconds_count{method="POST",route="/auth/login"} 3.*')
+
+def test_health_ready():
+    health_url = f"http://{addr}:{port}/health"
+    health_response = requests.get(health_url)
+    expect("health", "status", health_response.status_code, 200)
+    expect("health", "mailer", json.loads(health_response.text)["checks"], [{"name": "mailer", "ok": True, "details": {"disabled": True}}])
+
+    ready_url = f"http://{addr}:{port}/ready"
+    ready_response = requests.get(ready_url)
+    expect("ready", "status", ready_response.status_code, 200)
+    expect("ready", "response", json.loads(ready_response.text)["status"], "ready")
+    expect("ready", "checks", [x["name"] for x in json.loads(ready_response.text)["checks"]], ["database", "game", "secret"])
+
+    if stop:
+        return
+
+    conn = connect_db()
+    conn.execute("DELETE FROM game")
+    conn.commit()
+    conn.close()
+
+    not_ready_response = requests.get(ready_url)
+    expect("ready", "missing game status", not_ready_response.status_code, 503)
+
+    if stop:
+        return
+
+    problems = json.loads(not_ready_response.text)["problems"]
+    expect("ready", "problems", [(x["name"], x["error"]) for x in problems], [("game", "Game row is missing")])
+
+def test_janitor():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    register_url = f"http://{addr}:{port}/auth/register"
+    register_data = {
+        "email": "unverified@user.io",
+        "plaintext_password": "unverified_passwd",
+        "first_name": "un",
+        "last_name": "verified",
+        "gender": "f"
+    }
+    unverified_id = json.loads(requests.post(register_url, json=register_data).text)["account_id"]
+    register_data["email"] = "fresh@user.io"
+    fresh_id = json.loads(requests.post(register_url, json=register_data).text)["account_id"]
+
+    conn = connect_db()
+    conn.execute("UPDATE verifications SET timestamp = 0 WHERE user_id = ?", (unverified_id,))
+    conn.execute("INSERT INTO sessions (user_id, session_token, timestamp, valid_until) VALUES (?, 'old', 0, 1)", (user_id,))
+    conn.commit()
+    conn.close()
+
+    janitor_url = f"http://{addr}:{port}/admin/janitor/run"
+    janitor_response = requests.post(janitor_url, json={"jwt": admin_jwt})
+    expect("janitor", "status", janitor_response.status_code, 200)
+
+    if stop:
+        return
+
+    counts = json.loads(janitor_response.text)
+    expect("janitor", "unverified users", counts["unverified_users"], 1)
+    # the two verified accounts leave their verifications behind, the purged account's go with it
+    expect("janitor", "verifications", counts["verifications"], 2)
+    expect("janitor", "sessions", counts["sessions"], 1)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT user_id FROM users WHERE verified = false")
+    expect("janitor", "remaining unverified", cur.fetchall(), [(fresh_id,)])
+    cur.execute("SELECT user_id FROM verifications")
+    expect("janitor", "remaining verifications", cur.fetchall(), [(fresh_id,)])
+    conn.close()
+
+    login_response = login_user("admin.mail@user.io", "admin_passwd")
+    expect("janitor", "active session kept", login_response.status_code, 200)
+
+def test_rate_limits():
+    register_user("limited@user.io", "limited_passwd", "rate", "limited")
+
+    if stop:
+        return
+
+    for i in range(5):
+        wrong_response = login_user("limited@user.io", "wrong_passwd")
+        expect("rate limits", f"wrong login {i} status", wrong_response.status_code, 400)
+
+    locked_response = login_user("limited@user.io", "limited_passwd")
+    expect("rate limits", "locked login status", locked_response.status_code, 429)
+    expect("rate limits", "locked login has retry after", "Retry-After" in locked_response.headers, True)
+
+    if stop:
+        return
+
+    expect("rate limits", "locked login retry after", int(locked_response.headers["Retry-After"]) > 0, True)
+
+    login_url = f"http://{addr}:{port}/auth/login"
+    spoofed_statuses = [requests.post(login_url,
+                                      json={"email": f"spoofed.{i}@user.io", "plaintext_password": "wrong"},
+                                      headers={"X-Real-IP": f"10.0.0.{i}"}).status_code for i in range(20)]
+    expect("rate limits", "spoofed ip limited", spoofed_statuses[-1], 429)
+
+    reset_url = f"http://{addr}:{port}/auth/password_reset"
+    reset_data = {"email": "limited@user.io", "plaintext_password": "new_passwd"}
+    reset_statuses = [requests.post(reset_url, json=reset_data).status_code for _ in range(4)]
+    expect("rate limits", "password reset statuses", reset_statuses, [200, 400, 400, 429])
+
+def totp_code(secret, offset=0):
+    key = base64.b32decode(secret + "=" * (-len(secret) % 8))
+    step = int(time.time()) // 30 + offset
+    digest = hmac.new(key, struct.pack(">Q", step), hashlib.sha1).digest()
+    start = digest[-1] & 0xf
+    code = struct.unpack(">I", digest[start:start + 4])[0] & 0x7fffffff
+    return f"{code % 1000000:06d}"
+
+def enable_totp(jwt):
+    enroll_url = f"http://{addr}:{port}/user/totp/enroll"
+    enroll_response = requests.post(enroll_url, json={"jwt": jwt})
+    expect("totp", "enroll status", enroll_response.status_code, 200)
+    secret = json.loads(enroll_response.text)["secret"]
+
+    confirm_url = f"http://{addr}:{port}/user/totp/confirm"
+    confirm_response = requests.post(confirm_url, json={"jwt": jwt, "code": totp_code(secret)})
+    expect("totp", "confirm status", confirm_response.status_code, 200)
+    return (secret, json.loads(confirm_response.text)["recovery_codes"])
+
+def login_totp(challenge, code):
+    login_url = f"http://{addr}:{port}/auth/login/totp"
+    return requests.post(login_url, json={"challenge": challenge, "code": code})
+
+def test_totp():
+    register_user("totp@user.io", "totp_passwd", "totp", "user")
+    jwt = json.loads(login_user("totp@user.io", "totp_passwd").text)["jwt"]
+
+    enroll_url = f"http://{addr}:{port}/user/totp/enroll"
+    enroll_response = requests.post(enroll_url, json={"jwt": jwt})
+    expect("totp", "enroll status", enroll_response.status_code, 200)
+
+    if stop:
+        return
+
+    enrolment = json.loads(enroll_response.text)
+    expect_pattern("totp", "otpauth uri", enrolment["otpauth_uri"],
+                   f"^otpauth://totp/WiedzieLISCIE:totp@user.io\\?secret={enrolment['secret']}&issuer=WiedzieLISCIE")
+
+    confirm_url = f"http://{addr}:{port}/user/totp/confirm"
+    wrong_response = requests.post(confirm_url, json={"jwt": jwt, "code": "12345"})
+    expect("totp", "wrong confirm status", wrong_response.status_code, 400)
+
+    secret = enrolment["secret"]
+    used_code = totp_code(secret)
+    confirm_response = requests.post(confirm_url, json={"jwt": jwt, "code": used_code})
+    expect("totp", "confirm status", confirm_response.status_code, 200)
+
+    if stop:
+        return
+
+    recovery_codes = json.loads(confirm_response.text)["recovery_codes"]
+    expect("totp", "recovery code count", len(recovery_codes), 10)
+
+    login_response = login_user("totp@user.io", "totp_passwd")
+    expect("totp", "login status", login_response.status_code, 202)
+    login = json.loads(login_response.text)
+    expect("totp", "login has no jwt", "jwt" in login, False)
+
+    if stop:
+        return
+
+    expect("totp", "replayed code status", login_totp(login["challenge"], used_code).status_code, 400)
+
+    totp_response = login_totp(login["challenge"], totp_code(secret, 1))
+    expect("totp", "second step status", totp_response.status_code, 200)
+    expect("totp", "second step has jwt", "jwt" in json.loads(totp_response.text), True)
+    expect("totp", "used challenge status", login_totp(login["challenge"], recovery_codes[0]).status_code, 400)
+
+    challenge = json.loads(login_user("totp@user.io", "totp_passwd").text)["challenge"]
+    expect("totp", "recovery code status", login_totp(challenge, recovery_codes[0]).status_code, 200)
+    challenge = json.loads(login_user("totp@user.io", "totp_passwd").text)["challenge"]
+    expect("totp", "reused recovery code status", login_totp(challenge, recovery_codes[0]).status_code, 400)
+
+    status_url = f"http://{addr}:{port}/user/totp/status"
+    status = json.loads(requests.post(status_url, json={"jwt": jwt}).text)
+    expect("totp", "status", status, {"enabled": True, "recovery_codes_left": 9})
+
+    disable_url = f"http://{addr}:{port}/user/totp/disable"
+    disable_response = requests.post(disable_url, json={"jwt": jwt, "code": recovery_codes[1]})
+    expect("totp", "disable status", disable_response.status_code, 200)
+    expect("totp", "login after disable status", login_user("totp@user.io", "totp_passwd").status_code, 200)
+
+def test_totp_required_for_admins():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    list_url = f"http://{addr}:{port}/admin/users/get"
+    list_response = requests.post(list_url, json={"jwt": admin_jwt})
+    expect("totp required", "list without totp status", list_response.status_code, 403)
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    retrieve_url = f"http://{addr}:{port}/auth/retrieve_user"
+    retrieve_response = requests.post(retrieve_url, json={"jwt": user_jwt})
+    expect("totp required", "users unaffected", retrieve_response.status_code, 200)
+
+    (secret, _) = enable_totp(admin_jwt)
+
+    if stop:
+        return
+
+    challenge = json.loads(login_user("admin.mail@user.io", "admin_passwd").text)["challenge"]
+    admin_jwt = json.loads(login_totp(challenge, totp_code(secret, 1)).text)["jwt"]
+    list_response = requests.post(list_url, json={"jwt": admin_jwt})
+    expect("totp required", "list with totp status", list_response.status_code, 200)
+
+def test_user_export():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+
+    report_url = f"http://{addr}:{port}/report/error"
+    report_data = {"jwt": user_jwt, "title": "crash", "message": "app crashes on start"}
+    expect("export", "report status", requests.post(report_url, json=report_data).status_code, 200)
+
+    export_url = f"http://{addr}:{port}/user/export"
+    export_response = requests.post(export_url, json={"jwt": user_jwt})
+    expect("export", "status", export_response.status_code, 200)
+
+    if stop:
+        return
+
+    export = json.loads(export_response.text)
+    expect("export", "profile", export["profile"], {
+        "account_id": user_id,
+        "email": "user.mail.2@user.io",
+        "first_name": "user",
+        "last_name": "number2",
+        "gender": "m",
+        "verified": True,
+        "admin": False
+    })
+    expect("export", "sessions", len(export["sessions"]), 1)
+    expect("export", "verifications", len(export["verifications"]), 1)
+    expect("export", "error report", export["error_reports"][0]["title"], "crash")
+    expect("export", "no password", "user_2_passwd" in export_response.text, False)
+    expect("export", "no session token", "session_token" in export_response.text, False)
+
+    admin_export_url = f"http://{addr}:{port}/admin/users/export"
+    user_response = requests.post(admin_export_url, json={"jwt": user_jwt, "account_id": admin_id})
+    expect("export", "user exporting admin status", user_response.status_code, 400)
+
+    admin_response = requests.post(admin_export_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("export", "admin export status", admin_response.status_code, 200)
+    expect("export", "admin export profile", json.loads(admin_response.text)["profile"], export["profile"])
+
+    missing_response = requests.post(admin_export_url, json={"jwt": admin_jwt, "account_id": 1000})
+    expect("export", "missing account status", missing_response.status_code, 404)
+
+def delete_account(email):
+    delete_url = f"http://{addr}:{port}/auth/delete_user"
+    delete_response = requests.post(delete_url, json={"email": email})
+    expect("deletion", "delete request status", delete_response.status_code, 200)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT delete_token FROM delete_requests WHERE user_id = (SELECT user_id FROM users WHERE email = ?)", (email,))
+    delete_token = cur.fetchall()[0][0]
+    conn.close()
+
+    requests.get(f"http://{addr}:{port}/auth/delete_user/verify/{delete_token}")
+
+def test_deleted_user_anonymisation():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    report_url = f"http://{addr}:{port}/report/error"
+    report_data = {"jwt": user_jwt, "title": "crash", "message": "app crashes on start"}
+    expect("deletion", "report status", requests.post(report_url, json=report_data).status_code, 200)
+
+    delete_account("user.mail.2@user.io")
+    expect("deletion", "deleted login status", login_user("user.mail.2@user.io", "user_2_passwd").status_code, 400)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT COUNT(*) FROM sessions WHERE user_id = ?", (user_id,))
+    expect("deletion", "sessions removed", cur.fetchall(), [(0,)])
+    conn.close()
+
+    deleted_url = f"http://{addr}:{port}/admin/users/deleted/get"
+    deleted = json.loads(requests.post(deleted_url, json={"jwt": admin_jwt}).text)["users"]
+    expect("deletion", "deleted users", len(deleted), 1)
+
+    if stop:
+        return
+
+    expect("deletion", "deleted email", deleted[0]["email"], "user.mail.2@user.io")
+    expect("deletion", "restorable until", deleted[0]["restorable_until"], deleted[0]["deleted_at"] + 2592000)
+
+    restore_url = f"http://{addr}:{port}/admin/users/restore"
+    restore_response = requests.post(restore_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("deletion", "restore status", restore_response.status_code, 200)
+    expect("deletion", "restored login status", login_user("user.mail.2@user.io", "user_2_passwd").status_code, 200)
+
+    email_url = f"http://{addr}:{port}/user/modify/email"
+    for new_email in ["renamed.2@user.io", "user.mail.2@user.io"]:
+        email_response = requests.post(email_url, json={"jwt": admin_jwt, "account_id": user_id, "new_value": new_email})
+        expect("deletion", f"email change to {new_email} status", email_response.status_code, 200)
+
+    delete_account("user.mail.2@user.io")
+
+    conn = connect_db()
+    conn.execute("UPDATE deleted_users SET deleted_at = 0 WHERE user_id = ?", (user_id,))
+    conn.commit()
+    conn.close()
+
+    expired_response = requests.post(restore_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("deletion", "expired restore status", expired_response.status_code, 400)
+
+    janitor_url = f"http://{addr}:{port}/admin/janitor/run"
+    janitor_response = requests.post(janitor_url, json={"jwt": admin_jwt})
+    expect("deletion", "anonymised users", json.loads(janitor_response.text)["anonymised_users"], 1)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT email, first_name, last_name, password, anonymised FROM deleted_users WHERE user_id = ?", (user_id,))
+    expect("deletion", "anonymised row", cur.fetchall(),
+           [(hashlib.sha256(b"user.mail.2@user.io").hexdigest(), "", "", None, 1)])
+    cur.execute("SELECT user_id FROM error_report")
+    expect("deletion", "detached report", cur.fetchall(), [(None,)])
+    cur.execute("SELECT before, after FROM audit_log WHERE action = 'update_email' ORDER BY audit_id")
+    original_hash = hashlib.sha256(b"user.mail.2@user.io").hexdigest()
+    renamed_hash = hashlib.sha256(b"renamed.2@user.io").hexdigest()
+    expect("deletion", "hashed audit emails", [tuple(json.loads(x) for x in row) for row in cur.fetchall()],
+           [({"email": original_hash}, {"email": renamed_hash}), ({"email": renamed_hash}, {"email": original_hash})])
+    conn.close()
+
+    restore_response = requests.post(restore_url, json={"jwt": admin_jwt, "account_id": user_id})
+    expect("deletion", "anonymised restore status", restore_response.status_code, 400)
+
+def test_api_v1():
+    api_url = f"http://{addr}:{port}/api/v1"
+    register_data = {
+        "email": "v1.mail@user.io",
+        "plaintext_password": "v1_passwd",
+        "first_name": "v1",
+        "last_name": "user",
+        "gender": "m"
+    }
+    register_response = requests.post(f"{api_url}/auth/register", json=register_data)
+    expect("api v1", "register status", register_response.status_code, 201)
+    expect("api v1", "register deprecation", "Deprecation" in register_response.headers, False)
+
+    if stop:
+        return
+
+    account_id = json.loads(register_response.text)["account_id"]
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT verification_token FROM verifications WHERE user_id = ?", (account_id,))
+    ver_token = cur.fetchall()[0][0]
+    conn.close()
+
+    verify_response = requests.get(f"{api_url}/auth/verify/{ver_token}")
+    expect_pattern("api v1", "verify response", verify_response.text, ".*successful.*")
+
+    login_response = requests.post(f"{api_url}/auth/login", json={"email": "v1.mail@user.io", "plaintext_password": "v1_passwd"})
+    expect("api v1", "login status", login_response.status_code, 200)
+
+    if stop:
+        return
+
+    jwt = json.loads(login_response.text)["jwt"]
+
+    modify_data = {"jwt": jwt, "new_value": "renamed", "account_id": account_id}
+    modify_response = requests.put(f"{api_url}/users/me/first_name", json=modify_data)
+    expect("api v1", "modify status", modify_response.status_code, 200)
+
+    user_response = requests.get(f"{api_url}/users/{account_id}")
+    expect("api v1", "user status", user_response.status_code, 200)
+    expect("api v1", "user first name", json.loads(user_response.text)["first_name"], "renamed")
+    expect("api v1", "missing user status", requests.get(f"{api_url}/users/1000").status_code, 404)
+    expect("api v1", "user count", json.loads(requests.get(f"{api_url}/users/count").text), 1)
+
+    make_admin(account_id)
+    media_id = json.loads(upload_image(jwt, make_png(4, 4)).text)["media_id"]
+    character_data = {
+        "jwt": jwt,
+        "name": "character",
+        "short_description": "short",
+        "full_description": "full",
+        "media_id": media_id
+    }
+    character_response = requests.post(f"{api_url}/admin/characters", json=character_data)
+    expect("api v1", "character add status", character_response.status_code, 200)
+
+    if stop:
+        return
+
+    character_id = json.loads(character_response.text)["character_id"]
+
+    get_response = requests.get(f"{api_url}/characters/{character_id}")
+    expect("api v1", "character get status", get_response.status_code, 200)
+    expect("api v1", "character name", json.loads(get_response.text)["name"], "character")
+
+    legacy_response = requests.post(f"http://{addr}:{port}/get/character", json={"character_id": character_id})
+    expect("api v1", "legacy status", legacy_response.status_code, 200)
+    expect("api v1", "legacy deprecation", legacy_response.headers.get("Deprecation"), "@1792368000")
+    expect("api v1", "legacy link", legacy_response.headers.get("Link"), '</api/v1/characters/{character_id}>; rel="successor-version"')
+
+    resend_response = requests.post(f"http://{addr}:{port}/auth/resend_verification/{account_id}")
+    expect("api v1", "legacy link with segment", resend_response.headers.get("Link"), f'</api/v1/auth/register/{account_id}/resend>; rel="successor-version"')
+
+    delete_response = requests.delete(f"{api_url}/admin/characters", json={"jwt": jwt, "character_id": character_id})
+    expect("api v1", "character delete status", delete_response.status_code, 200)
+
+    search_response = requests.post(f"{api_url}/admin/characters/search", json={"jwt": jwt})
+    expect("api v1", "character search", json.loads(search_response.text)["characters"], [])
+
+    expect("api v1", "old path under v1", requests.post(f"{api_url}/admin/characters/add", json=character_data).status_code, 404)
+    expect("api v1", "health deprecation", "Deprecation" in requests.get(f"http://{addr}:{port}/health").headers, False)
+
+def read_events(response, count):
+    events = []
+    event = {}
+    for line in response.iter_lines(decode_unicode=True):
+        if line.startswith("event:"):
+            event["event"] = line[len("event:"):].strip()
+        elif line.startswith("data:"):
+            event["data"] = json.loads(line[len("data:"):].strip())
+        elif line == "" and event:
+            events.append(event)
+            event = {}
+            if len(events) == count:
+                break
+    return events
+
+def test_events():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+
+    expect("events", "invalid token status", requests.get(f"{api_url}/events", params={"jwt": "invalid"}).status_code, 400)
+
+    stream = requests.get(f"{api_url}/events", params={"jwt": user_jwt}, stream=True, timeout=10)
+    expect("events", "status", stream.status_code, 200)
+    expect("events", "content type", stream.headers.get("Content-Type"), "text/event-stream")
+
+    if stop:
+        return
+
+    requests.post(f"{api_url}/admin/game/pause", json={"jwt": admin_jwt})
+    requests.post(f"{api_url}/admin/game/pause", json={"jwt": admin_jwt})
+    requests.put(f"{api_url}/admin/game/location_radius", json={"jwt": admin_jwt, "distance": 25.5})
+    requests.post(f"{api_url}/admin/game/unpause", json={"jwt": admin_jwt})
+    quest_data = {
+        "jwt": admin_jwt,
+        "name": "quest",
+        "description": "description",
+        "unlocks": [],
+        "points": 10,
+        "coins": 5,
+        "rewards": []
+    }
+    quest_id = json.loads(requests.post(f"{api_url}/admin/quests", json=quest_data).text)["quest_id"]
+
+    events = read_events(stream, 4)
+    stream.close()
+
+    expect("events", "events", events, [
+        {"event": "game_paused", "data": {"type": "game_paused"}},
+        {"event": "location_radius_changed", "data": {"type": "location_radius_changed", "radius": 25.5}},
+        {"event": "game_unpaused", "data": {"type": "game_unpaused"}},
+        {"event": "quest_available", "data": {"type": "quest_available", "quest_id": quest_id}},
+    ])
+
+    def stream_ended(response):
+        try:
+            for _ in response.iter_lines():
+                pass
+            return True
+        except requests.exceptions.ConnectionError:
+            return False
+
+    stream = requests.get(f"{api_url}/events", params={"jwt": user_jwt}, stream=True, timeout=10)
+    requests.post(f"{api_url}/auth/logout", json={"jwt": user_jwt})
+    expect("events", "logged out stream ended", stream_ended(stream), True)
+    expect("events", "logged out status", requests.get(f"{api_url}/events", params={"jwt": user_jwt}).status_code, 400)
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    stream = requests.get(f"{api_url}/events", params={"jwt": user_jwt}, stream=True, timeout=10)
+    ban_data = {"jwt": admin_jwt, "account_id": user_id, "reason": "spam", "duration": 3600}
+    expect("events", "ban status", requests.post(f"{api_url}/admin/users/ban", json=ban_data).status_code, 200)
+    expect("events", "banned stream ended", stream_ended(stream), True)
+
+def test_announcements():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+
+    stream = requests.get(f"{api_url}/events", params={"jwt": user_jwt}, stream=True, timeout=10)
+    admin_stream = requests.get(f"{api_url}/events", params={"jwt": admin_jwt}, stream=True, timeout=10)
+
+    def announce(title, **fields):
+        return requests.post(f"{api_url}/admin/announcements", json={"jwt": admin_jwt, "title": title, "message": "message", **fields})
+
+    def inbox(jwt):
+        return [x["title"] for x in json.loads(requests.post(f"{api_url}/users/me/inbox", json={"jwt": jwt}).text)]
+
+    def unread(jwt):
+        return json.loads(requests.post(f"{api_url}/users/me/inbox/unread", json={"jwt": jwt}).text)["unread"]
+
+    expect("announcements", "user can't create", requests.post(f"{api_url}/admin/announcements", json={"jwt": user_jwt, "title": "title", "message": "message", "audience": "all"}).status_code, 400)
+    expect("announcements", "empty title", announce("", audience="all").status_code, 400)
+    expect("announcements", "missing quest", announce("quest", audience="quest_completed").status_code, 400)
+    expect("announcements", "unknown quest", announce("quest", audience="quest_completed", quest_id=99).status_code, 404)
+    expect("announcements", "expiry before publishing", announce("expired", audience="all", publish_at=int(time.time()) + 100, expires_at=int(time.time()) + 50).status_code, 400)
+
+    everyone = announce("everyone", audience="all")
+    expect("announcements", "create status", everyone.status_code, 200)
+
+    if stop:
+        return
+
+    everyone_id = json.loads(everyone.text)["announcement_id"]
+    admins_id = json.loads(announce("admins only", audience="admins").text)["announcement_id"]
+    scheduled_id = json.loads(announce("scheduled", audience="all", publish_at=int(time.time()) + 2).text)["announcement_id"]
+
+    expect("announcements", "user inbox", inbox(user_jwt), ["everyone"])
+    expect("announcements", "admin inbox", inbox(admin_jwt), ["admins only", "everyone"])
+    expect("announcements", "user unread", unread(user_jwt), 1)
+
+    time.sleep(4)
+    expect("announcements", "scheduled published", inbox(user_jwt), ["scheduled", "everyone"])
+
+    published = [x["data"] for x in read_events(stream, 2)]
+    stream.close()
+    expect("announcements", "published events", published, [
+        {"type": "announcement_published", "announcement_id": everyone_id, "audience": "all"},
+        {"type": "announcement_published", "announcement_id": scheduled_id, "audience": "all"},
+    ])
+    admin_published = [x["data"]["announcement_id"] for x in read_events(admin_stream, 3)]
+    admin_stream.close()
+    expect("announcements", "admin events", admin_published, [everyone_id, admins_id, scheduled_id])
+
+    read_response = requests.put(f"{api_url}/users/me/inbox/read", json={"jwt": user_jwt, "announcement_id": everyone_id})
+    expect("announcements", "read status", read_response.status_code, 200)
+    expect("announcements", "unread after read", unread(user_jwt), 1)
+    expect("announcements", "admin unread", unread(admin_jwt), 3)
+    entries = json.loads(requests.post(f"{api_url}/users/me/inbox", json={"jwt": user_jwt}).text)
+    expect("announcements", "read flags", [x["read"] for x in entries], [False, True])
+
+    expect("announcements", "read other audience", requests.put(f"{api_url}/users/me/inbox/read", json={"jwt": user_jwt, "announcement_id": admins_id}).status_code, 404)
+
+    expire_response = requests.put(f"{api_url}/admin/announcements/expire", json={"jwt": admin_jwt, "announcement_id": scheduled_id})
+    expect("announcements", "expire status", expire_response.status_code, 200)
+    expect("announcements", "expired gone", inbox(user_jwt), ["everyone"])
+    expect("announcements", "unread after expiry", unread(user_jwt), 0)
+    expect("announcements", "expire again", requests.put(f"{api_url}/admin/announcements/expire", json={"jwt": admin_jwt, "announcement_id": scheduled_id}).status_code, 400)
+    expect("announcements", "expire missing", requests.put(f"{api_url}/admin/announcements/expire", json={"jwt": admin_jwt, "announcement_id": 99}).status_code, 404)
+
+def test_announcement_emails():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+
+    def announce(title, **fields):
+        return requests.post(f"{api_url}/admin/announcements", json={"jwt": admin_jwt, "title": title, "message": "message", "send_email": True, **fields})
+
+    def published(title):
+        conn = connect_db()
+        rows = conn.execute("SELECT published FROM announcements WHERE title = ?", (title,)).fetchall()
+        conn.close()
+        return [bool(x[0]) for x in rows]
+
+    # Due but not picked up by the publisher yet, creating another announcement leaves it alone
+    conn = connect_db()
+    conn.execute("INSERT INTO announcements (admin_id, title, message, audience, publish_at, send_email, published, timestamp) VALUES (?, 'backlog', 'message', 'all', 0, ?, ?, 0)", (admin_id, False, False))
+    conn.commit()
+    conn.close()
+
+    expect("announcement emails", "create status", announce("everyone", audience="all").status_code, 200)
+    expect("announcement emails", "created published", published("everyone"), [True])
+    expect("announcement emails", "other due left alone", published("backlog"), [False])
+
+    # The emails go out after the response, give them a moment
+    def recipients(title, count):
+        for _ in range(50):
+            sent = sorted(x["to"][0] for x in mail_server["sent"] if x["subject"] == title)
+            if len(sent) >= count:
+                break
+            time.sleep(0.1)
+        return sent
+
+    expect("announcement emails", "all recipients", recipients("everyone", 2), ["admin.mail@user.io", "user.mail.2@user.io"])
+    expect("announcement emails", "admins status", announce("admins", audience="admins").status_code, 200)
+    expect("announcement emails", "admin recipients", recipients("admins", 1), ["admin.mail@user.io"])
+
+    mail_server["failing"] = True
+    failed_mail_response = announce("mail down", audience="all")
+    time.sleep(1)
+    mail_server["failing"] = False
+    expect("announcement emails", "failed mail status", failed_mail_response.status_code, 200)
+    expect("announcement emails", "failed mail published once", published("mail down"), [True])
+
+def test_media():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    image = make_png(600, 300, (0, 128, 255))
+
+    expect("media", "user upload", upload_image(user_jwt, image).status_code, 400)
+    query_upload = requests.post(f"{api_url}/admin/media", params={"jwt": admin_jwt}, data=image, headers={"Content-Type": "image/png"})
+    expect("media", "token in query", query_upload.status_code, 401)
+    expect("media", "gif upload", upload_image(admin_jwt, b"GIF89a" + b"\x00" * 32).status_code, 400)
+    expect("media", "empty upload", upload_image(admin_jwt, b"").status_code, 400)
+    expect("media", "too wide", upload_image(admin_jwt, make_png(5000, 1)).status_code, 400)
+    expect("media", "too large", upload_image(admin_jwt, os.urandom(6 * 1024 * 1024)).status_code, 413)
+
+    upload_response = upload_image(admin_jwt, image)
+    expect("media", "upload status", upload_response.status_code, 200)
+
+    if stop:
+        return
+
+    media_id = json.loads(upload_response.text)["media_id"]
+    expect("media", "same upload", json.loads(upload_image(admin_jwt, image).text)["media_id"], media_id)
+
+    media = json.loads(requests.post(f"{api_url}/admin/media/search", json={"jwt": admin_jwt}).text)
+    expect("media", "listed", [(x["media_id"], x["format"], x["width"], x["height"], x["size"]) for x in media],
+           [(media_id, "png", 600, 300, len(image))])
+    digest = hashlib.sha256(image).hexdigest()
+    expect("media", "hash", media[0]["hash"], digest)
+    expect("media", "stored under hash", open(f"media/{digest}.png", "rb").read() == image, True)
+
+    get_response = requests.get(f"{api_url}/media/{media_id}")
+    expect("media", "get status", get_response.status_code, 200)
+    expect("media", "get body", get_response.content == image, True)
+    expect("media", "content type", get_response.headers.get("Content-Type"), "image/png")
+    expect("media", "cache control", get_response.headers.get("Cache-Control"), "public, max-age=31536000, immutable")
+    expect("media", "etag", get_response.headers.get("ETag"), f'"{digest}"')
+
+    cached_response = requests.get(f"{api_url}/media/{media_id}", headers={"If-None-Match": f'"{digest}"'})
+    expect("media", "not modified", cached_response.status_code, 304)
+
+    thumbnail_response = requests.get(f"{api_url}/media/{media_id}/thumbnail")
+    expect("media", "thumbnail status", thumbnail_response.status_code, 200)
+    (width, height) = struct.unpack(">II", thumbnail_response.content[16:24])
+    expect("media", "thumbnail size", (width, height), (256, 128))
+    expect("media", "missing media", requests.get(f"{api_url}/media/1000").status_code, 404)
+
+    character_data = {
+        "jwt": admin_jwt,
+        "name": "character",
+        "short_description": "short",
+        "full_description": "full",
+        "media_id": 1000
+    }
+    expect("media", "character with missing media", requests.post(f"{api_url}/admin/characters", json=character_data).status_code, 404)
+    character_data["media_id"] = media_id
+    character_id = json.loads(requests.post(f"{api_url}/admin/characters", json=character_data).text)["character_id"]
+    character = json.loads(requests.get(f"{api_url}/characters/{character_id}").text)
+    expect("media", "character media", (character["media_id"], character["image"]), (media_id, None))
+
+def test_admin_lists():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    media_id = json.loads(upload_image(admin_jwt, make_png(4, 4)).text)["media_id"]
+
+    def search(path, **fields):
+        return json.loads(requests.post(f"{api_url}/admin/{path}", json={"jwt": admin_jwt, **fields}).text)
+
+    for name in ["Bolek", "Lolek", "Tola", "Reksio", "Koziolek Matolek"]:
+        requests.post(f"{api_url}/admin/characters", json={
+            "jwt": admin_jwt,
+            "name": name,
+            "short_description": f"{name} short",
+            "full_description": "a dog" if name == "Reksio" else "full",
+            "media_id": media_id
+        })
+
+    pages = []
+    cursor = None
+    while True:
+        page = search("characters/search", limit=2, cursor=cursor)
+        pages.append([x["name"] for x in page["characters"]])
+        expect("admin lists", "character total", page["total"], 5)
+        cursor = page["next_cursor"]
+        if cursor is None or stop:
+            break
+    expect("admin lists", "character pages", pages, [["Bolek", "Lolek"], ["Tola", "Reksio"], ["Koziolek Matolek"]])
+
+    descending = search("characters/search", limit=2, order="desc")
+    expect("admin lists", "descending", [x["name"] for x in descending["characters"]], ["Koziolek Matolek", "Reksio"])
+    expect("admin lists", "descending next page", [x["name"] for x in search("characters/search", limit=2, order="desc", cursor=descending["next_cursor"])["characters"]], ["Tola", "Lolek"])
+
+    found = search("characters/search", search="dog")
+    expect("admin lists", "search description", ([x["name"] for x in found["characters"]], found["total"]), (["Reksio"], 1))
+    expect("admin lists", "search name", [x["name"] for x in search("characters/search", search="olek")["characters"]], ["Bolek", "Lolek", "Koziolek Matolek"])
+    expect("admin lists", "search wildcard", search("characters/search", search="%")["total"], 0)
+
+    quest_ids = []
+    for name in ["First quest", "Second quest"]:
+        quest_data = {"jwt": admin_jwt, "name": name, "description": f"{name} description", "unlocks": [], "points": 1, "coins": 1, "rewards": []}
+        quest_ids.append(json.loads(requests.post(f"{api_url}/admin/quests", json=quest_data).text)["quest_id"])
+    expect("admin lists", "quest search", [x["name"] for x in search("quests/search", search="second")["quests"]], ["Second quest"])
+
+    for (name, quest_id) in [("intro", quest_ids[0]), ("outro", quest_ids[0]), ("spare", None)]:
+        requests.post(f"{api_url}/admin/dialogues", json={"jwt": admin_jwt, "quest_id": quest_id, "name": name, "is_skippable": False, "parts": [[1, "text"]]})
+    expect("admin lists", "dialogues by quest", [x["name"] for x in search("dialogues/search", quest_id=quest_ids[0])["dialogues"]], ["intro", "outro"])
+    expect("admin lists", "unused dialogues", [x["name"] for x in search("dialogues/search/unused")["dialogues"]], ["spare"])
+
+    requests.post(f"{api_url}/admin/tasks/location", json={"jwt": admin_jwt, "quest_id": quest_ids[1], "name": "walk", "desc": "go to the park", "min_radius": 1.0, "max_radius": 2.0, "location_to_duplicate": None})
+    requests.post(f"{api_url}/admin/tasks/text_answer", json={"jwt": admin_jwt, "quest_id": quest_ids[1], "name": "riddle", "desc": None, "question": "what walks?", "correct_answers": ["a dog"]})
+    requests.post(f"{api_url}/admin/tasks/text_answer", json={"jwt": admin_jwt, "quest_id": None, "name": "spare riddle", "desc": None, "question": "?", "correct_answers": ["!"]})
+
+    def task_names(page):
+        return [list(x.values())[0]["name"] for x in page["tasks"]]
+
+    expect("admin lists", "tasks by type", task_names(search("tasks/search", task_type="text")), ["riddle", "spare riddle"])
+    expect("admin lists", "tasks by quest and type", task_names(search("tasks/search", quest_id=quest_ids[1], task_type="text")), ["riddle"])
+    expect("admin lists", "task search", task_names(search("tasks/search", search="walk")), ["walk", "riddle"])
+    expect("admin lists", "unused tasks", task_names(search("tasks/search/unused")), ["spare riddle"])
+    expect("admin lists", "unknown task type", requests.post(f"{api_url}/admin/tasks/search", json={"jwt": admin_jwt, "task_type": "dance"}).status_code, 422)
+
+    for title in ["Crash on start", "Map is blank", "Crash on logout"]:
+        requests.post(f"{api_url}/reports/errors", json={"jwt": user_jwt, "title": title, "message": "message"})
+    reports = search("reports/errors/search", search="crash", limit=1)
+    expect("admin lists", "newest report first", ([x["title"] for x in reports["reports"]], reports["total"]), (["Crash on logout"], 2))
+    expect("admin lists", "older report", [x["title"] for x in search("reports/errors/search", search="crash", cursor=reports["next_cursor"])["reports"]], ["Crash on start"])
+    expect("admin lists", "limit clamped", len(search("characters/search", limit=0)["characters"]), 1)
+
+def test_tasks():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+
+    def add(kind, **fields):
+        return requests.post(f"{api_url}/admin/tasks/{kind}", json={"jwt": admin_jwt, "quest_id": None, "desc": None, **fields})
+
+    def get_task(task_id):
+        tasks = json.loads(requests.post(f"{api_url}/admin/tasks/search", json={"jwt": admin_jwt}).text)["tasks"]
+        return next((x for x in tasks if list(x.values())[0]["task_id"] == task_id), None)
+
+    answers = [f"answer {i}" for i in range(40)]
+    choice_response = add("multiple_choice", name="quiz", question="which?", answers=answers, correct_answers=[0, 35])
+    expect("tasks", "many answers status", choice_response.status_code, 200)
+
+    if stop:
+        return
+
+    choice = get_task(json.loads(choice_response.text)["task_id"])["Choice"]
+    expect("tasks", "many answers", (choice["answers"], choice["choice_answers"]), (answers, [0, 35]))
+
+    expect("tasks", "answer out of range", add("multiple_choice", name="quiz", question="?", answers=["a"], correct_answers=[1]).status_code, 400)
+    expect("tasks", "duplicate answer", add("multiple_choice", name="quiz", question="?", answers=["a", "b"], correct_answers=[1, 1]).status_code, 400)
+    expect("tasks", "no text answers", add("text_answer", name="riddle", question="?", correct_answers=[]).status_code, 400)
+    expect("tasks", "inverted radius", add("location", name="walk", min_radius=5.0, max_radius=1.0, location_to_duplicate=None).status_code, 400)
+
+    # Legacy layouts only ever existed in sqlite databases
+    if postgres:
+        return
+
+    conn = connect_db()
+    for (column, definition) in [("min_radius", "real"), ("max_radius", "real"), ("location_to_duplicate", "int"), ("question", "varchar(65536)"), ("answers", "varchar(65536)"), ("choice_answers", "varchar(32)"), ("text_answers", "varchar(65536)")]:
+        conn.execute(f"ALTER TABLE tasks ADD COLUMN {column} {definition}")
+    conn.execute("INSERT INTO tasks (task_id, type, name, min_radius, max_radius, location_to_duplicate) VALUES (100, 'location', 'old walk', 1.5, 3.0, 7)")
+    conn.execute("INSERT INTO tasks (task_id, type, name, question, answers, choice_answers) VALUES (101, 'choice', 'old quiz', 'which?', ' a\nb\nc \n', ?)", ("0" + "1" + "0" * 29 + "1",))
+    conn.execute("INSERT INTO tasks (task_id, type, name, question, text_answers) VALUES (102, 'text', 'old riddle', 'what?', 'a dog\n')")
+    conn.execute("INSERT INTO tasks (task_id, type, name, question) VALUES (103, 'choice', 'broken quiz', 'which?')")
+    conn.commit()
+
+    broken_result = admin_cli("migrate")
+    expect("tasks", "broken migration exit code", broken_result.returncode, 1)
+    expect_pattern("tasks", "broken migration error", broken_result.stderr, ".*task 103.*")
+    expect("tasks", "broken migration changes nothing", conn.execute("SELECT COUNT(*) FROM tasks WHERE payload IS NULL").fetchone()[0], 4)
+
+    conn.execute("DELETE FROM tasks WHERE task_id = 103")
+    conn.commit()
+    conn.close()
+
+    expect("tasks", "migration exit code", admin_cli("migrate").returncode, 0)
+
+    if stop:
+        return
+
+    expect("tasks", "migrated location", get_task(100), {"Location": {"task_id": 100, "name": "old walk", "quest_id": None, "desc": None, "min_radius": 1.5, "max_radius": 3.0, "location_to_duplicate": 7}})
+    expect("tasks", "migrated choice", get_task(101), {"Choice": {"task_id": 101, "name": "old quiz", "quest_id": None, "desc": None, "question": "which?", "answers": [" a", "b", "c "], "choice_answers": [1, 31]}})
+    expect("tasks", "migrated text", get_task(102), {"Text": {"task_id": 102, "name": "old riddle", "quest_id": None, "desc": None, "question": "what?", "text_answers": ["a dog"]}})
+
+def test_json_lists():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+
+    def get_quest(quest_id):
+        quests = json.loads(requests.post(f"{api_url}/admin/quests/search", json={"jwt": admin_jwt}).text)["quests"]
+        return next(((x["unlocks"], x["rewards"]) for x in quests if x["quest_id"] == quest_id), None)
+
+    def add_quest(unlocks, rewards):
+        quest_data = {"jwt": admin_jwt, "name": "quest", "description": "desc", "unlocks": unlocks, "points": 1, "coins": 1, "rewards": rewards}
+        return json.loads(requests.post(f"{api_url}/admin/quests", json=quest_data).text)["quest_id"]
+
+    expect("json lists", "empty lists", get_quest(add_quest([], [])), ([], []))
+    expect("json lists", "filled lists", get_quest(add_quest([1, 2], [0])), ([1, 2], [0]))
+
+    text_response = requests.post(f"{api_url}/admin/tasks/text_answer", json={"jwt": admin_jwt, "quest_id": None, "name": "poem", "desc": None, "question": "finish it", "correct_answers": ["roses are red\nviolets are blue", ""]})
+    choice_response = requests.post(f"{api_url}/admin/tasks/multiple_choice", json={"jwt": admin_jwt, "quest_id": None, "name": "quiz", "desc": None, "question": "which?", "answers": ["one\ntwo", "three\n"], "correct_answers": [1]})
+    tasks = json.loads(requests.post(f"{api_url}/admin/tasks/search", json={"jwt": admin_jwt}).text)["tasks"]
+    expect("json lists", "task ids", [list(x.values())[0]["task_id"] for x in tasks], [json.loads(text_response.text)["task_id"], json.loads(choice_response.text)["task_id"]])
+
+    if stop:
+        return
+
+    expect("json lists", "multi-line text answers", tasks[0]["Text"]["text_answers"], ["roses are red\nviolets are blue", ""])
+    expect("json lists", "multi-line choice answers", tasks[1]["Choice"]["answers"], ["one\ntwo", "three\n"])
+
+    # Legacy layouts only ever existed in sqlite databases
+    if postgres:
+        return
+
+    conn = connect_db()
+    conn.execute("INSERT INTO quests (quest_id, quest_name, desc, unlocks, points, coins, rewards) VALUES (100, 'old', 'old', '3\n4\n', 1, 1, '')")
+    conn.execute("INSERT INTO quests (quest_id, quest_name, desc, unlocks, points, coins, rewards) VALUES (101, 'broken', 'broken', 'x\n', 1, 1, '')")
+    conn.commit()
+
+    broken_result = admin_cli("migrate")
+    expect("json lists", "broken migration exit code", broken_result.returncode, 1)
+    expect_pattern("json lists", "broken migration error", broken_result.stderr, "quest 101 unlocks")
+    expect("json lists", "broken migration changes nothing", conn.execute("SELECT unlocks FROM quests WHERE quest_id = 100").fetchone()[0], "3\n4\n")
+
+    conn.execute("DELETE FROM quests WHERE quest_id = 101")
+    conn.commit()
+    conn.close()
+
+    expect("json lists", "migration exit code", admin_cli("migrate").returncode, 0)
+    expect("json lists", "migrated lists", get_quest(100), ([3, 4], []))
+
+def test_dialogue_text():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+
+    media_id = json.loads(upload_image(admin_jwt, make_png(4, 4)).text)["media_id"]
+    character_ids = [json.loads(requests.post(f"{api_url}/admin/characters", json={"jwt": admin_jwt, "name": f"c{i}", "short_description": "s", "full_description": "f", "media_id": media_id}).text)["character_id"] for i in range(6)]
+
+    def add(parts):
+        return requests.post(f"{api_url}/admin/dialogues", json={"jwt": admin_jwt, "quest_id": None, "name": "talk", "is_skippable": False, "parts": parts})
+
+    def stored_parts(dialogue_id):
+        conn = connect_db()
+        rows = conn.execute("SELECT part_id, character_id, text FROM dialogue_parts WHERE dialogue_id = ? ORDER BY part_id", (dialogue_id,)).fetchall()
+        conn.close()
+        return rows
+
+    lines = [
+        'He said "hello"',
+        "it's fine",
+        '"); DELETE FROM users; --',
+        "Zażółć gęślą jaźń, 你好, 🦀",
+        "first line\nsecond line",
+        "x" * 100000,
+    ]
+    response = add([[character_ids[i], x] for (i, x) in enumerate(lines)])
+    expect("dialogue text", "add status", response.status_code, 200)
+
+    if stop:
+        return
+
+    dialogue_id = json.loads(response.text)["dialogue_id"]
+    expect("dialogue text", "stored lines", stored_parts(dialogue_id), [(i, character_ids[i], x) for (i, x) in enumerate(lines)])
+    expect("dialogue text", "users intact", login_user("user.mail.2@user.io", "user_2_passwd").status_code, 200)
+
+    many = [[character_ids[0], f"line {i}"] for i in range(1234)]
+    many_id = json.loads(add(many).text)["dialogue_id"]
+    expect("dialogue text", "many parts", stored_parts(many_id), [(i, character_ids[0], f"line {i}") for i in range(1234)])
+
+    conn = connect_db()
+    dialogue_count = conn.execute("SELECT COUNT(*) FROM dialogues").fetchone()[0]
+    conn.close()
+    expect("dialogue text", "empty parts status", add([]).status_code, 400)
+    conn = connect_db()
+    expect("dialogue text", "empty parts leave nothing", conn.execute("SELECT COUNT(*) FROM dialogues").fetchone()[0], dialogue_count)
+    conn.close()
+
+    delete_response = requests.delete(f"{api_url}/admin/dialogues", json={"jwt": admin_jwt, "dialogue_id": many_id})
+    expect("dialogue text", "delete status", delete_response.status_code, 200)
+    expect("dialogue text", "parts deleted", stored_parts(many_id), [])
+
+    user_jwt = json.loads(login_user("user.mail.2@user.io", "user_2_passwd").text)["jwt"]
+    for (field, value) in [("first_name", 'O"Brien'), ("last_name", "Łukasiewicz-Żółć")]:
+        modify_response = requests.put(f"{api_url}/users/me/{field}", json={"jwt": user_jwt, "new_value": value, "account_id": user_id})
+        expect("dialogue text", f"{field} status", modify_response.status_code, 200)
+    conn = connect_db()
+    expect("dialogue text", "stored names", conn.execute("SELECT first_name, last_name FROM users WHERE user_id = ?", (user_id,)).fetchone(), ('O"Brien', "Łukasiewicz-Żółć"))
+    conn.close()
+
+def test_concurrent_ids():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    media_id = json.loads(upload_image(admin_jwt, make_png(4, 4)).text)["media_id"]
+    narrator_id = json.loads(requests.post(f"{api_url}/admin/characters", json={"jwt": admin_jwt, "name": "narrator", "short_description": "s", "full_description": "f", "media_id": media_id}).text)["character_id"]
+
+    def create(i):
+        return [
+            requests.post(f"{api_url}/admin/characters", json={"jwt": admin_jwt, "name": f"c{i}", "short_description": "s", "full_description": "f", "media_id": media_id}),
+            requests.post(f"{api_url}/admin/dialogues", json={"jwt": admin_jwt, "quest_id": None, "name": f"d{i}", "is_skippable": False, "parts": [[narrator_id, "hi"]]}),
+            requests.post(f"{api_url}/admin/tasks/text_answer", json={"jwt": admin_jwt, "quest_id": None, "name": f"t{i}", "desc": None, "question": "?", "correct_answers": ["!"]}),
+            requests.post(f"{api_url}/admin/quests", json={"jwt": admin_jwt, "name": f"q{i}", "description": "d", "unlocks": [], "points": 1, "coins": 1, "rewards": []}),
+            requests.post(f"{api_url}/auth/register", json={"email": f"racer{i}@user.io", "plaintext_password": "racer_passwd", "first_name": "racer", "last_name": str(i), "gender": "m"}),
+        ]
+
+    with ThreadPoolExecutor(max_workers=16) as pool:
+        results = list(pool.map(create, range(24)))
+
+    for (column, key) in enumerate(["character_id", "dialogue_id", "task_id", "quest_id", "account_id"]):
+        responses = [x[column] for x in results]
+        expect("concurrent ids", f"{key} statuses", sorted({x.status_code for x in responses}), [201 if key == "account_id" else 200])
+        if stop:
+            return
+        ids = [json.loads(x.text)[key] for x in responses]
+        expect("concurrent ids", f"unique {key}", len(set(ids)), len(ids))
+
+    conn = connect_db()
+    for (table, count) in [("characters", 25), ("dialogues", 24), ("tasks", 24), ("quests", 24), ("users", 26), ("dialogue_parts", 24)]:
+        expect("concurrent ids", f"{table} rows", conn.execute(f"SELECT COUNT(*) FROM {table}").fetchone()[0], count)
+    conn.close()
+
+    quest_id = json.loads(results[0][3].text)["quest_id"]
+    dialogue_id = json.loads(results[0][1].text)["dialogue_id"]
+    def add_stage(i):
+        return requests.post(f"{api_url}/admin/quests/stages", json={"jwt": admin_jwt, "quest_id": quest_id, "task_id": None, "dialogue_id": dialogue_id})
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        stage_statuses = [x.status_code for x in pool.map(add_stage, range(8))]
+    expect("concurrent ids", "stage statuses", set(stage_statuses), {200})
+    conn = connect_db()
+    stage_ids = [x[0] for x in conn.execute("SELECT stage_id FROM quest_stages WHERE quest_id = ? ORDER BY stage_id", (quest_id,)).fetchall()]
+    conn.close()
+    expect("concurrent ids", "stage ids", stage_ids, list(range(1, 9)))
+
+    count_url = f"http://{addr}:{port}/user/retrieve/count"
+    expect("concurrent ids", "user count", json.loads(requests.get(count_url).text), 26)
+    last_id = max(json.loads(x[4].text)["account_id"] for x in results)
+    last_email = next(f"racer{i}@user.io" for (i, x) in enumerate(results) if json.loads(x[4].text)["account_id"] == last_id)
+    delete_account(last_email)
+    expect("concurrent ids", "user count after deletion", json.loads(requests.get(count_url).text), 25)
+    expect("concurrent ids", "deleted id not reused", register_user("late@user.io", "late_passwd", "late", "user") > last_id, True)
+
+    # Legacy layouts only ever existed in sqlite databases
+    if postgres:
+        return
+
+    conn = connect_db()
+    conn.execute("PRAGMA legacy_alter_table = ON")
+    conn.execute("ALTER TABLE characters RENAME TO characters_keyed")
+    conn.execute("CREATE TABLE characters (character_id int, name varchar(255), short_desc varchar(255), full_desc varchar(255), image varchar(255), media_id int)")
+    conn.execute("INSERT INTO characters SELECT * FROM characters_keyed")
+    conn.execute("DROP TABLE characters_keyed")
+    conn.execute("INSERT INTO characters (character_id, name) VALUES (1, 'copy')")
+    conn.execute("PRAGMA user_version = 0")
+    conn.commit()
+
+    duplicate_result = admin_cli("migrate")
+    expect("concurrent ids", "duplicate migration exit code", duplicate_result.returncode, 1)
+    expect_pattern("concurrent ids", "duplicate migration error", duplicate_result.stderr, r"duplicate characters \(character_id\): 1")
+
+    conn.execute("DELETE FROM characters WHERE name = 'copy'")
+    conn.commit()
+    expect("concurrent ids", "migration exit code", admin_cli("migrate").returncode, 0)
+    expect("concurrent ids", "primary key", conn.execute("SELECT pk FROM pragma_table_info('characters') WHERE name = 'character_id'").fetchone()[0], 1)
+    expect("concurrent ids", "rows kept", conn.execute("SELECT COUNT(*) FROM characters").fetchone()[0], 25)
+    conn.close()
+
+def test_constraints():
+    (admin_id, user_id, admin_jwt) = setup_admin_and_user()
+
+    if stop:
+        return
+
+    api_url = f"http://{addr}:{port}/api/v1"
+    register_url = f"http://{addr}:{port}/auth/register"
+    register_data = {"email": "User.Mail.2@USER.io", "plaintext_password": "other_passwd", "first_name": "user", "last_name": "copy", "gender": "m"}
+    expect("constraints", "duplicate email status", requests.post(register_url, json=register_data).status_code, 400)
+    expect("constraints", "login with other case", login_user("USER.MAIL.2@user.io", "user_2_passwd").status_code, 200)
+
+    media_id = json.loads(upload_image(admin_jwt, make_png(4, 4)).text)["media_id"]
+    character_id = json.loads(requests.post(f"{api_url}/admin/characters", json={"jwt": admin_jwt, "name": "c", "short_description": "s", "full_description": "f", "media_id": media_id}).text)["character_id"]
+    quest_id = json.loads(requests.post(f"{api_url}/admin/quests", json={"jwt": admin_jwt, "name": "q", "description": "d", "unlocks": [], "points": 1, "coins": 1, "rewards": []}).text)["quest_id"]
+
+    def add_task(quest_id):
+        return requests.post(f"{api_url}/admin/tasks/text_answer", json={"jwt": admin_jwt, "quest_id": quest_id, "name": "t", "desc": None, "question": "?", "correct_answers": ["!"]})
+
+    def add_dialogue(quest_id, character_id):
+        return requests.post(f"{api_url}/admin/dialogues", json={"jwt": admin_jwt, "quest_id": quest_id, "name": "d", "is_skippable": False, "parts": [[character_id, "hi"]]})
+
+    def add_stage(quest_id, task_id, dialogue_id):
+        return requests.post(f"{api_url}/admin/quests/stages", json={"jwt": admin_jwt, "quest_id": quest_id, "task_id": task_id, "dialogue_id": dialogue_id})
+
+    expect("constraints", "task with missing quest", add_task(999).status_code, 404)
+    expect("constraints", "dialogue with missing quest", add_dialogue(999, character_id).status_code, 404)
+    expect("constraints", "dialogue with missing character", add_dialogue(quest_id, 999).status_code, 404)
+
+    task_id = json.loads(add_task(quest_id).text)["task_id"]
+    dialogue_id = json.loads(add_dialogue(quest_id, character_id).text)["dialogue_id"]
+    expect("constraints", "stage with missing quest", add_stage(999, task_id, None).status_code, 404)
+    expect("constraints", "stage with missing task", add_stage(quest_id, 999, None).status_code, 404)
+    expect("constraints", "stage with missing dialogue", add_stage(quest_id, None, 999).status_code, 404)
+    expect("constraints", "tutorial with missing quest", requests.put(f"{api_url}/admin/game/tutorial", json={"jwt": admin_jwt, "quest_id": 999}).status_code, 404)
+    expect("constraints", "task stage status", add_stage(quest_id, task_id, None).status_code, 200)
+    expect("constraints", "dialogue stage status", add_stage(quest_id, None, dialogue_id).status_code, 200)
+
+    if stop:
+        return
+
+    move_data = {"jwt": admin_jwt, "quest_id": quest_id, "position": 1}
+    expect("constraints", "move forward status", requests.post(f"{api_url}/admin/quests/stages/move_forward", json=move_data).status_code, 200)
+    conn = connect_db()
+    expect("constraints", "swapped stages", conn.execute("SELECT stage_id, task_id, dialogue_id FROM quest_stages WHERE quest_id = ? ORDER BY stage_id", (quest_id,)).fetchall(), [(1, None, dialogue_id), (2, task_id, None)])
+    conn.close()
+
+    character_delete = requests.delete(f"{api_url}/admin/characters", json={"jwt": admin_jwt, "character_id": character_id})
+    expect("constraints", "used character delete status", character_delete.status_code, 409)
+    expect("constraints", "quest delete status", requests.delete(f"{api_url}/admin/quests", json={"jwt": admin_jwt, "quest_id": quest_id}).status_code, 200)
+
+    conn = connect_db()
+    expect("constraints", "stages deleted with quest", conn.execute("SELECT COUNT(*) FROM quest_stages WHERE quest_id = ?", (quest_id,)).fetchone()[0], 0)
+    expect("constraints", "task kept without quest", conn.execute("SELECT quest_id FROM tasks WHERE task_id = ?", (task_id,)).fetchone(), (None,))
+
+    # Postgres tables are created with their keys, there is nothing to migrate
+    if postgres:
+        expect("constraints", "email index", "UNIQUE" in conn.execute("SELECT indexdef FROM pg_indexes WHERE indexname = 'users_email'").fetchone()[0], True)
+        try:
+            conn.execute("INSERT INTO sessions (user_id, session_token, timestamp, valid_until) VALUES (999, 'orphan', 0, 1)")
+            orphan_error = ""
+        except Exception as err:
+            orphan_error = str(err)
+        expect_pattern("constraints", "orphan session rejected", orphan_error, "foreign key")
+        conn.close()
+        return
+
+    expect("constraints", "email index", conn.execute("SELECT \"unique\" FROM pragma_index_list('users') WHERE name = 'users_email'").fetchone(), (1,))
+    expect("constraints", "schema version", conn.execute("PRAGMA user_version").fetchone()[0], 1)
+
+    conn.execute("INSERT INTO sessions (user_id, session_token, timestamp, valid_until) VALUES (999, 'orphan', 0, 1)")
+    conn.execute("DROP INDEX users_email")
+    conn.execute("UPDATE users SET email = 'ADMIN.MAIL@USER.IO' WHERE user_id = ?", (user_id,))
+    conn.execute("PRAGMA user_version = 0")
+    conn.commit()
+
+    report_result = admin_cli("migrate")
+    expect("constraints", "report exit code", report_result.returncode, 1)
+    expect_pattern("constraints", "orphan session reported", report_result.stderr, r"sessions\.user_id points at missing users: 999")
+    expect_pattern("constraints", "duplicate email reported", report_result.stderr, r"duplicate users \(lower\(email\)\): admin\.mail@user\.io")
+    expect("constraints", "nothing changed", conn.execute("PRAGMA user_version").fetchone()[0], 0)
+
+    launch = subprocess.run(["cargo", "run", *cargo_features], capture_output=True, text=True, timeout=600,
+                            env={**os.environ, "WIEDZIELISCIE_BACKEND_PORT": str(port + 1),
+                                 "WIEDZIELISCIE_BACKEND_RESET_DB": "false"})
+    expect("constraints", "unmigrated launch aborted", launch.returncode != 0, True)
+    expect_pattern("constraints", "unmigrated launch error", launch.stdout, r"(?s)Database setup failed: .*duplicate users")
+
+    conn.execute("DELETE FROM sessions WHERE session_token = 'orphan'")
+    conn.execute("UPDATE users SET email = 'user.mail.2@user.io' WHERE user_id = ?", (user_id,))
+    conn.commit()
+    expect("constraints", "migration exit code", admin_cli("migrate").returncode, 0)
+    expect("constraints", "migrated schema version", conn.execute("PRAGMA user_version").fetchone()[0], 1)
+    expect("constraints", "email index restored", conn.execute("SELECT COUNT(*) FROM pragma_index_list('users') WHERE name = 'users_email'").fetchone()[0], 1)
+
+    conn.execute("DROP INDEX users_email")
+    conn.execute("CREATE TABLE users_email (email varchar(255))")
+    conn.execute("PRAGMA user_version = 0")
+    conn.commit()
+    index_result = admin_cli("migrate")
+    expect("constraints", "failed index exit code", index_result.returncode, 1)
+    expect_pattern("constraints", "failed index reported", index_result.stderr, "Failed to create index users_email")
+    expect("constraints", "failed index schema version", conn.execute("PRAGMA user_version").fetchone()[0], 0)
+
+    conn.execute("DROP TABLE users_email")
+    conn.commit()
+    expect("constraints", "index retry exit code", admin_cli("migrate").returncode, 0)
+    expect("constraints", "index retry schema version", conn.execute("PRAGMA user_version").fetchone()[0], 1)
+    conn.close()
+
+# Tables as the first release created them
+BASELINE_SCHEMA = [
+    "CREATE TABLE users (user_id int, first_name varchar(255), last_name varchar(255), email varchar(255), password varchar(255), gender bool, verified bool, admin bool)",
+    "CREATE TABLE deleted_users (user_id int, first_name varchar(255), last_name varchar(255), email varchar(255), password varchar(255), gender bool, verified bool, admin bool)",
+    "CREATE TABLE delete_requests (user_id int, delete_token varchar(255), timestamp int, valid_until int)",
+    "CREATE TABLE verifications (user_id int, timestamp int, verification_token varchar(255))",
+    "CREATE TABLE sessions (user_id int, session_token varchar(255), timestamp int, valid_until int)",
+    "CREATE TABLE password_resets (user_id int, reset_token varchar(255), password varchar(255), timestamp int, valid_until int)",
+    "CREATE TABLE email_updates (user_id int, update_token varchar(255), email varchar(255), timestamp int, valid_until int)",
+    "CREATE TABLE characters (character_id int, name varchar(255), short_desc varchar(255), full_desc varchar(255), image varchar(255))",
+    "CREATE TABLE dialogues (dialogue_id int, quest_id int, name varchar(255), is_skippable bool)",
+    "CREATE TABLE dialogue_parts (dialogue_id int, part_id int, character_id int, text varchar(65536))",
+    "CREATE TABLE tasks (task_id int, type varchar(255), name varchar(255), quest_id int, desc varchar(65536), min_radius real, max_radius real, location_to_duplicate int, question varchar(65536), answers varchar(65536), choice_answers varchar(32), text_answers varchar(65536))",
+    "CREATE TABLE quests (quest_id int, quest_name varchar(65536), desc varchar(65536), unlocks varchar(65536), points int, coins int, rewards varchar(65536))",
+    "CREATE TABLE quest_stages (quest_id int, stage_id int, task_id int, dialogue_id int)",
+    "CREATE TABLE game (paused bool, location_radius real, tutorial_id int)",
+    "CREATE TABLE error_report (title varchar(65536), message varchar(65536))",
+    "CREATE TABLE suggestion (title varchar(65536), message varchar(65536))",
+]
+
+def test_baseline_upgrade():
+    # Only sqlite databases predate the migrations
+    if postgres:
+        return
+
+    path = "baseline.sqlite"
+    if os.path.exists(path):
+        os.remove(path)
+    conn = sqlite3.connect(path)
+    for statement in BASELINE_SCHEMA:
+        conn.execute(statement)
+    conn.execute("INSERT INTO users VALUES (1, 'old', 'user', 'old@user.io', 'hash', 1, 1, 0)")
+    conn.execute("INSERT INTO error_report VALUES ('old error', 'it broke')")
+    conn.execute("INSERT INTO suggestion VALUES ('old idea', 'add more')")
+    conn.execute("INSERT INTO game VALUES (0, 10.0, NULL)")
+    conn.commit()
+
+    result = admin_cli("migrate", env={"WIEDZIELISCIE_BACKEND_DATABASES__DB__URL": path})
+    expect("baseline upgrade", "migrate exit code", result.returncode, 0)
+    expect("baseline upgrade", "migrate error", result.stderr, "")
+    expect("baseline upgrade", "schema version", conn.execute("PRAGMA user_version").fetchone()[0], 1)
+    expect("baseline upgrade", "error reports", conn.execute("SELECT report_id, user_id, title, message, status FROM error_report").fetchall(), [(1, None, "old error", "it broke", "open")])
+    expect("baseline upgrade", "suggestions", conn.execute("SELECT report_id, user_id, title, message, status FROM suggestion").fetchall(), [(1, None, "old idea", "add more", "open")])
+    expect("baseline upgrade", "report dates", conn.execute("SELECT COUNT(*) FROM error_report WHERE timestamp > 0 AND updated = timestamp").fetchone()[0], 1)
+    expect("baseline upgrade", "users kept", conn.execute("SELECT user_id, email FROM users").fetchall(), [(1, "old@user.io")])
+    conn.execute("INSERT INTO error_report (title, message, status, timestamp, updated) VALUES ('new', 'new', 'open', 0, 0)")
+    expect("baseline upgrade", "next report id", conn.execute("SELECT MAX(report_id) FROM error_report").fetchone()[0], 2)
+    conn.close()
+    os.remove(path)
+
+def test_openapi():
+    main_rs = open("src/main.rs").read()
+    mounted = ",".join(re.findall(r"routes!\[(.*?)\]", main_rs, re.S))
+    handlers = [x.strip().split("::")[-1] for x in mounted.split(",") if x.strip()]
+
+    spec_url = f"http://{addr}:{port}/openapi.json"
+    spec_response = requests.get(spec_url)
+    expect("openapi", "status", spec_response.status_code, 200)
+
+    if stop:
+        return
+
+    spec = json.loads(spec_response.text)
+    expect("openapi", "version", spec["openapi"], "3.0.3")
+
+    operations = {}
+    for (path, item) in spec["paths"].items():
+        for (method, operation) in item.items():
+            operations[operation["operationId"]] = (method, path, operation)
+
+    for handler in handlers:
+        expect("openapi", f"{handler} in spec", handler in operations or f"legacy_{handler}" in operations, True)
+
+    for (name, (method, path, operation)) in operations.items():
+        expect("openapi", f"{name} documented", "x-undocumented" in operation, False)
+        expect("openapi", f"{name} deprecated", operation.get("deprecated", False), name.startswith("legacy_"))
+
+    if stop:
+        return
+
+    (method, path, operation) = operations["admin_quests_stages_add"]
+    expect("openapi", "stage add method", (method, path), ("post", "/api/v1/admin/quests/stages"))
+    expect("openapi", "stage add legacy path", operations["legacy_admin_quests_stages_add"][1], "/admin/quests/stages/add")
+    request_ref = operation["requestBody"]["content"]["application/json"]["schema"]["$ref"]
+    request_schema = spec["components"]["schemas"][request_ref.split("/")[-1]]
+    expect("openapi", "stage add request", sorted(request_schema["properties"].keys()),
+           sorted(["jwt", "quest_id", "task_id", "dialogue_id"]))
+    expect("openapi", "error schema", operation["responses"]["403"]["content"]["application/json"]["schema"],
+           {"$ref": "#/components/schemas/Error"})
+
+    (method, path, operation) = operations["auth_resend_verification"]
+    expect("openapi", "path parameter", path, "/api/v1/auth/register/{account_id}/resend")
+    expect("openapi", "path parameter type", operation["parameters"][0]["schema"]["type"], "integer")
+
+    expect("openapi", "login rate limit", "429" in operations["auth_login"][2]["responses"], True)
+    expect("openapi", "character path", operations["characters_get"][:2], ("get", "/api/v1/characters/{character_id}"))
+    expect("openapi", "task schema", "Task" in spec["components"]["schemas"], True)
+
+    docs_response = requests.get(f"http://{addr}:{port}/docs")
+    expect("openapi", "docs status", docs_response.status_code, 200)
+    expect("openapi", "docs page", "/openapi.json" in docs_response.text, True)
+
+    bundle_response = requests.get(f"http://{addr}:{port}/docs/swagger-ui-bundle.js")
+    expect("openapi", "bundle status", bundle_response.status_code, 200)
+    expect("openapi", "missing asset status", requests.get(f"http://{addr}:{port}/docs/missing.js").status_code, 404)
+
+def admin_cli(*args, env={}):
+    return subprocess.run(["cargo", "run", "-q", *cargo_features, "--bin", "wiedzieliscie-admin", "--", *args],
+                          capture_output=True,
+                          text=True,
+                          env={**os.environ, **env})
+
+def test_admin_cli():
+    create_result = admin_cli("create-admin", "cli.admin@user.io", "cli_passwd", "cli", "admin", "m")
+    expect("admin cli", "create-admin exit code", create_result.returncode, 0)
+
+    if stop:
+        return
+
+    login_response = login_user("cli.admin@user.io", "cli_passwd")
+    expect("admin cli", "login status", login_response.status_code, 200)
+
+    if stop:
+        return
+
+    admin_jwt = json.loads(login_response.text)["jwt"]
+    character_url = f"http://{addr}:{port}/admin/characters/add"
+    media_id = json.loads(upload_image(admin_jwt, make_png(4, 4)).text)["media_id"]
+    character_data = {
+        "jwt": admin_jwt,
+        "name": "Bolek",
+        "short_description": "short",
+        "full_description": "full",
+        "media_id": media_id
+    }
+    character_response = requests.post(character_url, json=character_data)
+    expect("admin cli", "character status", character_response.status_code, 200)
+
+    export_result = admin_cli("export-content", "cli_content.json")
+    expect("admin cli", "export-content exit code", export_result.returncode, 0)
+
+    conn = connect_db()
+    conn.execute("DELETE FROM characters")
+    conn.execute("DELETE FROM media")
+    conn.commit()
+    conn.close()
+
+    import_result = admin_cli("import-content", "cli_content.json")
+    expect("admin cli", "import-content exit code", import_result.returncode, 0)
+
+    conn = connect_db()
+    cur = conn.cursor()
+    cur.execute("SELECT name, short_desc, full_desc, media_id FROM characters")
+    expect("admin cli", "imported characters", cur.fetchall(), [("Bolek", "short", "full", media_id)])
+    cur.execute("SELECT media_id FROM media")
+    expect("admin cli", "imported media", cur.fetchall(), [(media_id,)])
+    conn.close()
+    os.remove("cli_content.json")
+
+    pause_url = f"http://{addr}:{port}/get/pause_state"
+    admin_cli("pause")
+    expect("admin cli", "paused", json.loads(requests.get(pause_url).text), True)
+    admin_cli("unpause")
+    expect("admin cli", "unpaused", json.loads(requests.get(pause_url).text), False)
+
+    reset_result = admin_cli("reset-password", "cli.admin@user.io", "new_cli_passwd")
+    expect("admin cli", "reset-password exit code", reset_result.returncode, 0)
+    expect("admin cli", "old password login", login_user("cli.admin@user.io", "cli_passwd").status_code, 400)
+    expect("admin cli", "new password login", login_user("cli.admin@user.io", "new_cli_passwd").status_code, 200)
+
+    promote_result = admin_cli("promote", "cli.admin@user.io")
+    expect("admin cli", "promote admin exit code", promote_result.returncode, 1)
+    expect("admin cli", "promote admin error", promote_result.stderr.strip(), "User is already admin")
+
+    unknown_result = admin_cli("frobnicate")
+    expect("admin cli", "unknown command exit code", unknown_result.returncode, 1)
+
 set_env()
+start_mail_server()
 
 tests = [
     (test_register, "Register and verify"),
@@ -266,12 +2016,47 @@ tests = [
     (test_retrieve_user_name, "Retrieve user name"),
     (test_retrieve_user_id, "Retrieve user id"),
     (test_retrieve_user_count, "Retrieve user count"),
+    (test_admin_users_list, "Admin users list"),
+    (test_admin_users_ban, "Admin users ban"),
+    (test_admin_users_promote_demote, "Admin users promote and demote"),
+    (test_admin_users_impersonate, "Admin users impersonate"),
+    (test_admin_audit, "Admin audit log"),
+    (test_report_triage, "Report triage"),
+    (test_report_status_email, "Report status email", mail_env()),
+    (test_admin_get_logs, "Admin get logs"),
+    (test_metrics, "Metrics",
+     {"WIEDZIELISCIE_BACKEND_METRICS__ALLOWED_IPS": "10.9.9.9"}),
+    (test_health_ready, "Health and readiness"),
+    (test_admin_cli, "Admin cli"),
+    (test_janitor, "Janitor"),
+    (test_rate_limits, "Rate limits"),
+    (test_totp, "Totp"),
+    (test_user_export, "User export"),
+    (test_deleted_user_anonymisation, "Deleted user anonymisation"),
+    (test_openapi, "Openapi"),
+    (test_api_v1, "Api v1"),
+    (test_events, "Events"),
+    (test_announcement_emails, "Announcement emails", mail_env()),
+    (test_media, "Media"),
+    (test_admin_lists, "Admin lists"),
+    (test_tasks, "Tasks"),
+    (test_json_lists, "Json lists"),
+    (test_dialogue_text, "Dialogue text"),
+    (test_concurrent_ids, "Concurrent ids",
+     {"WIEDZIELISCIE_BACKEND_RATE_LIMITS__ENABLED": "false"}),
+    (test_constraints, "Constraints"),
+    (test_baseline_upgrade, "Baseline upgrade"),
+    (test_announcements, "Announcements",
+     {"WIEDZIELISCIE_BACKEND_ANNOUNCEMENTS__PUBLISH_INTERVAL": "1"}),
+    (test_totp_required_for_admins, "Totp required for admins",
+     {"WIEDZIELISCIE_BACKEND_TOTP__REQUIRED_FOR_ADMINS": "true"}),
 ]
 
-for (test, i) in tests:
-    instance = subprocess.Popen(["cargo", "run"], 
+for (test, i, *env) in tests:
+    instance = subprocess.Popen(["cargo", "run", *cargo_features], 
                                 stdout=subprocess.DEVNULL, 
-                                stderr=subprocess.DEVNULL)
+                                stderr=subprocess.DEVNULL,
+                                env={**os.environ, **(env[0] if env else {})})
     sock = socket.socket()
     while sock.connect_ex((addr, port)) != 0:
         time.sleep(0.1)